@@ -196,6 +196,7 @@ pub mod {{mod}} {
             impl<T, R> {{crate}}::Interface for Wrapper<T, R> where T: {{crate}}::Interface, R: Send + Sync {
                 fn as_binder(&self) -> {{crate}}::SIBinder { self._inner.as_binder() }
                 fn dump(&self, _writer: &mut dyn std::io::Write, _args: &[String]) -> {{crate}}::Result<()> { self._inner.dump(_writer, _args) }
+                fn shell_command(&self, _input: &mut dyn std::io::Read, _output: &mut dyn std::io::Write, _error: &mut dyn std::io::Write, _args: &[String]) -> {{crate}}::Result<i32> { self._inner.shell_command(_input, _output, _error, _args) }
             }
             impl<T, R> {{bn_name}}Adapter for Wrapper<T, R>
             where
@@ -224,7 +225,7 @@ pub mod {{mod}} {
             {%- if is_vintf %}
             let binder = {{crate}}::native::Binder::new_with_stability({{bn_name}}(Box::new(wrapped)), {{crate}}::Stability::Vintf);
             {%- else %}
-            let binder = {{crate}}::native::Binder::new_with_stability({{bn_name}}(Box::new(wrapped)), {{crate}}::Stability::default());
+            let binder = {{crate}}::native::Binder::new_with_stability({{bn_name}}(Box::new(wrapped)), {{crate}}::Stability::Local);
             {%- endif %}
             {{crate}}::Strong::new(Box::new(binder))
         }
@@ -17,6 +17,13 @@ mod type_generator;
 pub use generator::Generator;
 pub use parser::parse_document;
 
+// Types which are built into the AIDL compiler, but may be imported like
+// user-defined ones.
+const BUILTIN_IMPORTS: &[&str] = &[
+    "android.os.ParcelFileDescriptor",
+    "android.os.ParcelableHolder",
+];
+
 #[derive(Default, Hash, Eq, PartialEq, Debug, Clone)]
 pub struct Namespace {
     ns: Vec<String>,
@@ -255,6 +262,10 @@ impl Builder {
                     }
 
                     for import in doc.imports.values() {
+                        // Built-in types may be imported, but have no AIDL files.
+                        if BUILTIN_IMPORTS.contains(&import.as_str()) {
+                            continue;
+                        }
                         let rel_path =
                             PathBuf::from(import.replace('.', "/")).with_extension("aidl");
                         let mut found = false;
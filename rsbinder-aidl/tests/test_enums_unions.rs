@@ -395,6 +395,7 @@ pub mod ITestService {
             impl<T, R> rsbinder::Interface for Wrapper<T, R> where T: rsbinder::Interface, R: Send + Sync {
                 fn as_binder(&self) -> rsbinder::SIBinder { self._inner.as_binder() }
                 fn dump(&self, _writer: &mut dyn std::io::Write, _args: &[String]) -> rsbinder::Result<()> { self._inner.dump(_writer, _args) }
+                fn shell_command(&self, _input: &mut dyn std::io::Read, _output: &mut dyn std::io::Write, _error: &mut dyn std::io::Write, _args: &[String]) -> rsbinder::Result<i32> { self._inner.shell_command(_input, _output, _error, _args) }
             }
             impl<T, R> BnTestServiceAdapter for Wrapper<T, R>
             where
@@ -418,7 +419,7 @@ pub mod ITestService {
                 }
             }
             let wrapped = Wrapper { _inner: inner, _rt: rt };
-            let binder = rsbinder::native::Binder::new_with_stability(BnTestService(Box::new(wrapped)), rsbinder::Stability::default());
+            let binder = rsbinder::native::Binder::new_with_stability(BnTestService(Box::new(wrapped)), rsbinder::Stability::Local);
             rsbinder::Strong::new(Box::new(binder))
         }
     }
@@ -0,0 +1,73 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+
+// Write the AIDL files to a directory of their own, laid out by package, and
+// generate the Rust code of the first one.
+fn generate(name: &str, files: &[(&str, &str)]) -> Result<String, Box<dyn Error>> {
+    let dir = std::env::temp_dir().join(format!("rsbinder-aidl-{name}-{}", std::process::id()));
+    for (path, content) in files {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, content)?;
+    }
+
+    let output = PathBuf::from(format!("{name}.rs"));
+    let result = rsbinder_aidl::Builder::new()
+        .source(dir.join(files[0].0))
+        .output(dir.join(&output))
+        .generate()
+        .and_then(|_| Ok(fs::read_to_string(dir.join(&output))?));
+    fs::remove_dir_all(&dir)?;
+    result
+}
+
+const ACCESSOR: &str = r#"
+    package android.os;
+    import android.os.ParcelFileDescriptor;
+    import android.os.ParcelableHolder;
+    interface IAccessor {
+        ParcelFileDescriptor addConnection();
+    }
+"#;
+
+#[test]
+fn test_builtin_imports() -> Result<(), Box<dyn Error>> {
+    let content = generate("builtin", &[("android/os/IAccessor.aidl", ACCESSOR)])?;
+    assert!(content.contains(
+        "fn r#addConnection(&self) -> rsbinder::status::Result<rsbinder::ParcelFileDescriptor>"
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_imports() -> Result<(), Box<dyn Error>> {
+    let content = generate(
+        "user",
+        &[
+            (
+                "android/os/IFoo.aidl",
+                "package android.os;\nimport android.os.Bar;\ninterface IFoo { Bar get(); }",
+            ),
+            (
+                "android/os/Bar.aidl",
+                "package android.os;\nparcelable Bar { int value; }",
+            ),
+        ],
+    )?;
+    assert!(content.contains("pub mod Bar"));
+
+    // A user-defined type which has no AIDL file.
+    assert!(generate(
+        "missing",
+        &[(
+            "android/os/IFoo.aidl",
+            "package android.os;\nimport android.os.Missing;\ninterface IFoo { Missing get(); }",
+        )],
+    )
+    .is_err());
+    Ok(())
+}
@@ -0,0 +1,106 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+use std::time::Duration;
+
+use env_logger::Env;
+use rsbinder::*;
+
+// The exit code of Android's cmd when the service can't be found.
+const EXIT_NO_SERVICE: i32 = 20;
+// The exit code when the call or the command fails with a status code, which
+// is negative and so can't be an exit code itself.
+const EXIT_FAILURE: i32 = 1;
+// How long to wait for the result of a command after the service ran it.
+const RESULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let matches = clap::Command::new("rsb_cmd")
+        .version(env!("CARGO_PKG_VERSION"))
+        .author(env!("CARGO_PKG_AUTHORS"))
+        .about("Runs a shell command of a binder service")
+        .arg(
+            clap::Arg::new("list")
+                .help("List the registered services")
+                .short('l')
+                .long("list")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("service")
+                .help("Name of the service to run the command of")
+                .required_unless_present("list")
+                .index(1),
+        )
+        .arg(
+            clap::Arg::new("args")
+                .help("Arguments of the command")
+                .index(2)
+                .num_args(0..)
+                .trailing_var_arg(true)
+                .allow_hyphen_values(true),
+        )
+        .after_help(
+            "Examples:\n    \
+            List the services which can be given a command:\n    \
+            $ rsb_cmd -l\n\n\
+            Run the 'help' command of the service 'my.service':\n    \
+            $ rsb_cmd my.service help\n\n\
+            The exit code of rsb_cmd is the result of the command, or 1 if it failed.",
+        )
+        .get_matches();
+
+    env_logger::Builder::from_env(Env::default().default_filter_or("warn")).init();
+
+    ProcessState::init(DEFAULT_BINDER_PATH, 0);
+    // The result of the command is sent to a binder object of this process.
+    ProcessState::start_thread_pool();
+
+    if matches.get_flag("list") {
+        for name in hub::list_services(hub::DUMP_FLAG_PRIORITY_ALL) {
+            println!("{name}");
+        }
+        return Ok(());
+    }
+
+    let name = matches.get_one::<String>("service").unwrap();
+    let args: Vec<String> = matches
+        .get_many::<String>("args")
+        .unwrap_or_default()
+        .cloned()
+        .collect();
+
+    let Some(service) = hub::check_service(name) else {
+        eprintln!("rsb_cmd: Can't find service: {name}");
+        std::process::exit(EXIT_NO_SERVICE);
+    };
+    let Some(proxy) = service.as_proxy() else {
+        eprintln!("rsb_cmd: Service {name} is not a remote object");
+        std::process::exit(EXIT_NO_SERVICE);
+    };
+
+    let receiver = ResultReceiver::new();
+    if let Err(err) = proxy.shell_command(
+        std::io::stdin(),
+        std::io::stdout(),
+        std::io::stderr(),
+        &args,
+        Some(&receiver.as_binder()),
+    ) {
+        eprintln!("rsb_cmd: Failure calling service {name}: {err}");
+        std::process::exit(EXIT_FAILURE);
+    }
+
+    let Some(result) = receiver.wait_timeout(RESULT_TIMEOUT) else {
+        eprintln!("rsb_cmd: No result from service {name} in {RESULT_TIMEOUT:?}");
+        std::process::exit(EXIT_FAILURE);
+    };
+    if result < 0 {
+        eprintln!(
+            "rsb_cmd: Failure of service {name}: {}",
+            StatusCode::from(result)
+        );
+        std::process::exit(EXIT_FAILURE);
+    }
+    std::process::exit(result)
+}
@@ -0,0 +1,505 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use env_logger::Env;
+use rsbinder::*;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// What is printed for each service.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Dump,
+    Pid,
+    Stability,
+}
+
+fn priority_flag(name: &str) -> Option<i32> {
+    match name {
+        "CRITICAL" => Some(hub::DUMP_FLAG_PRIORITY_CRITICAL),
+        "HIGH" => Some(hub::DUMP_FLAG_PRIORITY_HIGH),
+        "NORMAL" => Some(hub::DUMP_FLAG_PRIORITY_NORMAL),
+        "ALL" => Some(hub::DUMP_FLAG_PRIORITY_ALL),
+        _ => None,
+    }
+}
+
+// The pids of the services, as the hub knows them.
+fn service_pids() -> Vec<hub::ServiceDebugInfo> {
+    hub::get_service_debug_info().unwrap_or_else(|err| {
+        eprintln!("rsb_dumpsys: Failed to get the debug info of the services: {err}");
+        Vec::new()
+    })
+}
+
+// A pipe, whose read end is a file.
+fn pipe() -> std::io::Result<(File, OwnedFd)> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(unsafe { (File::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+// Copy from `input` to `output` until all the write ends of `input` are closed,
+// or `TimedOut` once `deadline` passes.
+fn copy_until(mut input: File, output: &mut dyn Write, deadline: Instant) -> Result<()> {
+    let mut buf = [0u8; 4096];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let mut poll_fd = libc::pollfd {
+            fd: input.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let millis = remaining.as_micros().div_ceil(1000).min(i32::MAX as _) as i32;
+        match unsafe { libc::poll(&mut poll_fd, 1, millis) } {
+            0 => return Err(StatusCode::TimedOut),
+            n if n < 0 => {
+                let err = std::io::Error::last_os_error();
+                if err.kind() != std::io::ErrorKind::Interrupted {
+                    return Err(err.into());
+                }
+            }
+            _ => match input.read(&mut buf) {
+                Ok(0) => return Ok(()),
+                Ok(len) => output.write_all(&buf[..len])?,
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err.into()),
+            },
+        }
+    }
+}
+
+fn dump_service(
+    service: &SIBinder,
+    args: &[String],
+    timeout: Duration,
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    service.as_proxy().ok_or("not a remote object")?;
+    let deadline = Instant::now() + timeout;
+    // The service writes to a pipe, which is copied to the standard output. A
+    // service which doesn't finish in time is left behind like in dumpsys, and
+    // its writes fail once the read end is closed.
+    let (read_end, write_end) = pipe()?;
+    let (sender, receiver) = mpsc::channel();
+    let (service, args) = (service.clone(), args.to_vec());
+    std::thread::spawn(move || {
+        let proxy = service.as_proxy().expect("remote object");
+        let _ = sender.send(proxy.dump(write_end, &args));
+    });
+
+    let mut stdout = std::io::stdout().lock();
+    copy_until(read_end, &mut stdout, deadline)?;
+    stdout.flush()?;
+    match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+        Ok(result) => Ok(result?),
+        Err(mpsc::RecvTimeoutError::Timeout) => Err(StatusCode::TimedOut.into()),
+        Err(mpsc::RecvTimeoutError::Disconnected) => Err("the dump call panicked".into()),
+    }
+}
+
+// The line printed instead of the dump in the pid mode.
+fn pid_info(pids: &[hub::ServiceDebugInfo], name: &str) -> String {
+    match pids.iter().find(|info| info.name == name) {
+        Some(info) => format!("Service host process PID: {}", info.debugPid),
+        None => format!("Got an error when trying to get the PID of {name}"),
+    }
+}
+
+// The line printed instead of the dump in the stability mode.
+fn stability_info(stability: Stability) -> String {
+    format!("Stability: {stability:?}")
+}
+
+fn print_service(
+    name: &str,
+    mode: Mode,
+    args: &[String],
+    timeout: Duration,
+    pids: &[hub::ServiceDebugInfo],
+) {
+    let Some(service) = hub::check_service(name) else {
+        println!("Can't find service: {name}");
+        return;
+    };
+
+    match mode {
+        Mode::Dump => {
+            if let Err(err) = dump_service(&service, args, timeout) {
+                if err.downcast_ref::<StatusCode>() == Some(&StatusCode::TimedOut) {
+                    println!(
+                        "\n*** SERVICE '{name}' DUMP TIMEOUT ({}ms) EXPIRED ***\n",
+                        timeout.as_millis()
+                    );
+                } else {
+                    println!("Error dumping service info: ({err}) {name}");
+                }
+            }
+        }
+        Mode::Pid => println!("{}", pid_info(pids, name)),
+        Mode::Stability => println!("{}", stability_info(service.stability())),
+    }
+}
+
+// The options of the command line.
+struct Options {
+    list: bool,
+    dump_priority: i32,
+    proto: bool,
+    timeout: Duration,
+    mode: Mode,
+    service: Option<String>,
+    skipped: Vec<String>,
+    // The arguments of the dump.
+    args: Vec<String>,
+}
+
+impl Options {
+    fn from_matches(matches: &clap::ArgMatches) -> Self {
+        let priority = matches.get_one::<String>("priority");
+        let proto = matches.get_flag("proto");
+        let dump_priority = priority
+            .and_then(|name| priority_flag(name))
+            .unwrap_or(hub::DUMP_FLAG_PRIORITY_ALL);
+
+        let timeout = match (
+            matches.get_one::<u64>("timeout"),
+            matches.get_one::<u64>("timeout_ms"),
+        ) {
+            (Some(seconds), _) => Duration::from_secs(*seconds),
+            (_, Some(millis)) => Duration::from_millis(*millis),
+            _ => DEFAULT_TIMEOUT,
+        };
+
+        let mode = if matches.get_flag("pid") {
+            Mode::Pid
+        } else if matches.get_flag("stability") {
+            Mode::Stability
+        } else {
+            Mode::Dump
+        };
+
+        // The arguments tell the services which part of their state is asked for.
+        let mut args: Vec<String> = Vec::new();
+        if let Some(priority) = priority {
+            args.extend(["--dump-priority".to_owned(), priority.clone()]);
+        }
+        if proto {
+            args.push("--proto".to_owned());
+        }
+        args.extend(
+            matches
+                .get_many::<String>("args")
+                .unwrap_or_default()
+                .cloned(),
+        );
+
+        Self {
+            list: matches.get_flag("list"),
+            dump_priority,
+            proto,
+            timeout,
+            mode,
+            service: matches.get_one::<String>("service").cloned(),
+            skipped: matches
+                .get_many::<String>("skip")
+                .unwrap_or_default()
+                .cloned()
+                .collect(),
+            args,
+        }
+    }
+
+    // The services listed with the dump priority, which are sorted and, for
+    // proto dumps, are also listed in `protos`.
+    fn select(&self, mut services: Vec<String>, protos: &[String]) -> Vec<String> {
+        if self.proto {
+            services.retain(|name| protos.contains(name));
+        }
+        services.sort();
+        services
+    }
+
+    fn is_skipped(&self, name: &str) -> bool {
+        self.skipped.iter().any(|skipped| skipped == name)
+    }
+}
+
+fn command() -> clap::Command {
+    clap::Command::new("rsb_dumpsys")
+        .version(env!("CARGO_PKG_VERSION"))
+        .author(env!("CARGO_PKG_AUTHORS"))
+        .about("Dumps the state of binder services, like Android's dumpsys")
+        .arg(
+            clap::Arg::new("list")
+                .help("List the services")
+                .short('l')
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("timeout")
+                .help("Timeout of each service in seconds (default 10)")
+                .short('t')
+                .value_parser(clap::value_parser!(u64))
+                .conflicts_with("timeout_ms"),
+        )
+        .arg(
+            clap::Arg::new("timeout_ms")
+                .help("Timeout of each service in milliseconds (default 10000)")
+                .short('T')
+                .value_parser(clap::value_parser!(u64)),
+        )
+        .arg(
+            clap::Arg::new("priority")
+                .help("Only the services registered with the dump priority")
+                .long("priority")
+                .value_parser(["CRITICAL", "HIGH", "NORMAL", "ALL"]),
+        )
+        .arg(
+            clap::Arg::new("proto")
+                .help("Only the services which support proto dumps, dumped in proto format")
+                .long("proto")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("skip")
+                .help("Dump all services but the comma separated ones")
+                .long("skip")
+                .value_delimiter(',')
+                .conflicts_with("service"),
+        )
+        .arg(
+            clap::Arg::new("pid")
+                .help("Print the PID of the services instead of dumping them")
+                .long("pid")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("stability")
+                .help("Print the stability of the services instead of dumping them")
+                .long("stability")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with("pid"),
+        )
+        .arg(
+            clap::Arg::new("service")
+                .help("Name of the service to dump, all services if it is omitted")
+                .index(1),
+        )
+        .arg(
+            clap::Arg::new("args")
+                .help("Arguments of the dump")
+                .index(2)
+                .num_args(0..)
+                .trailing_var_arg(true)
+                .allow_hyphen_values(true),
+        )
+        .after_help(
+            "Examples:\n    \
+            List the services with the HIGH dump priority:\n    \
+            $ rsb_dumpsys -l --priority HIGH\n\n\
+            Dump the service 'my.service' with arguments:\n    \
+            $ rsb_dumpsys my.service --verbose\n\n\
+            Print the PIDs of all services:\n    \
+            $ rsb_dumpsys --pid",
+        )
+}
+
+fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let options = Options::from_matches(&command().get_matches());
+
+    env_logger::Builder::from_env(Env::default().default_filter_or("warn")).init();
+
+    ProcessState::init(DEFAULT_BINDER_PATH, 0);
+
+    // The proto flag is registered together with the dump priorities.
+    let protos = if options.proto {
+        hub::list_services(hub::DUMP_FLAG_PROTO)
+    } else {
+        Vec::new()
+    };
+    let services = options.select(hub::list_services(options.dump_priority), &protos);
+
+    if options.list {
+        println!("Currently running services:");
+        for name in &services {
+            println!("  {name}");
+        }
+        return Ok(());
+    }
+
+    let pids = if options.mode == Mode::Pid {
+        service_pids()
+    } else {
+        Vec::new()
+    };
+
+    if let Some(name) = &options.service {
+        print_service(name, options.mode, &options.args, options.timeout, &pids);
+        return Ok(());
+    }
+
+    for name in services.iter().filter(|name| !options.is_skipped(name)) {
+        println!("-------------------------------------------------------------------------------");
+        println!("DUMP OF SERVICE {name}:");
+        let start = Instant::now();
+        print_service(name, options.mode, &options.args, options.timeout, &pids);
+        if options.mode == Mode::Dump {
+            println!(
+                "--------- {:.3}s was the duration of dumpsys {name}",
+                start.elapsed().as_secs_f64()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(args: &[&str]) -> Options {
+        let matches = command()
+            .try_get_matches_from(std::iter::once("rsb_dumpsys").chain(args.iter().copied()))
+            .unwrap();
+        Options::from_matches(&matches)
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_command() {
+        command().debug_assert();
+
+        let default = options(&[]);
+        assert!(!default.list);
+        assert_eq!(default.dump_priority, hub::DUMP_FLAG_PRIORITY_ALL);
+        assert_eq!(default.timeout, DEFAULT_TIMEOUT);
+        assert_eq!(default.mode, Mode::Dump);
+        assert!(default.service.is_none());
+        assert!(default.args.is_empty());
+
+        let dump = options(&["-T", "500", "my.service", "--verbose", "-a"]);
+        assert_eq!(dump.timeout, Duration::from_millis(500));
+        assert_eq!(dump.service.as_deref(), Some("my.service"));
+        assert_eq!(dump.args, names(&["--verbose", "-a"]));
+        assert_eq!(options(&["-t", "3"]).timeout, Duration::from_secs(3));
+
+        assert_eq!(options(&["--pid"]).mode, Mode::Pid);
+        assert_eq!(options(&["--stability"]).mode, Mode::Stability);
+
+        // Conflicting and unknown options.
+        let parse = |args: &[&str]| {
+            command()
+                .try_get_matches_from(std::iter::once("rsb_dumpsys").chain(args.iter().copied()))
+        };
+        assert!(parse(&["-t", "3", "-T", "500"]).is_err());
+        assert!(parse(&["--pid", "--stability"]).is_err());
+        assert!(parse(&["--skip", "a", "my.service"]).is_err());
+        assert!(parse(&["--priority", "LOW"]).is_err());
+    }
+
+    #[test]
+    fn test_priority() {
+        let high = options(&["--priority", "HIGH", "my.service", "--verbose"]);
+        assert_eq!(high.dump_priority, hub::DUMP_FLAG_PRIORITY_HIGH);
+        assert_eq!(high.args, names(&["--dump-priority", "HIGH", "--verbose"]));
+        assert_eq!(
+            options(&["--priority", "CRITICAL"]).dump_priority,
+            hub::DUMP_FLAG_PRIORITY_CRITICAL
+        );
+        assert_eq!(
+            options(&["--priority", "NORMAL"]).dump_priority,
+            hub::DUMP_FLAG_PRIORITY_NORMAL
+        );
+        assert_eq!(
+            options(&["--priority", "ALL"]).dump_priority,
+            hub::DUMP_FLAG_PRIORITY_ALL
+        );
+    }
+
+    #[test]
+    fn test_proto() {
+        let services = names(&["c.service", "a.service", "b.service"]);
+        let protos = names(&["b.service", "c.service", "d.service"]);
+
+        // Without --proto, all the services are dumped in order.
+        let all = options(&[]);
+        assert_eq!(
+            all.select(services.clone(), &protos),
+            names(&["a.service", "b.service", "c.service"])
+        );
+
+        let proto = options(&["--proto", "--priority", "HIGH"]);
+        assert_eq!(proto.args, names(&["--dump-priority", "HIGH", "--proto"]));
+        assert_eq!(
+            proto.select(services, &protos),
+            names(&["b.service", "c.service"])
+        );
+    }
+
+    #[test]
+    fn test_skip() {
+        let skip = options(&["--skip", "a.service,c.service"]);
+        assert!(skip.is_skipped("a.service"));
+        assert!(!skip.is_skipped("b.service"));
+        assert!(skip.is_skipped("c.service"));
+        assert!(!options(&[]).is_skipped("a.service"));
+    }
+
+    #[test]
+    fn test_pid() {
+        let pids = vec![hub::ServiceDebugInfo {
+            name: "my.service".to_owned(),
+            debugPid: 1234,
+        }];
+        assert_eq!(
+            pid_info(&pids, "my.service"),
+            "Service host process PID: 1234"
+        );
+        assert_eq!(
+            pid_info(&pids, "other.service"),
+            "Got an error when trying to get the PID of other.service"
+        );
+    }
+
+    #[test]
+    fn test_stability() {
+        assert_eq!(stability_info(Stability::Local), "Stability: Local");
+        assert_eq!(stability_info(Stability::Vintf), "Stability: Vintf");
+    }
+
+    #[test]
+    fn test_copy_until() {
+        let deadline = || Instant::now() + Duration::from_secs(10);
+        let (read_end, write_end) = pipe().unwrap();
+        File::from(write_end).write_all(b"dump").unwrap();
+        let mut output = Vec::new();
+        assert_eq!(copy_until(read_end, &mut output, deadline()), Ok(()));
+        assert_eq!(output, b"dump");
+
+        // A service which keeps the write end open.
+        let (read_end, write_end) = pipe().unwrap();
+        let mut writer = File::from(write_end);
+        writer.write_all(b"partial").unwrap();
+        let mut output = Vec::new();
+        let deadline = Instant::now() + Duration::from_millis(50);
+        assert_eq!(
+            copy_until(read_end, &mut output, deadline),
+            Err(StatusCode::TimedOut)
+        );
+        assert_eq!(output, b"partial");
+        // The read end is closed once the dump timed out.
+        assert!(writer.write_all(b"late").is_err());
+    }
+}
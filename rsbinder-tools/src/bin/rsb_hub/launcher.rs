@@ -0,0 +1,300 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+//! Starting services on demand, like the lazy services of Android's init.
+//!
+//! The services file is a TOML file which tells which program registers which
+//! services:
+//!
+//! ```toml
+//! [[service]]
+//! names = ["com.example.lights"]
+//! path = "/usr/bin/lights_service"
+//! args = ["--verbose"]
+//! # Optional, a user name or uid to run the program as.
+//! user = "lights"
+//! env = { RUST_LOG = "info" }
+//! # always, on-failure (default) or never.
+//! restart = "on-failure"
+//! ```
+//!
+//! Looking up a service which isn't registered starts its program, unless it
+//! is already running. The hub answers at once, and the client waits for the
+//! registration. The program is started again when it exits, as its restart
+//! policy says.
+
+use std::collections::BTreeMap;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+// The delay before a program is started again, so that a program which fails
+// at once doesn't keep the hub busy.
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ServicesFile {
+    #[serde(default)]
+    service: Vec<ServiceConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ServiceConfig {
+    names: Vec<String>,
+    path: PathBuf,
+    #[serde(default)]
+    args: Vec<String>,
+    user: Option<String>,
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+    #[serde(default)]
+    restart: Restart,
+}
+
+/// When a program is started again after it exits.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Restart {
+    Always,
+    #[default]
+    OnFailure,
+    Never,
+}
+
+impl Restart {
+    fn should_restart(self, status: ExitStatus) -> bool {
+        match self {
+            Restart::Always => true,
+            Restart::OnFailure => !status.success(),
+            Restart::Never => false,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Program {
+    config: ServiceConfig,
+    // The uid and the gid to run as.
+    credentials: Option<(u32, u32)>,
+    running: AtomicBool,
+}
+
+impl Program {
+    fn command(&self) -> Command {
+        let mut command = Command::new(&self.config.path);
+        command.args(&self.config.args).envs(&self.config.env);
+        if let Some((uid, gid)) = self.credentials {
+            command.uid(uid).gid(gid);
+        }
+        command
+    }
+
+    // Run the program until it exits for good, on a thread of its own.
+    fn supervise(self: Arc<Self>) {
+        std::thread::spawn(move || {
+            let path = self.config.path.display();
+            loop {
+                let mut child = match self.command().spawn() {
+                    Ok(child) => child,
+                    Err(err) => {
+                        log::error!("Failed to start {path}: {err}");
+                        break;
+                    }
+                };
+                log::info!("Started {path} as pid {}", child.id());
+
+                let status = match child.wait() {
+                    Ok(status) => status,
+                    Err(err) => {
+                        log::error!("Failed to wait for {path}: {err}");
+                        break;
+                    }
+                };
+                if !self.config.restart.should_restart(status) {
+                    log::info!("{path} exited with {status}");
+                    break;
+                }
+                log::warn!("{path} exited with {status}, restarting it");
+                std::thread::sleep(RESTART_DELAY);
+            }
+            self.running.store(false, Ordering::Release);
+        });
+    }
+}
+
+// The uid and the primary gid of `user`, a user name or a uid, from the
+// passwd database `passwd`. A uid which isn't in the database is its own gid.
+fn lookup_user(passwd: &str, user: &str) -> Option<(u32, u32)> {
+    let uid = user.parse::<u32>().ok();
+    for line in passwd.lines() {
+        let fields: Vec<&str> = line.split(':').collect();
+        let [name, _, entry_uid, entry_gid, ..] = fields.as_slice() else {
+            continue;
+        };
+        let (Ok(entry_uid), Ok(entry_gid)) = (entry_uid.parse(), entry_gid.parse()) else {
+            continue;
+        };
+        if *name == user || uid == Some(entry_uid) {
+            return Some((entry_uid, entry_gid));
+        }
+    }
+    uid.map(|uid| (uid, uid))
+}
+
+/// The programs which register services.
+#[derive(Debug, Default)]
+pub(crate) struct Launcher {
+    programs: Vec<Arc<Program>>,
+}
+
+impl Launcher {
+    pub(crate) fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| format!("Can't read {}: {err}", path.display()))?;
+        let passwd = std::fs::read_to_string("/etc/passwd").unwrap_or_default();
+        Self::parse(&text, &passwd).map_err(|err| format!("{}: {err}", path.display()))
+    }
+
+    fn parse(text: &str, passwd: &str) -> Result<Self, String> {
+        let file: ServicesFile = toml::from_str(text).map_err(|err| err.to_string())?;
+        let mut launcher = Self::default();
+        for config in file.service {
+            if config.names.is_empty() {
+                return Err(format!("{} registers no services", config.path.display()));
+            }
+            if let Some(name) = config
+                .names
+                .iter()
+                .find(|name| launcher.program_of(name).is_some())
+            {
+                return Err(format!("{name} is registered by more than one program"));
+            }
+            let credentials = match &config.user {
+                Some(user) => {
+                    Some(lookup_user(passwd, user).ok_or(format!("Unknown user: {user}"))?)
+                }
+                None => None,
+            };
+            launcher.programs.push(Arc::new(Program {
+                config,
+                credentials,
+                running: AtomicBool::new(false),
+            }));
+        }
+        Ok(launcher)
+    }
+
+    fn program_of(&self, name: &str) -> Option<&Arc<Program>> {
+        self.programs
+            .iter()
+            .find(|program| program.config.names.iter().any(|n| n == name))
+    }
+
+    /// Start the program which registers the service `name`, unless it is
+    /// running. Returns false if no program registers the service.
+    pub(crate) fn start(&self, name: &str) -> bool {
+        let Some(program) = self.program_of(name) else {
+            return false;
+        };
+        if !program.running.swap(true, Ordering::AcqRel) {
+            log::info!("Starting {} for {name}", program.config.path.display());
+            Arc::clone(program).supervise();
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+    use std::time::Instant;
+
+    const PASSWD: &str = "root:x:0:0:root:/root:/bin/sh\n\
+                          lights:x:1000:1001::/home/lights:/bin/sh\n";
+
+    #[test]
+    fn test_lookup_user() {
+        assert_eq!(lookup_user(PASSWD, "root"), Some((0, 0)));
+        assert_eq!(lookup_user(PASSWD, "lights"), Some((1000, 1001)));
+        assert_eq!(lookup_user(PASSWD, "1000"), Some((1000, 1001)));
+        assert_eq!(lookup_user(PASSWD, "2000"), Some((2000, 2000)));
+        assert_eq!(lookup_user(PASSWD, "nobody"), None);
+    }
+
+    #[test]
+    fn test_restart() {
+        let success = ExitStatus::from_raw(0);
+        let failure = ExitStatus::from_raw(1 << 8);
+        assert!(Restart::Always.should_restart(success));
+        assert!(!Restart::OnFailure.should_restart(success));
+        assert!(Restart::OnFailure.should_restart(failure));
+        assert!(!Restart::Never.should_restart(failure));
+    }
+
+    #[test]
+    fn test_parse() {
+        let launcher = Launcher::parse(
+            r#"
+            [[service]]
+            names = ["com.example.lights", "com.example.lights.debug"]
+            path = "/usr/bin/lights_service"
+            args = ["--verbose"]
+            user = "lights"
+            env = { RUST_LOG = "info" }
+            restart = "always"
+            "#,
+            PASSWD,
+        )
+        .unwrap();
+        let program = launcher.program_of("com.example.lights.debug").unwrap();
+        assert_eq!(program.credentials, Some((1000, 1001)));
+        assert_eq!(program.config.restart, Restart::Always);
+        assert_eq!(program.config.env["RUST_LOG"], "info");
+        assert!(launcher.program_of("com.example.other").is_none());
+        assert!(!launcher.start("com.example.other"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let service =
+            |extra: &str| format!("[[service]]\nnames = [\"a\"]\npath = \"/bin/a\"\n{extra}");
+        assert!(Launcher::parse(&service("user = \"nobody\""), PASSWD).is_err());
+        assert!(Launcher::parse(&service("restart = \"sometimes\""), PASSWD).is_err());
+        assert!(Launcher::parse(&service("owner = \"me\""), PASSWD).is_err());
+        assert!(Launcher::parse(&service("timeout = 5"), PASSWD).is_err());
+        assert!(Launcher::parse(&format!("{}\n{}", service(""), service("")), PASSWD).is_err());
+        assert!(Launcher::parse("[[service]]\nnames = []\npath = \"/bin/a\"", PASSWD).is_err());
+        assert!(Launcher::parse("", PASSWD).is_ok());
+    }
+
+    #[test]
+    fn test_start() {
+        let launcher = Launcher::parse(
+            r#"
+            [[service]]
+            names = ["my.service"]
+            path = "/bin/sh"
+            args = ["-c", "exit 0"]
+            "#,
+            PASSWD,
+        )
+        .unwrap();
+        assert!(launcher.start("my.service"));
+
+        // The program exits successfully, and isn't restarted.
+        let program = launcher.program_of("my.service").unwrap();
+        let start = Instant::now();
+        while program.running.load(Ordering::Acquire) {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
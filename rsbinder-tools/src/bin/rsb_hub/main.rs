@@ -2,14 +2,24 @@
 // SPDX-License-Identifier: Apache-2.0
 #![allow(non_snake_case)]
 
+mod launcher;
+mod manifest;
+mod policy;
+
 use env_logger::Env;
 use hub::android_16::{BnServiceManager, IServiceManager, DUMP_FLAG_PRIORITY_DEFAULT};
+use launcher::Launcher;
+use manifest::Manifest;
+use policy::{Operation, Policy};
 use rsbinder::*;
 use std::{
     collections::HashMap,
+    path::PathBuf,
     sync::{mpsc, Arc, Mutex},
 };
 
+const CLIENT_CALLBACK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
 struct Service {
     binder: SIBinder,
     _allow_isolated: bool,
@@ -24,10 +34,6 @@ impl Service {
     fn _get_node_strong_ref_count(&self) -> usize {
         unimplemented!("get_node_strong_ref_count")
     }
-
-    fn _try_start_service(&self) -> rsbinder::Result<SIBinder> {
-        unimplemented!("try_start_service")
-    }
 }
 
 struct DeathRecipientWrapper(mpsc::Sender<rsbinder::WIBinder>);
@@ -158,8 +164,8 @@ impl Inner {
 
             if let Some(service) = self.name_to_service.get_mut(service_name) {
                 // guarantee is temporary
-                service.has_clients = true;
-                has_clients = true;
+                service.guarentee_client = false;
+                has_clients = service.has_clients;
             }
         }
 
@@ -188,11 +194,7 @@ impl Inner {
         Ok(has_clients)
     }
 
-    fn try_get_binder(
-        &mut self,
-        name: &str,
-        _start_if_not_found: bool,
-    ) -> rsbinder::status::Result<Option<SIBinder>> {
+    fn try_get_binder(&mut self, name: &str) -> rsbinder::status::Result<Option<SIBinder>> {
         let service = if let Some(service) = self.name_to_service.get_mut(name) {
             service
         } else {
@@ -244,21 +246,48 @@ impl Inner {
 
 struct ServiceManager {
     inner: Arc<Mutex<Inner>>,
+    policy: Policy,
+    manifest: Manifest,
+    // Refuse @VintfStability services which aren't declared in the manifest.
+    require_declared: bool,
+    launcher: Launcher,
 }
 
 impl ServiceManager {
-    fn new() -> Self {
+    fn new(policy: Policy, manifest: Manifest, require_declared: bool, launcher: Launcher) -> Self {
         let (death_sender, death_receiver) = mpsc::channel();
 
         let this = Self {
             inner: Arc::new(Mutex::new(Inner::new(death_sender))),
+            policy,
+            manifest,
+            require_declared,
+            launcher,
         };
 
         this.run_death_receiver(death_receiver);
+        this.run_client_callback_timer();
 
         this
     }
 
+    // Services learn that they have no clients anymore only on this interval,
+    // so that a service which is used again soon isn't shut down.
+    fn run_client_callback_timer(&self) {
+        let inner_clone = Arc::clone(&self.inner);
+        std::thread::spawn(move || loop {
+            std::thread::sleep(CLIENT_CALLBACK_INTERVAL);
+
+            let mut inner = inner_clone.lock().unwrap();
+            let names: Vec<String> = inner.name_to_client_callbacks.keys().cloned().collect();
+            for name in names {
+                if let Err(e) = inner.handle_service_client_callback(1 /* sm */, &name, true) {
+                    log::error!("Failed to handle client callbacks of {name}: {e:?}");
+                }
+            }
+        });
+    }
+
     fn run_death_receiver(&self, death_receiver: mpsc::Receiver<rsbinder::WIBinder>) {
         let inner_clone = Arc::clone(&self.inner);
         std::thread::spawn(move || {
@@ -274,6 +303,79 @@ impl ServiceManager {
         });
     }
 
+    // Whether the caller of the current transaction may do `operation` on `name`.
+    // The hub itself may do anything with the calls it makes outside of a
+    // transaction, like registering itself. A transaction is always checked,
+    // whichever pid sent it.
+    fn can(&self, operation: Operation, name: &str) -> bool {
+        if !rsbinder::thread_state::is_handling_transaction() {
+            return true;
+        }
+        let context = rsbinder::thread_state::CallingContext::default();
+        self.policy.check(operation, name, &context)
+    }
+
+    // The service `name`. If it isn't registered, but a program of the
+    // launcher registers it, the program is started. The binder thread doesn't
+    // wait for the registration: the client waits for it with
+    // registerForNotifications, as with Android's servicemanager.
+    fn try_get_binder(
+        &self,
+        name: &str,
+        start_if_not_found: bool,
+    ) -> rsbinder::status::Result<Option<SIBinder>> {
+        let mut inner = self.inner.lock().unwrap();
+        if start_if_not_found && !inner.name_to_service.contains_key(name) {
+            self.launcher.start(name);
+        }
+        inner.try_get_binder(name)
+    }
+
+    // The service `name`, or its accessor if the manifest declares one. An
+    // IAccessor registered under the name of the instance it provides is
+    // the accessor of that instance too.
+    fn try_get_service(
+        &self,
+        name: &str,
+        start_if_not_found: bool,
+    ) -> rsbinder::status::Result<hub::android_16::android::os::Service::Service> {
+        use hub::android_16::android::os::{
+            Service::Service, ServiceWithMetadata::ServiceWithMetadata,
+        };
+
+        if !self.can(Operation::Find, name) {
+            return Ok(Service::Accessor(None));
+        }
+        if let Some(accessor) = self.manifest.accessor_of(name) {
+            if !self.can(Operation::Find, &accessor) {
+                return Ok(Service::Accessor(None));
+            }
+            let binder = self.try_get_binder(&accessor, start_if_not_found)?;
+            return Ok(Service::Accessor(binder));
+        }
+        match self.try_get_binder(name, start_if_not_found)? {
+            Some(binder) if Self::is_accessor(&binder) && !self.manifest.is_accessor(name) => {
+                Ok(Service::Accessor(Some(binder)))
+            }
+            Some(binder) => Ok(Service::ServiceWithMetadata(ServiceWithMetadata {
+                service: Some(binder),
+                isLazyService: false, // Default to false for Linux implementation
+            })),
+            // An accessor of None tells that the service doesn't exist.
+            None => Ok(Service::Accessor(None)),
+        }
+    }
+
+    fn is_accessor(binder: &SIBinder) -> bool {
+        binder.descriptor()
+            == <hub::android_16::BpAccessor as hub::android_16::IAccessor>::descriptor()
+    }
+
+    fn security_error(operation: &str, name: &str) -> Status {
+        let msg = format!("The caller is not allowed to {operation} {name}");
+        (ExceptionCode::Security, msg.as_str()).into()
+    }
+
     fn is_valid_service_name(name: &str) -> bool {
         if name.is_empty() || name.len() > 127 {
             return false;
@@ -302,7 +404,10 @@ impl Interface for ServiceManager {}
 
 impl IServiceManager for ServiceManager {
     fn getService(&self, name: &str) -> rsbinder::status::Result<Option<rsbinder::SIBinder>> {
-        self.inner.lock().unwrap().try_get_binder(name, false)
+        if !self.can(Operation::Find, name) {
+            return Ok(None);
+        }
+        self.try_get_binder(name, true)
     }
 
     fn addService(
@@ -312,10 +417,23 @@ impl IServiceManager for ServiceManager {
         allowIsolated: bool,
         dumpPriority: i32,
     ) -> rsbinder::status::Result<()> {
+        if !self.can(Operation::Add, name) {
+            return Err(Self::security_error("add", name));
+        }
+
         if !Self::is_valid_service_name(name) {
             return Err(ExceptionCode::IllegalArgument.into());
         }
 
+        if self.require_declared
+            && service.stability() == Stability::Vintf
+            && !self.manifest.is_declared(name)
+        {
+            let msg = format!("Could not find {name} in the manifest");
+            log::error!("{msg}");
+            return Err((ExceptionCode::IllegalArgument, msg.as_str()).into());
+        }
+
         let mut inner = self.inner.lock().unwrap();
 
         // Only if the service is a proxy, link to death.
@@ -370,10 +488,17 @@ impl IServiceManager for ServiceManager {
     }
 
     fn checkService(&self, name: &str) -> rsbinder::status::Result<Option<SIBinder>> {
-        self.inner.lock().unwrap().try_get_binder(name, false)
+        if !self.can(Operation::Find, name) {
+            return Ok(None);
+        }
+        self.try_get_binder(name, false)
     }
 
     fn listServices(&self, dump_priority: i32) -> rsbinder::status::Result<Vec<String>> {
+        if !self.can(Operation::List, "") {
+            return Err(Self::security_error("list", "the services"));
+        }
+
         let inner = self.inner.lock().unwrap();
 
         let mut services = Vec::new();
@@ -394,6 +519,10 @@ impl IServiceManager for ServiceManager {
             dyn hub::android_16::android::os::IServiceCallback::IServiceCallback,
         >,
     ) -> rsbinder::status::Result<()> {
+        if !self.can(Operation::Find, name) {
+            return Err(Self::security_error("find", name));
+        }
+
         if !Self::is_valid_service_name(name) {
             return Err(ExceptionCode::IllegalArgument.into());
         }
@@ -439,30 +568,41 @@ impl IServiceManager for ServiceManager {
         }
     }
 
-    fn isDeclared(&self, _arg_name: &str) -> rsbinder::status::Result<bool> {
-        // TODO: Implement this
-        log::warn!("isDeclared is not implemented");
-        Ok(false)
+    fn isDeclared(&self, name: &str) -> rsbinder::status::Result<bool> {
+        if !self.can(Operation::Find, name) {
+            return Err(Self::security_error("find", name));
+        }
+        Ok(self.manifest.is_declared(name))
     }
 
-    fn getDeclaredInstances(&self, _arg_iface: &str) -> rsbinder::status::Result<Vec<String>> {
-        log::warn!("getDeclaredInstances is not implemented");
-        Ok(vec![])
+    fn getDeclaredInstances(&self, iface: &str) -> rsbinder::status::Result<Vec<String>> {
+        let mut instances = self.manifest.declared_instances(iface);
+        instances.retain(|instance| self.can(Operation::Find, &format!("{iface}/{instance}")));
+        Ok(instances)
     }
 
-    fn updatableViaApex(&self, _arg_name: &str) -> rsbinder::status::Result<Option<String>> {
-        log::warn!("updatableViaApex is not implemented");
-        Ok(None)
+    fn updatableViaApex(&self, name: &str) -> rsbinder::status::Result<Option<String>> {
+        if !self.can(Operation::Find, name) {
+            return Err(Self::security_error("find", name));
+        }
+        Ok(self.manifest.package_of(name))
     }
 
     fn getConnectionInfo(
         &self,
-        _arg_name: &str,
+        name: &str,
     ) -> rsbinder::status::Result<
         Option<hub::android_16::android::os::ConnectionInfo::ConnectionInfo>,
     > {
-        log::warn!("getConnectionInfo is not implemented");
-        Ok(None)
+        if !self.can(Operation::Find, name) {
+            return Err(Self::security_error("find", name));
+        }
+        Ok(self.manifest.connection_of(name).map(|(ip_address, port)| {
+            hub::android_16::android::os::ConnectionInfo::ConnectionInfo {
+                ipAddress: ip_address,
+                port: port.into(),
+            }
+        }))
     }
 
     fn registerClientCallback(
@@ -562,8 +702,8 @@ impl IServiceManager for ServiceManager {
             return Err((ExceptionCode::IllegalState, msg.as_str()).into());
         }
 
-        let res = inner.handle_service_client_callback(2, name, false);
-        if res.is_err() {
+        let res = inner.handle_service_client_callback(2 /* sm + transaction */, name, false);
+        if !matches!(res, Ok(false)) {
             let msg = format!("{context:?} Tried to unregister {name}, but there are clients.");
             log::warn!("{}", &msg);
             if let Some(service) = inner.name_to_service.get_mut(name) {
@@ -582,6 +722,10 @@ impl IServiceManager for ServiceManager {
     ) -> rsbinder::status::Result<
         Vec<hub::android_16::android::os::ServiceDebugInfo::ServiceDebugInfo>,
     > {
+        if !self.can(Operation::List, "") {
+            return Err(Self::security_error("list", "the services"));
+        }
+
         let inner = self.inner.lock().unwrap();
 
         let mut out = Vec::with_capacity(inner.name_to_service.len());
@@ -602,75 +746,80 @@ impl IServiceManager for ServiceManager {
         &self,
         name: &str,
     ) -> rsbinder::status::Result<hub::android_16::android::os::Service::Service> {
-        match self.inner.lock().unwrap().try_get_binder(name, false)? {
-            Some(binder) => {
-                // Create ServiceWithMetadata
-                let service_with_metadata =
-                    hub::android_16::android::os::ServiceWithMetadata::ServiceWithMetadata {
-                        service: Some(binder),
-                        isLazyService: false, // Default to false for Linux implementation
-                    };
-                Ok(
-                    hub::android_16::android::os::Service::Service::ServiceWithMetadata(
-                        service_with_metadata,
-                    ),
-                )
-            }
-            None => {
-                // Return accessor variant with None for non-existent services
-                Ok(hub::android_16::android::os::Service::Service::Accessor(
-                    None,
-                ))
-            }
-        }
+        self.try_get_service(name, true)
     }
 
     fn checkService2(
         &self,
         name: &str,
     ) -> rsbinder::status::Result<hub::android_16::android::os::Service::Service> {
-        match self.inner.lock().unwrap().try_get_binder(name, false)? {
-            Some(binder) => {
-                // Create ServiceWithMetadata
-                let service_with_metadata =
-                    hub::android_16::android::os::ServiceWithMetadata::ServiceWithMetadata {
-                        service: Some(binder),
-                        isLazyService: false, // Default to false for Linux implementation
-                    };
-                Ok(
-                    hub::android_16::android::os::Service::Service::ServiceWithMetadata(
-                        service_with_metadata,
-                    ),
-                )
-            }
-            None => {
-                // Return accessor variant with None for non-existent services
-                Ok(hub::android_16::android::os::Service::Service::Accessor(
-                    None,
-                ))
-            }
-        }
+        self.try_get_service(name, false)
     }
 
-    fn getUpdatableNames(&self, _apex_name: &str) -> rsbinder::status::Result<Vec<String>> {
-        log::warn!("getUpdatableNames is not implemented for Linux target");
-        Ok(vec![])
+    fn getUpdatableNames(&self, apex_name: &str) -> rsbinder::status::Result<Vec<String>> {
+        let mut names = self.manifest.names_of_package(apex_name);
+        names.retain(|name| self.can(Operation::Find, name));
+        Ok(names)
     }
 }
 
 fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
-    let _ = clap::Command::new("rsb_hub")
+    let matches = clap::Command::new("rsb_hub")
         .version(env!("CARGO_PKG_VERSION"))
         .author(env!("CARGO_PKG_AUTHORS"))
         .about("A service manager for Binder IPC on Linux. Facilitates service registration, discovery, and management.")
+        .arg(
+            clap::Arg::new("policy")
+                .help("Policy file which decides who may add, find and list services")
+                .long("policy")
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(
+            clap::Arg::new("manifest")
+                .help("Directory of the manifest files which declare the services")
+                .long("manifest")
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
+        .arg(
+            clap::Arg::new("require_declared")
+                .help("Refuse @VintfStability services which aren't declared in the manifest")
+                .long("require-declared")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            clap::Arg::new("services")
+                .help("Services file which tells the programs to start when their services are looked up")
+                .long("services")
+                .value_parser(clap::value_parser!(PathBuf)),
+        )
         .get_matches();
 
     env_logger::Builder::from_env(Env::default().default_filter_or("warn")).init();
 
-    ProcessState::init(DEFAULT_BINDER_PATH, 0);
+    let policy = match matches.get_one::<PathBuf>("policy") {
+        Some(path) => Policy::load(path)?,
+        None => Policy::allow_all(),
+    };
+    let manifest = match matches.get_one::<PathBuf>("manifest") {
+        Some(dir) => Manifest::load(dir)?,
+        None => Manifest::default(),
+    };
+    let launcher = match matches.get_one::<PathBuf>("services") {
+        Some(path) => Launcher::load(path)?,
+        None => Launcher::default(),
+    };
+
+    // The security contexts of the callers let the policy check who registers
+    // and looks up services.
+    ProcessState::builder().security_context(true).init()?;
 
     // Create a binder service.
-    let service = BnServiceManager::new_binder(ServiceManager::new());
+    let service = BnServiceManager::new_binder(ServiceManager::new(
+        policy,
+        manifest,
+        matches.get_flag("require_declared"),
+        launcher,
+    ));
     service.addService(
         "manager",
         &service.as_binder(),
@@ -686,6 +835,40 @@ fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rsbinder::driver::MemoryDevice;
+    use std::sync::OnceLock;
+
+    // A device whose context manager is a hub which denies everything to
+    // transactions.
+    fn device() -> &'static MemoryDevice {
+        static DEVICE: OnceLock<MemoryDevice> = OnceLock::new();
+        DEVICE.get_or_init(|| {
+            let device = MemoryDevice::new();
+            let process = ProcessState::builder()
+                .driver(Arc::new(device.open()))
+                .init()
+                .expect("init");
+            ProcessState::start_thread_pool();
+            let sm = BnServiceManager::new_binder(ServiceManager::new(
+                Policy::parse("default deny").unwrap(),
+                Manifest::default(),
+                false,
+                Launcher::default(),
+            ));
+            // The hub registers itself outside of a transaction.
+            sm.addService(
+                "manager",
+                &sm.as_binder(),
+                false,
+                DUMP_FLAG_PRIORITY_DEFAULT,
+            )
+            .unwrap();
+            process
+                .become_context_manager(sm.as_binder())
+                .expect("context manager");
+            device
+        })
+    }
 
     #[test]
     fn test_is_valid_service_name() {
@@ -698,4 +881,89 @@ mod tests {
         assert!(ServiceManager::is_valid_service_name("test1"));
         assert!(ServiceManager::is_valid_service_name("TEST2"));
     }
+
+    struct Accessor;
+
+    impl Interface for Accessor {}
+
+    impl hub::android_16::IAccessor for Accessor {
+        fn addConnection(&self) -> rsbinder::status::Result<ParcelFileDescriptor> {
+            Err(StatusCode::InvalidOperation.into())
+        }
+        fn getInstanceName(&self) -> rsbinder::status::Result<String> {
+            Ok("com.example.IFoo/default".to_owned())
+        }
+    }
+
+    #[test]
+    fn test_registered_accessor() {
+        use hub::android_16::android::os::Service::Service;
+
+        let mut manifest = Manifest::default();
+        manifest
+            .add(
+                r#"
+                [[interface]]
+                descriptor = "com.example.IBar"
+                instances = ["default"]
+                accessor = "com.example.bar.accessor"
+                "#,
+            )
+            .unwrap();
+        device();
+        let new_service_manager = |manifest| {
+            ServiceManager::new(Policy::allow_all(), manifest, false, Launcher::default())
+        };
+        let sm = new_service_manager(manifest);
+
+        let accessor = hub::android_16::BnAccessor::new_binder(Accessor).as_binder();
+        let other = BnServiceManager::new_binder(new_service_manager(Manifest::default()));
+        for (name, binder) in [
+            ("com.example.IFoo/default", &accessor),
+            ("com.example.bar.accessor", &accessor),
+            ("com.example.other", &other.as_binder()),
+        ] {
+            sm.addService(name, binder, false, DUMP_FLAG_PRIORITY_DEFAULT)
+                .unwrap();
+        }
+
+        // An accessor registered under the name of its instance.
+        assert!(matches!(
+            sm.checkService2("com.example.IFoo/default").unwrap(),
+            Service::Accessor(Some(_))
+        ));
+        // The accessor of the manifest is a service of its own, and is
+        // looked up for its instances.
+        assert!(matches!(
+            sm.checkService2("com.example.bar.accessor").unwrap(),
+            Service::ServiceWithMetadata(_)
+        ));
+        assert!(matches!(
+            sm.checkService2("com.example.IBar/default").unwrap(),
+            Service::Accessor(Some(_))
+        ));
+        assert!(matches!(
+            sm.checkService2("com.example.other").unwrap(),
+            Service::ServiceWithMetadata(_)
+        ));
+        assert!(matches!(
+            sm.checkService2("com.example.missing").unwrap(),
+            Service::Accessor(None)
+        ));
+    }
+
+    #[test]
+    fn test_policy_of_own_pid() -> rsbinder::Result<()> {
+        // A client in the process of the hub is checked like any other.
+        let client = ProcessState::builder()
+            .driver(Arc::new(device().open()))
+            .init_context()
+            .expect("client context");
+        let sm = hub::for_context(client);
+        assert!(sm.check_service("manager").is_none());
+        assert!(sm
+            .add_service("my.service", client.context_object()?)
+            .is_err());
+        Ok(())
+    }
 }
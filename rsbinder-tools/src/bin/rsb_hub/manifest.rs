@@ -0,0 +1,270 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+//! The manifest of the services which are declared to exist, like Android's
+//! VINTF manifest.
+//!
+//! The manifest is a directory of TOML files, which declare the instances of
+//! interfaces and the packages which provide them:
+//!
+//! ```toml
+//! [[interface]]
+//! descriptor = "android.hardware.light.ILights"
+//! instances = ["default"]
+//! # Optional, the package which provides and updates the instances.
+//! package = "com.example.lights"
+//! # Optional, the service which is the `android.os.IAccessor` of the instances.
+//! accessor = "com.example.lights.accessor"
+//! # Optional, the address which the instances listen on for RPC connections.
+//! ip_address = "127.0.0.1"
+//! port = 5000
+//! ```
+//!
+//! An instance is registered as the service `<descriptor>/<instance>`, e.g.
+//! `android.hardware.light.ILights/default`.
+//!
+//! The XML manifests of Android aren't supported. `.xml` files in the
+//! directory are skipped with a warning, and must be converted to TOML.
+
+use std::path::Path;
+
+use serde::Deserialize;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ManifestFile {
+    #[serde(default)]
+    interface: Vec<Interface>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Interface {
+    descriptor: String,
+    instances: Vec<String>,
+    package: Option<String>,
+    accessor: Option<String>,
+    ip_address: Option<String>,
+    port: Option<u16>,
+}
+
+impl Interface {
+    fn names(&self) -> impl Iterator<Item = String> + '_ {
+        self.instances
+            .iter()
+            .map(|instance| format!("{}/{instance}", self.descriptor))
+    }
+}
+
+/// The declared instances of interfaces.
+#[derive(Debug, Default)]
+pub(crate) struct Manifest {
+    interfaces: Vec<Interface>,
+}
+
+impl Manifest {
+    /// Load the `.toml` files of the directory `dir`. Other files are skipped.
+    pub(crate) fn load(dir: &Path) -> Result<Self, String> {
+        let entries =
+            std::fs::read_dir(dir).map_err(|err| format!("Can't read {}: {err}", dir.display()))?;
+        let mut paths = entries
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<Vec<_>>>()
+            .map_err(|err| format!("Can't read {}: {err}", dir.display()))?;
+        paths.retain(|path| match path.extension() {
+            Some(ext) if ext == "toml" => true,
+            Some(ext) if ext == "xml" => {
+                log::warn!("{}: XML manifests are not supported", path.display());
+                false
+            }
+            _ => false,
+        });
+        paths.sort();
+
+        let mut manifest = Self::default();
+        for path in paths {
+            let text = std::fs::read_to_string(&path)
+                .map_err(|err| format!("Can't read {}: {err}", path.display()))?;
+            manifest
+                .add(&text)
+                .map_err(|err| format!("{}: {err}", path.display()))?;
+        }
+        Ok(manifest)
+    }
+
+    /// Add the declarations of a manifest file.
+    pub(crate) fn add(&mut self, text: &str) -> Result<(), String> {
+        let file: ManifestFile = toml::from_str(text).map_err(|err| err.to_string())?;
+        if let Some(interface) = file
+            .interface
+            .iter()
+            .find(|interface| interface.ip_address.is_some() != interface.port.is_some())
+        {
+            return Err(format!(
+                "{}: ip_address and port must be declared together",
+                interface.descriptor
+            ));
+        }
+        self.interfaces.extend(file.interface);
+        Ok(())
+    }
+
+    fn interface_of(&self, name: &str) -> Option<&Interface> {
+        self.interfaces
+            .iter()
+            .find(|interface| interface.names().any(|declared| declared == name))
+    }
+
+    /// Whether the service `name`, `<descriptor>/<instance>`, is declared.
+    pub(crate) fn is_declared(&self, name: &str) -> bool {
+        self.interface_of(name).is_some()
+    }
+
+    /// The declared instances of the interface `descriptor`.
+    pub(crate) fn declared_instances(&self, descriptor: &str) -> Vec<String> {
+        let mut instances: Vec<String> = self
+            .interfaces
+            .iter()
+            .filter(|interface| interface.descriptor == descriptor)
+            .flat_map(|interface| interface.instances.iter().cloned())
+            .collect();
+        instances.sort();
+        instances.dedup();
+        instances
+    }
+
+    /// The package which provides the service `name`, if it is declared with one.
+    pub(crate) fn package_of(&self, name: &str) -> Option<String> {
+        self.interface_of(name)?.package.clone()
+    }
+
+    /// The name of the accessor service of the service `name`, if it has one.
+    pub(crate) fn accessor_of(&self, name: &str) -> Option<String> {
+        self.interface_of(name)?.accessor.clone()
+    }
+
+    /// Whether the service `name` is declared as the accessor of instances.
+    pub(crate) fn is_accessor(&self, name: &str) -> bool {
+        self.interfaces
+            .iter()
+            .any(|interface| interface.accessor.as_deref() == Some(name))
+    }
+
+    /// The IP address and the port which the service `name` listens on.
+    pub(crate) fn connection_of(&self, name: &str) -> Option<(String, u16)> {
+        let interface = self.interface_of(name)?;
+        Some((interface.ip_address.clone()?, interface.port?))
+    }
+
+    /// The services which the package `package` provides.
+    pub(crate) fn names_of_package(&self, package: &str) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .interfaces
+            .iter()
+            .filter(|interface| interface.package.as_deref() == Some(package))
+            .flat_map(Interface::names)
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIGHTS: &str = r#"
+        [[interface]]
+        descriptor = "android.hardware.light.ILights"
+        instances = ["default", "secondary"]
+        package = "com.example.lights"
+
+        [[interface]]
+        descriptor = "android.hardware.vibrator.IVibrator"
+        instances = ["default"]
+        accessor = "android.hardware.vibrator.accessor"
+        ip_address = "127.0.0.1"
+        port = 5000
+    "#;
+
+    #[test]
+    fn test_manifest() {
+        let mut manifest = Manifest::default();
+        manifest.add(LIGHTS).unwrap();
+
+        assert!(manifest.is_declared("android.hardware.light.ILights/default"));
+        assert!(manifest.is_declared("android.hardware.vibrator.IVibrator/default"));
+        assert!(!manifest.is_declared("android.hardware.light.ILights/third"));
+        assert!(!manifest.is_declared("android.hardware.light.ILights"));
+
+        assert_eq!(
+            manifest.declared_instances("android.hardware.light.ILights"),
+            vec!["default", "secondary"]
+        );
+        assert!(manifest.declared_instances("IUnknown").is_empty());
+
+        assert_eq!(
+            manifest.package_of("android.hardware.light.ILights/secondary"),
+            Some("com.example.lights".to_owned())
+        );
+        assert_eq!(
+            manifest.package_of("android.hardware.vibrator.IVibrator/default"),
+            None
+        );
+        assert_eq!(
+            manifest.names_of_package("com.example.lights"),
+            vec![
+                "android.hardware.light.ILights/default",
+                "android.hardware.light.ILights/secondary"
+            ]
+        );
+
+        assert_eq!(
+            manifest.accessor_of("android.hardware.vibrator.IVibrator/default"),
+            Some("android.hardware.vibrator.accessor".to_owned())
+        );
+        assert_eq!(
+            manifest.accessor_of("android.hardware.light.ILights/default"),
+            None
+        );
+        assert_eq!(
+            manifest.connection_of("android.hardware.vibrator.IVibrator/default"),
+            Some(("127.0.0.1".to_owned(), 5000))
+        );
+        assert_eq!(
+            manifest.connection_of("android.hardware.light.ILights/default"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_manifest_errors() {
+        let mut manifest = Manifest::default();
+        assert!(manifest
+            .add("[[interface]]\ninstances = [\"default\"]")
+            .is_err());
+        assert!(manifest
+            .add("[[interface]]\ndescriptor = \"IFoo\"\ninstances = []\nowner = \"me\"")
+            .is_err());
+        assert!(manifest
+            .add("[[interface]]\ndescriptor = \"IFoo\"\ninstances = []\nport = 5000")
+            .is_err());
+        assert!(manifest.add("").is_ok());
+    }
+
+    #[test]
+    fn test_manifest_load() {
+        let dir = std::env::temp_dir().join(format!("rsb_hub-manifest-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("lights.toml"), LIGHTS).unwrap();
+        std::fs::write(dir.join("README"), "Not a manifest").unwrap();
+        std::fs::write(dir.join("camera.xml"), "<manifest version=\"1.0\"/>").unwrap();
+
+        let manifest = Manifest::load(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(manifest
+            .unwrap()
+            .is_declared("android.hardware.light.ILights/secondary"));
+    }
+}
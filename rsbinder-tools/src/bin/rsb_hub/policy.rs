@@ -0,0 +1,359 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+//! Access control of the service operations of rsb_hub.
+//!
+//! A policy file has a rule per line, and `#` starts a comment:
+//!
+//! ```text
+//! default deny
+//! # <allow|deny> <operations> <service name pattern> <callers...>
+//! allow add,find,list * uid:0
+//! allow add com.example.* uid:1000 gid:1000 sid:u:r:example_t:*
+//! deny find com.example.secret *
+//! allow find,list * *
+//! ```
+//!
+//! The operations are `add`, `find` and `list`, and the patterns may have `*`
+//! wildcards. The pattern of a service name is not used for `list`. A caller
+//! is `*`, `uid:<uid>`, `gid:<gid>`, which matches the primary and the
+//! supplementary groups of the caller, or `sid:<pattern>`, which matches the
+//! SELinux context of the caller. A rule matches when any of its callers does.
+//!
+//! The first rule which matches decides. An operation which no rule matches is
+//! decided by the `default` line, or denied if there is none.
+//!
+//! The driver tells only the pid, the uid and the SELinux context of a caller,
+//! so its groups are read from `/proc/<pid>/status` while its transaction is
+//! served. If the caller exited meanwhile, e.g. after a oneway call, its pid
+//! may belong to another process by then. The groups are only used if that
+//! process still has the effective uid of the caller, so a `gid:` rule can't
+//! be matched through a process of another user, but `uid:` and `sid:` rules
+//! are the ones which can't be raced.
+
+use std::cell::OnceCell;
+use std::path::Path;
+
+use rsbinder::thread_state::CallingContext;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Operation {
+    Add,
+    Find,
+    List,
+}
+
+impl Operation {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "add" => Some(Operation::Add),
+            "find" => Some(Operation::Find),
+            "list" => Some(Operation::List),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Operation::Add => "add",
+            Operation::Find => "find",
+            Operation::List => "list",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Caller {
+    Any,
+    Uid(u32),
+    Gid(u32),
+    Sid(String),
+}
+
+impl Caller {
+    fn parse(text: &str) -> Option<Self> {
+        if text == "*" {
+            return Some(Caller::Any);
+        }
+        let (kind, value) = text.split_once(':')?;
+        match kind {
+            "uid" => value.parse().ok().map(Caller::Uid),
+            "gid" => value.parse().ok().map(Caller::Gid),
+            "sid" if !value.is_empty() => Some(Caller::Sid(value.to_owned())),
+            _ => None,
+        }
+    }
+
+    fn matches(&self, caller: &Identity) -> bool {
+        match self {
+            Caller::Any => true,
+            Caller::Uid(uid) => caller.context.uid == *uid,
+            Caller::Gid(gid) => caller.gids().contains(gid),
+            Caller::Sid(pattern) => caller
+                .sid()
+                .is_some_and(|sid| matches_pattern(pattern, &sid)),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Rule {
+    allow: bool,
+    operations: Vec<Operation>,
+    pattern: String,
+    callers: Vec<Caller>,
+}
+
+impl Rule {
+    fn matches(&self, operation: Operation, name: &str, caller: &Identity) -> bool {
+        self.operations.contains(&operation)
+            && (operation == Operation::List || matches_pattern(&self.pattern, name))
+            && self.callers.iter().any(|c| c.matches(caller))
+    }
+}
+
+// The caller of an operation. Its groups are read only if a rule asks for them.
+struct Identity<'a> {
+    context: &'a CallingContext,
+    gids: OnceCell<Vec<u32>>,
+}
+
+impl Identity<'_> {
+    fn gids(&self) -> &[u32] {
+        self.gids
+            .get_or_init(|| process_gids(self.context.pid, self.context.uid))
+    }
+
+    fn sid(&self) -> Option<String> {
+        self.context
+            .sid
+            .as_ref()
+            .map(|sid| sid.to_string_lossy().into_owned())
+    }
+}
+
+// The real and the supplementary groups of the process `pid`, from /proc.
+// No groups if the process doesn't have the effective uid `uid`: the caller
+// exited, and its pid was reused by a process of another user.
+fn process_gids(pid: i32, uid: u32) -> Vec<u32> {
+    let Ok(status) = std::fs::read_to_string(format!("/proc/{pid}/status")) else {
+        log::warn!("Can't read the groups of the process {pid}");
+        return Vec::new();
+    };
+    let mut gids = Vec::new();
+    for line in status.lines() {
+        if let Some(value) = line.strip_prefix("Uid:") {
+            let euid = value.split_whitespace().nth(1);
+            if euid.and_then(|euid| euid.parse::<u32>().ok()) != Some(uid) {
+                log::warn!("The process {pid} isn't the caller with uid {uid} anymore");
+                return Vec::new();
+            }
+        } else if let Some(value) = line.strip_prefix("Gid:") {
+            gids.extend(
+                value
+                    .split_whitespace()
+                    .next()
+                    .and_then(|gid| gid.parse::<u32>().ok()),
+            );
+        } else if let Some(value) = line.strip_prefix("Groups:") {
+            gids.extend(
+                value
+                    .split_whitespace()
+                    .filter_map(|gid| gid.parse::<u32>().ok()),
+            );
+        }
+    }
+    gids
+}
+
+// Match `text` against `pattern`, where `*` matches any characters.
+fn matches_pattern(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<&str> = parts.collect();
+    let Some(last) = parts.pop() else {
+        // No wildcard.
+        return rest.is_empty();
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// Which callers may register, look up and list services.
+#[derive(Debug)]
+pub(crate) struct Policy {
+    rules: Vec<Rule>,
+    default_allow: bool,
+}
+
+impl Policy {
+    /// A policy which allows everything, used without a policy file.
+    pub(crate) fn allow_all() -> Self {
+        Self {
+            rules: Vec::new(),
+            default_allow: true,
+        }
+    }
+
+    pub(crate) fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| format!("Can't read {}: {err}", path.display()))?;
+        Self::parse(&text).map_err(|err| format!("{}: {err}", path.display()))
+    }
+
+    pub(crate) fn parse(text: &str) -> Result<Self, String> {
+        let mut policy = Self {
+            rules: Vec::new(),
+            default_allow: false,
+        };
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let invalid = || format!("line {}: invalid rule '{}'", index + 1, line.trim());
+            match words.as_slice() {
+                [] => {}
+                ["default", "allow"] => policy.default_allow = true,
+                ["default", "deny"] => policy.default_allow = false,
+                [action, operations, pattern, callers @ ..] if !callers.is_empty() => {
+                    let allow = match *action {
+                        "allow" => true,
+                        "deny" => false,
+                        _ => return Err(invalid()),
+                    };
+                    let operations = operations
+                        .split(',')
+                        .map(Operation::parse)
+                        .collect::<Option<Vec<_>>>()
+                        .ok_or_else(invalid)?;
+                    let callers = callers
+                        .iter()
+                        .map(|caller| Caller::parse(caller))
+                        .collect::<Option<Vec<_>>>()
+                        .ok_or_else(invalid)?;
+                    policy.rules.push(Rule {
+                        allow,
+                        operations,
+                        pattern: pattern.to_string(),
+                        callers,
+                    });
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(policy)
+    }
+
+    /// Whether `caller` may do `operation` on the service `name`.
+    /// A denial is logged.
+    pub(crate) fn check(&self, operation: Operation, name: &str, caller: &CallingContext) -> bool {
+        let identity = Identity {
+            context: caller,
+            gids: OnceCell::new(),
+        };
+        let allowed = self
+            .rules
+            .iter()
+            .find(|rule| rule.matches(operation, name, &identity))
+            .map_or(self.default_allow, |rule| rule.allow);
+        if !allowed {
+            log::warn!(
+                "Denied {} of '{name}' to pid {} uid {} sid {:?}",
+                operation.name(),
+                caller.pid,
+                caller.uid,
+                identity.sid()
+            );
+        }
+        allowed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+
+    fn caller(uid: u32, sid: Option<&str>) -> CallingContext {
+        CallingContext {
+            pid: std::process::id() as _,
+            uid,
+            sid: sid.map(|sid| CString::new(sid).unwrap()),
+        }
+    }
+
+    #[test]
+    fn test_matches_pattern() {
+        assert!(matches_pattern("*", ""));
+        assert!(matches_pattern("*", "my.service"));
+        assert!(matches_pattern("my.service", "my.service"));
+        assert!(!matches_pattern("my.service", "my.service2"));
+        assert!(matches_pattern("my.*", "my.service"));
+        assert!(!matches_pattern("my.*", "your.service"));
+        assert!(matches_pattern("*.service", "my.service"));
+        assert!(matches_pattern("u:r:*_t:*", "u:r:example_t:s0"));
+        assert!(!matches_pattern("a*b*c", "acb"));
+    }
+
+    #[test]
+    fn test_policy() {
+        let policy = Policy::parse(
+            "# Services of example.\n\
+             allow add com.example.* uid:1000 sid:u:r:example_t:*\n\
+             deny find com.example.secret *\n\
+             allow find,list * *\n",
+        )
+        .unwrap();
+
+        let example = caller(1000, None);
+        let other = caller(2000, None);
+        let labeled = caller(2000, Some("u:r:example_t:s0"));
+        assert!(policy.check(Operation::Add, "com.example.foo", &example));
+        assert!(policy.check(Operation::Add, "com.example.foo", &labeled));
+        assert!(!policy.check(Operation::Add, "com.example.foo", &other));
+        assert!(!policy.check(Operation::Add, "org.example.foo", &example));
+
+        assert!(policy.check(Operation::Find, "com.example.foo", &other));
+        assert!(!policy.check(Operation::Find, "com.example.secret", &example));
+        assert!(policy.check(Operation::List, "", &other));
+    }
+
+    #[test]
+    fn test_policy_groups() {
+        // The real group of the test process.
+        // SAFETY: geteuid() has no preconditions.
+        let uid = unsafe { libc::geteuid() };
+        let gid = process_gids(std::process::id() as _, uid)[0];
+        let policy = Policy::parse(&format!("allow add * gid:{gid}")).unwrap();
+        assert!(policy.check(Operation::Add, "my.service", &caller(uid, None)));
+        assert!(!policy.check(Operation::Find, "my.service", &caller(uid, None)));
+
+        // The pid of the caller belongs to a process of another user.
+        assert!(process_gids(std::process::id() as _, uid + 1).is_empty());
+        assert!(!policy.check(Operation::Add, "my.service", &caller(uid + 1, None)));
+    }
+
+    #[test]
+    fn test_policy_default() {
+        assert!(Policy::allow_all().check(Operation::Add, "my.service", &caller(1000, None)));
+        let policy = Policy::parse("default allow\ndeny add * uid:1000").unwrap();
+        assert!(!policy.check(Operation::Add, "my.service", &caller(1000, None)));
+        assert!(policy.check(Operation::Add, "my.service", &caller(0, None)));
+    }
+
+    #[test]
+    fn test_policy_errors() {
+        assert!(Policy::parse("allow add *").is_err());
+        assert!(Policy::parse("permit add * *").is_err());
+        assert!(Policy::parse("allow remove * *").is_err());
+        assert!(Policy::parse("allow add * user:root").is_err());
+        assert!(Policy::parse("default maybe").is_err());
+    }
+}
@@ -0,0 +1,280 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+use env_logger::Env;
+use rsbinder::recording::RecordedTransaction;
+use rsbinder::*;
+
+fn service_arg() -> clap::Arg {
+    clap::Arg::new("service")
+        .help("Name of the service")
+        .required(true)
+        .index(1)
+}
+
+fn file_arg() -> clap::Arg {
+    clap::Arg::new("file")
+        .help("Path of the recording")
+        .required(true)
+        .value_parser(clap::value_parser!(PathBuf))
+        .index(2)
+}
+
+fn remote(name: &str) -> std::result::Result<SIBinder, Box<dyn std::error::Error>> {
+    let service = hub::check_service(name).ok_or(format!("Can't find service: {name}"))?;
+    if service.as_proxy().is_none() {
+        return Err(format!("Service {name} is not a remote object").into());
+    }
+    Ok(service)
+}
+
+// Replay the transactions of the recording `reader` to `service`, and report
+// them to `out`. Returns whether every transaction got the recorded result.
+fn replay(
+    service: &SIBinder,
+    reader: &mut impl Read,
+    out: &mut impl Write,
+) -> std::result::Result<bool, Box<dyn std::error::Error>> {
+    let mut same = true;
+    let mut index = 0;
+    while let Some(transaction) = RecordedTransaction::read_from(reader)? {
+        let (status, reply) = match transaction.replay(service) {
+            Ok(reply) => (StatusCode::Ok, reply),
+            Err(err) => (err, None),
+        };
+        let recorded_status = StatusCode::from(transaction.status);
+        let matches = status == recorded_status && transaction.reply_matches(reply.as_ref());
+        writeln!(
+            out,
+            "#{index} code {:#x} flags {:#x}: {status} (recorded {recorded_status}){}",
+            transaction.code,
+            transaction.flags,
+            if matches { "" } else { " MISMATCH" }
+        )?;
+        same &= matches;
+        index += 1;
+    }
+    writeln!(out, "Replayed {index} transactions")?;
+    Ok(same)
+}
+
+fn command() -> clap::Command {
+    clap::Command::new("rsb_record")
+        .version(env!("CARGO_PKG_VERSION"))
+        .author(env!("CARGO_PKG_AUTHORS"))
+        .about("Records the transactions of a binder service, and replays them")
+        .subcommand_required(true)
+        .subcommand(
+            clap::Command::new("start")
+                .about("Start recording the transactions of a service to a file")
+                .arg(service_arg())
+                .arg(file_arg()),
+        )
+        .subcommand(
+            clap::Command::new("stop")
+                .about("Stop recording the transactions of a service")
+                .arg(service_arg()),
+        )
+        .subcommand(
+            clap::Command::new("replay")
+                .about("Send the transactions of a recording to a service again")
+                .arg(service_arg())
+                .arg(file_arg()),
+        )
+        .after_help(
+            "Examples:\n    \
+            $ rsb_record start my.service /tmp/my.service.rec\n    \
+            $ rsb_record stop my.service\n    \
+            $ rsb_record replay my.service /tmp/my.service.rec\n\n\
+            replay exits with 1 if a transaction doesn't get the recorded result.",
+        )
+}
+
+fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let matches = command().get_matches();
+
+    env_logger::Builder::from_env(Env::default().default_filter_or("warn")).init();
+
+    ProcessState::init(DEFAULT_BINDER_PATH, 0);
+
+    let (command, args) = matches.subcommand().unwrap();
+    let name = args.get_one::<String>("service").unwrap();
+    match command {
+        "start" => {
+            let path = args.get_one::<PathBuf>("file").unwrap();
+            let file = File::create(path)?;
+            remote(name)?.as_proxy().unwrap().start_recording(&file)?;
+            println!("Recording {name} to {}", path.display());
+        }
+        "stop" => {
+            remote(name)?.as_proxy().unwrap().stop_recording()?;
+            println!("Stopped recording {name}");
+        }
+        "replay" => {
+            let path = args.get_one::<PathBuf>("file").unwrap();
+            let mut reader = File::open(path)?;
+            if !replay(&remote(name)?, &mut reader, &mut std::io::stdout())? {
+                std::process::exit(1);
+            }
+        }
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsbinder::driver::MemoryDevice;
+    use std::sync::Arc;
+
+    const ECHO: TransactionCode = FIRST_CALL_TRANSACTION;
+
+    struct Echo;
+
+    impl Remotable for Echo {
+        fn descriptor() -> &'static str {
+            "rsbinder.test.IEcho"
+        }
+
+        fn on_transact(
+            &self,
+            code: TransactionCode,
+            reader: &mut Parcel,
+            reply: &mut Parcel,
+        ) -> Result<()> {
+            match code {
+                ECHO => reply.write(&reader.read::<String>()?),
+                _ => Err(StatusCode::UnknownTransaction),
+            }
+        }
+
+        fn on_dump(&self, _writer: &mut dyn Write, _args: &[String]) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_command() {
+        command().debug_assert();
+
+        let matches = command()
+            .try_get_matches_from(["rsb_record", "start", "my.service", "/tmp/my.rec"])
+            .unwrap();
+        let (name, args) = matches.subcommand().unwrap();
+        assert_eq!(name, "start");
+        assert_eq!(args.get_one::<String>("service").unwrap(), "my.service");
+        assert_eq!(
+            args.get_one::<PathBuf>("file").unwrap(),
+            &PathBuf::from("/tmp/my.rec")
+        );
+        assert!(command()
+            .try_get_matches_from(["rsb_record", "stop", "my.service"])
+            .is_ok());
+        assert!(command()
+            .try_get_matches_from(["rsb_record", "replay", "my.service", "/tmp/my.rec"])
+            .is_ok());
+
+        // A missing or an extra file, and a missing command.
+        assert!(command()
+            .try_get_matches_from(["rsb_record", "start", "my.service"])
+            .is_err());
+        assert!(command()
+            .try_get_matches_from(["rsb_record", "replay", "my.service"])
+            .is_err());
+        assert!(command()
+            .try_get_matches_from(["rsb_record", "stop", "my.service", "/tmp/my.rec"])
+            .is_err());
+        assert!(command().try_get_matches_from(["rsb_record"]).is_err());
+    }
+
+    fn echo(service: &SIBinder, text: &str) -> Result<()> {
+        let proxy = service.as_proxy().unwrap();
+        let mut data = proxy.prepare_transact(true)?;
+        data.write(text)?;
+        proxy.submit_transact(ECHO, &data, 0)?;
+        Ok(())
+    }
+
+    // Record ECHO transactions of `texts` to `service`.
+    fn record(service: &SIBinder, texts: &[&str]) -> Result<Vec<RecordedTransaction>> {
+        let path = std::env::temp_dir().join(format!("rsb_record-{}.rec", std::process::id()));
+        let proxy = service.as_proxy().unwrap();
+        proxy.start_recording(File::create(&path)?)?;
+        for text in texts {
+            echo(service, text)?;
+        }
+        proxy.stop_recording()?;
+
+        let mut reader = File::open(&path)?;
+        let mut transactions = Vec::new();
+        while let Some(transaction) = RecordedTransaction::read_from(&mut reader)? {
+            transactions.push(transaction);
+        }
+        std::fs::remove_file(&path)?;
+        Ok(transactions)
+    }
+
+    fn run_replay(service: &SIBinder, transactions: &[RecordedTransaction]) -> (bool, String) {
+        let mut recording = Vec::new();
+        for transaction in transactions {
+            transaction.write_to(&mut recording).unwrap();
+        }
+        let mut out = Vec::new();
+        let same = replay(service, &mut recording.as_slice(), &mut out).unwrap();
+        (same, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_replay() -> Result<()> {
+        let device = MemoryDevice::new();
+        let process = ProcessState::builder()
+            .driver(Arc::new(device.open()))
+            .init()
+            .expect("init");
+        ProcessState::start_thread_pool();
+        process
+            .become_context_manager(Binder::new(Echo).as_binder())
+            .expect("context manager");
+        let client = ProcessState::builder()
+            .driver(Arc::new(device.open()))
+            .init_context()
+            .expect("client context");
+        client.start_pool();
+        let service = client.context_object()?;
+
+        let transactions = record(&service, &["a", "b"])?;
+        assert_eq!(transactions.len(), 2);
+        let (same, out) = run_replay(&service, &transactions);
+        assert!(same, "{out}");
+        assert!(!out.contains("MISMATCH"));
+        assert!(out.ends_with("Replayed 2 transactions\n"));
+
+        // A different reply.
+        let mut changed = transactions.clone();
+        changed[1].reply = changed[0].reply.clone();
+        let (same, out) = run_replay(&service, &changed);
+        assert!(!same);
+        assert_eq!(out.matches("MISMATCH").count(), 1);
+        assert!(out.lines().nth(1).unwrap().ends_with("MISMATCH"));
+
+        // An object offset past the end of the data fails the replay of its
+        // transaction, as it was recorded to.
+        let mut bad_offset = transactions[0].clone();
+        bad_offset.objects = vec![u64::MAX];
+        bad_offset.status = StatusCode::BadValue.into();
+        bad_offset.reply.clear();
+        let (same, out) = run_replay(&service, &[bad_offset]);
+        assert!(same, "{out}");
+
+        assert_eq!(
+            run_replay(&service, &[]),
+            (true, "Replayed 0 transactions\n".to_owned())
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,285 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fs::File;
+use std::os::fd::{FromRawFd, OwnedFd, RawFd};
+
+use env_logger::Env;
+use rsbinder::*;
+
+const ARGUMENTS_HELP: &str = "Arguments of call:\n    \
+    i32 N    write the 32-bit integer N\n    \
+    i64 N    write the 64-bit integer N\n    \
+    f N      write the 32-bit float N\n    \
+    d N      write the 64-bit double N\n    \
+    s16 STR  write the string STR\n    \
+    null     write a null binder\n    \
+    fd PATH  write a ParcelFileDescriptor of the file PATH, opened for reading\n    \
+    nfd N    write a ParcelFileDescriptor of the file descriptor N of rsb_service";
+
+fn parse<T: std::str::FromStr>(
+    kind: &str,
+    value: Option<&String>,
+) -> std::result::Result<T, String> {
+    let value = value.ok_or(format!("Missing the value of '{kind}'"))?;
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value of '{kind}': {value}"))
+}
+
+// Duplicate the file descriptor `fd`, which fails with EBADF if it isn't open.
+fn dup_raw_fd(fd: RawFd) -> std::io::Result<OwnedFd> {
+    // SAFETY: fcntl() only reads its arguments, and checks that fd is open.
+    let dup = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) };
+    if dup < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: dup is a new file descriptor, which nothing else owns.
+    Ok(unsafe { OwnedFd::from_raw_fd(dup) })
+}
+
+// Write the typed arguments, e.g. ["i32", "1", "s16", "foo"], to the parcel.
+fn write_arguments(
+    data: &mut Parcel,
+    args: &[String],
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let mut args = args.iter();
+    while let Some(kind) = args.next() {
+        match kind.as_str() {
+            "i32" => data.write(&parse::<i32>(kind, args.next())?)?,
+            "i64" => data.write(&parse::<i64>(kind, args.next())?)?,
+            "f" => data.write(&parse::<f32>(kind, args.next())?)?,
+            "d" => data.write(&parse::<f64>(kind, args.next())?)?,
+            "s16" => data.write(args.next().ok_or("Missing the value of 's16'")?)?,
+            "null" => data.write(&Option::<SIBinder>::None)?,
+            "fd" => {
+                let path = args.next().ok_or("Missing the path of 'fd'")?;
+                let file = File::open(path).map_err(|err| format!("Can't open {path}: {err}"))?;
+                data.write(&ParcelFileDescriptor::new(file))?
+            }
+            "nfd" => {
+                let fd = parse::<RawFd>(kind, args.next())?;
+                data.write(&ParcelFileDescriptor::new(dup_raw_fd(fd)?))?
+            }
+            _ => return Err(format!("Unknown argument type: {kind}").into()),
+        }
+    }
+    Ok(())
+}
+
+fn call(
+    name: &str,
+    code: TransactionCode,
+    args: &[String],
+) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let service = hub::check_service(name).ok_or(format!("Can't find service: {name}"))?;
+    let proxy = service
+        .as_proxy()
+        .ok_or(format!("Service {name} is not a remote object"))?;
+
+    let mut data = proxy.prepare_transact(true)?;
+    write_arguments(&mut data, args)?;
+
+    let mut reply = proxy
+        .submit_transact(code, &data, FLAG_CLEAR_BUF)
+        .map_err(|err| format!("Transaction failed: {err}"))?
+        .unwrap_or_default();
+    // The reply of an AIDL method starts with its status.
+    if let Ok(status) = reply.read::<Status>() {
+        println!("Status: {status}");
+    }
+    reply.set_data_position(0);
+    println!("Result: {reply:?}");
+    Ok(())
+}
+
+fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let matches = clap::Command::new("rsb_service")
+        .version(env!("CARGO_PKG_VERSION"))
+        .author(env!("CARGO_PKG_AUTHORS"))
+        .about("Lists, checks and calls binder services, like Android's service")
+        .subcommand_required(true)
+        .subcommand(clap::Command::new("list").about("List the registered services"))
+        .subcommand(
+            clap::Command::new("check")
+                .about("Check whether a service is registered")
+                .arg(
+                    clap::Arg::new("service")
+                        .help("Name of the service")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("call")
+                .about("Call a service with typed arguments")
+                .arg(
+                    clap::Arg::new("service")
+                        .help("Name of the service")
+                        .required(true),
+                )
+                .arg(
+                    clap::Arg::new("code")
+                        .help("Transaction code")
+                        .required(true)
+                        .value_parser(clap::value_parser!(u32)),
+                )
+                .arg(
+                    clap::Arg::new("args")
+                        .help("Typed arguments")
+                        .num_args(0..)
+                        .trailing_var_arg(true)
+                        .allow_hyphen_values(true),
+                )
+                .after_help(ARGUMENTS_HELP),
+        )
+        .after_help(
+            "Examples:\n    \
+            $ rsb_service list\n    \
+            $ rsb_service check my.service\n    \
+            $ rsb_service call my.service 1 i32 1 s16 \"foo\" fd /tmp/input",
+        )
+        .get_matches();
+
+    env_logger::Builder::from_env(Env::default().default_filter_or("warn")).init();
+
+    ProcessState::init(DEFAULT_BINDER_PATH, 0);
+
+    match matches.subcommand() {
+        Some(("list", _)) => {
+            let services = hub::list_services(hub::DUMP_FLAG_PRIORITY_ALL);
+            println!("Found {} services:", services.len());
+            for (index, name) in services.iter().enumerate() {
+                let descriptor = hub::check_service(name)
+                    .map(|service| service.descriptor().to_owned())
+                    .unwrap_or_default();
+                println!("{index}\t{name}: [{descriptor}]");
+            }
+        }
+        Some(("check", args)) => {
+            let name = args.get_one::<String>("service").unwrap();
+            match hub::check_service(name) {
+                Some(_) => println!("Service {name}: found"),
+                None => println!("Service {name}: not found"),
+            }
+        }
+        Some(("call", args)) => {
+            let name = args.get_one::<String>("service").unwrap();
+            let code = *args.get_one::<u32>("code").unwrap();
+            let call_args: Vec<String> = args
+                .get_many::<String>("args")
+                .unwrap_or_default()
+                .cloned()
+                .collect();
+            if let Err(err) = call(name, code, &call_args) {
+                eprintln!("rsb_service: {err}");
+                std::process::exit(1);
+            }
+        }
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::os::fd::AsRawFd;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn write(arguments: &[&str]) -> std::result::Result<Parcel, String> {
+        let mut data = Parcel::new();
+        write_arguments(&mut data, &args(arguments)).map_err(|err| err.to_string())?;
+        data.set_data_position(0);
+        Ok(data)
+    }
+
+    fn read_file(fd: ParcelFileDescriptor) -> String {
+        let mut text = String::new();
+        File::from(OwnedFd::from(fd))
+            .read_to_string(&mut text)
+            .unwrap();
+        text
+    }
+
+    #[test]
+    fn test_write_arguments() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("rsb_service-{}.txt", std::process::id()));
+        std::fs::write(&path, "content")?;
+        let file = File::open(&path)?;
+        let nfd = file.as_raw_fd().to_string();
+
+        let mut data = write(&[
+            "i32",
+            "-1",
+            "i64",
+            "1099511627776",
+            "f",
+            "1.5",
+            "d",
+            "2.25",
+            "s16",
+            "foo",
+            "null",
+            "fd",
+            path.to_str().unwrap(),
+            "nfd",
+            &nfd,
+        ])
+        .unwrap();
+        std::fs::remove_file(&path)?;
+
+        assert_eq!(data.read::<i32>()?, -1);
+        assert_eq!(data.read::<i64>()?, 1 << 40);
+        assert_eq!(data.read::<f32>()?, 1.5);
+        assert_eq!(data.read::<f64>()?, 2.25);
+        assert_eq!(data.read::<String>()?, "foo");
+        assert!(data.read::<Option<SIBinder>>()?.is_none());
+        assert_eq!(read_file(data.read()?), "content");
+        assert_eq!(read_file(data.read()?), "content");
+        assert_eq!(data.data_avail(), 0);
+
+        assert_eq!(write(&[]).unwrap().data_size(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_arguments_errors() {
+        for kind in ["i32", "i64", "f", "d", "s16", "fd", "nfd"] {
+            let err = write(&[kind]).unwrap_err();
+            assert!(err.starts_with("Missing the "), "{kind}: {err}");
+            assert!(err.contains(&format!("'{kind}'")), "{kind}: {err}");
+        }
+
+        assert_eq!(
+            write(&["i32", "one"]).unwrap_err(),
+            "Invalid value of 'i32': one"
+        );
+        assert_eq!(
+            write(&["i32", "4294967296"]).unwrap_err(),
+            "Invalid value of 'i32': 4294967296"
+        );
+        assert_eq!(
+            write(&["f", "1.5.2"]).unwrap_err(),
+            "Invalid value of 'f': 1.5.2"
+        );
+        assert_eq!(
+            write(&["i32", "1", "u8", "1"]).unwrap_err(),
+            "Unknown argument type: u8"
+        );
+        assert_eq!(
+            write(&["str", "foo"]).unwrap_err(),
+            "Unknown argument type: str"
+        );
+
+        assert!(write(&["fd", "/nonexistent/rsb_service"])
+            .unwrap_err()
+            .starts_with("Can't open /nonexistent/rsb_service"));
+        // A file descriptor which isn't open.
+        assert!(write(&["nfd", &i32::MAX.to_string()]).is_err());
+        assert!(write(&["nfd", "-1"]).is_err());
+    }
+}
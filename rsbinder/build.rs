@@ -34,6 +34,7 @@ fn main() {
 
     rsbinder_aidl::Builder::new()
         .source(PathBuf::from("aidl/16/android/os/IServiceManager.aidl"))
+        .source(PathBuf::from("aidl/16/android/os/IAccessor.aidl"))
         .output(PathBuf::from("service_manager_16.rs"))
         .set_crate_support(true)
         .generate()
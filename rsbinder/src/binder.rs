@@ -50,7 +50,9 @@ pub const FLAG_ONEWAY: TransactionFlags = sys::transaction_flags_TF_ONE_WAY;
 pub const FLAG_CLEAR_BUF: TransactionFlags = sys::transaction_flags_TF_CLEAR_BUF;
 /// Set to the vendor flag if we are building for the VNDK, 0 otherwise
 pub const FLAG_PRIVATE_LOCAL: TransactionFlags = 0;
-pub const FLAG_PRIVATE_VENDOR: TransactionFlags = FLAG_PRIVATE_LOCAL;
+/// Requires the remote object to be [`Stability::Vendor`] stable, whatever the
+/// stability of the calling process is. It is not sent to the driver.
+pub const FLAG_PRIVATE_VENDOR: TransactionFlags = 0x10000000;
 
 const fn b_pack_chars(c1: char, c2: char, c3: char, c4: char) -> u32 {
     ((c1 as u32) << 24) | ((c2 as u32) << 16) | ((c3 as u32) << 8) | (c4 as u32)
@@ -82,6 +84,8 @@ pub const TWEET_TRANSACTION: u32 = b_pack_chars('_', 'T', 'W', 'T');
 pub const LIKE_TRANSACTION: u32 = b_pack_chars('_', 'L', 'I', 'K');
 
 pub const INTERFACE_HEADER: u32 = b_pack_chars('S', 'Y', 'S', 'T');
+/// The interface header of the transactions of a [`Stability::Vendor`] process.
+pub const VENDOR_INTERFACE_HEADER: u32 = b_pack_chars('V', 'N', 'D', 'R');
 
 /// Base trait for all binder interfaces.
 ///
@@ -103,6 +107,21 @@ pub trait Interface: Send + Sync {
     fn dump(&self, _writer: &mut dyn std::io::Write, _args: &[String]) -> Result<()> {
         Ok(())
     }
+
+    /// Shell command transaction handler for this Binder object.
+    ///
+    /// The command reads from `input`, writes to `output` and `error`, and
+    /// returns its exit code. An error is reported to the caller as the exit
+    /// code. By default, shell commands fail with `StatusCode::InvalidOperation`.
+    fn shell_command(
+        &self,
+        _input: &mut dyn std::io::Read,
+        _output: &mut dyn std::io::Write,
+        _error: &mut dyn std::io::Write,
+        _args: &[String],
+    ) -> Result<i32> {
+        Err(StatusCode::InvalidOperation)
+    }
 }
 
 /// Trait for converting a generic Binder object into a specific Binder
@@ -141,6 +160,23 @@ pub trait DeathRecipient: Send + Sync {
     fn binder_died(&self, who: &WIBinder);
 }
 
+/// Whether the process hosting a remote binder object is frozen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrozenState {
+    Frozen,
+    Unfrozen,
+}
+
+/// Callback interface for frozen state notifications.
+///
+/// This corresponds to the C++ `IBinder::FrozenStateChangeCallback`. Register it with
+/// [`ProxyHandle::add_frozen_state_change_callback`](crate::proxy::ProxyHandle::add_frozen_state_change_callback)
+/// to learn when the process hosting a remote binder object is frozen or unfrozen.
+pub trait FrozenStateChangeCallback: Send + Sync {
+    /// Called with the current state after registration and on every change.
+    fn on_state_changed(&self, who: &WIBinder, state: FrozenState);
+}
+
 /// Core interface for binder objects, both local and remote.
 ///
 /// This trait corresponds to the public interface of the C++ `IBinder` class,
@@ -179,6 +215,19 @@ pub trait IBinder: Any + Send + Sync {
     /// Retrieve if this object is remote.
     fn is_remote(&self) -> bool;
 
+    /// Retrieve the stability of this object.
+    ///
+    /// A local object of [`Stability::Local`] takes the stability of the
+    /// process which sends it. A remote object has the stability its sender
+    /// gave it, and can only be called if it is stable enough for the calling
+    /// process, see [`Stability::check`].
+    fn stability(&self) -> Stability;
+
+    /// Retrieve the extension object of this binder, if it has one.
+    ///
+    /// See [`Binder::set_extension`](crate::native::Binder::set_extension).
+    fn get_extension(&self) -> Result<Option<SIBinder>>;
+
     fn inc_strong(&self, strong: &SIBinder) -> Result<()>;
     fn attempt_inc_strong(&self) -> bool;
     fn dec_strong(&self, strong: Option<ManuallyDrop<SIBinder>>) -> Result<()>;
@@ -229,6 +278,18 @@ pub trait Remotable: Send + Sync {
     /// Handle a request to invoke the dump transaction on this
     /// object.
     fn on_dump(&self, writer: &mut dyn std::io::Write, args: &[String]) -> Result<()>;
+
+    /// Handle a request to invoke the shell command transaction on this
+    /// object, and return the exit code of the command.
+    fn on_shell_command(
+        &self,
+        _input: &mut dyn std::io::Read,
+        _output: &mut dyn std::io::Write,
+        _error: &mut dyn std::io::Write,
+        _args: &[String],
+    ) -> Result<i32> {
+        Err(StatusCode::InvalidOperation)
+    }
 }
 
 /// A transactable object that can be used to process Binder commands.
@@ -273,14 +334,22 @@ where
 /// Interface stability promise
 ///
 /// An interface can promise to be a stable vendor interface ([`Stability::Vintf`]), or
-/// makes no stability guarantees ([`Stability::Local`]). [`Stability::Local`] is
-/// currently the default stability.
+/// makes no stability guarantees ([`Stability::Local`]). Binder objects are
+/// created with [`Stability::Local`] unless they declare a stability, and are
+/// sent with the stability of their process. The `Default` of the type is
+/// [`Stability::System`].
+///
+/// A process is either on the system or on the vendor partition, see
+/// [`ProcessStateBuilder::stability`](crate::ProcessStateBuilder::stability), and
+/// only calls the remote objects which are stable on its partition.
 #[derive(Default, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Stability {
     /// Default stability, visible to other modules in the same compilation
     /// context (e.g. modules on system.img)
     Local,
+    /// Stable on the vendor partition
     Vendor,
+    /// Stable on the system partition
     #[default]
     System,
 
@@ -297,14 +366,7 @@ pub enum Stability {
 // http://aospxref.com/android-14.0.0_r2/xref/frameworks/native/libs/binder/include/binder/Stability.h
 impl From<Stability> for i32 {
     fn from(stability: Stability) -> i32 {
-        use Stability::*;
-
-        let stability = match stability {
-            Local => 0,
-            Vendor => 0b000011,
-            System => 0b001100,
-            Vintf => 0b111111,
-        };
+        let stability = stability.level();
 
         #[cfg(target_os = "android")]
         {
@@ -320,6 +382,24 @@ impl From<Stability> for i32 {
     }
 }
 
+impl Stability {
+    // The bits of the stability level, without the category of Android 12.
+    fn level(self) -> i32 {
+        match self {
+            Stability::Local => 0,
+            Stability::Vendor => 0b000011,
+            Stability::System => 0b001100,
+            Stability::Vintf => 0b111111,
+        }
+    }
+
+    /// Whether an object of this stability can be used where `required` is
+    /// expected, e.g. a [`Stability::Vintf`] object by a vendor process.
+    pub fn check(self, required: Stability) -> bool {
+        self.level() & required.level() == required.level()
+    }
+}
+
 impl TryFrom<i32> for Stability {
     type Error = StatusCode;
     fn try_from(stability: i32) -> Result<Stability> {
@@ -331,8 +411,7 @@ impl TryFrom<i32> for Stability {
             stability if stability == Vintf.into() => Ok(Vintf),
             _ => {
                 log::error!("Stability value is invalid: {stability:X}");
-                // Err(StatusCode::BadValue)
-                Ok(Local)
+                Err(StatusCode::BadValue)
             }
         }
     }
@@ -370,10 +449,6 @@ impl SIBinder {
         WIBinder::new_with_inner(Arc::clone(&this.inner))
     }
 
-    // pub fn stability(&self) -> Stability {
-    //     self.inner.data.stability()
-    // }
-
     pub(crate) fn increase(&self) -> Result<()> {
         self.inner.inc_strong(self)
     }
@@ -515,6 +590,18 @@ impl<I: FromIBinder + ?Sized> Strong<I> {
         Weak::new(this)
     }
 
+    /// Retrieve the extension object of this binder as the interface `E`.
+    ///
+    /// Returns `None` if the binder has no extension. If the extension doesn't
+    /// implement `E`, the error `StatusCode::BadType` is returned.
+    pub fn get_extension<E: FromIBinder + ?Sized>(&self) -> Result<Option<Strong<E>>> {
+        self.0
+            .as_binder()
+            .get_extension()?
+            .map(FromIBinder::try_from)
+            .transpose()
+    }
+
     /// Convert this synchronous binder handle into an asynchronous one.
     pub fn into_async<P>(self) -> Strong<<I as ToAsyncInterface<P>>::Target>
     where
@@ -676,5 +763,13 @@ mod tests {
             Stability::try_from(0b1111111).unwrap_err(),
             StatusCode::BadValue
         );
+
+        assert!(Stability::Vintf.check(Stability::Vendor));
+        assert!(Stability::Vintf.check(Stability::System));
+        assert!(Stability::System.check(Stability::System));
+        assert!(!Stability::System.check(Stability::Vendor));
+        assert!(!Stability::Vendor.check(Stability::System));
+        assert!(!Stability::Local.check(Stability::System));
+        assert!(Stability::Local.check(Stability::Local));
     }
 }
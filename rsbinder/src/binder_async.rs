@@ -49,7 +49,7 @@ pub trait BinderAsyncPool {
     /// and immediately return an error.
     ///
     /// The only difference between different implementations should be which
-    /// `spawn_thread` method is used. For Tokio, it would be `tokio::task::spawn_blocking`.
+    /// `spawn_thread` method is used. For Tokio, it is [`ClientPool::spawn`](crate::ClientPool::spawn).
     ///
     /// This method has the design it has because the only way to define a trait that
     /// allows the return type of the spawn to be chosen by the caller is to return a
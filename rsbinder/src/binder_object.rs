@@ -6,7 +6,7 @@ use std::mem::ManuallyDrop;
 use rustix::fd::{BorrowedFd, FromRawFd, OwnedFd};
 
 pub(crate) use crate::sys::binder::flat_binder_object;
-use crate::{binder::*, error::*, process_state, sys::*};
+use crate::{binder::*, error::*, process_state::ProcessState, sys::*, thread_state};
 
 impl Default for flat_binder_object {
     /// Creates a new flat_binder_object with safe default values.
@@ -83,7 +83,8 @@ impl flat_binder_object {
         self.cookie = cookie;
     }
 
-    pub(crate) fn acquire(&self) -> Result<()> {
+    // `process` is the context of the handle, None for the context of the calling thread.
+    pub(crate) fn acquire(&self, process: Option<&'static ProcessState>) -> Result<()> {
         match self.hdr.type_ {
             BINDER_TYPE_BINDER => {
                 if self.pointer() != 0 {
@@ -93,13 +94,15 @@ impl flat_binder_object {
 
                 Ok(())
             }
-            BINDER_TYPE_HANDLE => process_state::ProcessState::as_self()
+            BINDER_TYPE_HANDLE => process
+                .unwrap_or_else(thread_state::current_process)
                 .strong_proxy_for_handle(self.handle())?
                 .increase(),
             BINDER_TYPE_FD => {
                 // Notion to do.
                 Ok(())
             }
+            BINDER_TYPE_PTR => Ok(()),
             _ => {
                 log::error!("Invalid object type {:08x}", self.hdr.type_);
                 Err(StatusCode::InvalidOperation)
@@ -107,7 +110,7 @@ impl flat_binder_object {
         }
     }
 
-    pub(crate) fn release(&self) -> Result<()> {
+    pub(crate) fn release(&self, process: Option<&'static ProcessState>) -> Result<()> {
         match self.hdr.type_ {
             BINDER_TYPE_BINDER => {
                 if self.pointer() != 0 {
@@ -116,7 +119,8 @@ impl flat_binder_object {
                 }
                 Ok(())
             }
-            BINDER_TYPE_HANDLE => process_state::ProcessState::as_self()
+            BINDER_TYPE_HANDLE => process
+                .unwrap_or_else(thread_state::current_process)
                 .strong_proxy_for_handle(self.handle())?
                 .decrease(),
             BINDER_TYPE_FD => {
@@ -127,6 +131,8 @@ impl flat_binder_object {
 
                 Ok(())
             }
+            // The memory of a buffer object is owned by the parcel.
+            BINDER_TYPE_PTR => Ok(()),
             _ => {
                 log::error!("Invalid object type {:08x}", self.hdr.type_);
                 Err(StatusCode::InvalidOperation)
@@ -151,10 +157,10 @@ fn sched_policy_mask(policy: u32, priority: u32) -> u32 {
         | ((policy & 3) << FLAT_BINDER_FLAG_SCHED_POLICY_SHIFT)
 }
 
-impl From<&SIBinder> for flat_binder_object {
-    fn from(binder: &SIBinder) -> Self {
-        let sched_bits = if !process_state::ProcessState::as_self().background_scheduling_disabled()
-        {
+impl flat_binder_object {
+    /// Flatten `binder` to be sent over the driver of `process`.
+    pub(crate) fn from_binder(binder: &SIBinder, process: &ProcessState) -> Self {
+        let sched_bits = if !process.background_scheduling_disabled() {
             sched_policy_mask(SCHED_NORMAL, 19)
         } else {
             0
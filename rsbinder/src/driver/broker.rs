@@ -0,0 +1,968 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+//! Sharing a [`MemoryDevice`](super::MemoryDevice) with forked processes.
+//!
+//! The process which creates the device runs a broker thread, which reads
+//! requests from a control socket. A forked process attaches to the device by
+//! sending it one end of a new socket pair: the broker attaches a process to
+//! the device, and releases it when the other end is closed, e.g. because the
+//! forked process exits. Each thread of the forked process then sends its
+//! calls over a connection of its own, which a broker thread serves with its
+//! own thread id, so that the device tells the threads apart as usual.
+//!
+//! The memory of the transactions is copied over the connection: the data,
+//! the offsets and the buffers of scatter-gather objects go to the broker,
+//! which points the transaction at its copies, and the transaction buffers
+//! received by the forked process come back to it, with their pointers
+//! relocated to the copies. File descriptors travel as `SCM_RIGHTS`.
+
+use std::collections::HashMap;
+use std::io::{IoSlice, IoSliceMut};
+use std::mem::{size_of, MaybeUninit};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
+use std::sync::{Arc, Mutex};
+use std::thread::ThreadId;
+
+use rustix::io::Errno;
+use rustix::net::{
+    AddressFamily, RecvAncillaryBuffer, RecvAncillaryMessage, RecvFlags, SendAncillaryBuffer,
+    SendAncillaryMessage, SendFlags, SocketFlags, SocketType,
+};
+
+use super::memory::{object_size, Attachment, ProcId, Shared};
+use super::{BinderDriver, DriverFeature, Result};
+use crate::sys::binder::*;
+
+// Requests on the control socket, which pass the broker's end of a connection.
+const OPEN: u32 = 1;
+const ATTACH: u32 = 2;
+
+// Requests on the connection of a thread.
+const WRITE_READ: u32 = 1;
+const SET_MAX_THREADS: u32 = 2;
+const ENABLE_ONEWAY_SPAM_DETECTION: u32 = 3;
+const BECOME_CONTEXT_MANAGER: u32 = 4;
+const STRONG_REF_COUNT_FOR_HANDLE: u32 = 5;
+const FREEZE: u32 = 6;
+const FROZEN_INFO: u32 = 7;
+const EXTENDED_ERROR: u32 = 8;
+const WAKE_THREADS: u32 = 9;
+
+// SCM_MAX_FD, the most descriptors the kernel passes in one message.
+const MAX_FDS: usize = 253;
+
+// A message is a header of its code, the number of its descriptors and the
+// length of its body, followed by the body.
+const HEADER_SIZE: usize = 16;
+
+// A received message: its code, its body and its descriptors.
+type Message = (u32, Vec<u8>, Vec<OwnedFd>);
+
+// The size of the argument of a BC_* or BR_* command, which is encoded in the
+// command like in an ioctl number.
+fn command_size(cmd: u32) -> usize {
+    ((cmd >> 16) & 0x3fff) as usize
+}
+
+#[derive(Default)]
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn put<T: Copy>(&mut self, value: T) {
+        let bytes =
+            unsafe { std::slice::from_raw_parts(&value as *const T as *const u8, size_of::<T>()) };
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn put_bytes(&mut self, bytes: &[u8]) {
+        self.put(bytes.len() as u64);
+        self.0.extend_from_slice(bytes);
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take<T: Copy>(&mut self) -> Result<T> {
+        if self.0.len() < size_of::<T>() {
+            return Err(Errno::PROTO);
+        }
+        let value = unsafe { std::ptr::read_unaligned(self.0.as_ptr() as *const T) };
+        self.0 = &self.0[size_of::<T>()..];
+        Ok(value)
+    }
+
+    fn take_bytes(&mut self) -> Result<&'a [u8]> {
+        let len = usize::try_from(self.take::<u64>()?).map_err(|_| Errno::PROTO)?;
+        if self.0.len() < len {
+            return Err(Errno::PROTO);
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+}
+
+fn read_at<T: Copy>(bytes: &[u8], offset: usize) -> T {
+    assert!(offset + size_of::<T>() <= bytes.len());
+    unsafe { std::ptr::read_unaligned(bytes.as_ptr().add(offset) as *const T) }
+}
+
+fn write_at<T: Copy>(bytes: &mut [u8], offset: usize, value: T) {
+    assert!(offset + size_of::<T>() <= bytes.len());
+    unsafe { std::ptr::write_unaligned(bytes.as_mut_ptr().add(offset) as *mut T, value) }
+}
+
+// A copy of `bytes` in memory aligned like the buffers of the device.
+fn aligned_copy(bytes: &[u8]) -> Box<[u64]> {
+    let mut memory = vec![0u64; bytes.len().div_ceil(8).max(1)].into_boxed_slice();
+    unsafe {
+        std::ptr::copy_nonoverlapping(bytes.as_ptr(), memory.as_mut_ptr() as *mut u8, bytes.len())
+    };
+    memory
+}
+
+fn as_bytes_mut(memory: &mut [u64], len: usize) -> &mut [u8] {
+    unsafe { std::slice::from_raw_parts_mut(memory.as_mut_ptr() as *mut u8, len) }
+}
+
+// The memory at `ptr`, which may be dangling if `len` is 0.
+unsafe fn memory<'a>(ptr: binder_uintptr_t, len: usize) -> &'a [u8] {
+    if len == 0 {
+        return &[];
+    }
+    std::slice::from_raw_parts(ptr as *const u8, len)
+}
+
+// An object of a transaction which refers to memory or to file descriptors.
+#[derive(Clone, Copy)]
+enum Object {
+    // The buffer object with index `index`, at `offset` of the data.
+    Buffer {
+        index: usize,
+        offset: usize,
+        object: binder_buffer_object,
+    },
+    // A file descriptor object at `offset` of the data.
+    Fd {
+        offset: usize,
+        object: flat_binder_object,
+    },
+    // `count` descriptors at `at` in the buffer of the buffer object with index `parent`.
+    FdArray {
+        parent: usize,
+        at: usize,
+        count: usize,
+    },
+}
+
+// The objects of the transaction data `data` with the object offsets `offsets`.
+// The broker relies on them to find the memory it copies, so objects the
+// device would reject fail the whole request.
+fn objects(data: &[u8], offsets: &[u8]) -> Result<Vec<Object>> {
+    if offsets.len() % size_of::<binder_size_t>() != 0 {
+        return Err(Errno::INVAL);
+    }
+
+    let mut objects = Vec::new();
+    let mut buffers: HashMap<usize, binder_buffer_object> = HashMap::new();
+    let mut min_offset = 0;
+    for index in 0..offsets.len() / size_of::<binder_size_t>() {
+        let offset = usize::try_from(read_at::<binder_size_t>(
+            offsets,
+            index * size_of::<binder_size_t>(),
+        ))
+        .map_err(|_| Errno::INVAL)?;
+        if offset < min_offset
+            || offset % size_of::<u32>() != 0
+            || offset.saturating_add(size_of::<binder_object_header>()) > data.len()
+        {
+            return Err(Errno::INVAL);
+        }
+        let hdr: binder_object_header = read_at(data, offset);
+        min_offset = offset + object_size(hdr.type_);
+        if min_offset > data.len() {
+            return Err(Errno::INVAL);
+        }
+
+        match hdr.type_ {
+            BINDER_TYPE_PTR => {
+                let object: binder_buffer_object = read_at(data, offset);
+                buffers.insert(index, object);
+                objects.push(Object::Buffer {
+                    index,
+                    offset,
+                    object,
+                });
+            }
+            BINDER_TYPE_FD => objects.push(Object::Fd {
+                offset,
+                object: read_at(data, offset),
+            }),
+            BINDER_TYPE_FDA => {
+                let fda: binder_fd_array_object = read_at(data, offset);
+                let parent = usize::try_from(fda.parent).map_err(|_| Errno::INVAL)?;
+                let at = usize::try_from(fda.parent_offset).map_err(|_| Errno::INVAL)?;
+                let count = usize::try_from(fda.num_fds).map_err(|_| Errno::INVAL)?;
+                let parent_length = buffers.get(&parent).ok_or(Errno::INVAL)?.length;
+                if at % size_of::<u32>() != 0
+                    || count
+                        .checked_mul(size_of::<u32>())
+                        .and_then(|size| size.checked_add(at))
+                        .map_or(true, |end| end as u64 > parent_length)
+                {
+                    return Err(Errno::INVAL);
+                }
+                objects.push(Object::FdArray { parent, at, count });
+            }
+            _ => {}
+        }
+    }
+    Ok(objects)
+}
+
+fn socket_pair(type_: SocketType) -> Result<(OwnedFd, OwnedFd)> {
+    rustix::net::socketpair(AddressFamily::UNIX, type_, SocketFlags::CLOEXEC, None)
+}
+
+fn send_all(socket: BorrowedFd<'_>, message: &[u8], fds: &[BorrowedFd<'_>]) -> Result<()> {
+    let mut space = vec![MaybeUninit::uninit(); SendAncillaryMessage::ScmRights(fds).size()];
+    let mut control = SendAncillaryBuffer::new(&mut space);
+    if !fds.is_empty() && !control.push(SendAncillaryMessage::ScmRights(fds)) {
+        return Err(Errno::INVAL);
+    }
+    let mut sent = loop {
+        match rustix::net::sendmsg(
+            socket,
+            &[IoSlice::new(message)],
+            &mut control,
+            SendFlags::NOSIGNAL,
+        ) {
+            Err(Errno::INTR) => continue,
+            res => break res?,
+        }
+    };
+    // The descriptors went with the first part of the message.
+    while sent < message.len() {
+        match rustix::net::send(socket, &message[sent..], SendFlags::NOSIGNAL) {
+            Ok(n) => sent += n,
+            Err(Errno::INTR) => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+// Receive a message of up to `buf.len()` bytes with its descriptors. Returns
+// the number of bytes received, 0 at the end of the stream.
+fn recv_some(socket: BorrowedFd<'_>, buf: &mut [u8], fds: &mut Vec<OwnedFd>) -> Result<usize> {
+    let mut space = vec![MaybeUninit::uninit(); rustix::cmsg_space!(ScmRights(MAX_FDS))];
+    let mut control = RecvAncillaryBuffer::new(&mut space);
+    let msg = loop {
+        match rustix::net::recvmsg(
+            socket,
+            &mut [IoSliceMut::new(buf)],
+            &mut control,
+            RecvFlags::CMSG_CLOEXEC,
+        ) {
+            Err(Errno::INTR) => continue,
+            res => break res?,
+        }
+    };
+    for message in control.drain() {
+        if let RecvAncillaryMessage::ScmRights(rights) = message {
+            fds.extend(rights);
+        }
+    }
+    Ok(msg.bytes)
+}
+
+fn recv_exact(socket: BorrowedFd<'_>, buf: &mut [u8]) -> Result<()> {
+    let mut pos = 0;
+    while pos < buf.len() {
+        match rustix::net::recv(socket, &mut buf[pos..], RecvFlags::empty()) {
+            Ok((0, _)) => return Err(Errno::PIPE),
+            Ok((n, _)) => pos += n,
+            Err(Errno::INTR) => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+fn send(socket: BorrowedFd<'_>, code: u32, body: &[u8], fds: &[BorrowedFd<'_>]) -> Result<()> {
+    let mut message = Writer::default();
+    message.put(code);
+    message.put(fds.len() as u32);
+    message.put(body.len() as u64);
+    message.0.extend_from_slice(body);
+    send_all(socket, &message.0, fds)
+}
+
+// Receive a message from a connection, None at the end of the stream.
+fn recv(socket: BorrowedFd<'_>) -> Result<Option<Message>> {
+    let mut header = [0u8; HEADER_SIZE];
+    let mut fds = Vec::new();
+    let received = recv_some(socket, &mut header, &mut fds)?;
+    if received == 0 {
+        return Ok(None);
+    }
+    recv_exact(socket, &mut header[received..])?;
+
+    let mut reader = Reader(&header);
+    let code: u32 = reader.take()?;
+    let count: u32 = reader.take()?;
+    let len = usize::try_from(reader.take::<u64>()?).map_err(|_| Errno::PROTO)?;
+    if fds.len() != count as usize {
+        return Err(Errno::PROTO);
+    }
+    let mut body = vec![0u8; len];
+    recv_exact(socket, &mut body)?;
+    Ok(Some((code, body, fds)))
+}
+
+// Control requests are single datagrams of the request, two arguments and the
+// broker's end of the connection.
+fn send_control(control: BorrowedFd<'_>, code: u32, args: [u64; 2], fd: OwnedFd) -> Result<()> {
+    let mut message = Writer::default();
+    message.put(code);
+    message.put(0u32);
+    message.put(args);
+    send_all(control, &message.0, &[fd.as_fd()])
+}
+
+/// The broker of a device, which serves the drivers opened in processes forked
+/// from the process which created the device.
+pub(super) struct Broker {
+    control: Arc<OwnedFd>,
+    owner: pid_t,
+}
+
+impl Broker {
+    pub(super) fn start(shared: Arc<Shared>) -> Self {
+        let (control, server) = socket_pair(SocketType::SEQPACKET)
+            .expect("Failed to create the control socket of the memory device");
+        std::thread::spawn(move || serve_control(shared, server));
+        Broker {
+            control: Arc::new(control),
+            owner: rustix::process::getpid().as_raw_nonzero().get(),
+        }
+    }
+
+    /// Whether this is the process which created the device.
+    pub(super) fn is_owner(&self) -> bool {
+        rustix::process::getpid().as_raw_nonzero().get() == self.owner
+    }
+
+    /// Attach a new process to the device through the broker. If the broker
+    /// can't be reached, every call of the driver fails with ECONNREFUSED.
+    pub(super) fn open(&self, pid: pid_t, uid: uid_t) -> Remote {
+        let connection = self.connect(pid, uid).map_err(|err| {
+            log::error!("Failed to attach to the memory device: {err}");
+        });
+        let (lifetime, proc) = match connection {
+            Ok((lifetime, proc)) => (Some(lifetime), proc),
+            Err(()) => (None, 0),
+        };
+        Remote {
+            control: self.control.clone(),
+            lifetime,
+            proc,
+            connections: Mutex::new(HashMap::new()),
+            buffers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn connect(&self, pid: pid_t, uid: uid_t) -> Result<(OwnedFd, ProcId)> {
+        let (lifetime, server) = socket_pair(SocketType::STREAM)?;
+        send_control(self.control.as_fd(), OPEN, [pid as u64, uid as u64], server)?;
+        let (_, body, _) = recv(lifetime.as_fd())?.ok_or(Errno::CONNREFUSED)?;
+        let proc = Reader(&body).take()?;
+        Ok((lifetime, proc))
+    }
+}
+
+fn serve_control(shared: Arc<Shared>, control: OwnedFd) {
+    loop {
+        let mut message = [0u8; 24];
+        let mut fds = Vec::new();
+        match recv_some(control.as_fd(), &mut message, &mut fds) {
+            // All the devices are dropped, in this process and in the forked ones.
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(err) => {
+                log::error!("Failed to receive from the memory device control socket: {err}");
+                break;
+            }
+        }
+        let mut reader = Reader(&message);
+        let code: u32 = reader.take().unwrap();
+        let _: u32 = reader.take().unwrap();
+        let [first, second]: [u64; 2] = reader.take().unwrap();
+        let (Some(connection), 1) = (fds.pop(), fds.len() + 1) else {
+            log::error!("Memory device control request {code} without a connection");
+            continue;
+        };
+
+        match code {
+            OPEN => {
+                let attachment = Attachment::new(&shared, first as pid_t, second as uid_t);
+                std::thread::spawn(move || watch(attachment, connection));
+            }
+            ATTACH => {
+                let attachment = Attachment::with_id(&shared, first);
+                std::thread::spawn(move || serve(attachment, connection));
+            }
+            _ => log::error!("Unknown memory device control request {code}"),
+        }
+    }
+}
+
+// Tell the process its id, and release it when its end of the connection is
+// closed, like the kernel driver does when /dev/binder is closed.
+fn watch(attachment: Attachment, connection: OwnedFd) {
+    let mut body = Writer::default();
+    body.put(attachment.id());
+    if send(connection.as_fd(), 0, &body.0, &[]).is_ok() {
+        let mut buf = [0u8; 1];
+        while let Ok(n) = recv_some(connection.as_fd(), &mut buf, &mut Vec::new()) {
+            if n == 0 {
+                break;
+            }
+        }
+    }
+    attachment.release();
+}
+
+// Serve the calls of a thread of a forked process.
+fn serve(attachment: Attachment, connection: OwnedFd) {
+    loop {
+        let (code, body, fds) = match recv(connection.as_fd()) {
+            Ok(Some(request)) => request,
+            Ok(None) => break,
+            Err(err) => {
+                log::error!("Failed to receive a memory device request: {err}");
+                break;
+            }
+        };
+
+        let mut reply = Writer::default();
+        let mut reply_fds = Vec::new();
+        let status = match handle(&attachment, code, &body, fds, &mut reply, &mut reply_fds) {
+            Ok(()) => 0,
+            Err(err) => err.raw_os_error() as u32,
+        };
+        let reply_fds: Vec<BorrowedFd<'_>> = reply_fds.iter().map(|fd| fd.as_fd()).collect();
+        if send(connection.as_fd(), status, &reply.0, &reply_fds).is_err() {
+            break;
+        }
+    }
+}
+
+fn handle(
+    attachment: &Attachment,
+    code: u32,
+    body: &[u8],
+    fds: Vec<OwnedFd>,
+    reply: &mut Writer,
+    reply_fds: &mut Vec<OwnedFd>,
+) -> Result<()> {
+    let mut reader = Reader(body);
+    match code {
+        WRITE_READ => write_read(attachment, &mut reader, fds, reply, reply_fds),
+        SET_MAX_THREADS => attachment.set_max_threads(reader.take()?),
+        ENABLE_ONEWAY_SPAM_DETECTION => {
+            attachment.enable_oneway_spam_detection(reader.take::<u32>()? != 0)
+        }
+        BECOME_CONTEXT_MANAGER => attachment.become_context_manager(false),
+        STRONG_REF_COUNT_FOR_HANDLE => {
+            let count = attachment.strong_ref_count_for_handle(reader.take()?)?;
+            reply.put(count as u64);
+            Ok(())
+        }
+        FREEZE => {
+            let [pid, enable, timeout_ms]: [u32; 3] = reader.take()?;
+            attachment.freeze(pid, enable != 0, timeout_ms)
+        }
+        FROZEN_INFO => {
+            reply.put(attachment.frozen_info(reader.take()?)?);
+            Ok(())
+        }
+        EXTENDED_ERROR => {
+            reply.put(attachment.extended_error()?);
+            Ok(())
+        }
+        WAKE_THREADS => attachment.wake_threads(),
+        _ => Err(Errno::INVAL),
+    }
+}
+
+// The memory a transaction of a forked process refers to, copied to the broker.
+#[derive(Default)]
+struct Copies {
+    memory: Vec<Box<[u64]>>,
+    fds: Vec<OwnedFd>,
+}
+
+impl Copies {
+    fn add(&mut self, bytes: &[u8]) -> *mut u8 {
+        let mut memory = aligned_copy(bytes);
+        let ptr = memory.as_mut_ptr() as *mut u8;
+        self.memory.push(memory);
+        ptr
+    }
+}
+
+// Point the transaction `tr` of a forked process at copies of its memory in
+// this process, and its file descriptors at the received ones.
+fn copy_transaction(
+    tr: &mut binder_transaction_data,
+    payload: &mut Reader<'_>,
+    fds: &mut impl Iterator<Item = OwnedFd>,
+    copies: &mut Copies,
+) -> Result<()> {
+    let (data, offsets) = (payload.take_bytes()?, payload.take_bytes()?);
+    if data.len() as u64 != tr.data_size || offsets.len() as u64 != tr.offsets_size {
+        return Err(Errno::PROTO);
+    }
+    let objects = objects(data, offsets)?;
+    let mut data_copy = aligned_copy(data);
+    let data_bytes = as_bytes_mut(&mut data_copy, data.len());
+
+    let mut buffers: HashMap<usize, *mut u8> = HashMap::new();
+    for object in objects {
+        match object {
+            Object::Buffer {
+                index,
+                offset,
+                mut object,
+            } => {
+                let buffer = payload.take_bytes()?;
+                if buffer.len() as u64 != object.length {
+                    return Err(Errno::PROTO);
+                }
+                let ptr = copies.add(buffer);
+                object.buffer = ptr as binder_uintptr_t;
+                write_at(data_bytes, offset, object);
+                buffers.insert(index, ptr);
+            }
+            Object::Fd { offset, mut object } => {
+                let fd = fds.next().ok_or(Errno::PROTO)?;
+                object.__bindgen_anon_1.handle = fd.as_raw_fd() as u32;
+                write_at(data_bytes, offset, object);
+                copies.fds.push(fd);
+            }
+            Object::FdArray { parent, at, count } => {
+                let array = unsafe { buffers[&parent].add(at) as *mut u32 };
+                for i in 0..count {
+                    let fd = fds.next().ok_or(Errno::PROTO)?;
+                    unsafe { std::ptr::write_unaligned(array.add(i), fd.as_raw_fd() as u32) };
+                    copies.fds.push(fd);
+                }
+            }
+        }
+    }
+
+    tr.data.ptr.buffer = data_copy.as_ptr() as binder_uintptr_t;
+    tr.data.ptr.offsets = copies.add(offsets) as binder_uintptr_t;
+    copies.memory.push(data_copy);
+    Ok(())
+}
+
+// Add the transaction buffer of `tr` received by a forked process and the
+// file descriptors in it to the reply. The process owns the descriptors, so
+// the copies of this process are closed once they are sent.
+fn send_buffer(
+    attachment: &Attachment,
+    tr: &binder_transaction_data,
+    reply: &mut Writer,
+    reply_fds: &mut Vec<OwnedFd>,
+) -> Result<()> {
+    let base = unsafe { tr.data.ptr.buffer };
+    let buffer = attachment.buffer(base)?;
+    let offsets_start = (unsafe { tr.data.ptr.offsets } - base) as usize;
+    let data = &buffer[..tr.data_size as usize];
+    let offsets = &buffer[offsets_start..offsets_start + tr.offsets_size as usize];
+
+    let mut buffers = HashMap::new();
+    for object in objects(data, offsets)? {
+        match object {
+            Object::Buffer { index, object, .. } => {
+                buffers.insert(index, (object.buffer - base) as usize);
+            }
+            Object::Fd { object, .. } => {
+                let fd = unsafe { object.__bindgen_anon_1.handle } as RawFd;
+                reply_fds.push(unsafe { OwnedFd::from_raw_fd(fd) });
+            }
+            Object::FdArray { parent, at, count } => {
+                for i in 0..count {
+                    let fd: u32 = read_at(&buffer, buffers[&parent] + at + i * size_of::<u32>());
+                    reply_fds.push(unsafe { OwnedFd::from_raw_fd(fd as RawFd) });
+                }
+            }
+        }
+    }
+    reply.put_bytes(&buffer);
+    Ok(())
+}
+
+fn write_read(
+    attachment: &Attachment,
+    request: &mut Reader<'_>,
+    fds: Vec<OwnedFd>,
+    reply: &mut Writer,
+    reply_fds: &mut Vec<OwnedFd>,
+) -> Result<()> {
+    let read_size = usize::try_from(request.take::<u64>()?).map_err(|_| Errno::PROTO)?;
+    let mut commands = request.take_bytes()?.to_vec();
+    let mut fds = fds.into_iter();
+    let mut copies = Copies::default();
+
+    let mut pos = 0;
+    while pos + size_of::<u32>() <= commands.len() {
+        let cmd: u32 = read_at(&commands, pos);
+        let arg = pos + size_of::<u32>();
+        pos = arg + command_size(cmd);
+        if pos > commands.len() {
+            break;
+        }
+        if matches!(
+            cmd,
+            BC_TRANSACTION | BC_REPLY | BC_TRANSACTION_SG | BC_REPLY_SG
+        ) {
+            let mut tr: binder_transaction_data = read_at(&commands, arg);
+            copy_transaction(&mut tr, request, &mut fds, &mut copies)?;
+            write_at(&mut commands, arg, tr);
+        }
+    }
+
+    let mut read = vec![0u64; read_size.div_ceil(8)];
+    let mut bwr = binder_write_read {
+        write_size: commands.len() as _,
+        write_consumed: 0,
+        write_buffer: commands.as_ptr() as _,
+        read_size: read_size as _,
+        read_consumed: 0,
+        read_buffer: read.as_mut_ptr() as _,
+    };
+    let res = attachment.write_read(&mut bwr);
+    drop(copies);
+
+    let read = &as_bytes_mut(&mut read, read_size)[..bwr.read_consumed as usize];
+    reply.put(bwr.write_consumed);
+    reply.put_bytes(read);
+    let mut pos = 0;
+    while pos + size_of::<u32>() <= read.len() {
+        let cmd: u32 = read_at(read, pos);
+        let arg = pos + size_of::<u32>();
+        if cmd == BR_TRANSACTION || cmd == BR_REPLY {
+            send_buffer(attachment, &read_at(read, arg), reply, reply_fds)?;
+        }
+        pos = arg + command_size(cmd);
+    }
+    res
+}
+
+/// A process attached to a device through the broker of another process.
+pub(super) struct Remote {
+    control: Arc<OwnedFd>,
+    // Closing it releases the process. None if the broker couldn't be reached.
+    lifetime: Option<OwnedFd>,
+    proc: ProcId,
+    // The connection of each thread which called the driver.
+    connections: Mutex<HashMap<ThreadId, Arc<OwnedFd>>>,
+    // The transaction buffers received by this process, by their address here.
+    buffers: Mutex<HashMap<binder_uintptr_t, Received>>,
+}
+
+// A copy of a transaction buffer in the broker.
+struct Received {
+    remote: binder_uintptr_t,
+    _memory: Box<[u64]>,
+}
+
+impl Remote {
+    fn connection(&self) -> Result<Arc<OwnedFd>> {
+        if self.lifetime.is_none() {
+            return Err(Errno::CONNREFUSED);
+        }
+        let tid = std::thread::current().id();
+        let mut connections = self.connections.lock().unwrap();
+        if let Some(connection) = connections.get(&tid) {
+            return Ok(connection.clone());
+        }
+
+        let (connection, server) = socket_pair(SocketType::STREAM)?;
+        send_control(self.control.as_fd(), ATTACH, [self.proc, 0], server)
+            .map_err(|_| Errno::CONNREFUSED)?;
+        let connection = Arc::new(connection);
+        connections.insert(tid, connection.clone());
+        Ok(connection)
+    }
+
+    // Send a request and wait for the reply: its status, its body and its descriptors.
+    fn call(&self, code: u32, body: &[u8], fds: &[BorrowedFd<'_>]) -> Result<Message> {
+        let connection = self.connection()?;
+        // The thread's connection is gone with the broker.
+        let disconnected = |err: Errno| {
+            log::error!("Lost the connection to the memory device: {err}");
+            Errno::CONNREFUSED
+        };
+        send(connection.as_fd(), code, body, fds).map_err(disconnected)?;
+        recv(connection.as_fd())
+            .map_err(disconnected)?
+            .ok_or_else(|| disconnected(Errno::PIPE))
+    }
+
+    fn call_simple(&self, code: u32, body: &[u8]) -> Result<Vec<u8>> {
+        let (status, body, _) = self.call(code, body, &[])?;
+        match status {
+            0 => Ok(body),
+            errno => Err(Errno::from_raw_os_error(errno as i32)),
+        }
+    }
+
+    // Add the memory of the transaction `tr` and its descriptors to the request.
+    unsafe fn put_transaction<'a>(
+        &self,
+        tr: &binder_transaction_data,
+        payload: &mut Writer,
+        fds: &mut Vec<BorrowedFd<'a>>,
+    ) -> Result<()> {
+        let data = memory(tr.data.ptr.buffer, tr.data_size as usize);
+        let offsets = memory(tr.data.ptr.offsets, tr.offsets_size as usize);
+        payload.put_bytes(data);
+        payload.put_bytes(offsets);
+
+        let mut buffers = HashMap::new();
+        for object in objects(data, offsets)? {
+            match object {
+                Object::Buffer { index, object, .. } => {
+                    payload.put_bytes(memory(object.buffer, object.length as usize));
+                    buffers.insert(index, object.buffer);
+                }
+                Object::Fd { object, .. } => {
+                    fds.push(BorrowedFd::borrow_raw(
+                        object.__bindgen_anon_1.handle as RawFd,
+                    ));
+                }
+                Object::FdArray { parent, at, count } => {
+                    let array = (buffers[&parent] as *const u8).add(at) as *const u32;
+                    for i in 0..count {
+                        let fd = std::ptr::read_unaligned(array.add(i));
+                        fds.push(BorrowedFd::borrow_raw(fd as RawFd));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Keep a copy of a transaction buffer received from the broker, and point
+    // `tr` at it.
+    fn receive_buffer(
+        &self,
+        tr: &mut binder_transaction_data,
+        buffer: &[u8],
+        fds: &mut impl Iterator<Item = OwnedFd>,
+    ) -> Result<()> {
+        let remote = unsafe { tr.data.ptr.buffer };
+        let mut copy = aligned_copy(buffer);
+        let local = copy.as_ptr() as binder_uintptr_t;
+        let bytes = as_bytes_mut(&mut copy, buffer.len());
+        let relocate = |addr: binder_uintptr_t| match addr.checked_sub(remote) {
+            Some(offset) if offset < buffer.len() as u64 => local + offset,
+            _ => addr,
+        };
+
+        let offsets_start = (unsafe { tr.data.ptr.offsets } - remote) as usize;
+        let data_size = tr.data_size as usize;
+        let offsets_end = offsets_start + tr.offsets_size as usize;
+        if data_size > buffer.len() || offsets_end > buffer.len() {
+            return Err(Errno::PROTO);
+        }
+        let objects = objects(&buffer[..data_size], &buffer[offsets_start..offsets_end])?;
+
+        let mut buffers = HashMap::new();
+        for object in objects {
+            match object {
+                Object::Buffer {
+                    index,
+                    offset,
+                    mut object,
+                } => {
+                    object.buffer = relocate(object.buffer);
+                    write_at(bytes, offset, object);
+                    let start = (object.buffer - local) as usize;
+                    buffers.insert(index, start);
+                    // The pointer to the buffer in its parent.
+                    if object.flags & BINDER_BUFFER_FLAG_HAS_PARENT != 0 {
+                        let parent = buffers.get(&(object.parent as usize)).ok_or(Errno::PROTO)?;
+                        let at = parent + object.parent_offset as usize;
+                        if at + size_of::<binder_uintptr_t>() > bytes.len() {
+                            return Err(Errno::PROTO);
+                        }
+                        let ptr: binder_uintptr_t = read_at(bytes, at);
+                        write_at(bytes, at, relocate(ptr));
+                    }
+                }
+                Object::Fd { offset, mut object } => {
+                    let fd = fds.next().ok_or(Errno::PROTO)?;
+                    object.__bindgen_anon_1.handle = fd.into_raw_fd() as u32;
+                    write_at(bytes, offset, object);
+                }
+                Object::FdArray { parent, at, count } => {
+                    for i in 0..count {
+                        let fd = fds.next().ok_or(Errno::PROTO)?;
+                        let at = buffers[&parent] + at + i * size_of::<u32>();
+                        write_at(bytes, at, fd.into_raw_fd() as u32);
+                    }
+                }
+            }
+        }
+
+        tr.data.ptr.buffer = local;
+        tr.data.ptr.offsets = relocate(unsafe { tr.data.ptr.offsets });
+        self.buffers.lock().unwrap().insert(
+            local,
+            Received {
+                remote,
+                _memory: copy,
+            },
+        );
+        Ok(())
+    }
+}
+
+impl BinderDriver for Remote {
+    fn name(&self) -> &str {
+        "memory"
+    }
+
+    fn write_read(&self, bwr: &mut binder_write_read) -> Result<()> {
+        let write = unsafe {
+            memory(
+                bwr.write_buffer + bwr.write_consumed,
+                (bwr.write_size - bwr.write_consumed) as usize,
+            )
+        };
+        let mut commands = write.to_vec();
+        let mut payload = Writer::default();
+        let mut fds = Vec::new();
+
+        let mut pos = 0;
+        while pos + size_of::<u32>() <= commands.len() {
+            let cmd: u32 = read_at(&commands, pos);
+            let arg = pos + size_of::<u32>();
+            pos = arg + command_size(cmd);
+            if pos > commands.len() {
+                break;
+            }
+            match cmd {
+                BC_TRANSACTION | BC_REPLY | BC_TRANSACTION_SG | BC_REPLY_SG => unsafe {
+                    self.put_transaction(&read_at(&commands, arg), &mut payload, &mut fds)?;
+                },
+                BC_FREE_BUFFER => {
+                    let addr: binder_uintptr_t = read_at(&commands, arg);
+                    if let Some(received) = self.buffers.lock().unwrap().remove(&addr) {
+                        write_at(&mut commands, arg, received.remote);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let read_size = bwr.read_size - bwr.read_consumed;
+        let mut request = Writer::default();
+        request.put(read_size);
+        request.put_bytes(&commands);
+        request.0.extend_from_slice(&payload.0);
+        let (status, body, fds) = self.call(WRITE_READ, &request.0, &fds)?;
+
+        let mut reply = Reader(&body);
+        if !body.is_empty() {
+            let write_consumed: binder_size_t = reply.take()?;
+            let read = reply.take_bytes()?;
+            if write_consumed as usize > write.len() || read.len() as u64 > read_size {
+                return Err(Errno::PROTO);
+            }
+            bwr.write_consumed += write_consumed;
+
+            let mut read = read.to_vec();
+            let mut fds = fds.into_iter();
+            let mut pos = 0;
+            while pos + size_of::<u32>() <= read.len() {
+                let cmd: u32 = read_at(&read, pos);
+                let arg = pos + size_of::<u32>();
+                if cmd == BR_TRANSACTION || cmd == BR_REPLY {
+                    let mut tr: binder_transaction_data = read_at(&read, arg);
+                    self.receive_buffer(&mut tr, reply.take_bytes()?, &mut fds)?;
+                    write_at(&mut read, arg, tr);
+                }
+                pos = arg + command_size(cmd);
+            }
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    read.as_ptr(),
+                    (bwr.read_buffer + bwr.read_consumed) as *mut u8,
+                    read.len(),
+                )
+            };
+            bwr.read_consumed += read.len() as binder_size_t;
+        }
+
+        match status {
+            0 => Ok(()),
+            errno => Err(Errno::from_raw_os_error(errno as i32)),
+        }
+    }
+
+    fn set_max_threads(&self, max_threads: u32) -> Result<()> {
+        let mut body = Writer::default();
+        body.put(max_threads);
+        self.call_simple(SET_MAX_THREADS, &body.0).map(|_| ())
+    }
+
+    fn enable_oneway_spam_detection(&self, enable: bool) -> Result<()> {
+        let mut body = Writer::default();
+        body.put(enable as u32);
+        self.call_simple(ENABLE_ONEWAY_SPAM_DETECTION, &body.0)
+            .map(|_| ())
+    }
+
+    fn become_context_manager(&self, _security_context: bool) -> Result<()> {
+        self.call_simple(BECOME_CONTEXT_MANAGER, &[]).map(|_| ())
+    }
+
+    fn strong_ref_count_for_handle(&self, handle: u32) -> Result<usize> {
+        let mut body = Writer::default();
+        body.put(handle);
+        let reply = self.call_simple(STRONG_REF_COUNT_FOR_HANDLE, &body.0)?;
+        Ok(Reader(&reply).take::<u64>()? as usize)
+    }
+
+    fn freeze(&self, pid: u32, enable: bool, timeout_ms: u32) -> Result<()> {
+        let mut body = Writer::default();
+        body.put([pid, enable as u32, timeout_ms]);
+        self.call_simple(FREEZE, &body.0).map(|_| ())
+    }
+
+    fn frozen_info(&self, pid: u32) -> Result<binder_frozen_status_info> {
+        let mut body = Writer::default();
+        body.put(pid);
+        Reader(&self.call_simple(FROZEN_INFO, &body.0)?).take()
+    }
+
+    fn extended_error(&self) -> Result<binder_extended_error> {
+        Reader(&self.call_simple(EXTENDED_ERROR, &[])?).take()
+    }
+
+    fn is_feature_enabled(&self, _feature: DriverFeature) -> bool {
+        true
+    }
+
+    fn wake_threads(&self) -> Result<()> {
+        self.call_simple(WAKE_THREADS, &[]).map(|_| ())
+    }
+}
@@ -0,0 +1,177 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fs::File;
+use std::os::raw::c_void;
+use std::path::Path;
+
+use super::{BinderDriver, DriverFeature, Result};
+use crate::sys::binder;
+
+/// The Linux/Android kernel binder driver.
+pub struct KernelDriver {
+    name: String,
+    file: File,
+    mmap: (*mut c_void, usize),
+}
+
+// The mapping is only handed to the kernel and unmapped on drop.
+unsafe impl Sync for KernelDriver {}
+unsafe impl Send for KernelDriver {}
+
+impl KernelDriver {
+    /// Open the binder device at `path`, check the protocol version and map
+    /// the receive buffer.
+    pub fn open(path: &str) -> std::result::Result<Self, Box<dyn std::error::Error>> {
+        Self::open_with_vm_size(path, super::default_vm_size())
+    }
+
+    /// Same as [`KernelDriver::open`], but maps a receive buffer of `vm_size` bytes.
+    /// Incoming transactions, including pending oneway ones, must fit into it.
+    pub fn open_with_vm_size(
+        path: &str,
+        vm_size: usize,
+    ) -> std::result::Result<Self, Box<dyn std::error::Error>> {
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .open(Path::new(path))
+            .map_err(|e| format!("Opening '{path}' failed: {e}\n"))?;
+
+        let mut vers = binder::binder_version {
+            protocol_version: 0,
+        };
+
+        binder::version(&file, &mut vers)
+            .map_err(|e| format!("Binder ioctl to obtain version failed: {e}"))?;
+        log::info!("Binder driver protocol version: {}", vers.protocol_version);
+
+        if vers.protocol_version != binder::BINDER_CURRENT_PROTOCOL_VERSION as i32 {
+            return Err(format!(
+                "Binder driver protocol({}) does not match user space protocol({})!",
+                vers.protocol_version,
+                binder::BINDER_CURRENT_PROTOCOL_VERSION
+            )
+            .into());
+        }
+
+        let vm_start = unsafe {
+            rustix::mm::mmap(
+                std::ptr::null_mut(),
+                vm_size,
+                rustix::mm::ProtFlags::READ,
+                rustix::mm::MapFlags::PRIVATE | rustix::mm::MapFlags::NORESERVE,
+                &file,
+                0,
+            )?
+        };
+
+        Ok(KernelDriver {
+            name: path.to_owned(),
+            file,
+            mmap: (vm_start, vm_size),
+        })
+    }
+}
+
+impl BinderDriver for KernelDriver {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn write_read(&self, bwr: &mut binder::binder_write_read) -> Result<()> {
+        binder::write_read(&self.file, bwr)
+    }
+
+    fn set_max_threads(&self, max_threads: u32) -> Result<()> {
+        binder::set_max_threads(&self.file, max_threads)
+    }
+
+    fn enable_oneway_spam_detection(&self, enable: bool) -> Result<()> {
+        binder::enable_oneway_spam_detection(&self.file, enable as _)
+    }
+
+    fn become_context_manager(&self, security_context: bool) -> Result<()> {
+        let mut flags = binder::FLAT_BINDER_FLAG_ACCEPTS_FDS;
+        if security_context {
+            flags |= binder::FLAT_BINDER_FLAG_TXN_SECURITY_CTX;
+        }
+        let obj = binder::flat_binder_object::new_binder_with_flags(flags);
+
+        if binder::set_context_mgr_ext(&self.file, obj).is_err() {
+            //     android_errorWriteLog(0x534e4554, "121035042");
+            binder::set_context_mgr(&self.file, 0)?;
+        }
+        Ok(())
+    }
+
+    fn strong_ref_count_for_handle(&self, handle: u32) -> Result<usize> {
+        let mut info = binder::binder_node_info_for_ref {
+            handle,
+            strong_count: 0,
+            weak_count: 0,
+            reserved1: 0,
+            reserved2: 0,
+            reserved3: 0,
+        };
+
+        binder::get_node_info_for_ref(&self.file, &mut info)?;
+        Ok(info.strong_count as usize)
+    }
+
+    fn freeze(&self, pid: u32, enable: bool, timeout_ms: u32) -> Result<()> {
+        let info = binder::binder_freeze_info {
+            pid,
+            enable: enable as _,
+            timeout_ms,
+        };
+        binder::freeze(&self.file, info)
+    }
+
+    fn frozen_info(&self, pid: u32) -> Result<binder::binder_frozen_status_info> {
+        let mut info = binder::binder_frozen_status_info {
+            pid,
+            sync_recv: 0,
+            async_recv: 0,
+        };
+        binder::get_frozen_info(&self.file, &mut info)?;
+        Ok(info)
+    }
+
+    fn extended_error(&self) -> Result<binder::binder_extended_error> {
+        let mut error = binder::binder_extended_error {
+            id: 0,
+            command: 0,
+            param: 0,
+        };
+        binder::get_extended_error(&self.file, &mut error)?;
+        Ok(error)
+    }
+
+    fn is_feature_enabled(&self, feature: DriverFeature) -> bool {
+        // The features directory is next to the device in binderfs, e.g.
+        // /dev/binderfs/features/freeze_notification for /dev/binderfs/binder.
+        let Some(binderfs) = std::fs::canonicalize(&self.name)
+            .ok()
+            .and_then(|path| path.parent().map(Path::to_path_buf))
+        else {
+            return false;
+        };
+        std::fs::read_to_string(binderfs.join("features").join(feature.name()))
+            .is_ok_and(|value| value.trim() == "1")
+    }
+
+    fn wake_threads(&self) -> Result<()> {
+        // Closing any file of the device flushes it, which wakes up its waiting threads.
+        drop(rustix::io::fcntl_dupfd_cloexec(&self.file, 0)?);
+        Ok(())
+    }
+}
+
+impl Drop for KernelDriver {
+    fn drop(&mut self) {
+        unsafe {
+            rustix::mm::munmap(self.mmap.0, self.mmap.1).expect("Failed to unmap memory");
+        }
+    }
+}
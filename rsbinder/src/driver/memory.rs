@@ -0,0 +1,2614 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::mem::size_of;
+use std::os::fd::{AsRawFd, BorrowedFd, IntoRawFd, OwnedFd};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::ThreadId;
+use std::time::{Duration, Instant};
+
+use rustix::io::Errno;
+
+use super::{broker, BinderDriver, DriverFeature, Result};
+use crate::sys::binder::*;
+
+pub(super) type ProcId = u64;
+type NodeId = u64;
+type TxnId = u64;
+
+const SPAM_DETECTION_THRESHOLD_PERCENT: usize = 80;
+
+/// A binder device implemented entirely in user space.
+///
+/// Every [`MemoryDriver`] opened from the same device behaves like a separate
+/// process attached to the same binder context: handles, reference counts,
+/// death notifications and transaction buffers are tracked per driver, and
+/// file descriptors are duplicated on the way through. Freezing a pid holds the
+/// oneway transactions to its drivers until it is unfrozen and rejects
+/// synchronous ones, as the kernel driver does for a frozen process.
+///
+/// The device lives in the memory of the process which created it. Processes
+/// forked from it after [`MemoryDevice::new`] share the device as well: a driver
+/// opened in a forked process sends its commands over a Unix domain socket to
+/// a broker thread of the creating process, which copies the transaction
+/// buffers and passes file descriptors between the processes. The driver is
+/// released when its process exits, so the nodes of a process which dies are
+/// reported to their death recipients as they would be by the kernel driver.
+/// Unlike the kernel driver, a process may transact with its own nodes, which
+/// allows the context manager, services and clients to live in one test binary.
+#[derive(Clone)]
+pub struct MemoryDevice {
+    shared: Arc<Shared>,
+    broker: Arc<broker::Broker>,
+}
+
+impl std::fmt::Debug for MemoryDevice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MemoryDevice").finish()
+    }
+}
+
+impl Default for MemoryDevice {
+    fn default() -> Self {
+        let shared = Arc::new(Shared::default());
+        MemoryDevice {
+            broker: Arc::new(broker::Broker::start(shared.clone())),
+            shared,
+        }
+    }
+}
+
+impl MemoryDevice {
+    /// Create an empty device without a context manager.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Attach a new process to the device, using the credentials of the calling process.
+    pub fn open(&self) -> MemoryDriver {
+        self.open_with_credentials(
+            rustix::process::getpid().as_raw_nonzero().get(),
+            rustix::process::getuid().as_raw(),
+        )
+    }
+
+    /// Attach a new process to the device which reports `pid` and `uid`
+    /// as the sender credentials of its transactions.
+    pub fn open_with_credentials(&self, pid: pid_t, uid: uid_t) -> MemoryDriver {
+        let inner = if self.broker.is_owner() {
+            Inner::Local(self.attach(pid, uid))
+        } else {
+            Inner::Remote(self.broker.open(pid, uid))
+        };
+        MemoryDriver { inner }
+    }
+
+    // A driver which goes through the broker even in the creating process.
+    #[cfg(test)]
+    fn open_remote(&self, pid: pid_t, uid: uid_t) -> MemoryDriver {
+        MemoryDriver {
+            inner: Inner::Remote(self.broker.open(pid, uid)),
+        }
+    }
+
+    fn attach(&self, pid: pid_t, uid: uid_t) -> Attachment {
+        Attachment::new(&self.shared, pid, uid)
+    }
+}
+
+/// A process attached to a [`MemoryDevice`].
+/// Dropping it releases everything the process owned, like closing `/dev/binder`.
+pub struct MemoryDriver {
+    inner: Inner,
+}
+
+enum Inner {
+    Local(Attachment),
+    Remote(broker::Remote),
+}
+
+impl MemoryDriver {
+    fn inner(&self) -> &dyn BinderDriver {
+        match &self.inner {
+            Inner::Local(attachment) => attachment,
+            Inner::Remote(remote) => remote,
+        }
+    }
+}
+
+impl BinderDriver for MemoryDriver {
+    fn name(&self) -> &str {
+        "memory"
+    }
+
+    fn write_read(&self, bwr: &mut binder_write_read) -> Result<()> {
+        self.inner().write_read(bwr)
+    }
+
+    fn set_max_threads(&self, max_threads: u32) -> Result<()> {
+        self.inner().set_max_threads(max_threads)
+    }
+
+    fn enable_oneway_spam_detection(&self, enable: bool) -> Result<()> {
+        self.inner().enable_oneway_spam_detection(enable)
+    }
+
+    fn become_context_manager(&self, security_context: bool) -> Result<()> {
+        self.inner().become_context_manager(security_context)
+    }
+
+    fn strong_ref_count_for_handle(&self, handle: u32) -> Result<usize> {
+        self.inner().strong_ref_count_for_handle(handle)
+    }
+
+    fn freeze(&self, pid: u32, enable: bool, timeout_ms: u32) -> Result<()> {
+        self.inner().freeze(pid, enable, timeout_ms)
+    }
+
+    fn frozen_info(&self, pid: u32) -> Result<binder_frozen_status_info> {
+        self.inner().frozen_info(pid)
+    }
+
+    fn extended_error(&self) -> Result<binder_extended_error> {
+        self.inner().extended_error()
+    }
+
+    fn is_feature_enabled(&self, feature: DriverFeature) -> bool {
+        self.inner().is_feature_enabled(feature)
+    }
+
+    fn wake_threads(&self) -> Result<()> {
+        self.inner().wake_threads()
+    }
+}
+
+impl Drop for MemoryDriver {
+    fn drop(&mut self) {
+        if let Inner::Local(attachment) = &self.inner {
+            attachment.release();
+        }
+    }
+}
+
+/// A process attached to the device in the memory of this process. The broker
+/// serves the drivers of forked processes with one as well.
+#[derive(Clone)]
+pub(super) struct Attachment {
+    shared: Arc<Shared>,
+    proc: ProcId,
+}
+
+impl Attachment {
+    pub(super) fn new(shared: &Arc<Shared>, pid: pid_t, uid: uid_t) -> Self {
+        let mut device = shared.device.lock().unwrap();
+        let id = device.next_id();
+        device.procs.insert(id, Proc::new(pid, uid));
+
+        Attachment {
+            shared: shared.clone(),
+            proc: id,
+        }
+    }
+
+    /// Another handle to the process `proc`, for a thread of a forked process.
+    pub(super) fn with_id(shared: &Arc<Shared>, proc: ProcId) -> Self {
+        Attachment {
+            shared: shared.clone(),
+            proc,
+        }
+    }
+
+    /// The id of the process, which stays the same for all its threads.
+    pub(super) fn id(&self) -> ProcId {
+        self.proc
+    }
+
+    // Lock the device, unless the process is released already. Only the
+    // processes served by the broker may be released while they are in use.
+    fn device(&self) -> Result<MutexGuard<'_, Device>> {
+        let device = self.shared.device.lock().unwrap();
+        if !device.procs.contains_key(&self.proc) {
+            return Err(Errno::BADF);
+        }
+        Ok(device)
+    }
+
+    fn with_proc<R>(&self, f: impl FnOnce(&mut Proc) -> R) -> Result<R> {
+        let mut device = self.device()?;
+        Ok(f(device.proc_mut(self.proc)))
+    }
+
+    /// Release everything the process owned, if it isn't released already.
+    pub(super) fn release(&self) {
+        let mut device = self.shared.device.lock().unwrap();
+        device.release_proc(self.proc);
+        self.shared.wakeup.notify_all();
+    }
+
+    /// A copy of the memory of the transaction buffer at `addr`.
+    pub(super) fn buffer(&self, addr: binder_uintptr_t) -> Result<Vec<u8>> {
+        let mut device = self.device()?;
+        let buffer = device
+            .proc_mut(self.proc)
+            .buffers
+            .get(&addr)
+            .ok_or(Errno::INVAL)?;
+        Ok(unsafe { std::slice::from_raw_parts(addr as *const u8, buffer.size) }.to_vec())
+    }
+}
+
+impl BinderDriver for Attachment {
+    fn name(&self) -> &str {
+        "memory"
+    }
+
+    fn write_read(&self, bwr: &mut binder_write_read) -> Result<()> {
+        let tid = std::thread::current().id();
+        let mut device = self.device()?;
+        device.proc_mut(self.proc).threads.entry(tid).or_default();
+
+        if bwr.write_consumed < bwr.write_size {
+            let res = device.write(self.proc, tid, bwr);
+            self.shared.wakeup.notify_all();
+            res?;
+        }
+
+        if bwr.read_consumed < bwr.read_size {
+            loop {
+                let available = {
+                    let proc = device.proc_mut(self.proc);
+                    let available = proc.available_for_proc_work(&tid);
+                    if proc.has_work(&tid, available) {
+                        break;
+                    }
+                    if available {
+                        proc.waiting_threads += 1;
+                    }
+                    available
+                };
+
+                device = self.shared.wakeup.wait(device).unwrap();
+
+                let Some(proc) = device.procs.get_mut(&self.proc) else {
+                    return Err(Errno::BADF);
+                };
+                if available {
+                    proc.waiting_threads -= 1;
+                }
+            }
+            device.read(self.proc, tid, bwr);
+        }
+
+        Ok(())
+    }
+
+    fn set_max_threads(&self, max_threads: u32) -> Result<()> {
+        self.with_proc(|proc| proc.max_threads = max_threads)
+    }
+
+    fn enable_oneway_spam_detection(&self, enable: bool) -> Result<()> {
+        self.with_proc(|proc| proc.spam_detection = enable)
+    }
+
+    // The device has no security contexts to send.
+    fn become_context_manager(&self, _security_context: bool) -> Result<()> {
+        let mut device = self.device()?;
+        if device.context_manager.is_some() {
+            log::error!("BINDER_SET_CONTEXT_MGR already set");
+            return Err(Errno::BUSY);
+        }
+
+        let id = device.next_id();
+        // The context manager node is pinned by the driver for its whole lifetime.
+        let mut node = Node::new(self.proc, 0, 0, true);
+        node.pinned = true;
+        node.strong = 1;
+        node.weak = 1;
+        node.has_strong = true;
+        node.has_weak = true;
+        device.nodes.insert(id, node);
+        device.proc_mut(self.proc).nodes.insert(0, id);
+        device.context_manager = Some(id);
+        Ok(())
+    }
+
+    fn strong_ref_count_for_handle(&self, handle: u32) -> Result<usize> {
+        let device = self.device()?;
+        let is_context_manager = device
+            .context_manager
+            .and_then(|id| device.nodes.get(&id))
+            .is_some_and(|node| node.owner == self.proc);
+        if !is_context_manager {
+            return Err(Errno::PERM);
+        }
+
+        let node = device.procs[&self.proc]
+            .refs
+            .get(&handle)
+            .and_then(|r| device.nodes.get(&r.node))
+            .ok_or(Errno::INVAL)?;
+        Ok(node.strong - node.pinned as usize)
+    }
+
+    fn freeze(&self, pid: u32, enable: bool, timeout_ms: u32) -> Result<()> {
+        let mut device = self.device()?;
+        let procs = device.procs_with_pid(pid as _);
+        if procs.is_empty() {
+            return Err(Errno::INVAL);
+        }
+
+        for &id in &procs {
+            device.set_frozen(id, enable);
+        }
+        self.shared.wakeup.notify_all();
+        if !enable {
+            return Ok(());
+        }
+
+        // Give the processes a chance to pick up the transactions queued to them.
+        let deadline = Instant::now() + Duration::from_millis(timeout_ms as _);
+        while procs.iter().any(|&id| device.has_queued_transactions(id)) {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            device = self
+                .shared
+                .wakeup
+                .wait_timeout(device, deadline - now)
+                .unwrap()
+                .0;
+        }
+
+        if procs.iter().any(|&id| device.has_pending_transactions(id)) {
+            for &id in &procs {
+                device.set_frozen(id, false);
+            }
+            self.shared.wakeup.notify_all();
+            return Err(Errno::AGAIN);
+        }
+        Ok(())
+    }
+
+    fn frozen_info(&self, pid: u32) -> Result<binder_frozen_status_info> {
+        let device = self.device()?;
+        let procs = device.procs_with_pid(pid as _);
+        if procs.is_empty() {
+            return Err(Errno::INVAL);
+        }
+
+        let mut info = binder_frozen_status_info {
+            pid,
+            sync_recv: 0,
+            async_recv: 0,
+        };
+        for id in procs {
+            let proc = &device.procs[&id];
+            info.sync_recv |= proc.sync_recv as u32;
+            info.sync_recv |= (device.has_pending_transactions(id) as u32) << 1;
+            info.async_recv |= proc.async_recv as u32;
+        }
+        Ok(info)
+    }
+
+    fn extended_error(&self) -> Result<binder_extended_error> {
+        let tid = std::thread::current().id();
+        let mut device = self.device()?;
+        let thread = device.proc_mut(self.proc).thread_mut(&tid);
+        Ok(thread
+            .extended_error
+            .take()
+            .unwrap_or(binder_extended_error {
+                id: 0,
+                command: BR_OK,
+                param: 0,
+            }))
+    }
+
+    fn is_feature_enabled(&self, _feature: DriverFeature) -> bool {
+        true
+    }
+
+    fn wake_threads(&self) -> Result<()> {
+        self.with_proc(|proc| {
+            for thread in proc.threads.values_mut() {
+                thread.need_return = true;
+            }
+        })?;
+        self.shared.wakeup.notify_all();
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub(super) struct Shared {
+    device: Mutex<Device>,
+    wakeup: Condvar,
+}
+
+#[derive(Clone, Copy)]
+struct Delivery {
+    target_ptr: binder_uintptr_t,
+    cookie: binder_uintptr_t,
+    code: u32,
+    flags: u32,
+    sender_pid: pid_t,
+    sender_euid: uid_t,
+    buffer: binder_uintptr_t,
+    data_size: usize,
+    offsets_size: usize,
+}
+
+enum Work {
+    TransactionComplete,
+    TransactionPendingFrozen,
+    OnewaySpamSuspect,
+    Transaction(Option<TxnId>, Delivery),
+    Reply(Delivery),
+    DeadReply,
+    FailedReply,
+    FrozenReply,
+    NodeCommand(u32, binder_uintptr_t, binder_uintptr_t),
+    DeadBinder(binder_uintptr_t),
+    ClearDeathNotificationDone(binder_uintptr_t),
+    FrozenBinder(binder_uintptr_t, bool),
+    ClearFreezeNotificationDone(binder_uintptr_t),
+}
+
+// Why a transaction failed: the work returned to the sender and the `param`
+// reported by BINDER_GET_EXTENDED_ERROR.
+struct TxnError {
+    work: Work,
+    param: i32,
+}
+
+impl TxnError {
+    fn failed(errno: Errno) -> Self {
+        TxnError {
+            work: Work::FailedReply,
+            param: -errno.raw_os_error(),
+        }
+    }
+
+    fn dead() -> Self {
+        TxnError {
+            work: Work::DeadReply,
+            param: 0,
+        }
+    }
+
+    fn frozen() -> Self {
+        TxnError {
+            work: Work::FrozenReply,
+            param: 0,
+        }
+    }
+}
+
+impl Work {
+    fn size(&self) -> usize {
+        size_of::<u32>()
+            + match self {
+                Work::Transaction(..) | Work::Reply(_) => size_of::<binder_transaction_data>(),
+                Work::NodeCommand(..) => size_of::<binder_ptr_cookie>(),
+                Work::DeadBinder(_)
+                | Work::ClearDeathNotificationDone(_)
+                | Work::ClearFreezeNotificationDone(_) => size_of::<binder_uintptr_t>(),
+                Work::FrozenBinder(..) => size_of::<binder_frozen_state_info>(),
+                _ => 0,
+            }
+    }
+
+    // A transaction, a reply or an error ends the read, as in the kernel driver.
+    fn ends_read(&self) -> bool {
+        matches!(
+            self,
+            Work::Transaction(..)
+                | Work::Reply(_)
+                | Work::DeadReply
+                | Work::FailedReply
+                | Work::FrozenReply
+        )
+    }
+}
+
+struct Node {
+    owner: ProcId,
+    ptr: binder_uintptr_t,
+    cookie: binder_uintptr_t,
+    accept_fds: bool,
+    strong: usize,
+    weak: usize,
+    has_strong: bool,
+    has_weak: bool,
+    pinned: bool,
+    dead: bool,
+    async_busy: bool,
+    async_todo: VecDeque<Work>,
+}
+
+impl Node {
+    fn new(
+        owner: ProcId,
+        ptr: binder_uintptr_t,
+        cookie: binder_uintptr_t,
+        accept_fds: bool,
+    ) -> Self {
+        Node {
+            owner,
+            ptr,
+            cookie,
+            accept_fds,
+            strong: 0,
+            weak: 0,
+            has_strong: false,
+            has_weak: false,
+            pinned: false,
+            dead: false,
+            async_busy: false,
+            async_todo: VecDeque::new(),
+        }
+    }
+}
+
+struct Ref {
+    node: NodeId,
+    strong: u32,
+    weak: u32,
+    death: Option<binder_uintptr_t>,
+    death_sent: bool,
+    freeze: Option<FreezeNotification>,
+}
+
+struct FreezeNotification {
+    cookie: binder_uintptr_t,
+    // The state reported by the last BR_FROZEN_BINDER.
+    frozen: bool,
+    // The last BR_FROZEN_BINDER is not acknowledged by BC_FREEZE_NOTIFICATION_DONE yet.
+    in_flight: bool,
+    // BC_CLEAR_FREEZE_NOTIFICATION arrived while a notification was in flight.
+    clearing: bool,
+}
+
+// A reference held by a transaction buffer until BC_FREE_BUFFER.
+enum Held {
+    Ref(NodeId, bool),
+    Node(NodeId, bool),
+}
+
+struct Buffer {
+    _memory: Box<[u64]>,
+    size: usize,
+    async_node: Option<NodeId>,
+    held: Vec<Held>,
+}
+
+struct Transaction {
+    from: Option<(ProcId, ThreadId)>,
+    to_proc: ProcId,
+    to_thread: Option<ThreadId>,
+    flags: u32,
+}
+
+#[derive(Default)]
+struct Thread {
+    todo: VecDeque<Work>,
+    stack: Vec<TxnId>,
+    looper: bool,
+    // The error of the last failed transaction; None stands for BR_OK.
+    extended_error: Option<binder_extended_error>,
+    // Return from the next read even without work.
+    need_return: bool,
+}
+
+struct Proc {
+    pid: pid_t,
+    uid: uid_t,
+    nodes: HashMap<binder_uintptr_t, NodeId>,
+    refs: BTreeMap<u32, Ref>,
+    handles: HashMap<NodeId, u32>,
+    threads: HashMap<ThreadId, Thread>,
+    todo: VecDeque<Work>,
+    buffers: HashMap<binder_uintptr_t, Buffer>,
+    buffer_limit: usize,
+    allocated: usize,
+    async_allocated: usize,
+    max_threads: u32,
+    requested_threads: u32,
+    requested_threads_started: u32,
+    waiting_threads: u32,
+    spam_detection: bool,
+    frozen: bool,
+    sync_recv: bool,
+    async_recv: bool,
+    // Oneway transactions received while frozen.
+    frozen_todo: VecDeque<Work>,
+}
+
+impl Proc {
+    fn new(pid: pid_t, uid: uid_t) -> Self {
+        Proc {
+            pid,
+            uid,
+            nodes: HashMap::new(),
+            refs: BTreeMap::new(),
+            handles: HashMap::new(),
+            threads: HashMap::new(),
+            todo: VecDeque::new(),
+            buffers: HashMap::new(),
+            buffer_limit: super::default_vm_size(),
+            allocated: 0,
+            async_allocated: 0,
+            max_threads: 0,
+            requested_threads: 0,
+            requested_threads_started: 0,
+            waiting_threads: 0,
+            spam_detection: false,
+            frozen: false,
+            sync_recv: false,
+            async_recv: false,
+            frozen_todo: VecDeque::new(),
+        }
+    }
+
+    fn thread_mut(&mut self, tid: &ThreadId) -> &mut Thread {
+        self.threads.entry(*tid).or_default()
+    }
+
+    fn available_for_proc_work(&self, tid: &ThreadId) -> bool {
+        self.threads
+            .get(tid)
+            .is_some_and(|t| t.looper && t.stack.is_empty() && t.todo.is_empty())
+    }
+
+    fn has_work(&self, tid: &ThreadId, available: bool) -> bool {
+        self.threads
+            .get(tid)
+            .is_some_and(|t| !t.todo.is_empty() || t.need_return)
+            || (available && !self.todo.is_empty())
+    }
+
+    fn queue_async(&mut self, work: Work) {
+        if self.frozen {
+            self.frozen_todo.push_back(work);
+        } else {
+            self.todo.push_back(work);
+        }
+    }
+
+    // Work that is not bound to a thread goes to the thread itself when it is a looper.
+    fn queue_for_looper(&mut self, tid: &ThreadId, work: Work) {
+        let thread = self.thread_mut(tid);
+        if thread.looper {
+            thread.todo.push_back(work);
+        } else {
+            self.todo.push_back(work);
+        }
+    }
+}
+
+#[derive(Default)]
+struct Device {
+    next_id: u64,
+    procs: HashMap<ProcId, Proc>,
+    nodes: HashMap<NodeId, Node>,
+    transactions: HashMap<TxnId, Transaction>,
+    context_manager: Option<NodeId>,
+}
+
+struct Reader {
+    base: *const u8,
+    size: usize,
+    pos: usize,
+}
+
+impl Reader {
+    fn read<T: Copy>(&mut self) -> Result<T> {
+        if self.pos + size_of::<T>() > self.size {
+            return Err(Errno::FAULT);
+        }
+        let value = unsafe { std::ptr::read_unaligned(self.base.add(self.pos) as *const T) };
+        self.pos += size_of::<T>();
+        Ok(value)
+    }
+}
+
+fn align8(size: usize) -> usize {
+    (size + 7) & !7
+}
+
+// The buffer object `parent` referred to by the object with index `index`. The
+// parent must be an earlier object, so it has been copied to `base` already.
+fn parent_buffer(
+    base: *const u8,
+    offsets: *const binder_size_t,
+    index: usize,
+    parent: usize,
+) -> std::result::Result<binder_buffer_object, TxnError> {
+    (parent < index)
+        .then(|| unsafe {
+            let offset = *offsets.add(parent) as usize;
+            std::ptr::read_unaligned(base.add(offset) as *const binder_buffer_object)
+        })
+        .filter(|parent| parent.hdr.type_ == BINDER_TYPE_PTR)
+        .ok_or_else(|| {
+            log::error!("Object with invalid parent {parent}");
+            TxnError::failed(Errno::INVAL)
+        })
+}
+
+// Duplicate the descriptors of a fd array in place. They are stored in the
+// copy of its parent buffer.
+fn translate_fd_array(
+    base: *const u8,
+    offsets: *const binder_size_t,
+    index: usize,
+    fda: &binder_fd_array_object,
+    fds: &mut Vec<OwnedFd>,
+) -> std::result::Result<(), TxnError> {
+    let parent = parent_buffer(base, offsets, index, fda.parent as usize)?;
+    let (parent_offset, num_fds) = (fda.parent_offset as usize, fda.num_fds as usize);
+    if parent_offset % size_of::<u32>() != 0
+        || num_fds
+            .checked_mul(size_of::<u32>())
+            .and_then(|size| size.checked_add(parent_offset))
+            .map_or(true, |end| end > parent.length as usize)
+    {
+        log::error!("Fd array of {num_fds} fds at invalid parent offset {parent_offset}");
+        return Err(TxnError::failed(Errno::INVAL));
+    }
+
+    let array = unsafe { (parent.buffer as *mut u8).add(parent_offset) as *mut u32 };
+    for i in 0..num_fds {
+        let fd = unsafe { std::ptr::read_unaligned(array.add(i)) } as i32;
+        let dup = unsafe { rustix::io::fcntl_dupfd_cloexec(BorrowedFd::borrow_raw(fd), 0) }
+            .map_err(|e| {
+                log::error!("Failed to duplicate file descriptor {fd}: {e}");
+                TxnError::failed(e)
+            })?;
+        unsafe { std::ptr::write_unaligned(array.add(i), dup.as_raw_fd() as u32) };
+        fds.push(dup);
+    }
+    Ok(())
+}
+
+pub(super) fn object_size(type_: u32) -> usize {
+    match type_ {
+        BINDER_TYPE_PTR => size_of::<binder_buffer_object>(),
+        BINDER_TYPE_FDA => size_of::<binder_fd_array_object>(),
+        _ => size_of::<flat_binder_object>(),
+    }
+}
+
+impl Device {
+    fn next_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn proc_mut(&mut self, id: ProcId) -> &mut Proc {
+        self.procs.get_mut(&id).expect("binder proc is released")
+    }
+
+    fn write(&mut self, proc: ProcId, tid: ThreadId, bwr: &mut binder_write_read) -> Result<()> {
+        let mut reader = Reader {
+            base: bwr.write_buffer as *const u8,
+            size: bwr.write_size as usize,
+            pos: bwr.write_consumed as usize,
+        };
+
+        while reader.pos < reader.size {
+            let cmd: u32 = reader.read()?;
+            match cmd {
+                BC_TRANSACTION | BC_REPLY => {
+                    let tr: binder_transaction_data = reader.read()?;
+                    self.transaction(proc, tid, &tr, 0, cmd == BC_REPLY);
+                }
+                BC_TRANSACTION_SG | BC_REPLY_SG => {
+                    let tr: binder_transaction_data_sg = reader.read()?;
+                    self.transaction(
+                        proc,
+                        tid,
+                        &tr.transaction_data,
+                        tr.buffers_size as _,
+                        cmd == BC_REPLY_SG,
+                    );
+                }
+                BC_FREE_BUFFER => {
+                    let buffer: binder_uintptr_t = reader.read()?;
+                    self.free_buffer(proc, buffer);
+                }
+                BC_INCREFS | BC_ACQUIRE | BC_RELEASE | BC_DECREFS => {
+                    let handle: u32 = reader.read()?;
+                    let strong = cmd == BC_ACQUIRE || cmd == BC_RELEASE;
+                    if cmd == BC_INCREFS || cmd == BC_ACQUIRE {
+                        self.inc_ref_for_handle(proc, handle, strong);
+                    } else {
+                        self.dec_ref(proc, handle, strong);
+                    }
+                }
+                BC_INCREFS_DONE | BC_ACQUIRE_DONE => {
+                    reader.read::<binder_ptr_cookie>()?;
+                }
+                BC_REGISTER_LOOPER => {
+                    let proc = self.proc_mut(proc);
+                    if proc.requested_threads == 0 {
+                        log::warn!("BC_REGISTER_LOOPER called without request");
+                    } else {
+                        proc.requested_threads -= 1;
+                        proc.requested_threads_started += 1;
+                    }
+                    proc.thread_mut(&tid).looper = true;
+                }
+                BC_ENTER_LOOPER => {
+                    self.proc_mut(proc).thread_mut(&tid).looper = true;
+                }
+                BC_EXIT_LOOPER => {
+                    self.proc_mut(proc).thread_mut(&tid).looper = false;
+                }
+                BC_REQUEST_DEATH_NOTIFICATION | BC_CLEAR_DEATH_NOTIFICATION => {
+                    let handle: u32 = reader.read()?;
+                    let cookie: binder_uintptr_t = reader.read()?;
+                    if cmd == BC_REQUEST_DEATH_NOTIFICATION {
+                        self.request_death_notification(proc, tid, handle, cookie);
+                    } else {
+                        self.clear_death_notification(proc, tid, handle, cookie);
+                    }
+                }
+                BC_DEAD_BINDER_DONE => {
+                    reader.read::<binder_uintptr_t>()?;
+                }
+                BC_REQUEST_FREEZE_NOTIFICATION | BC_CLEAR_FREEZE_NOTIFICATION => {
+                    let handle: u32 = reader.read()?;
+                    let cookie: binder_uintptr_t = reader.read()?;
+                    if cmd == BC_REQUEST_FREEZE_NOTIFICATION {
+                        self.request_freeze_notification(proc, handle, cookie);
+                    } else {
+                        self.clear_freeze_notification(proc, handle, cookie);
+                    }
+                }
+                BC_FREEZE_NOTIFICATION_DONE => {
+                    let cookie: binder_uintptr_t = reader.read()?;
+                    self.freeze_notification_done(proc, cookie);
+                }
+                _ => {
+                    log::error!("Unsupported binder command {cmd:#x}");
+                    return Err(Errno::INVAL);
+                }
+            }
+            bwr.write_consumed = reader.pos as _;
+        }
+
+        Ok(())
+    }
+
+    fn read(&mut self, proc: ProcId, tid: ThreadId, bwr: &mut binder_write_read) {
+        let base = bwr.read_buffer as *mut u8;
+        let size = bwr.read_size as usize;
+        let mut pos = bwr.read_consumed as usize;
+        // Keep room for BR_SPAWN_LOOPER.
+        let limit = size.saturating_sub(size_of::<u32>());
+
+        loop {
+            let p = self.proc_mut(proc);
+            let available = p.available_for_proc_work(&tid);
+            let thread = p.thread_mut(&tid);
+            let work = match thread.todo.pop_front() {
+                Some(work) => work,
+                None if available => match p.todo.pop_front() {
+                    Some(work) => work,
+                    None => break,
+                },
+                None => break,
+            };
+
+            if pos + work.size() > limit {
+                if pos == bwr.read_consumed as usize {
+                    log::error!("Binder read buffer is too small: {size}");
+                }
+                p.thread_mut(&tid).todo.push_front(work);
+                break;
+            }
+
+            unsafe { pos += write_work(base.add(pos), &work) };
+
+            if let Work::Transaction(Some(id), _) = work {
+                if let Some(txn) = self.transactions.get_mut(&id) {
+                    txn.to_thread = Some(tid);
+                }
+                self.proc_mut(proc).thread_mut(&tid).stack.push(id);
+            }
+
+            if work.ends_read() {
+                break;
+            }
+        }
+
+        let p = self.proc_mut(proc);
+        if std::mem::take(&mut p.thread_mut(&tid).need_return)
+            && pos == bwr.read_consumed as usize
+            && pos + size_of::<u32>() <= size
+        {
+            unsafe { std::ptr::write_unaligned(base.add(pos) as *mut u32, BR_NOOP) };
+            pos += size_of::<u32>();
+        }
+        if p.requested_threads == 0
+            && p.waiting_threads == 0
+            && p.requested_threads_started < p.max_threads
+            && p.thread_mut(&tid).looper
+            && pos + size_of::<u32>() <= size
+        {
+            p.requested_threads += 1;
+            unsafe { std::ptr::write_unaligned(base.add(pos) as *mut u32, BR_SPAWN_LOOPER) };
+            pos += size_of::<u32>();
+        }
+
+        bwr.read_consumed = pos as _;
+    }
+
+    fn transaction(
+        &mut self,
+        proc: ProcId,
+        tid: ThreadId,
+        tr: &binder_transaction_data,
+        buffers_size: usize,
+        reply: bool,
+    ) {
+        let res = if reply {
+            self.reply(proc, tid, tr, buffers_size)
+        } else {
+            self.call(proc, tid, tr, buffers_size)
+        };
+
+        let id = self.next_id();
+        let thread = self.proc_mut(proc).thread_mut(&tid);
+        match res {
+            Ok(work) => {
+                thread.extended_error = None;
+                thread.todo.push_back(work);
+            }
+            Err(err) => {
+                let command = match err.work {
+                    Work::DeadReply => BR_DEAD_REPLY,
+                    Work::FrozenReply => BR_FROZEN_REPLY,
+                    _ => BR_FAILED_REPLY,
+                };
+                thread.extended_error = Some(binder_extended_error {
+                    id: id as _,
+                    command,
+                    param: err.param,
+                });
+                thread.todo.push_back(err.work);
+            }
+        }
+    }
+
+    fn call(
+        &mut self,
+        proc: ProcId,
+        tid: ThreadId,
+        tr: &binder_transaction_data,
+        buffers_size: usize,
+    ) -> std::result::Result<Work, TxnError> {
+        let oneway = tr.flags & transaction_flags_TF_ONE_WAY != 0;
+        let handle = unsafe { tr.target.handle };
+        let node_id = match self.node_for_handle(proc, handle) {
+            Some(id) => id,
+            None if handle == 0 => return Err(TxnError::dead()),
+            None => {
+                log::error!("Transaction to invalid handle {handle}");
+                return Err(TxnError::failed(Errno::INVAL));
+            }
+        };
+
+        let node = &self.nodes[&node_id];
+        if node.dead || !self.procs.contains_key(&node.owner) {
+            return Err(TxnError::dead());
+        }
+        let (target_proc, target_ptr, target_cookie, accept_fds) =
+            (node.owner, node.ptr, node.cookie, node.accept_fds);
+
+        let target = self.proc_mut(target_proc);
+        if target.frozen {
+            if !oneway {
+                target.sync_recv = true;
+                return Err(TxnError::frozen());
+            }
+            target.async_recv = true;
+        }
+
+        // A nested call goes back to the thread which is waiting for our reply.
+        let target_thread = if oneway {
+            None
+        } else {
+            self.procs[&proc].threads[&tid]
+                .stack
+                .iter()
+                .rev()
+                .filter_map(|id| self.transactions.get(id))
+                .filter(|txn| txn.to_thread == Some(tid))
+                .find_map(|txn| txn.from.filter(|(from, _)| *from == target_proc))
+                .map(|(_, thread)| thread)
+        };
+
+        let (sender_pid, sender_euid) = (self.procs[&proc].pid, self.procs[&proc].uid);
+        let buffer =
+            self.copy_buffer(proc, tid, target_proc, tr, buffers_size, accept_fds, oneway)?;
+
+        let delivery = Delivery {
+            target_ptr,
+            cookie: target_cookie,
+            code: tr.code,
+            flags: tr.flags,
+            sender_pid: if oneway { 0 } else { sender_pid },
+            sender_euid,
+            buffer,
+            data_size: tr.data_size as _,
+            offsets_size: tr.offsets_size as _,
+        };
+
+        if oneway {
+            let target = self.proc_mut(target_proc);
+            if let Some(buffer) = target.buffers.get_mut(&delivery.buffer) {
+                buffer.async_node = Some(node_id);
+            }
+            let suspect = target.spam_detection
+                && target.async_allocated * 100
+                    > (target.buffer_limit / 2) * SPAM_DETECTION_THRESHOLD_PERCENT;
+
+            let frozen = target.frozen;
+
+            let node = self.nodes.get_mut(&node_id).unwrap();
+            if node.async_busy {
+                node.async_todo.push_back(Work::Transaction(None, delivery));
+            } else {
+                node.async_busy = true;
+                self.proc_mut(target_proc)
+                    .queue_async(Work::Transaction(None, delivery));
+            }
+
+            if frozen {
+                Ok(Work::TransactionPendingFrozen)
+            } else if suspect {
+                Ok(Work::OnewaySpamSuspect)
+            } else {
+                Ok(Work::TransactionComplete)
+            }
+        } else {
+            let id = self.next_id();
+            self.transactions.insert(
+                id,
+                Transaction {
+                    from: Some((proc, tid)),
+                    to_proc: target_proc,
+                    to_thread: None,
+                    flags: tr.flags,
+                },
+            );
+            self.proc_mut(proc).thread_mut(&tid).stack.push(id);
+
+            let work = Work::Transaction(Some(id), delivery);
+            let target = self.proc_mut(target_proc);
+            match target_thread.and_then(|t| target.threads.get_mut(&t)) {
+                Some(thread) => thread.todo.push_back(work),
+                None => target.todo.push_back(work),
+            }
+            Ok(Work::TransactionComplete)
+        }
+    }
+
+    fn reply(
+        &mut self,
+        proc: ProcId,
+        tid: ThreadId,
+        tr: &binder_transaction_data,
+        buffers_size: usize,
+    ) -> std::result::Result<Work, TxnError> {
+        let id = match self.procs[&proc].threads[&tid].stack.last() {
+            Some(id)
+                if self
+                    .transactions
+                    .get(id)
+                    .is_some_and(|txn| txn.to_thread == Some(tid)) =>
+            {
+                *id
+            }
+            _ => {
+                log::error!("Got reply with no transaction in progress");
+                return Err(TxnError::failed(Errno::PROTO));
+            }
+        };
+        self.proc_mut(proc).thread_mut(&tid).stack.pop();
+        let txn = self.transactions.remove(&id).unwrap();
+
+        let Some((from_proc, from_thread)) = txn.from else {
+            return Err(TxnError::dead());
+        };
+
+        let accept_fds = txn.flags & transaction_flags_TF_ACCEPT_FDS != 0;
+        let buffer = self.copy_buffer(proc, tid, from_proc, tr, buffers_size, accept_fds, false);
+
+        let delivery = buffer.map(|buffer| Delivery {
+            target_ptr: 0,
+            cookie: 0,
+            code: tr.code,
+            flags: tr.flags,
+            sender_pid: 0,
+            sender_euid: self.procs[&proc].uid,
+            buffer,
+            data_size: tr.data_size as _,
+            offsets_size: tr.offsets_size as _,
+        });
+
+        let caller = self.proc_mut(from_proc).thread_mut(&from_thread);
+        caller.stack.retain(|&t| t != id);
+        match delivery {
+            Ok(delivery) => {
+                caller.todo.push_back(Work::Reply(delivery));
+                Ok(Work::TransactionComplete)
+            }
+            Err(err) => {
+                // The caller must not wait forever for a reply which could not be delivered.
+                caller.todo.push_back(Work::FailedReply);
+                caller.extended_error = Some(binder_extended_error {
+                    id: id as _,
+                    command: BR_FAILED_REPLY,
+                    param: 0,
+                });
+                Err(err)
+            }
+        }
+    }
+
+    // Copy the transaction data into a buffer owned by `to` and translate the
+    // embedded objects into its handle space.
+    #[allow(clippy::too_many_arguments)]
+    fn copy_buffer(
+        &mut self,
+        from: ProcId,
+        tid: ThreadId,
+        to: ProcId,
+        tr: &binder_transaction_data,
+        buffers_size: usize,
+        accept_fds: bool,
+        is_async: bool,
+    ) -> std::result::Result<binder_uintptr_t, TxnError> {
+        let data_size = tr.data_size as usize;
+        let offsets_size = tr.offsets_size as usize;
+        if offsets_size % size_of::<binder_size_t>() != 0 {
+            log::error!("Transaction with invalid offsets size {offsets_size}");
+            return Err(TxnError::failed(Errno::INVAL));
+        }
+
+        // Buffer objects of scatter-gather transactions are copied after the offsets.
+        let sg_start = align8(data_size) + offsets_size;
+        let total = sg_start + align8(buffers_size);
+        let target = self.procs.get(&to).ok_or_else(TxnError::dead)?;
+        if target.allocated + total > target.buffer_limit
+            || (is_async && target.async_allocated + total > target.buffer_limit / 2)
+        {
+            log::error!("Binder buffer allocation of {total} bytes failed");
+            return Err(TxnError::failed(Errno::NOSPC));
+        }
+
+        let mut memory = vec![0u64; total.div_ceil(8).max(1)].into_boxed_slice();
+        let base = memory.as_mut_ptr() as *mut u8;
+        unsafe {
+            let (buffer, offsets) = (tr.data.ptr.buffer, tr.data.ptr.offsets);
+            if data_size > 0 {
+                std::ptr::copy_nonoverlapping(buffer as *const u8, base, data_size);
+            }
+            if offsets_size > 0 {
+                std::ptr::copy_nonoverlapping(
+                    offsets as *const u8,
+                    base.add(align8(data_size)),
+                    offsets_size,
+                );
+            }
+        }
+
+        let mut held = Vec::new();
+        let mut fds = Vec::new();
+        let res = self.translate_objects(
+            from,
+            tid,
+            to,
+            base,
+            data_size,
+            offsets_size / size_of::<binder_size_t>(),
+            sg_start..total,
+            accept_fds,
+            &mut held,
+            &mut fds,
+        );
+        if let Err(work) = res {
+            for held in held {
+                self.release_held(to, held);
+            }
+            return Err(work);
+        }
+        // The target process owns the translated descriptors from now on.
+        for fd in fds {
+            let _ = fd.into_raw_fd();
+        }
+
+        let addr = base as binder_uintptr_t;
+        let target = self.proc_mut(to);
+        target.allocated += total;
+        if is_async {
+            target.async_allocated += total;
+        }
+        target.buffers.insert(
+            addr,
+            Buffer {
+                _memory: memory,
+                size: total,
+                async_node: None,
+                held,
+            },
+        );
+        Ok(addr)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn translate_objects(
+        &mut self,
+        from: ProcId,
+        tid: ThreadId,
+        to: ProcId,
+        base: *mut u8,
+        data_size: usize,
+        count: usize,
+        mut sg: std::ops::Range<usize>,
+        accept_fds: bool,
+        held: &mut Vec<Held>,
+        fds: &mut Vec<OwnedFd>,
+    ) -> std::result::Result<(), TxnError> {
+        let offsets = unsafe { base.add(align8(data_size)) as *const binder_size_t };
+        let mut min_offset = 0;
+
+        for i in 0..count {
+            let offset = unsafe { *offsets.add(i) } as usize;
+            let object_size = if offset + size_of::<binder_object_header>() > data_size {
+                size_of::<binder_object_header>()
+            } else {
+                let hdr = unsafe {
+                    std::ptr::read_unaligned(base.add(offset) as *const binder_object_header)
+                };
+                object_size(hdr.type_)
+            };
+            if offset < min_offset
+                || offset % size_of::<u32>() != 0
+                || offset + object_size > data_size
+            {
+                log::error!("Transaction with invalid object offset {offset}");
+                return Err(TxnError::failed(Errno::INVAL));
+            }
+            min_offset = offset + object_size;
+
+            let ptr = unsafe { base.add(offset) };
+            match unsafe { std::ptr::read_unaligned(ptr as *const binder_object_header) }.type_ {
+                BINDER_TYPE_PTR => {
+                    let obj = ptr as *mut binder_buffer_object;
+                    let mut bp = unsafe { std::ptr::read_unaligned(obj) };
+                    self.translate_buffer(base, offsets, i, &mut bp, &mut sg)?;
+                    unsafe { std::ptr::write_unaligned(obj, bp) };
+                    continue;
+                }
+                BINDER_TYPE_FDA => {
+                    if !accept_fds {
+                        log::error!("Target does not accept file descriptors");
+                        return Err(TxnError::failed(Errno::PERM));
+                    }
+                    let fda =
+                        unsafe { std::ptr::read_unaligned(ptr as *const binder_fd_array_object) };
+                    translate_fd_array(base, offsets, i, &fda, fds)?;
+                    continue;
+                }
+                _ => {}
+            }
+
+            let ptr = ptr as *mut flat_binder_object;
+            let mut obj = unsafe { std::ptr::read_unaligned(ptr) };
+
+            match obj.hdr.type_ {
+                BINDER_TYPE_BINDER | BINDER_TYPE_WEAK_BINDER => {
+                    let strong = obj.hdr.type_ == BINDER_TYPE_BINDER;
+                    let binder = unsafe { obj.__bindgen_anon_1.binder };
+                    let node_id = self.node_for_binder(
+                        from,
+                        binder,
+                        obj.cookie,
+                        obj.flags & FLAT_BINDER_FLAG_ACCEPTS_FDS != 0,
+                    );
+                    if self.nodes[&node_id].cookie != obj.cookie {
+                        log::error!("Sending binder {binder:#x} with mismatched cookie");
+                        return Err(TxnError::failed(Errno::INVAL));
+                    }
+                    self.translate_node(from, tid, to, node_id, strong, &mut obj, held);
+                }
+                BINDER_TYPE_HANDLE | BINDER_TYPE_WEAK_HANDLE => {
+                    let strong = obj.hdr.type_ == BINDER_TYPE_HANDLE;
+                    let handle = unsafe { obj.__bindgen_anon_1.handle };
+                    let node_id = self.node_for_handle(from, handle).ok_or_else(|| {
+                        log::error!("Sending invalid handle {handle}");
+                        TxnError::failed(Errno::INVAL)
+                    })?;
+                    self.translate_node(from, tid, to, node_id, strong, &mut obj, held);
+                }
+                BINDER_TYPE_FD => {
+                    if !accept_fds {
+                        log::error!("Target does not accept file descriptors");
+                        return Err(TxnError::failed(Errno::PERM));
+                    }
+                    let fd = unsafe { obj.__bindgen_anon_1.handle } as i32;
+                    let dup =
+                        unsafe { rustix::io::fcntl_dupfd_cloexec(BorrowedFd::borrow_raw(fd), 0) }
+                            .map_err(|e| {
+                            log::error!("Failed to duplicate file descriptor {fd}: {e}");
+                            TxnError::failed(e)
+                        })?;
+                    obj.__bindgen_anon_1.binder = 0;
+                    obj.__bindgen_anon_1.handle = dup.as_raw_fd() as _;
+                    obj.cookie = 0;
+                    fds.push(dup);
+                }
+                ty => {
+                    log::error!("Unsupported binder object type {ty:#x}");
+                    return Err(TxnError::failed(Errno::INVAL));
+                }
+            }
+
+            unsafe { std::ptr::write_unaligned(ptr, obj) };
+        }
+
+        Ok(())
+    }
+
+    // Copy the memory of the buffer object with index `index` into the `sg` area of
+    // the transaction buffer at `base` and patch the pointer in its parent.
+    fn translate_buffer(
+        &mut self,
+        base: *mut u8,
+        offsets: *const binder_size_t,
+        index: usize,
+        bp: &mut binder_buffer_object,
+        sg: &mut std::ops::Range<usize>,
+    ) -> std::result::Result<(), TxnError> {
+        let length = bp.length as usize;
+        if length > sg.len() {
+            log::error!("Buffer object of {length} bytes exceeds the scatter-gather size");
+            return Err(TxnError::failed(Errno::INVAL));
+        }
+        let dst = unsafe { base.add(sg.start) };
+        if length > 0 {
+            unsafe { std::ptr::copy_nonoverlapping(bp.buffer as *const u8, dst, length) };
+        }
+        bp.buffer = dst as binder_uintptr_t;
+        sg.start += align8(length);
+
+        if bp.flags & BINDER_BUFFER_FLAG_HAS_PARENT == 0 {
+            return Ok(());
+        }
+        let parent = parent_buffer(base, offsets, index, bp.parent as usize)?;
+        let parent_offset = bp.parent_offset as usize;
+        if parent_offset + size_of::<binder_uintptr_t>() > parent.length as usize {
+            log::error!("Buffer object with invalid parent offset {parent_offset}");
+            return Err(TxnError::failed(Errno::INVAL));
+        }
+        unsafe {
+            std::ptr::write_unaligned(
+                (parent.buffer as *mut u8).add(parent_offset) as *mut binder_uintptr_t,
+                bp.buffer,
+            );
+        }
+        Ok(())
+    }
+
+    // Rewrite `obj` so that it refers to `node_id` from the point of view of `to`.
+    #[allow(clippy::too_many_arguments)]
+    fn translate_node(
+        &mut self,
+        from: ProcId,
+        tid: ThreadId,
+        to: ProcId,
+        node_id: NodeId,
+        strong: bool,
+        obj: &mut flat_binder_object,
+        held: &mut Vec<Held>,
+    ) {
+        let node = &self.nodes[&node_id];
+        if node.owner == to {
+            obj.hdr.type_ = if strong {
+                BINDER_TYPE_BINDER
+            } else {
+                BINDER_TYPE_WEAK_BINDER
+            };
+            obj.__bindgen_anon_1.binder = node.ptr;
+            obj.cookie = node.cookie;
+            self.inc_node(node_id, strong, Some((from, tid)));
+            held.push(Held::Node(node_id, strong));
+        } else {
+            let handle = self.ref_for_node(to, node_id);
+            obj.hdr.type_ = if strong {
+                BINDER_TYPE_HANDLE
+            } else {
+                BINDER_TYPE_WEAK_HANDLE
+            };
+            obj.__bindgen_anon_1.binder = 0;
+            obj.__bindgen_anon_1.handle = handle;
+            obj.cookie = 0;
+            self.inc_ref(to, handle, strong, Some((from, tid)));
+            held.push(Held::Ref(node_id, strong));
+        }
+    }
+
+    fn node_for_binder(
+        &mut self,
+        owner: ProcId,
+        ptr: binder_uintptr_t,
+        cookie: binder_uintptr_t,
+        accept_fds: bool,
+    ) -> NodeId {
+        if let Some(id) = self.procs[&owner].nodes.get(&ptr) {
+            return *id;
+        }
+        let id = self.next_id();
+        self.nodes
+            .insert(id, Node::new(owner, ptr, cookie, accept_fds));
+        self.proc_mut(owner).nodes.insert(ptr, id);
+        id
+    }
+
+    fn node_for_handle(&self, proc: ProcId, handle: u32) -> Option<NodeId> {
+        match self.procs.get(&proc)?.refs.get(&handle) {
+            Some(r) => Some(r.node),
+            None if handle == 0 => self.context_manager,
+            None => None,
+        }
+    }
+
+    fn ref_for_node(&mut self, proc: ProcId, node: NodeId) -> u32 {
+        if let Some(handle) = self.procs[&proc].handles.get(&node) {
+            return *handle;
+        }
+
+        let mut handle = if Some(node) == self.context_manager {
+            0
+        } else {
+            1
+        };
+        let p = self.proc_mut(proc);
+        for used in p.refs.keys() {
+            match used.cmp(&handle) {
+                std::cmp::Ordering::Less => {}
+                std::cmp::Ordering::Equal => handle += 1,
+                std::cmp::Ordering::Greater => break,
+            }
+        }
+        p.refs.insert(
+            handle,
+            Ref {
+                node,
+                strong: 0,
+                weak: 0,
+                death: None,
+                death_sent: false,
+                freeze: None,
+            },
+        );
+        p.handles.insert(node, handle);
+        self.nodes.get_mut(&node).unwrap().weak += 1;
+        handle
+    }
+
+    fn inc_ref_for_handle(&mut self, proc: ProcId, handle: u32, strong: bool) {
+        if handle == 0 && !self.procs[&proc].refs.contains_key(&0) {
+            match self.context_manager {
+                Some(node) => {
+                    self.ref_for_node(proc, node);
+                }
+                None => {
+                    log::error!("Reference to handle 0 without a context manager");
+                    return;
+                }
+            }
+        }
+        if !self.inc_ref(proc, handle, strong, None) {
+            log::error!("Reference to invalid handle {handle}");
+        }
+    }
+
+    fn inc_ref(
+        &mut self,
+        proc: ProcId,
+        handle: u32,
+        strong: bool,
+        thread: Option<(ProcId, ThreadId)>,
+    ) -> bool {
+        let Some(r) = self.proc_mut(proc).refs.get_mut(&handle) else {
+            return false;
+        };
+        let node = r.node;
+        if strong {
+            r.strong += 1;
+            if r.strong == 1 {
+                self.inc_node(node, true, thread);
+            }
+        } else {
+            r.weak += 1;
+        }
+        true
+    }
+
+    fn dec_ref(&mut self, proc: ProcId, handle: u32, strong: bool) {
+        let p = self.proc_mut(proc);
+        let Some(r) = p.refs.get_mut(&handle) else {
+            log::error!("Release of invalid handle {handle}");
+            return;
+        };
+        let node = r.node;
+        let count = if strong { &mut r.strong } else { &mut r.weak };
+        if *count == 0 {
+            log::error!("Release of handle {handle} without a reference");
+            return;
+        }
+        *count -= 1;
+        let released_strong = strong && r.strong == 0;
+        let removed = r.strong == 0 && r.weak == 0;
+        if removed {
+            p.refs.remove(&handle);
+            p.handles.remove(&node);
+        }
+
+        if let Some(node) = self.nodes.get_mut(&node) {
+            if released_strong {
+                node.strong -= 1;
+            }
+            if removed {
+                node.weak -= 1;
+            }
+        }
+        self.update_node(node, None);
+    }
+
+    fn inc_node(&mut self, id: NodeId, strong: bool, thread: Option<(ProcId, ThreadId)>) {
+        let node = self.nodes.get_mut(&id).unwrap();
+        if strong {
+            node.strong += 1;
+        } else {
+            node.weak += 1;
+        }
+        self.update_node(id, thread);
+    }
+
+    fn dec_node(&mut self, id: NodeId, strong: bool) {
+        if let Some(node) = self.nodes.get_mut(&id) {
+            if strong {
+                node.strong -= 1;
+            } else {
+                node.weak -= 1;
+            }
+            self.update_node(id, None);
+        }
+    }
+
+    // Tell the owner about reference changes of the node and drop unused nodes.
+    // Work caused by the owner's own thread is queued to that thread.
+    fn update_node(&mut self, id: NodeId, thread: Option<(ProcId, ThreadId)>) {
+        let Some(node) = self.nodes.get_mut(&id) else {
+            return;
+        };
+        let want_strong = node.strong > 0;
+        let want_weak = want_strong || node.weak > 0;
+        let owner_alive = !node.dead && self.procs.contains_key(&node.owner);
+
+        let mut cmds = Vec::new();
+        if owner_alive {
+            if want_weak && !node.has_weak {
+                node.has_weak = true;
+                cmds.push(BR_INCREFS);
+            }
+            if want_strong && !node.has_strong {
+                node.has_strong = true;
+                cmds.push(BR_ACQUIRE);
+            }
+            if !want_strong && node.has_strong {
+                node.has_strong = false;
+                cmds.push(BR_RELEASE);
+            }
+            if !want_weak && node.has_weak {
+                node.has_weak = false;
+                cmds.push(BR_DECREFS);
+            }
+        }
+
+        let (owner, ptr, cookie) = (node.owner, node.ptr, node.cookie);
+        if !want_weak && !node.has_weak {
+            self.nodes.remove(&id);
+            if let Some(proc) = self.procs.get_mut(&owner) {
+                if proc.nodes.get(&ptr) == Some(&id) {
+                    proc.nodes.remove(&ptr);
+                }
+            }
+        }
+
+        if let Some(proc) = self.procs.get_mut(&owner) {
+            let todo = match thread {
+                Some((p, t)) if p == owner => &mut proc.thread_mut(&t).todo,
+                _ => &mut proc.todo,
+            };
+            todo.extend(
+                cmds.into_iter()
+                    .map(|cmd| Work::NodeCommand(cmd, ptr, cookie)),
+            );
+        }
+    }
+
+    fn release_held(&mut self, proc: ProcId, held: Held) {
+        match held {
+            Held::Ref(node, strong) => {
+                if let Some(handle) = self.procs[&proc].handles.get(&node).copied() {
+                    self.dec_ref(proc, handle, strong);
+                }
+            }
+            Held::Node(node, strong) => self.dec_node(node, strong),
+        }
+    }
+
+    fn free_buffer(&mut self, proc: ProcId, addr: binder_uintptr_t) {
+        let p = self.proc_mut(proc);
+        let Some(buffer) = p.buffers.remove(&addr) else {
+            log::error!("BC_FREE_BUFFER with unknown buffer {addr:#x}");
+            return;
+        };
+        p.allocated -= buffer.size;
+        if buffer.async_node.is_some() {
+            p.async_allocated -= buffer.size;
+        }
+
+        for held in buffer.held {
+            self.release_held(proc, held);
+        }
+
+        // The next oneway transaction to the node may be delivered now.
+        if let Some(node) = buffer.async_node.and_then(|id| self.nodes.get_mut(&id)) {
+            match node.async_todo.pop_front() {
+                Some(work) => {
+                    let owner = node.owner;
+                    if let Some(proc) = self.procs.get_mut(&owner) {
+                        proc.queue_async(work);
+                    }
+                }
+                None => node.async_busy = false,
+            }
+        }
+    }
+
+    fn request_death_notification(
+        &mut self,
+        proc: ProcId,
+        tid: ThreadId,
+        handle: u32,
+        cookie: binder_uintptr_t,
+    ) {
+        let Some(r) = self.proc_mut(proc).refs.get_mut(&handle) else {
+            log::error!("BC_REQUEST_DEATH_NOTIFICATION invalid handle {handle}");
+            return;
+        };
+        if r.death.is_some() {
+            log::error!("BC_REQUEST_DEATH_NOTIFICATION death notification already set");
+            return;
+        }
+        r.death = Some(cookie);
+
+        let node = r.node;
+        let dead = self.nodes.get(&node).map_or(true, |node| {
+            node.dead || !self.procs.contains_key(&node.owner)
+        });
+        if dead {
+            let p = self.proc_mut(proc);
+            p.refs.get_mut(&handle).unwrap().death_sent = true;
+            p.queue_for_looper(&tid, Work::DeadBinder(cookie));
+        }
+    }
+
+    fn clear_death_notification(
+        &mut self,
+        proc: ProcId,
+        tid: ThreadId,
+        handle: u32,
+        cookie: binder_uintptr_t,
+    ) {
+        let p = self.proc_mut(proc);
+        let Some(r) = p.refs.get_mut(&handle) else {
+            log::error!("BC_CLEAR_DEATH_NOTIFICATION invalid handle {handle}");
+            return;
+        };
+        if r.death != Some(cookie) {
+            log::error!("BC_CLEAR_DEATH_NOTIFICATION death notification cookie mismatch");
+            return;
+        }
+        r.death = None;
+        p.queue_for_looper(&tid, Work::ClearDeathNotificationDone(cookie));
+    }
+
+    fn procs_with_pid(&self, pid: pid_t) -> Vec<ProcId> {
+        self.procs
+            .iter()
+            .filter(|(_, proc)| proc.pid == pid)
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    // Synchronous transactions the process has not replied to yet.
+    fn has_pending_transactions(&self, proc: ProcId) -> bool {
+        self.transactions.values().any(|txn| txn.to_proc == proc)
+    }
+
+    fn has_queued_transactions(&self, proc: ProcId) -> bool {
+        let proc = &self.procs[&proc];
+        proc.todo
+            .iter()
+            .chain(proc.threads.values().flat_map(|thread| thread.todo.iter()))
+            .any(|work| matches!(work, Work::Transaction(..)))
+    }
+
+    fn is_node_frozen(&self, node: NodeId) -> bool {
+        self.nodes
+            .get(&node)
+            .and_then(|node| self.procs.get(&node.owner))
+            .is_some_and(|proc| proc.frozen)
+    }
+
+    fn set_frozen(&mut self, id: ProcId, frozen: bool) {
+        let proc = self.proc_mut(id);
+        proc.sync_recv = false;
+        proc.async_recv = false;
+        if proc.frozen == frozen {
+            return;
+        }
+        proc.frozen = frozen;
+        if !frozen {
+            let held = std::mem::take(&mut proc.frozen_todo);
+            proc.todo.extend(held);
+        }
+
+        // Tell the watchers of the nodes of the process, unless they still
+        // have to acknowledge the previous notification.
+        let nodes: Vec<NodeId> = proc.nodes.values().copied().collect();
+        for other in self.procs.values_mut() {
+            let mut notifications = Vec::new();
+            for r in other.refs.values_mut() {
+                let Some(freeze) = r.freeze.as_mut() else {
+                    continue;
+                };
+                if nodes.contains(&r.node)
+                    && !freeze.in_flight
+                    && !freeze.clearing
+                    && freeze.frozen != frozen
+                {
+                    freeze.frozen = frozen;
+                    freeze.in_flight = true;
+                    notifications.push(Work::FrozenBinder(freeze.cookie, frozen));
+                }
+            }
+            other.todo.extend(notifications);
+        }
+    }
+
+    // Unlike death notifications, freeze notifications always go to the process.
+    fn request_freeze_notification(&mut self, proc: ProcId, handle: u32, cookie: binder_uintptr_t) {
+        let Some(r) = self.procs[&proc].refs.get(&handle) else {
+            log::error!("BC_REQUEST_FREEZE_NOTIFICATION invalid handle {handle}");
+            return;
+        };
+        if r.freeze.is_some() {
+            log::error!("BC_REQUEST_FREEZE_NOTIFICATION freeze notification already set");
+            return;
+        }
+
+        // The current state is reported right away.
+        let frozen = self.is_node_frozen(r.node);
+        let p = self.proc_mut(proc);
+        p.refs.get_mut(&handle).unwrap().freeze = Some(FreezeNotification {
+            cookie,
+            frozen,
+            in_flight: true,
+            clearing: false,
+        });
+        p.todo.push_back(Work::FrozenBinder(cookie, frozen));
+    }
+
+    fn clear_freeze_notification(&mut self, proc: ProcId, handle: u32, cookie: binder_uintptr_t) {
+        let p = self.proc_mut(proc);
+        let Some(r) = p.refs.get_mut(&handle) else {
+            log::error!("BC_CLEAR_FREEZE_NOTIFICATION invalid handle {handle}");
+            return;
+        };
+        let Some(freeze) = r.freeze.as_mut().filter(|freeze| freeze.cookie == cookie) else {
+            log::error!("BC_CLEAR_FREEZE_NOTIFICATION freeze notification cookie mismatch");
+            return;
+        };
+        // An in-flight notification is cleared when it is acknowledged.
+        if freeze.in_flight {
+            freeze.clearing = true;
+        } else {
+            r.freeze = None;
+            p.todo.push_back(Work::ClearFreezeNotificationDone(cookie));
+        }
+    }
+
+    fn freeze_notification_done(&mut self, proc: ProcId, cookie: binder_uintptr_t) {
+        let found = self.procs[&proc].refs.iter().find_map(|(handle, r)| {
+            r.freeze
+                .as_ref()
+                .filter(|freeze| freeze.cookie == cookie && freeze.in_flight)
+                .map(|_| (*handle, r.node))
+        });
+        let Some((handle, node)) = found else {
+            log::error!("BC_FREEZE_NOTIFICATION_DONE unknown cookie {cookie:#x}");
+            return;
+        };
+
+        // The state may have changed again while the notification was in flight.
+        let frozen = self.is_node_frozen(node);
+        let p = self.proc_mut(proc);
+        let r = p.refs.get_mut(&handle).unwrap();
+        let freeze = r.freeze.as_mut().unwrap();
+        freeze.in_flight = false;
+        if freeze.clearing {
+            r.freeze = None;
+            p.todo.push_back(Work::ClearFreezeNotificationDone(cookie));
+        } else if freeze.frozen != frozen {
+            freeze.frozen = frozen;
+            freeze.in_flight = true;
+            p.todo.push_back(Work::FrozenBinder(cookie, frozen));
+        }
+    }
+
+    fn release_proc(&mut self, id: ProcId) {
+        let Some(proc) = self.procs.remove(&id) else {
+            return;
+        };
+
+        if self
+            .context_manager
+            .is_some_and(|node| self.nodes.get(&node).is_some_and(|n| n.owner == id))
+        {
+            self.context_manager = None;
+        }
+
+        // Nodes of the process die and their watchers are told about it.
+        for node_id in proc.nodes.values() {
+            let Some(node) = self.nodes.get_mut(node_id) else {
+                continue;
+            };
+            node.dead = true;
+            node.async_todo.clear();
+            if node.pinned {
+                node.pinned = false;
+                node.strong -= 1;
+                node.weak -= 1;
+            }
+
+            for other in self.procs.values_mut() {
+                let mut deaths = Vec::new();
+                for r in other.refs.values_mut() {
+                    if r.node == *node_id && !r.death_sent {
+                        if let Some(cookie) = r.death {
+                            r.death_sent = true;
+                            deaths.push(Work::DeadBinder(cookie));
+                        }
+                    }
+                }
+                other.todo.extend(deaths);
+            }
+            self.update_node(*node_id, None);
+        }
+
+        // References held by the process are released.
+        for r in proc.refs.values() {
+            if let Some(node) = self.nodes.get_mut(&r.node) {
+                if r.strong > 0 {
+                    node.strong -= 1;
+                }
+                node.weak -= 1;
+            }
+            self.update_node(r.node, None);
+        }
+        for buffer in proc.buffers.into_values() {
+            for held in buffer.held {
+                if let Held::Node(node, strong) = held {
+                    self.dec_node(node, strong);
+                }
+            }
+        }
+
+        // Callers waiting for this process get BR_DEAD_REPLY.
+        let ids: Vec<TxnId> = self.transactions.keys().copied().collect();
+        for txn_id in ids {
+            let txn = self.transactions.get_mut(&txn_id).unwrap();
+            if txn.from.is_some_and(|(from, _)| from == id) {
+                txn.from = None;
+            }
+            if txn.to_proc != id {
+                continue;
+            }
+            let txn = self.transactions.remove(&txn_id).unwrap();
+            if let Some((from, thread)) = txn.from {
+                if let Some(caller) = self.procs.get_mut(&from) {
+                    let thread = caller.thread_mut(&thread);
+                    thread.stack.retain(|&t| t != txn_id);
+                    thread.todo.push_back(Work::DeadReply);
+                }
+            }
+        }
+    }
+}
+
+unsafe fn write_work(dst: *mut u8, work: &Work) -> usize {
+    let cmd = match work {
+        Work::TransactionComplete => BR_TRANSACTION_COMPLETE,
+        Work::TransactionPendingFrozen => BR_TRANSACTION_PENDING_FROZEN,
+        Work::OnewaySpamSuspect => BR_ONEWAY_SPAM_SUSPECT,
+        Work::Transaction(..) => BR_TRANSACTION,
+        Work::Reply(_) => BR_REPLY,
+        Work::DeadReply => BR_DEAD_REPLY,
+        Work::FailedReply => BR_FAILED_REPLY,
+        Work::FrozenReply => BR_FROZEN_REPLY,
+        Work::NodeCommand(cmd, ..) => *cmd,
+        Work::DeadBinder(_) => BR_DEAD_BINDER,
+        Work::ClearDeathNotificationDone(_) => BR_CLEAR_DEATH_NOTIFICATION_DONE,
+        Work::FrozenBinder(..) => BR_FROZEN_BINDER,
+        Work::ClearFreezeNotificationDone(_) => BR_CLEAR_FREEZE_NOTIFICATION_DONE,
+    };
+    std::ptr::write_unaligned(dst as *mut u32, cmd);
+    let payload = dst.add(size_of::<u32>());
+
+    match work {
+        Work::Transaction(_, delivery) | Work::Reply(delivery) => {
+            let tr = binder_transaction_data {
+                target: binder_transaction_data__bindgen_ty_1 {
+                    ptr: delivery.target_ptr,
+                },
+                cookie: delivery.cookie,
+                code: delivery.code,
+                flags: delivery.flags,
+                sender_pid: delivery.sender_pid,
+                sender_euid: delivery.sender_euid,
+                data_size: delivery.data_size as _,
+                offsets_size: delivery.offsets_size as _,
+                data: binder_transaction_data__bindgen_ty_2 {
+                    ptr: binder_transaction_data__bindgen_ty_2__bindgen_ty_1 {
+                        buffer: delivery.buffer,
+                        offsets: delivery.buffer + align8(delivery.data_size) as binder_uintptr_t,
+                    },
+                },
+            };
+            std::ptr::write_unaligned(payload as *mut binder_transaction_data, tr);
+        }
+        Work::NodeCommand(_, ptr, cookie) => {
+            std::ptr::write_unaligned(
+                payload as *mut binder_ptr_cookie,
+                binder_ptr_cookie {
+                    ptr: *ptr,
+                    cookie: *cookie,
+                },
+            );
+        }
+        Work::DeadBinder(cookie)
+        | Work::ClearDeathNotificationDone(cookie)
+        | Work::ClearFreezeNotificationDone(cookie) => {
+            std::ptr::write_unaligned(payload as *mut binder_uintptr_t, *cookie);
+        }
+        Work::FrozenBinder(cookie, frozen) => {
+            std::ptr::write_unaligned(
+                payload as *mut binder_frozen_state_info,
+                binder_frozen_state_info {
+                    cookie: *cookie,
+                    is_frozen: *frozen as _,
+                    reserved: 0,
+                },
+            );
+        }
+        _ => {}
+    }
+
+    work.size()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::fd::FromRawFd;
+    use std::os::unix::fs::MetadataExt;
+
+    struct Txn {
+        ptr: binder_uintptr_t,
+        code: u32,
+        sender_pid: pid_t,
+        buffer: binder_uintptr_t,
+        data: Vec<u8>,
+    }
+
+    enum Ret {
+        Cmd(u32),
+        Txn(u32, Txn),
+        PtrCookie(u32, binder_uintptr_t, binder_uintptr_t),
+        Cookie(u32, binder_uintptr_t),
+        Frozen(binder_uintptr_t, bool),
+    }
+
+    // Opens a driver in this process, or through the broker as a forked process would.
+    type Open = fn(&MemoryDevice, pid_t, uid_t) -> MemoryDriver;
+
+    struct Endpoint {
+        driver: MemoryDriver,
+        pending: VecDeque<Ret>,
+    }
+
+    fn push<T: Copy>(buf: &mut Vec<u8>, value: T) {
+        let bytes =
+            unsafe { std::slice::from_raw_parts(&value as *const T as *const u8, size_of::<T>()) };
+        buf.extend_from_slice(bytes);
+    }
+
+    fn transaction(
+        cmd: u32,
+        handle: u32,
+        code: u32,
+        flags: u32,
+        data: &[u8],
+        offsets: &[u64],
+    ) -> Vec<u8> {
+        let mut buf = Vec::new();
+        push(&mut buf, cmd);
+        push(
+            &mut buf,
+            binder_transaction_data {
+                target: binder_transaction_data__bindgen_ty_1 { ptr: handle as _ },
+                cookie: 0,
+                code,
+                flags,
+                sender_pid: 0,
+                sender_euid: 0,
+                data_size: data.len() as _,
+                offsets_size: std::mem::size_of_val(offsets) as _,
+                data: binder_transaction_data__bindgen_ty_2 {
+                    ptr: binder_transaction_data__bindgen_ty_2__bindgen_ty_1 {
+                        buffer: data.as_ptr() as _,
+                        offsets: offsets.as_ptr() as _,
+                    },
+                },
+            },
+        );
+        buf
+    }
+
+    fn flat_object(type_: u32, value: u64, cookie: u64) -> Vec<u8> {
+        let mut buf = Vec::new();
+        push(
+            &mut buf,
+            flat_binder_object {
+                hdr: binder_object_header { type_ },
+                flags: FLAT_BINDER_FLAG_ACCEPTS_FDS,
+                __bindgen_anon_1: flat_binder_object__bindgen_ty_1 { binder: value },
+                cookie,
+            },
+        );
+        buf
+    }
+
+    impl Endpoint {
+        fn new(driver: MemoryDriver) -> Self {
+            Endpoint {
+                driver,
+                pending: VecDeque::new(),
+            }
+        }
+
+        fn write(&self, cmds: &[u8]) {
+            let mut bwr = binder_write_read {
+                write_size: cmds.len() as _,
+                write_consumed: 0,
+                write_buffer: cmds.as_ptr() as _,
+                read_size: 0,
+                read_consumed: 0,
+                read_buffer: 0,
+            };
+            self.driver.write_read(&mut bwr).unwrap();
+            assert_eq!(bwr.write_consumed, bwr.write_size);
+        }
+
+        fn command(&self, cmd: u32) {
+            let mut buf = Vec::new();
+            push(&mut buf, cmd);
+            self.write(&buf);
+        }
+
+        fn next(&mut self) -> Ret {
+            if self.pending.is_empty() {
+                let mut buf = [0u64; 64];
+                let mut bwr = binder_write_read {
+                    write_size: 0,
+                    write_consumed: 0,
+                    write_buffer: 0,
+                    read_size: std::mem::size_of_val(&buf) as _,
+                    read_consumed: 0,
+                    read_buffer: buf.as_mut_ptr() as _,
+                };
+                self.driver.write_read(&mut bwr).unwrap();
+
+                let mut reader = Reader {
+                    base: buf.as_ptr() as _,
+                    size: bwr.read_consumed as _,
+                    pos: 0,
+                };
+                while reader.pos < reader.size {
+                    let cmd: u32 = reader.read().unwrap();
+                    let ret = match cmd {
+                        BR_TRANSACTION | BR_REPLY => {
+                            let tr: binder_transaction_data = reader.read().unwrap();
+                            let data = unsafe {
+                                std::slice::from_raw_parts(
+                                    tr.data.ptr.buffer as *const u8,
+                                    tr.data_size as _,
+                                )
+                            };
+                            Ret::Txn(
+                                cmd,
+                                Txn {
+                                    ptr: unsafe { tr.target.ptr },
+                                    code: tr.code,
+                                    sender_pid: tr.sender_pid,
+                                    buffer: unsafe { tr.data.ptr.buffer },
+                                    data: data.to_vec(),
+                                },
+                            )
+                        }
+                        BR_INCREFS | BR_ACQUIRE | BR_RELEASE | BR_DECREFS => {
+                            let pc: binder_ptr_cookie = reader.read().unwrap();
+                            Ret::PtrCookie(cmd, pc.ptr, pc.cookie)
+                        }
+                        BR_DEAD_BINDER
+                        | BR_CLEAR_DEATH_NOTIFICATION_DONE
+                        | BR_CLEAR_FREEZE_NOTIFICATION_DONE => {
+                            Ret::Cookie(cmd, reader.read().unwrap())
+                        }
+                        BR_FROZEN_BINDER => {
+                            let info: binder_frozen_state_info = reader.read().unwrap();
+                            Ret::Frozen(info.cookie, info.is_frozen != 0)
+                        }
+                        _ => Ret::Cmd(cmd),
+                    };
+                    self.pending.push_back(ret);
+                }
+            }
+            self.pending.pop_front().unwrap()
+        }
+
+        fn expect_cmd(&mut self, expected: u32) {
+            match self.next() {
+                Ret::Cmd(cmd) => assert_eq!(cmd, expected),
+                _ => panic!("expected command {expected:#x}"),
+            }
+        }
+
+        fn expect_txn(&mut self, expected: u32) -> Txn {
+            match self.next() {
+                Ret::Txn(cmd, txn) if cmd == expected => txn,
+                _ => panic!("expected transaction {expected:#x}"),
+            }
+        }
+
+        fn free_buffer(&self, buffer: binder_uintptr_t) {
+            let mut buf = Vec::new();
+            push(&mut buf, BC_FREE_BUFFER);
+            push(&mut buf, buffer);
+            self.write(&buf);
+        }
+
+        fn freeze_notification_done(&self, cookie: binder_uintptr_t) {
+            let mut buf = Vec::new();
+            push(&mut buf, BC_FREEZE_NOTIFICATION_DONE);
+            push(&mut buf, cookie);
+            self.write(&buf);
+        }
+    }
+
+    #[test]
+    fn test_transaction_and_reply() {
+        transaction_and_reply(MemoryDevice::open_with_credentials);
+    }
+
+    #[test]
+    fn test_transaction_and_reply_through_broker() {
+        transaction_and_reply(MemoryDevice::open_remote);
+    }
+
+    fn transaction_and_reply(open: Open) {
+        let device = MemoryDevice::new();
+        let server = open(&device, 100, 1000);
+        server.become_context_manager(false).unwrap();
+        let mut client = Endpoint::new(open(&device, 200, 2000));
+        assert_eq!(
+            client.driver.become_context_manager(false),
+            Err(Errno::BUSY)
+        );
+
+        let server = std::thread::spawn(move || {
+            let mut server = Endpoint::new(server);
+            server.command(BC_ENTER_LOOPER);
+            let txn = server.expect_txn(BR_TRANSACTION);
+            assert_eq!(txn.ptr, 0);
+            assert_eq!(txn.code, 7);
+            assert_eq!(txn.sender_pid, 200);
+            assert_eq!(txn.data, b"ping");
+
+            server.free_buffer(txn.buffer);
+            server.write(&transaction(BC_REPLY, 0, 0, 0, b"pong", &[]));
+            server.expect_cmd(BR_TRANSACTION_COMPLETE);
+            server
+        });
+
+        client.write(&transaction(
+            BC_TRANSACTION,
+            0,
+            7,
+            transaction_flags_TF_ACCEPT_FDS,
+            b"ping",
+            &[],
+        ));
+        client.expect_cmd(BR_TRANSACTION_COMPLETE);
+        let reply = client.expect_txn(BR_REPLY);
+        assert_eq!(reply.data, b"pong");
+        client.free_buffer(reply.buffer);
+
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_binder_object_translation() {
+        let device = MemoryDevice::new();
+        let mut server = Endpoint::new(device.open());
+        server.driver.become_context_manager(false).unwrap();
+        server.command(BC_ENTER_LOOPER);
+        let mut client = Endpoint::new(device.open());
+
+        let data = flat_object(BINDER_TYPE_BINDER, 0x1000, 0x2000);
+        client.write(&transaction(
+            BC_TRANSACTION,
+            0,
+            1,
+            transaction_flags_TF_ONE_WAY,
+            &data,
+            &[0],
+        ));
+        assert!(matches!(
+            client.next(),
+            Ret::PtrCookie(BR_INCREFS, 0x1000, 0x2000)
+        ));
+        assert!(matches!(
+            client.next(),
+            Ret::PtrCookie(BR_ACQUIRE, 0x1000, 0x2000)
+        ));
+        client.expect_cmd(BR_TRANSACTION_COMPLETE);
+
+        let txn = server.expect_txn(BR_TRANSACTION);
+        let obj =
+            unsafe { std::ptr::read_unaligned(txn.data.as_ptr() as *const flat_binder_object) };
+        assert_eq!(obj.hdr.type_, BINDER_TYPE_HANDLE);
+        let handle = unsafe { obj.__bindgen_anon_1.handle };
+        assert_eq!(handle, 1);
+        assert_eq!(server.driver.strong_ref_count_for_handle(handle), Ok(1));
+        assert_eq!(
+            client.driver.strong_ref_count_for_handle(handle),
+            Err(Errno::PERM)
+        );
+
+        // Freeing the buffer drops the only reference to the node.
+        server.free_buffer(txn.buffer);
+        assert_eq!(
+            server.driver.strong_ref_count_for_handle(handle),
+            Err(Errno::INVAL)
+        );
+
+        client.command(BC_ENTER_LOOPER);
+        assert!(matches!(
+            client.next(),
+            Ret::PtrCookie(BR_RELEASE, 0x1000, 0x2000)
+        ));
+        assert!(matches!(
+            client.next(),
+            Ret::PtrCookie(BR_DECREFS, 0x1000, 0x2000)
+        ));
+    }
+
+    #[test]
+    fn test_death_notification() {
+        death_notification(MemoryDevice::open_with_credentials);
+    }
+
+    #[test]
+    fn test_death_notification_through_broker() {
+        death_notification(MemoryDevice::open_remote);
+    }
+
+    fn death_notification(open: Open) {
+        let device = MemoryDevice::new();
+        let server = open(&device, 0, 0);
+        server.become_context_manager(false).unwrap();
+        let mut client = Endpoint::new(open(&device, 0, 0));
+
+        let mut cmds = Vec::new();
+        push(&mut cmds, BC_ACQUIRE);
+        push(&mut cmds, 0u32);
+        push(&mut cmds, BC_REQUEST_DEATH_NOTIFICATION);
+        push(&mut cmds, 0u32);
+        push(&mut cmds, 0xbeef as binder_uintptr_t);
+        push(&mut cmds, BC_ENTER_LOOPER);
+        client.write(&cmds);
+
+        drop(server);
+        assert!(matches!(client.next(), Ret::Cookie(BR_DEAD_BINDER, 0xbeef)));
+
+        client.write(&transaction(BC_TRANSACTION, 0, 1, 0, &[], &[]));
+        client.expect_cmd(BR_DEAD_REPLY);
+
+        // The context manager can be claimed again.
+        open(&device, 0, 0).become_context_manager(false).unwrap();
+    }
+
+    #[test]
+    fn test_file_descriptor_translation() {
+        file_descriptor_translation(MemoryDevice::open_with_credentials);
+    }
+
+    #[test]
+    fn test_file_descriptor_translation_through_broker() {
+        file_descriptor_translation(MemoryDevice::open_remote);
+    }
+
+    fn file_descriptor_translation(open: Open) {
+        let device = MemoryDevice::new();
+        let mut server = Endpoint::new(open(&device, 0, 0));
+        server.driver.become_context_manager(false).unwrap();
+        server.command(BC_ENTER_LOOPER);
+        let mut client = Endpoint::new(open(&device, 0, 0));
+
+        let file = std::fs::File::open(concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml")).unwrap();
+        let data = flat_object(BINDER_TYPE_FD, file.as_raw_fd() as _, 0);
+        client.write(&transaction(
+            BC_TRANSACTION,
+            0,
+            1,
+            transaction_flags_TF_ONE_WAY,
+            &data,
+            &[0],
+        ));
+        client.expect_cmd(BR_TRANSACTION_COMPLETE);
+
+        let txn = server.expect_txn(BR_TRANSACTION);
+        let obj =
+            unsafe { std::ptr::read_unaligned(txn.data.as_ptr() as *const flat_binder_object) };
+        let fd = unsafe { obj.__bindgen_anon_1.handle } as i32;
+        assert_ne!(fd, file.as_raw_fd());
+
+        let received = unsafe { std::fs::File::from_raw_fd(fd) };
+        assert_eq!(
+            received.metadata().unwrap().ino(),
+            file.metadata().unwrap().ino()
+        );
+        server.free_buffer(txn.buffer);
+    }
+
+    #[test]
+    fn test_scatter_gather() {
+        scatter_gather(MemoryDevice::open_with_credentials);
+    }
+
+    #[test]
+    fn test_scatter_gather_through_broker() {
+        scatter_gather(MemoryDevice::open_remote);
+    }
+
+    fn scatter_gather(open: Open) {
+        let device = MemoryDevice::new();
+        let mut server = Endpoint::new(open(&device, 0, 0));
+        server.driver.become_context_manager(false).unwrap();
+        server.command(BC_ENTER_LOOPER);
+        let mut client = Endpoint::new(open(&device, 0, 0));
+
+        let parent = [0u64, 0u64];
+        let child = *b"embedded";
+        let buffer_object =
+            |buffer: u64, length: usize, flags: u32, parent: u64| binder_buffer_object {
+                hdr: binder_object_header {
+                    type_: BINDER_TYPE_PTR,
+                },
+                flags,
+                buffer,
+                length: length as _,
+                parent,
+                parent_offset: 8,
+            };
+        let mut data = Vec::new();
+        push(
+            &mut data,
+            buffer_object(parent.as_ptr() as _, std::mem::size_of_val(&parent), 0, 0),
+        );
+        push(
+            &mut data,
+            buffer_object(
+                child.as_ptr() as _,
+                child.len(),
+                BINDER_BUFFER_FLAG_HAS_PARENT,
+                0,
+            ),
+        );
+        let offsets = [0, size_of::<binder_buffer_object>() as u64];
+
+        let sg = |buffers_size: u64| {
+            let mut buf = transaction(
+                BC_TRANSACTION_SG,
+                0,
+                1,
+                transaction_flags_TF_ONE_WAY,
+                &data,
+                &offsets,
+            );
+            push(&mut buf, buffers_size);
+            buf
+        };
+
+        // The buffers don't fit into the scatter-gather size.
+        client.write(&sg(16));
+        client.expect_cmd(BR_FAILED_REPLY);
+
+        client.write(&sg(24));
+        client.expect_cmd(BR_TRANSACTION_COMPLETE);
+
+        let txn = server.expect_txn(BR_TRANSACTION);
+        let read = |index: usize| unsafe {
+            std::ptr::read_unaligned(
+                txn.data.as_ptr().add(offsets[index] as usize) as *const binder_buffer_object
+            )
+        };
+        let (parent_obj, child_obj) = (read(0), read(1));
+        let sg_start = data.len() + std::mem::size_of_val(&offsets);
+        assert_eq!(parent_obj.buffer, txn.buffer + sg_start as u64);
+        assert_eq!(child_obj.buffer, parent_obj.buffer + 16);
+        let (parent_copy, child_copy) = unsafe {
+            (
+                std::slice::from_raw_parts(parent_obj.buffer as *const u64, 2),
+                std::slice::from_raw_parts(child_obj.buffer as *const u8, child.len()),
+            )
+        };
+        assert_eq!(parent_copy, [0, child_obj.buffer]);
+        assert_eq!(child_copy, child);
+        server.free_buffer(txn.buffer);
+    }
+
+    #[test]
+    fn test_extended_error() {
+        let device = MemoryDevice::new();
+        let server = Endpoint::new(device.open());
+        server.driver.become_context_manager(false).unwrap();
+        server.command(BC_ENTER_LOOPER);
+        let mut client = Endpoint::new(device.open());
+
+        let error = client.driver.extended_error().unwrap();
+        assert_eq!((error.command, error.param), (BR_OK, 0));
+
+        client.write(&transaction(BC_TRANSACTION, 5, 1, 0, &[], &[]));
+        client.expect_cmd(BR_FAILED_REPLY);
+        let error = client.driver.extended_error().unwrap();
+        assert_ne!(error.id, 0);
+        assert_eq!(error.command, BR_FAILED_REPLY);
+        assert_eq!(error.param, -Errno::INVAL.raw_os_error());
+        // Reading the error resets it.
+        let error = client.driver.extended_error().unwrap();
+        assert_eq!((error.id, error.command, error.param), (0, BR_OK, 0));
+
+        let data = vec![0u8; 1024 * 1024];
+        client.write(&transaction(BC_TRANSACTION, 0, 1, 0, &data, &[]));
+        client.expect_cmd(BR_FAILED_REPLY);
+        let error = client.driver.extended_error().unwrap();
+        assert_eq!(error.param, -Errno::NOSPC.raw_os_error());
+
+        drop(server);
+        client.write(&transaction(BC_TRANSACTION, 0, 1, 0, &[], &[]));
+        client.expect_cmd(BR_DEAD_REPLY);
+        let error = client.driver.extended_error().unwrap();
+        assert_eq!((error.command, error.param), (BR_DEAD_REPLY, 0));
+    }
+
+    #[test]
+    fn test_freeze() {
+        let device = MemoryDevice::new();
+        let mut server = Endpoint::new(device.open_with_credentials(100, 1000));
+        server.driver.become_context_manager(false).unwrap();
+        server.command(BC_ENTER_LOOPER);
+        let mut client = Endpoint::new(device.open_with_credentials(200, 2000));
+
+        let mut cmds = Vec::new();
+        push(&mut cmds, BC_ACQUIRE);
+        push(&mut cmds, 0u32);
+        push(&mut cmds, BC_REQUEST_FREEZE_NOTIFICATION);
+        push(&mut cmds, 0u32);
+        push(&mut cmds, 0xf00d as binder_uintptr_t);
+        push(&mut cmds, BC_ENTER_LOOPER);
+        client.write(&cmds);
+        assert!(matches!(client.next(), Ret::Frozen(0xf00d, false)));
+        client.freeze_notification_done(0xf00d);
+
+        assert_eq!(client.driver.freeze(300, true, 0), Err(Errno::INVAL));
+        client.driver.freeze(100, true, 0).unwrap();
+        assert!(matches!(client.next(), Ret::Frozen(0xf00d, true)));
+
+        client.write(&transaction(BC_TRANSACTION, 0, 1, 0, &[], &[]));
+        client.expect_cmd(BR_FROZEN_REPLY);
+        client.write(&transaction(
+            BC_TRANSACTION,
+            0,
+            2,
+            transaction_flags_TF_ONE_WAY,
+            b"later",
+            &[],
+        ));
+        client.expect_cmd(BR_TRANSACTION_PENDING_FROZEN);
+
+        let info = client.driver.frozen_info(100).unwrap();
+        assert_eq!((info.sync_recv, info.async_recv), (1, 1));
+
+        // The process is unfrozen before the last notification is acknowledged.
+        client.driver.freeze(100, false, 0).unwrap();
+        client.freeze_notification_done(0xf00d);
+        assert!(matches!(client.next(), Ret::Frozen(0xf00d, false)));
+        client.freeze_notification_done(0xf00d);
+
+        // The oneway transaction is delivered after unfreezing.
+        let txn = server.expect_txn(BR_TRANSACTION);
+        assert_eq!(txn.code, 2);
+        assert_eq!(txn.data, b"later");
+        server.free_buffer(txn.buffer);
+
+        let mut cmds = Vec::new();
+        push(&mut cmds, BC_CLEAR_FREEZE_NOTIFICATION);
+        push(&mut cmds, 0u32);
+        push(&mut cmds, 0xf00d as binder_uintptr_t);
+        client.write(&cmds);
+        assert!(matches!(
+            client.next(),
+            Ret::Cookie(BR_CLEAR_FREEZE_NOTIFICATION_DONE, 0xf00d)
+        ));
+    }
+
+    #[test]
+    fn test_freeze_with_pending_transaction() {
+        let device = MemoryDevice::new();
+        let mut server = Endpoint::new(device.open_with_credentials(100, 1000));
+        server.driver.become_context_manager(false).unwrap();
+        server.command(BC_ENTER_LOOPER);
+        let mut client = Endpoint::new(device.open_with_credentials(200, 2000));
+
+        client.write(&transaction(BC_TRANSACTION, 0, 1, 0, &[], &[]));
+        client.expect_cmd(BR_TRANSACTION_COMPLETE);
+        let txn = server.expect_txn(BR_TRANSACTION);
+
+        // The server is busy with the transaction, so it can't be frozen.
+        assert_eq!(client.driver.freeze(100, true, 10), Err(Errno::AGAIN));
+        assert_eq!(client.driver.frozen_info(100).unwrap().sync_recv, 2);
+
+        server.free_buffer(txn.buffer);
+        server.write(&transaction(BC_REPLY, 0, 0, 0, &[], &[]));
+        server.expect_cmd(BR_TRANSACTION_COMPLETE);
+        let reply = client.expect_txn(BR_REPLY);
+        client.free_buffer(reply.buffer);
+
+        client.driver.freeze(100, true, 10).unwrap();
+        assert_eq!(client.driver.frozen_info(100).unwrap().sync_recv, 0);
+    }
+}
@@ -0,0 +1,173 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+//! # Binder Driver Backends
+//!
+//! [`ProcessState`](crate::ProcessState) and the per-thread state never talk to
+//! `/dev/binder` directly. Every `BINDER_WRITE_READ` exchange and every control
+//! ioctl goes through a [`BinderDriver`], so the transport underneath the
+//! Binder protocol can be replaced.
+//!
+//! Two backends are provided:
+//!
+//! - [`KernelDriver`]: the Linux/Android binder driver (binderfs or `/dev/binder`).
+//! - [`MemoryDevice`]/[`MemoryDriver`]: a pure Rust implementation of the driver
+//!   state machine. It needs no kernel support, which makes it suitable for
+//!   CI containers, macOS development machines and deterministic tests.
+//!   Processes forked from the one which created the device share it too.
+//!
+//! ```rust,no_run
+//! use std::sync::Arc;
+//! use rsbinder::{driver::MemoryDevice, ProcessState};
+//!
+//! let device = MemoryDevice::new();
+//! ProcessState::init_with_driver(Arc::new(device.open()), 0);
+//! ```
+
+mod broker;
+mod kernel;
+mod memory;
+
+pub use kernel::KernelDriver;
+pub use memory::{MemoryDevice, MemoryDriver};
+
+pub use crate::sys::binder::{binder_extended_error, binder_frozen_status_info, binder_write_read};
+
+/// Result type used by driver backends. Errors are reported as raw errno values,
+/// exactly as the kernel driver would report them.
+pub type Result<T> = std::result::Result<T, rustix::io::Errno>;
+
+/// Default size of the receive buffer of a process: 1 MiB minus two pages.
+pub fn default_vm_size() -> usize {
+    (1024 * 1024) - rustix::param::page_size() * 2
+}
+
+/// Optional features of a binder driver, as listed in `binderfs/features`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DriverFeature {
+    /// Frozen state notifications (`BC_REQUEST_FREEZE_NOTIFICATION`).
+    FreezeNotification,
+    /// Extended errors of failed transactions (`BINDER_GET_EXTENDED_ERROR`).
+    ExtendedError,
+}
+
+impl DriverFeature {
+    /// The name of the feature file in `binderfs/features`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            DriverFeature::FreezeNotification => "freeze_notification",
+            DriverFeature::ExtendedError => "extended_error",
+        }
+    }
+}
+
+/// The operations rsbinder needs from a binder driver.
+///
+/// The methods mirror the binder ioctls. `write_read` must implement the complete
+/// `BC_*`/`BR_*` command protocol on behalf of the calling thread, including
+/// blocking until work is available when `read_size` is non-zero.
+pub trait BinderDriver: Send + Sync {
+    /// Short name of the driver. It is used to name binder threads.
+    fn name(&self) -> &str;
+
+    /// `BINDER_WRITE_READ`: consume commands from the write buffer and fill
+    /// the read buffer with return commands for the calling thread.
+    fn write_read(&self, bwr: &mut binder_write_read) -> Result<()>;
+
+    /// `BINDER_SET_MAX_THREADS`: the number of looper threads the driver may
+    /// ask the process to spawn.
+    fn set_max_threads(&self, max_threads: u32) -> Result<()>;
+
+    /// `BINDER_ENABLE_ONEWAY_SPAM_DETECTION`
+    fn enable_oneway_spam_detection(&self, enable: bool) -> Result<()>;
+
+    /// `BINDER_SET_CONTEXT_MGR_EXT`: make this process the context manager (handle 0).
+    /// With `security_context`, the transactions to it carry the security
+    /// context of the caller.
+    fn become_context_manager(&self, security_context: bool) -> Result<()>;
+
+    /// `BINDER_GET_NODE_INFO_FOR_REF`: the strong reference count of the node behind `handle`.
+    /// It is only permitted for the context manager.
+    fn strong_ref_count_for_handle(&self, handle: u32) -> Result<usize>;
+
+    /// `BINDER_FREEZE`: freeze (`enable`) or unfreeze the binder state of the
+    /// processes with `pid`. Freezing waits up to `timeout_ms` for transactions
+    /// queued to the process and fails with `EAGAIN` if the process is still
+    /// serving a synchronous transaction.
+    fn freeze(&self, pid: u32, enable: bool, timeout_ms: u32) -> Result<()>;
+
+    /// `BINDER_GET_FROZEN_INFO`: what the processes with `pid` received since they were frozen.
+    fn frozen_info(&self, pid: u32) -> Result<binder_frozen_status_info>;
+
+    /// `BINDER_GET_EXTENDED_ERROR`: why the last transaction of the calling
+    /// thread failed. Reading the error resets it to `BR_OK`.
+    fn extended_error(&self) -> Result<binder_extended_error>;
+
+    /// Whether the driver supports an optional `feature`.
+    fn is_feature_enabled(&self, feature: DriverFeature) -> bool;
+
+    /// Make the threads of the process waiting in `write_read` return with
+    /// `BR_NOOP`, like the kernel driver does when a duplicate of the binder
+    /// fd is closed.
+    fn wake_threads(&self) -> Result<()>;
+}
+
+/// The driver of a [`crate::ProcessState`] which was shut down.
+pub(crate) struct ClosedDriver {
+    name: String,
+}
+
+impl ClosedDriver {
+    pub(crate) fn new(name: &str) -> Self {
+        ClosedDriver {
+            name: name.to_owned(),
+        }
+    }
+}
+
+impl BinderDriver for ClosedDriver {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn write_read(&self, _bwr: &mut binder_write_read) -> Result<()> {
+        Err(rustix::io::Errno::BADF)
+    }
+
+    fn set_max_threads(&self, _max_threads: u32) -> Result<()> {
+        Err(rustix::io::Errno::BADF)
+    }
+
+    fn enable_oneway_spam_detection(&self, _enable: bool) -> Result<()> {
+        Err(rustix::io::Errno::BADF)
+    }
+
+    fn become_context_manager(&self, _security_context: bool) -> Result<()> {
+        Err(rustix::io::Errno::BADF)
+    }
+
+    fn strong_ref_count_for_handle(&self, _handle: u32) -> Result<usize> {
+        Err(rustix::io::Errno::BADF)
+    }
+
+    fn freeze(&self, _pid: u32, _enable: bool, _timeout_ms: u32) -> Result<()> {
+        Err(rustix::io::Errno::BADF)
+    }
+
+    fn frozen_info(&self, _pid: u32) -> Result<binder_frozen_status_info> {
+        Err(rustix::io::Errno::BADF)
+    }
+
+    fn extended_error(&self) -> Result<binder_extended_error> {
+        Err(rustix::io::Errno::BADF)
+    }
+
+    fn is_feature_enabled(&self, _feature: DriverFeature) -> bool {
+        false
+    }
+
+    fn wake_threads(&self) -> Result<()> {
+        Ok(())
+    }
+}
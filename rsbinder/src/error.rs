@@ -70,6 +70,57 @@ pub enum StatusCode {
 
 impl Error for StatusCode {}
 
+impl StatusCode {
+    /// Why the last transaction of the calling thread failed, as reported by the
+    /// binder driver. It is only available for `FailedTransaction` and `DeadObject`,
+    /// and only until the thread starts another transaction.
+    pub fn extended_error(&self) -> Option<ExtendedError> {
+        match self {
+            StatusCode::FailedTransaction | StatusCode::DeadObject => {
+                crate::thread_state::last_extended_error()
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Details of a failed transaction from `BINDER_GET_EXTENDED_ERROR`.
+///
+/// A `BR_FAILED_REPLY` can mean anything from an invalid handle to an exhausted
+/// transaction buffer. The driver records the reason per thread, which lets
+/// callers tell these cases apart without kernel tracepoints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ExtendedError {
+    /// Debug id of the failed transaction.
+    pub id: u32,
+    /// The return command delivered to the caller, e.g. `BR_FAILED_REPLY`.
+    pub command: u32,
+    /// Negative errno of the failure, or 0 if the driver gave no reason.
+    pub param: i32,
+}
+
+impl ExtendedError {
+    /// The errno of the failure, if the driver reported one.
+    pub fn errno(&self) -> Option<rustix::io::Errno> {
+        (self.param < 0).then(|| rustix::io::Errno::from_raw_os_error(-self.param))
+    }
+}
+
+impl fmt::Display for ExtendedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} (transaction {})",
+            crate::thread_state::return_to_str(self.command),
+            self.id
+        )?;
+        match self.errno() {
+            Some(errno) => write!(f, ": {errno}"),
+            None => Ok(()),
+        }
+    }
+}
+
 impl fmt::Display for StatusCode {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
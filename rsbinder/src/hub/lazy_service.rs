@@ -0,0 +1,261 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+//! Services which exit when nobody uses them.
+//!
+//! [`LazyServiceRegistrar`] is the counterpart of Android's
+//! `LazyServiceRegistrar`. It registers services together with a client
+//! callback, and the service manager tells it when a service gets its first
+//! client or loses its last one. When none of the services of the process has
+//! clients, the registrar unregisters them all and exits the process. If a new
+//! client shows up while the services are unregistered, they are registered
+//! again and the process keeps running.
+//!
+//! ```rust,no_run
+//! # use rsbinder::*;
+//! # fn example(service: SIBinder) -> std::result::Result<(), Status> {
+//! ProcessState::init_default();
+//! hub::LazyServiceRegistrar::global().register_service("example_service", service)?;
+//! ProcessState::join_thread_pool()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use super::{BnClientCallback, IClientCallback, ServiceManager};
+use crate::*;
+
+type ActiveServicesCallback = Arc<dyn Fn(bool) -> bool + Send + Sync>;
+
+/// Registers services which are shut down when none of them has clients.
+///
+/// All services of a process should be registered with the same registrar,
+/// because the process exits only when none of its services is in use.
+#[derive(Clone)]
+pub struct LazyServiceRegistrar {
+    counter: Arc<ClientCounter>,
+    // The counter has only a weak reference of its callback.
+    _callback: Strong<dyn IClientCallback>,
+}
+
+impl LazyServiceRegistrar {
+    /// The registrar of the default service manager.
+    pub fn global() -> &'static LazyServiceRegistrar {
+        static GLOBAL: OnceLock<LazyServiceRegistrar> = OnceLock::new();
+        GLOBAL.get_or_init(|| Self::with_service_manager(super::default()))
+    }
+
+    /// Create a registrar which registers services with `service_manager`.
+    pub fn with_service_manager(service_manager: Arc<ServiceManager>) -> Self {
+        let counter = Arc::new(ClientCounter {
+            service_manager,
+            state: Mutex::new(CounterState::default()),
+            callback: OnceLock::new(),
+        });
+        let callback = BnClientCallback::new_binder(ClientCallback(counter.clone()));
+        let _ = counter.callback.set(Strong::downgrade(&callback));
+        LazyServiceRegistrar {
+            counter,
+            _callback: callback,
+        }
+    }
+
+    /// Register `service` as `name`, and watch its clients.
+    pub fn register_service(
+        &self,
+        name: &str,
+        service: SIBinder,
+    ) -> std::result::Result<(), Status> {
+        let mut state = self.counter.state.lock().unwrap();
+        self.counter.register(name, &service)?;
+        // A service is never removed, so that it is registered again after a
+        // failed shutdown.
+        state.services.insert(
+            name.to_owned(),
+            LazyService {
+                binder: service,
+                registered: true,
+                has_clients: false,
+            },
+        );
+        Ok(())
+    }
+
+    /// Keep the process running even if its services have no clients.
+    ///
+    /// When it is turned off again and no service has clients, the registrar
+    /// tries to shut down right away.
+    pub fn force_persist(&self, persist: bool) {
+        self.counter.state.lock().unwrap().force_persist = persist;
+        if !persist {
+            self.counter.maybe_try_shutdown();
+        }
+    }
+
+    /// Set a hook which is called when the services of the process get their
+    /// first client (`true`) or lose their last one (`false`).
+    ///
+    /// If the hook returns `true`, it handled the change and the registrar
+    /// doesn't try to shut down. A hook which wants to shut down in its own way
+    /// can call [`try_unregister`](Self::try_unregister), and
+    /// [`re_register`](Self::re_register) if it changes its mind.
+    pub fn set_active_services_callback(
+        &self,
+        callback: impl Fn(bool) -> bool + Send + Sync + 'static,
+    ) {
+        let mut state = self.counter.state.lock().unwrap();
+        state.active_services_callback = Some(Arc::new(callback));
+        state.previous_has_clients = None;
+    }
+
+    /// Try to unregister all the services. It fails when one of them has
+    /// clients; the services unregistered by then stay unregistered until
+    /// [`re_register`](Self::re_register) is called.
+    pub fn try_unregister(&self) -> bool {
+        self.counter
+            .try_unregister(&mut self.counter.state.lock().unwrap())
+    }
+
+    /// Register again the services which were unregistered.
+    pub fn re_register(&self) {
+        self.counter
+            .re_register(&mut self.counter.state.lock().unwrap());
+    }
+}
+
+struct LazyService {
+    binder: SIBinder,
+    registered: bool,
+    has_clients: bool,
+}
+
+#[derive(Default)]
+struct CounterState {
+    services: BTreeMap<String, LazyService>,
+    force_persist: bool,
+    active_services_callback: Option<ActiveServicesCallback>,
+    // What the active services callback was told last.
+    previous_has_clients: Option<bool>,
+}
+
+impl CounterState {
+    fn services_with_clients(&self) -> usize {
+        self.services.values().filter(|s| s.has_clients).count()
+    }
+}
+
+struct ClientCounter {
+    service_manager: Arc<ServiceManager>,
+    state: Mutex<CounterState>,
+    callback: OnceLock<Weak<dyn IClientCallback>>,
+}
+
+impl ClientCounter {
+    fn register(&self, name: &str, service: &SIBinder) -> std::result::Result<(), Status> {
+        let callback = self
+            .callback
+            .get()
+            .ok_or(StatusCode::DeadObject)?
+            .upgrade()?;
+        self.service_manager.add_service(name, service.clone())?;
+        self.service_manager
+            .register_client_callback(name, service, &callback)?;
+        Ok(())
+    }
+
+    fn try_unregister(&self, state: &mut CounterState) -> bool {
+        for (name, service) in state.services.iter_mut() {
+            if !service.registered {
+                continue;
+            }
+            if let Err(e) = self
+                .service_manager
+                .try_unregister_service(name, &service.binder)
+            {
+                log::info!("Failed to unregister service {name}: {e}");
+                return false;
+            }
+            service.registered = false;
+        }
+        true
+    }
+
+    fn re_register(&self, state: &mut CounterState) {
+        for (name, service) in state.services.iter_mut() {
+            if service.registered {
+                continue;
+            }
+            if let Err(e) = self.register(name, &service.binder) {
+                // Clients would never get the service again.
+                log::error!("Bad state: could not re-register service {name}: {e}");
+                std::process::abort();
+            }
+            service.registered = true;
+        }
+    }
+
+    fn maybe_try_shutdown(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.force_persist {
+            log::info!("Shutdown prevented by the force persist flag");
+            return;
+        }
+
+        let has_clients = state.services_with_clients() > 0;
+        let mut handled = false;
+        if let Some(active_services_callback) = state.active_services_callback.clone() {
+            if state.previous_has_clients != Some(has_clients) {
+                state.previous_has_clients = Some(has_clients);
+                // The hook may use the registrar.
+                drop(state);
+                handled = active_services_callback(has_clients);
+                state = self.state.lock().unwrap();
+            }
+        }
+
+        if !handled && state.services_with_clients() == 0 {
+            log::info!("Trying to shut down, no service of the process has clients");
+            if self.try_unregister(&mut state) {
+                log::info!("Unregistered all services and exiting");
+                std::process::exit(0);
+            }
+            self.re_register(&mut state);
+        }
+    }
+}
+
+struct ClientCallback(Arc<ClientCounter>);
+
+impl Interface for ClientCallback {}
+
+impl IClientCallback for ClientCallback {
+    fn onClients(&self, registered: &SIBinder, has_clients: bool) -> status::Result<()> {
+        {
+            let mut state = self.0.state.lock().unwrap();
+            let Some((name, service)) = state
+                .services
+                .iter_mut()
+                .find(|(_, service)| service.binder == *registered)
+            else {
+                log::error!("Got a client callback of a service which wasn't registered");
+                return Ok(());
+            };
+            if service.has_clients == has_clients {
+                log::warn!("Service {name} was told again that it has clients: {has_clients}");
+                return Ok(());
+            }
+            service.has_clients = has_clients;
+            let name = name.clone();
+            log::info!(
+                "Process has {} (of {}) services in use after {name} has clients: {has_clients}",
+                state.services_with_clients(),
+                state.services.len()
+            );
+        }
+
+        self.0.maybe_try_shutdown();
+        Ok(())
+    }
+}
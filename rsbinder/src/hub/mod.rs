@@ -46,7 +46,7 @@
 //! }
 //! ```
 
-use std::sync::{Arc, OnceLock};
+use std::sync::Arc;
 
 #[cfg(all(target_os = "android", feature = "android_11"))]
 mod servicemanager_11;
@@ -81,13 +81,19 @@ pub mod android_16 {
     pub use super::servicemanager_16::*;
 }
 
+mod lazy_service;
+pub use lazy_service::LazyServiceRegistrar;
+
+mod service_handle;
+pub use service_handle::{RetryPolicy, ServiceError, ServiceHandle};
+
 use crate::*;
 
 // Export Android 16 types as the default public API
 pub use android_16::{
-    BnServiceCallback, IServiceCallback, ServiceDebugInfo, DUMP_FLAG_PRIORITY_ALL,
-    DUMP_FLAG_PRIORITY_CRITICAL, DUMP_FLAG_PRIORITY_DEFAULT, DUMP_FLAG_PRIORITY_HIGH,
-    DUMP_FLAG_PRIORITY_NORMAL,
+    BnClientCallback, BnServiceCallback, IClientCallback, IServiceCallback, ServiceDebugInfo,
+    DUMP_FLAG_PRIORITY_ALL, DUMP_FLAG_PRIORITY_CRITICAL, DUMP_FLAG_PRIORITY_DEFAULT,
+    DUMP_FLAG_PRIORITY_HIGH, DUMP_FLAG_PRIORITY_NORMAL, DUMP_FLAG_PROTO,
 };
 
 /// Android SDK version constants
@@ -138,10 +144,13 @@ pub enum ServiceManager {
 /// for subsequent calls. The correct version-specific implementation is automatically
 /// selected based on the detected Android SDK version.
 pub fn default() -> Arc<ServiceManager> {
-    static GLOBAL_SM: OnceLock<Arc<ServiceManager>> = OnceLock::new();
+    for_context(ProcessState::as_self())
+}
 
-    GLOBAL_SM.get_or_init(|| {
-        let process = ProcessState::as_self();
+/// Returns the ServiceManager of the binder context `process`, e.g. one opened by
+/// [`ProcessStateBuilder::init_context`](crate::ProcessStateBuilder::init_context).
+pub fn for_context(process: &'static ProcessState) -> Arc<ServiceManager> {
+    process.service_manager().get_or_init(|| {
         let context = process.context_object()
             .expect("Failed to get context_object during ServiceManager initialization");
         #[cfg(target_os = "android")]
@@ -439,6 +448,80 @@ impl ServiceManager {
             }
         }
     }
+
+    /// Requests callbacks when the service `name`, registered by this process,
+    /// gets its first client or loses its last one.
+    ///
+    /// This method is version-agnostic and works across all supported Android versions.
+    pub fn register_client_callback(
+        &self,
+        name: &str,
+        service: &SIBinder,
+        callback: &crate::Strong<dyn IClientCallback>,
+    ) -> Result<()> {
+        match self {
+            #[cfg(all(target_os = "android", feature = "android_11"))]
+            ServiceManager::Android11(sm) => {
+                // SAFETY: This transmutation is safe because both types represent the same AIDL interface
+                let callback = unsafe {
+                    &*(callback as *const _
+                        as *const crate::Strong<dyn android_11::IClientCallback>)
+                };
+                android_11::register_client_callback(sm, name, service, callback)
+            }
+            #[cfg(all(target_os = "android", feature = "android_12"))]
+            ServiceManager::Android12(sm) => {
+                // SAFETY: This transmutation is safe because both types represent the same AIDL interface
+                let callback = unsafe {
+                    &*(callback as *const _
+                        as *const crate::Strong<dyn android_12::IClientCallback>)
+                };
+                android_12::register_client_callback(sm, name, service, callback)
+            }
+            #[cfg(all(target_os = "android", feature = "android_13"))]
+            ServiceManager::Android13(sm) => {
+                // SAFETY: This transmutation is safe because both types represent the same AIDL interface
+                let callback = unsafe {
+                    &*(callback as *const _
+                        as *const crate::Strong<dyn android_13::IClientCallback>)
+                };
+                android_13::register_client_callback(sm, name, service, callback)
+            }
+            #[cfg(all(target_os = "android", feature = "android_14"))]
+            ServiceManager::Android14(sm) => {
+                // SAFETY: This transmutation is safe because both types represent the same AIDL interface
+                let callback = unsafe {
+                    &*(callback as *const _
+                        as *const crate::Strong<dyn android_14::IClientCallback>)
+                };
+                android_14::register_client_callback(sm, name, service, callback)
+            }
+            ServiceManager::Android16(sm) => {
+                android_16::register_client_callback(sm, name, service, callback)
+            }
+        }
+    }
+
+    /// Unregisters the service `name`, registered by this process, if it has no clients.
+    ///
+    /// This method is version-agnostic and works across all supported Android versions.
+    pub fn try_unregister_service(
+        &self,
+        name: &str,
+        service: &SIBinder,
+    ) -> std::result::Result<(), Status> {
+        match self {
+            #[cfg(all(target_os = "android", feature = "android_11"))]
+            ServiceManager::Android11(sm) => android_11::try_unregister_service(sm, name, service),
+            #[cfg(all(target_os = "android", feature = "android_12"))]
+            ServiceManager::Android12(sm) => android_12::try_unregister_service(sm, name, service),
+            #[cfg(all(target_os = "android", feature = "android_13"))]
+            ServiceManager::Android13(sm) => android_13::try_unregister_service(sm, name, service),
+            #[cfg(all(target_os = "android", feature = "android_14"))]
+            ServiceManager::Android14(sm) => android_14::try_unregister_service(sm, name, service),
+            ServiceManager::Android16(sm) => android_16::try_unregister_service(sm, name, service),
+        }
+    }
 }
 
 //------------------------------------------------------------------------------
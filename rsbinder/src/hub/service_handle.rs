@@ -0,0 +1,274 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+//! Service handles which survive restarts of the service.
+//!
+//! A [`ServiceHandle`] keeps a proxy of a named service. When the service dies
+//! the handle forgets the proxy, and it fetches the service from the service
+//! manager again once it is registered anew. Idempotent calls made with
+//! [`ServiceHandle::call`] are retried across the restart.
+//!
+//! ```rust,no_run
+//! # use rsbinder::*;
+//! # fn example<T: FromIBinder + ?Sized + 'static>() -> rsbinder::Result<()> {
+//! let handle = hub::ServiceHandle::<T>::new("example_service")?;
+//! let binder = handle.call(|service| Ok::<_, StatusCode>(service.as_binder()))?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use super::{BnServiceCallback, IServiceCallback, ServiceManager};
+use crate::*;
+
+/// How [`ServiceHandle`] waits for a service and retries calls which failed
+/// because the service died.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// How many times a call is retried after the service died.
+    pub max_retries: u32,
+    /// How long to wait for the service to be registered again.
+    pub wait_timeout: Duration,
+    /// How often the service manager is asked for the service while waiting,
+    /// in case the registration notification is late.
+    pub poll_interval: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            wait_timeout: Duration::from_secs(5),
+            poll_interval: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Errors of binder calls which tell whether the remote service died.
+pub trait ServiceError: From<StatusCode> {
+    /// The call failed because the remote service is dead.
+    fn is_dead_object(&self) -> bool;
+}
+
+impl ServiceError for StatusCode {
+    fn is_dead_object(&self) -> bool {
+        *self == StatusCode::DeadObject
+    }
+}
+
+impl ServiceError for Status {
+    fn is_dead_object(&self) -> bool {
+        self.transaction_error() == StatusCode::DeadObject
+    }
+}
+
+/// A handle of a named service which follows the restarts of the service.
+pub struct ServiceHandle<T: FromIBinder + ?Sized + 'static> {
+    service_manager: Arc<ServiceManager>,
+    policy: RetryPolicy,
+    watch: Arc<ServiceWatch<T>>,
+    callback: Strong<dyn IServiceCallback>,
+}
+
+impl<T: FromIBinder + ?Sized + 'static> ServiceHandle<T> {
+    /// Create a handle of the service `name` of the default service manager.
+    ///
+    /// The service doesn't have to be registered yet.
+    pub fn new(name: &str) -> Result<Self> {
+        Self::with_service_manager(super::default(), name)
+    }
+
+    /// Create a handle of the service `name` of `service_manager`.
+    pub fn with_service_manager(service_manager: Arc<ServiceManager>, name: &str) -> Result<Self> {
+        let watch = Arc::new(ServiceWatch::new(name));
+        let callback = BnServiceCallback::new_binder(Registration(watch.clone()));
+        service_manager.register_for_notifications(name, &callback)?;
+
+        Ok(ServiceHandle {
+            service_manager,
+            policy: RetryPolicy::default(),
+            watch,
+            callback,
+        })
+    }
+
+    /// Set how the handle waits for the service and retries calls.
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.policy = policy;
+    }
+
+    /// The name of the service.
+    pub fn name(&self) -> &str {
+        &self.watch.name
+    }
+
+    /// The proxy of the service. If the service is dead or not registered yet,
+    /// it waits for the service up to [`RetryPolicy::wait_timeout`] and fails
+    /// with `DeadObject` then.
+    pub fn get(&self) -> Result<Strong<T>> {
+        let deadline = Instant::now() + self.policy.wait_timeout;
+        loop {
+            if let Some(service) = self.watch.state.lock().unwrap().service.clone() {
+                return Ok(service);
+            }
+
+            if let Some(binder) = self.service_manager.check_service(&self.watch.name) {
+                match self.watch.install(T::try_from(binder)?) {
+                    // The service manager didn't notice the death yet.
+                    Err(StatusCode::DeadObject) => {}
+                    result => return result,
+                }
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                log::warn!("Service {} is not available", self.watch.name);
+                return Err(StatusCode::DeadObject);
+            }
+            let state = self.watch.state.lock().unwrap();
+            let wait = (deadline - now).min(self.policy.poll_interval);
+            drop(
+                self.watch
+                    .registered
+                    .wait_timeout_while(state, wait, |state| state.service.is_none())
+                    .unwrap(),
+            );
+        }
+    }
+
+    /// Run `f` with the proxy of the service. If `f` fails because the service
+    /// died, it is run again with the proxy of the restarted service, up to
+    /// [`RetryPolicy::max_retries`] times.
+    ///
+    /// `f` must be idempotent: a call which failed with `DeadObject` might have
+    /// been served before the service died.
+    pub fn call<R, E: ServiceError>(
+        &self,
+        f: impl Fn(&Strong<T>) -> std::result::Result<R, E>,
+    ) -> std::result::Result<R, E> {
+        let mut retries = 0;
+        loop {
+            let service = self.get()?;
+            match f(&service) {
+                Err(e) if e.is_dead_object() && retries < self.policy.max_retries => {
+                    retries += 1;
+                    log::warn!(
+                        "Service {} died, retrying the call ({retries}/{})",
+                        self.watch.name,
+                        self.policy.max_retries
+                    );
+                    self.watch
+                        .forget(&SIBinder::downgrade(&service.as_binder()));
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl<T: FromIBinder + ?Sized + 'static> Drop for ServiceHandle<T> {
+    fn drop(&mut self) {
+        if let Err(e) = self
+            .service_manager
+            .unregister_for_notifications(&self.watch.name, &self.callback)
+        {
+            log::debug!(
+                "Failed to unregister notifications of {}: {e}",
+                self.watch.name
+            );
+        }
+    }
+}
+
+// The current proxy of a service, shared with the death recipient and the
+// registration callback.
+struct ServiceWatch<T: FromIBinder + ?Sized> {
+    name: String,
+    state: Mutex<WatchState<T>>,
+    registered: Condvar,
+}
+
+struct WatchState<T: FromIBinder + ?Sized> {
+    service: Option<Strong<T>>,
+    // The service manager may still return the binder of a dead service.
+    dead: Option<WIBinder>,
+}
+
+impl<T: FromIBinder + ?Sized + 'static> ServiceWatch<T> {
+    fn new(name: &str) -> Self {
+        ServiceWatch {
+            name: name.to_owned(),
+            state: Mutex::new(WatchState {
+                service: None,
+                dead: None,
+            }),
+            registered: Condvar::new(),
+        }
+    }
+
+    fn install(self: &Arc<Self>, service: Strong<T>) -> Result<Strong<T>> {
+        let binder = SIBinder::downgrade(&service.as_binder());
+        let mut state = self.state.lock().unwrap();
+        if let Some(current) = state.service.as_ref() {
+            if SIBinder::downgrade(&current.as_binder()) == binder {
+                return Ok(current.clone());
+            }
+        }
+        if state.dead.as_ref() == Some(&binder) {
+            return Err(StatusCode::DeadObject);
+        }
+
+        let recipient: Arc<dyn DeathRecipient> = self.clone();
+        if let Err(e) = service
+            .as_binder()
+            .link_to_death(Arc::downgrade(&recipient))
+        {
+            // A local service can't die, but a dead one can't be used.
+            if e == StatusCode::DeadObject {
+                return Err(e);
+            }
+        }
+        state.service = Some(service.clone());
+        self.registered.notify_all();
+        Ok(service)
+    }
+
+    fn forget(&self, binder: &WIBinder) {
+        let mut state = self.state.lock().unwrap();
+        if state
+            .service
+            .as_ref()
+            .is_some_and(|service| SIBinder::downgrade(&service.as_binder()) == *binder)
+        {
+            state.service = None;
+        }
+        state.dead = Some(binder.clone());
+    }
+}
+
+impl<T: FromIBinder + ?Sized + 'static> DeathRecipient for ServiceWatch<T> {
+    fn binder_died(&self, who: &WIBinder) {
+        log::info!("Service {} died", self.name);
+        self.forget(who);
+    }
+}
+
+struct Registration<T: FromIBinder + ?Sized>(Arc<ServiceWatch<T>>);
+
+impl<T: FromIBinder + ?Sized> Interface for Registration<T> {}
+
+impl<T: FromIBinder + ?Sized + 'static> IServiceCallback for Registration<T> {
+    fn onRegistration(&self, name: &str, service: &SIBinder) -> status::Result<()> {
+        if name == self.0.name {
+            match T::try_from(service.clone()) {
+                Ok(service) => {
+                    self.0.install(service)?;
+                }
+                Err(e) => log::error!("Service {name} has an unexpected interface: {e}"),
+            }
+        }
+        Ok(())
+    }
+}
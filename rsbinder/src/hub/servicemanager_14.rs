@@ -10,6 +10,7 @@ pub use android::os::IServiceManager::{
     DUMP_FLAG_PRIORITY_NORMAL, DUMP_FLAG_PROTO,
 };
 
+pub use android::os::IClientCallback::{BnClientCallback, IClientCallback};
 pub use android::os::IServiceCallback::{BnServiceCallback, IServiceCallback};
 
 pub use android::os::ServiceDebugInfo::ServiceDebugInfo;
@@ -78,6 +79,27 @@ pub fn unregister_for_notifications(
         .map_err(|e| e.into())
 }
 
+/// Request callbacks when the service `name`, registered by this process,
+/// gets its first client or loses its last one.
+pub fn register_client_callback(
+    sm: &BpServiceManager,
+    name: &str,
+    service: &SIBinder,
+    callback: &crate::Strong<dyn IClientCallback>,
+) -> Result<()> {
+    sm.registerClientCallback(name, service, callback)
+        .map_err(|e| e.into())
+}
+
+/// Unregister the service `name` if it has no clients.
+pub fn try_unregister_service(
+    sm: &BpServiceManager,
+    name: &str,
+    service: &SIBinder,
+) -> std::result::Result<(), Status> {
+    sm.tryUnregisterService(name, service)
+}
+
 /// Returns whether a given interface is declared on the device, even if it
 /// is not started yet. For instance, this could be a service declared in the VINTF
 /// manifest.
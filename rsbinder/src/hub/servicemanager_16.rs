@@ -3,30 +3,151 @@
 
 include!(concat!(env!("OUT_DIR"), "/service_manager_16.rs"));
 
+use std::os::fd::OwnedFd;
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::rpc::RpcSession;
 use crate::*;
+pub use android::os::IAccessor::{BnAccessor, BpAccessor, IAccessor};
 pub use android::os::IServiceManager::{
     BnServiceManager, BpServiceManager, IServiceManager, DUMP_FLAG_PRIORITY_ALL,
     DUMP_FLAG_PRIORITY_CRITICAL, DUMP_FLAG_PRIORITY_DEFAULT, DUMP_FLAG_PRIORITY_HIGH,
     DUMP_FLAG_PRIORITY_NORMAL, DUMP_FLAG_PROTO, FLAG_IS_LAZY_SERVICE,
 };
 
+pub use android::os::IClientCallback::{BnClientCallback, IClientCallback};
 pub use android::os::IServiceCallback::{BnServiceCallback, IServiceCallback};
 pub use android::os::ServiceDebugInfo::ServiceDebugInfo;
 
+// How long get_service() waits for a service which isn't registered yet, and
+// how often it asks for the service meanwhile, in case the process has no
+// thread to receive the registration notification.
+const GET_SERVICE_TIMEOUT: Duration = Duration::from_secs(5);
+const GET_SERVICE_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Connect to the service `name` through its accessor. The accessor adds the
+/// connections of an RPC session, whose root object is the service.
+fn connect_accessor(name: &str, accessor: SIBinder) -> Result<SIBinder> {
+    let accessor: Strong<dyn IAccessor> = FromIBinder::try_from(accessor)?;
+    let instance = accessor.getInstanceName()?;
+    if instance != name {
+        log::error!("The accessor of {name} is responsible for {instance}");
+        return Err(StatusCode::BadValue);
+    }
+
+    let session = RpcSession::new();
+    session.setup_client(|| {
+        let fd = accessor
+            .addConnection()
+            .map_err(|err| std::io::Error::other(err.to_string()))?;
+        // Only reading, writing and shutting down are needed, which work on
+        // any stream socket.
+        Ok(UnixStream::from(OwnedFd::from(fd)))
+    })?;
+    session.root_object()
+}
+
+fn service_with_metadata(
+    name: &str,
+    service: android::os::Service::Service,
+) -> Option<android::os::ServiceWithMetadata::ServiceWithMetadata> {
+    match service {
+        android::os::Service::Service::ServiceWithMetadata(service) => Some(service),
+        android::os::Service::Service::Accessor(accessor) => {
+            match connect_accessor(name, accessor?) {
+                Ok(service) => Some(android::os::ServiceWithMetadata::ServiceWithMetadata {
+                    service: Some(service),
+                    isLazyService: false,
+                }),
+                Err(err) => {
+                    log::error!("Failed to connect to {name} through its accessor: {err}");
+                    None
+                }
+            }
+        }
+    }
+}
+
+// Wakes up get_service() when the service it waits for is registered.
+#[derive(Default)]
+struct Registration {
+    registered: Mutex<bool>,
+    condvar: Condvar,
+}
+
+struct RegistrationCallback(Arc<Registration>);
+
+impl Interface for RegistrationCallback {}
+
+impl IServiceCallback for RegistrationCallback {
+    fn onRegistration(&self, _name: &str, _service: &SIBinder) -> crate::status::Result<()> {
+        *self.0.registered.lock().unwrap() = true;
+        self.0.condvar.notify_all();
+        Ok(())
+    }
+}
+
+// Wait for the service `name` to be registered. The service manager may be
+// starting the program which registers it.
+fn wait_for_service(
+    sm: &BpServiceManager,
+    name: &str,
+) -> Option<android::os::ServiceWithMetadata::ServiceWithMetadata> {
+    let registration = Arc::new(Registration::default());
+    let callback = BnServiceCallback::new_binder(RegistrationCallback(registration.clone()));
+    let notified = match sm.registerForNotifications(name, &callback) {
+        Ok(()) => true,
+        Err(err) => {
+            log::warn!("Failed to register for notifications of {name}: {err}");
+            false
+        }
+    };
+
+    let deadline = Instant::now() + GET_SERVICE_TIMEOUT;
+    let service = loop {
+        let now = Instant::now();
+        if now >= deadline {
+            log::warn!("Service {name} didn't start in {GET_SERVICE_TIMEOUT:?}");
+            break None;
+        }
+        let registered = registration.registered.lock().unwrap();
+        let wait = (deadline - now).min(GET_SERVICE_POLL_INTERVAL);
+        *registration
+            .condvar
+            .wait_timeout_while(registered, wait, |registered| !*registered)
+            .unwrap()
+            .0 = false;
+
+        match sm.checkService2(name) {
+            Ok(android::os::Service::Service::Accessor(None)) => {}
+            Ok(service) => break service_with_metadata(name, service),
+            Err(err) => {
+                log::error!("Failed to check service {name}: {err}");
+                break None;
+            }
+        }
+    };
+
+    if notified {
+        if let Err(err) = sm.unregisterForNotifications(name, &callback) {
+            log::warn!("Failed to unregister for notifications of {name}: {err}");
+        }
+    }
+    service
+}
+
 /// Retrieve an existing service, blocking for a few seconds if it doesn't yet
-/// exist.
+/// exist. A service which is provided through an accessor is connected to.
 pub fn get_service(
     sm: &BpServiceManager,
     name: &str,
 ) -> Option<android::os::ServiceWithMetadata::ServiceWithMetadata> {
     match sm.getService2(name) {
-        Ok(service) => match service {
-            android::os::Service::Service::ServiceWithMetadata(service) => Some(service),
-            android::os::Service::Service::Accessor(_accessor) => {
-                log::warn!("Service {name} is an Accessor, not a ServiceWithMetadata");
-                None
-            }
-        },
+        // The service isn't registered.
+        Ok(android::os::Service::Service::Accessor(None)) => wait_for_service(sm, name),
+        Ok(service) => service_with_metadata(name, service),
         Err(err) => {
             log::error!("Failed to get service {name}: {err}");
             None
@@ -36,19 +157,13 @@ pub fn get_service(
 
 /// Retrieve an existing service called @a name from the service
 /// manager. Non-blocking. Returns null if the service does not
-/// exist.
+/// exist. A service which is provided through an accessor is connected to.
 pub fn check_service(
     sm: &BpServiceManager,
     name: &str,
 ) -> Option<android::os::ServiceWithMetadata::ServiceWithMetadata> {
     match sm.checkService2(name) {
-        Ok(service) => match service {
-            android::os::Service::Service::ServiceWithMetadata(service) => Some(service),
-            android::os::Service::Service::Accessor(_accessor) => {
-                log::warn!("Service {name} is an Accessor, not a ServiceWithMetadata");
-                None
-            }
-        },
+        Ok(service) => service_with_metadata(name, service),
         Err(err) => {
             log::error!("Failed to check service {name}: {err}");
             None
@@ -95,6 +210,27 @@ pub fn unregister_for_notifications(
         .map_err(|e| e.into())
 }
 
+/// Request callbacks when the service `name`, registered by this process,
+/// gets its first client or loses its last one.
+pub fn register_client_callback(
+    sm: &BpServiceManager,
+    name: &str,
+    service: &SIBinder,
+    callback: &crate::Strong<dyn IClientCallback>,
+) -> Result<()> {
+    sm.registerClientCallback(name, service, callback)
+        .map_err(|e| e.into())
+}
+
+/// Unregister the service `name` if it has no clients.
+pub fn try_unregister_service(
+    sm: &BpServiceManager,
+    name: &str,
+    service: &SIBinder,
+) -> std::result::Result<(), Status> {
+    sm.tryUnregisterService(name, service)
+}
+
 /// Returns whether a given interface is declared on the device, even if it
 /// is not started yet. For instance, this could be a service declared in the VINTF
 /// manifest.
@@ -108,6 +244,21 @@ pub fn is_declared(sm: &BpServiceManager, name: &str) -> bool {
     }
 }
 
+/// Returns the address which the service `name` listens on for RPC
+/// connections, if the service manager knows it.
+pub fn get_connection_info(
+    sm: &BpServiceManager,
+    name: &str,
+) -> Option<android::os::ConnectionInfo::ConnectionInfo> {
+    match sm.getConnectionInfo(name) {
+        Ok(result) => result,
+        Err(err) => {
+            log::error!("Failed to get_connection_info({name}): {err}");
+            None
+        }
+    }
+}
+
 pub fn get_interface<T: FromIBinder + ?Sized>(
     sm: &BpServiceManager,
     name: &str,
@@ -132,6 +132,8 @@ pub mod binder_async;
 mod binder_object;
 /// BinderFS filesystem utilities
 pub mod binderfs;
+/// Binder driver backends
+pub mod driver;
 /// Error types and result handling
 pub mod error;
 /// File descriptor wrapper for IPC
@@ -148,7 +150,13 @@ pub mod parcelable_holder;
 mod process_state;
 /// Client proxy for remote services
 pub mod proxy;
+/// Recording and replay of binder transactions
+pub mod recording;
 mod ref_counter;
+/// RPC binder over sockets
+pub mod rpc;
+/// Shell commands of binder objects
+pub mod shell;
 /// Status and exception handling
 pub mod status;
 mod sys;
@@ -164,16 +172,17 @@ mod rt;
 pub use binder::*;
 #[cfg(feature = "async")]
 pub use binder_async::{BinderAsyncPool, BinderAsyncRuntime, BoxFuture};
-pub use error::{Result, StatusCode};
+pub use error::{ExtendedError, Result, StatusCode};
 pub use file_descriptor::ParcelFileDescriptor;
 pub use native::*;
 pub use parcel::Parcel;
 pub use parcelable::*;
 pub use parcelable_holder::ParcelableHolder;
-pub use process_state::ProcessState;
+pub use process_state::{FrozenInfo, ProcessState, ProcessStateBuilder, ThreadHook};
 pub use proxy::*;
-#[cfg(feature = "tokio")]
+#[cfg(feature = "async")]
 pub use rt::*;
+pub use shell::ResultReceiver;
 pub use status::{ExceptionCode, Status};
 
 /// Default path to the binder control device
@@ -61,6 +61,14 @@ macro_rules! __declare_binder_interface {
                     let binder = $crate::native::Binder::new_with_stability($native(Box::new(Wrapper {_inner: inner})), $stability);
                     $crate::Strong::new(Box::new(binder))
                 }
+
+                /// Attach an extension object to a binder service created by `new_binder`.
+                #[allow(dead_code)]
+                pub fn set_extension(binder: &$crate::Strong<dyn $interface>, extension: &$crate::SIBinder) -> $crate::Result<()> {
+                    let native = $crate::native::Binder::<$native>::try_from($crate::Interface::as_binder(&**binder))?;
+                    native.set_extension(extension);
+                    Ok(())
+                }
             }
 
             impl $crate::Remotable for $native {
@@ -75,6 +83,10 @@ macro_rules! __declare_binder_interface {
                 fn on_dump(&self, _writer: &mut dyn std::io::Write, _args: &[String]) -> $crate::Result<()> {
                     self.0.as_sync().dump(_writer, _args)
                 }
+
+                fn on_shell_command(&self, _input: &mut dyn std::io::Read, _output: &mut dyn std::io::Write, _error: &mut dyn std::io::Write, _args: &[String]) -> $crate::Result<i32> {
+                    self.0.as_sync().shell_command(_input, _output, _error, _args)
+                }
             }
         )?
 
@@ -160,6 +172,14 @@ macro_rules! __declare_binder_interface {
                 let binder = $crate::native::Binder::new_with_stability($native(Box::new(inner)), $stability);
                 $crate::Strong::new(Box::new(binder))
             }
+
+            /// Attach an extension object to a binder service created by `new_binder`.
+            #[allow(dead_code)]
+            pub fn set_extension(binder: &$crate::Strong<dyn $interface>, extension: &$crate::SIBinder) -> $crate::Result<()> {
+                let native = $crate::native::Binder::<$native>::try_from($crate::Interface::as_binder(&**binder))?;
+                native.set_extension(extension);
+                Ok(())
+            }
         }
 
         impl $crate::Remotable for $native {
@@ -174,6 +194,10 @@ macro_rules! __declare_binder_interface {
             fn on_dump(&self, _writer: &mut dyn std::io::Write, _args: &[String]) -> $crate::Result<()> {
                 self.0.dump(_writer, _args)
             }
+
+            fn on_shell_command(&self, _input: &mut dyn std::io::Read, _output: &mut dyn std::io::Write, _error: &mut dyn std::io::Write, _args: &[String]) -> $crate::Result<i32> {
+                self.0.shell_command(_input, _output, _error, _args)
+            }
         }
     };
 }
@@ -203,7 +227,7 @@ macro_rules! declare_binder_interface {
                 },
                 proxy: $proxy {},
                 $(r#async: $async_interface,)?
-                stability: $crate::Stability::default(),
+                stability: $crate::Stability::Local,
             }
         }
     };
@@ -258,7 +282,7 @@ macro_rules! declare_binder_interface {
                     $($fname: $fty = $finit),*
                 },
                 $(r#async: $async_interface,)?
-                stability: $crate::Stability::default(),
+                stability: $crate::Stability::Local,
             }
         }
     };
@@ -729,8 +753,7 @@ mod tests {
                 R: crate::BinderAsyncRuntime + Send + Sync + 'static,
             {
                 let bn = BnEcho(Box::new(Wrapper { inner, rt }));
-                let binder =
-                    crate::native::Binder::new_with_stability(bn, crate::Stability::default());
+                let binder = crate::native::Binder::new_with_stability(bn, crate::Stability::Local);
                 crate::Strong::new(Box::new(binder))
             }
         }
@@ -29,13 +29,20 @@ use std::fs::File;
 use std::mem::ManuallyDrop;
 use std::ops::{Deref, DerefMut};
 use std::os::fd::FromRawFd;
-use std::sync::{Arc, Weak};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::time::SystemTime;
 
-use crate::{binder::*, error::*, parcel::*, ref_counter::RefCounter, thread_state};
+use crate::{
+    binder::*, error::*, parcel::*, recording::RecordedTransaction, ref_counter::RefCounter,
+    thread_state,
+};
 
 struct Inner<T: Remotable + Send + Sync> {
     remotable: T,
-    _stability: Stability,
+    stability: Stability,
+    extension: RwLock<Option<SIBinder>>,
+    // The file which incoming transactions are recorded to.
+    recording: Mutex<Option<File>>,
     strong: RefCounter,
     weak: RefCounter,
 }
@@ -71,8 +78,9 @@ impl<T: Remotable> Inner<T> {
                 self.remotable.on_dump(file.deref_mut(), argv.as_slice())
             }
             SHELL_COMMAND_TRANSACTION => {
-                log::error!("SHELL_COMMAND_TRANSACTION is not supported.");
-                Err(StatusCode::InvalidOperation)
+                crate::shell::on_shell_command_transaction(_reader, |input, output, error, args| {
+                    self.remotable.on_shell_command(input, output, error, args)
+                })
             }
             SYSPROPS_TRANSACTION => {
                 log::error!("SYSPROPS_TRANSACTION is not supported.");
@@ -81,6 +89,79 @@ impl<T: Remotable> Inner<T> {
             _ => Err(StatusCode::UnknownTransaction),
         }
     }
+
+    fn dispatch(
+        &self,
+        code: TransactionCode,
+        reader: &mut Parcel,
+        reply: &mut Parcel,
+    ) -> Result<()> {
+        match code {
+            PING_TRANSACTION => {
+                // Noting to do for PING_TRANSACTION.
+                Ok(())
+            }
+            EXTENSION_TRANSACTION => reply.write(&*self.extension.read().unwrap()),
+
+            DEBUG_PID_TRANSACTION => {
+                reply.write::<i32>(&rustix::process::getpid().as_raw_nonzero().get())
+            }
+
+            _ => {
+                if (FIRST_CALL_TRANSACTION..=LAST_CALL_TRANSACTION).contains(&code)
+                    && !(thread_state::check_interface(reader, T::descriptor())?)
+                {
+                    reply.write(&StatusCode::BadType)?;
+                    return Ok(());
+                }
+
+                match self.remotable.on_transact(code, reader, reply) {
+                    Ok(_) => Ok(()),
+                    Err(err) => {
+                        if err == StatusCode::UnknownTransaction {
+                            self.on_transact(code, reader, reply)
+                        } else {
+                            Err(err)
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Only the owner of the service and root can start and stop the recording
+    // of its transactions.
+    fn check_recording_permission() -> Result<()> {
+        let uid = thread_state::CallingContext::default().uid;
+        if uid != 0 && uid != rustix::process::getuid().as_raw() {
+            log::error!("Uid {uid} is not allowed to record transactions.");
+            return Err(StatusCode::PermissionDenied);
+        }
+        Ok(())
+    }
+
+    fn start_recording(&self, reader: &mut Parcel) -> Result<()> {
+        Self::check_recording_permission()?;
+
+        let file = File::from(reader.read_fd()?);
+        let mut recording = self.recording.lock().unwrap();
+        if recording.is_some() {
+            log::error!("{} is already recording transactions.", T::descriptor());
+            return Err(StatusCode::InvalidOperation);
+        }
+        *recording = Some(file);
+        Ok(())
+    }
+
+    fn record(&self, transaction: RecordedTransaction) {
+        let mut recording = self.recording.lock().unwrap();
+        if let Some(file) = recording.as_mut() {
+            if let Err(err) = transaction.write_to(file) {
+                log::error!("Failed to record a transaction, stop recording: {err}");
+                *recording = None;
+            }
+        }
+    }
 }
 
 impl<T: 'static + Remotable> IBinder for Inner<T> {
@@ -102,10 +183,9 @@ impl<T: 'static + Remotable> IBinder for Inner<T> {
         Ok(())
     }
 
-    // /// Retrieve the stability of this object.
-    // fn stability(&self) -> Stability {
-    //     self.stability
-    // }
+    fn stability(&self) -> Stability {
+        self.stability
+    }
 
     fn as_any(&self) -> &dyn Any {
         self
@@ -123,6 +203,10 @@ impl<T: 'static + Remotable> IBinder for Inner<T> {
         false
     }
 
+    fn get_extension(&self) -> Result<Option<SIBinder>> {
+        Ok(self.extension.read().unwrap().clone())
+    }
+
     fn inc_strong(&self, _strong: &SIBinder) -> Result<()> {
         self.strong.inc(|| Ok(()))
     }
@@ -158,49 +242,27 @@ impl<T: Remotable> Transactable for Inner<T> {
     ) -> Result<()> {
         reader.set_data_position(0);
         match code {
-            PING_TRANSACTION => {
-                // Noting to do for PING_TRANSACTION.
-                Ok(())
-            }
-            EXTENSION_TRANSACTION => {
-                log::error!("EXTENSION_TRANSACTION is not supported.");
-                Err(StatusCode::InvalidOperation)
-                // CHECK(reply != nullptr);
-                // err = reply->writeStrongBinder(getExtension());
-            }
-
+            START_RECORDING_TRANSACTION => self.start_recording(reader),
             STOP_RECORDING_TRANSACTION => {
-                log::error!("STOP_RECORDING_TRANSACTION is not supported.");
-                Err(StatusCode::InvalidOperation)
-            }
-
-            START_RECORDING_TRANSACTION => {
-                log::error!("START_RECORDING_TRANSACTION is not supported.");
-                Err(StatusCode::InvalidOperation)
-            }
-
-            DEBUG_PID_TRANSACTION => {
-                reply.write::<i32>(&rustix::process::getpid().as_raw_nonzero().get())
+                Self::check_recording_permission()?;
+                self.recording.lock().unwrap().take();
+                Ok(())
             }
-
             _ => {
-                if (FIRST_CALL_TRANSACTION..=LAST_CALL_TRANSACTION).contains(&code)
-                    && !(thread_state::check_interface(reader, T::descriptor())?)
-                {
-                    reply.write(&StatusCode::BadType)?;
-                    return Ok(());
-                }
-
-                match self.remotable.on_transact(code, reader, reply) {
-                    Ok(_) => Ok(()),
-                    Err(err) => {
-                        if err == StatusCode::UnknownTransaction {
-                            self.on_transact(code, reader, reply)
-                        } else {
-                            Err(err)
-                        }
-                    }
+                if self.recording.lock().unwrap().is_none() {
+                    return self.dispatch(code, reader, reply);
                 }
+                let timestamp = SystemTime::now();
+                let result = self.dispatch(code, reader, reply);
+                self.record(RecordedTransaction::new(
+                    code,
+                    thread_state::transaction_flags(),
+                    timestamp,
+                    reader,
+                    reply,
+                    &result,
+                ));
+                result
             }
         }
     }
@@ -216,9 +278,10 @@ pub struct Binder<T: 'static + Remotable + Send + Sync> {
 }
 
 impl<T: 'static + Remotable> Binder<T> {
-    /// Create a new Binder object with default stability.
+    /// Create a new Binder object without a declared stability, which is sent
+    /// with the stability of its process.
     pub fn new(remotable: T) -> Self {
-        Self::new_with_stability(remotable, Default::default())
+        Self::new_with_stability(remotable, Stability::Local)
     }
 
     /// Create a new Binder object with the specified stability level.
@@ -226,12 +289,23 @@ impl<T: 'static + Remotable> Binder<T> {
         Binder::<T> {
             inner: Arc::new(Inner {
                 remotable,
-                _stability: stability,
+                stability,
+                extension: RwLock::new(None),
+                recording: Mutex::new(None),
                 strong: Default::default(),
                 weak: Default::default(),
             }),
         }
     }
+
+    /// Attach an extension object to this binder.
+    ///
+    /// An extension is an additional interface, typically defined by a vendor,
+    /// which clients can get with [`IBinder::get_extension`] without the base
+    /// interface knowing about it. It replaces the extension set before, if any.
+    pub fn set_extension(&self, extension: &SIBinder) {
+        *self.inner.extension.write().unwrap() = Some(extension.clone());
+    }
 }
 
 impl<T: 'static + Remotable> Interface for Binder<T> {
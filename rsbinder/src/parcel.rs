@@ -24,22 +24,40 @@
 //! alignment, and object references required for cross-process communication.
 
 use std::default::Default;
+use std::sync::Arc;
 use std::vec::Vec;
 
 use pretty_hex::*;
-use rustix::fd::IntoRawFd;
+use rustix::fd::{AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
 
 use crate::{
     binder,
     error::{Result, StatusCode},
+    file_descriptor::ParcelFileDescriptor,
     parcelable::*,
-    sys::binder::{binder_size_t, flat_binder_object},
-    sys::{binder_uintptr_t, BINDER_TYPE_FD},
+    process_state::ProcessState,
+    rpc::RpcSession,
+    sys::binder::{
+        binder_buffer_object, binder_fd_array_object, binder_size_t, flat_binder_object,
+    },
+    sys::{
+        binder_object_header, binder_uintptr_t, BINDER_BUFFER_FLAG_HAS_PARENT, BINDER_TYPE_FD,
+        BINDER_TYPE_FDA, BINDER_TYPE_PTR,
+    },
     thread_state,
 };
 
 const STRICT_MODE_PENALTY_GATHER: i32 = 1 << 31;
 
+// The size of a binder object with the header type `type_`.
+fn object_size(type_: u32) -> usize {
+    match type_ {
+        BINDER_TYPE_PTR => std::mem::size_of::<binder_buffer_object>(),
+        BINDER_TYPE_FDA => std::mem::size_of::<binder_fd_array_object>(),
+        _ => std::mem::size_of::<flat_binder_object>(),
+    }
+}
+
 #[inline]
 pub(crate) fn pad_size(len: usize) -> usize {
     (len + 3) & (!3)
@@ -93,20 +111,13 @@ impl<T: Clone + Default> ParcelData<T> {
         ParcelData::Slice(unsafe { std::slice::from_raw_parts_mut(data, len) })
     }
 
-    fn as_slice(&self) -> &[T] {
+    pub(crate) fn as_slice(&self) -> &[T] {
         match self {
             ParcelData::Vec(v) => v.as_slice(),
             ParcelData::Slice(s) => s,
         }
     }
 
-    fn as_mut_slice(&mut self) -> &mut [T] {
-        match self {
-            ParcelData::Vec(v) => v.as_mut_slice(),
-            ParcelData::Slice(_) => panic!("ParcelData::Slice can't support as_mut_slice()."),
-        }
-    }
-
     pub(crate) fn as_ptr(&self) -> *const T {
         match self {
             ParcelData::Vec(ref v) => v.as_ptr(),
@@ -132,13 +143,6 @@ impl<T: Clone + Default> ParcelData<T> {
         }
     }
 
-    fn resize(&mut self, len: usize) {
-        match self {
-            ParcelData::Vec(v) => v.resize_with(len, Default::default),
-            _ => panic!("&[u8] can't support resize()."),
-        }
-    }
-
     fn capacity(&self) -> usize {
         match self {
             ParcelData::Vec(v) => v.capacity(),
@@ -178,6 +182,11 @@ pub struct Parcel {
     request_header_present: bool,
     work_source_request_header_pos: usize,
     free_buffer: Option<FnFreeBuffer>,
+    rpc_session: Option<Arc<RpcSession>>,
+    // The binder context the handles in the parcel belong to.
+    process: Option<&'static ProcessState>,
+    // Memory of the buffer objects written to the parcel.
+    buffers: Vec<Vec<u8>>,
 }
 
 impl Default for Parcel {
@@ -202,6 +211,9 @@ impl Parcel {
             request_header_present: false,
             work_source_request_header_pos: 0,
             free_buffer: None,
+            rpc_session: None,
+            process: None,
+            buffers: Vec::new(),
         }
     }
 
@@ -226,6 +238,9 @@ impl Parcel {
             request_header_present: false,
             work_source_request_header_pos: 0,
             free_buffer: Some(free_buffer),
+            rpc_session: None,
+            process: None,
+            buffers: Vec::new(),
         }
     }
 
@@ -240,9 +255,39 @@ impl Parcel {
             request_header_present: false,
             work_source_request_header_pos: 0,
             free_buffer: None,
+            rpc_session: None,
+            process: None,
+            buffers: Vec::new(),
         }
     }
 
+    /// Whether the parcel belongs to an RPC binder session.
+    pub fn is_for_rpc(&self) -> bool {
+        self.rpc_session.is_some()
+    }
+
+    pub(crate) fn rpc_session(&self) -> Option<&Arc<RpcSession>> {
+        self.rpc_session.as_ref()
+    }
+
+    pub(crate) fn set_rpc_session(&mut self, session: Arc<RpcSession>) {
+        self.rpc_session = Some(session);
+    }
+
+    /// The binder context of the parcel. A parcel which was not created for a
+    /// context belongs to the context of the calling thread.
+    pub(crate) fn process(&self) -> &'static ProcessState {
+        self.process.unwrap_or_else(thread_state::current_process)
+    }
+
+    pub(crate) fn set_process(&mut self, process: &'static ProcessState) {
+        self.process = Some(process);
+    }
+
+    pub(crate) fn as_slice(&self) -> &[u8] {
+        self.data.as_slice()
+    }
+
     pub fn as_mut_ptr(&mut self) -> *mut u8 {
         self.data.as_mut_ptr()
     }
@@ -269,13 +314,30 @@ impl Parcel {
     pub fn close_file_descriptors(&self) {
         for offset in self.objects.as_slice() {
             let obj: &flat_binder_object = (self.data.as_ptr(), *offset as usize).into();
-            if obj.header_type() == BINDER_TYPE_FD {
-                // Close the file descriptor
-                obj.owned_fd();
+            match obj.header_type() {
+                BINDER_TYPE_FD => {
+                    // Close the file descriptor
+                    obj.owned_fd();
+                }
+                BINDER_TYPE_FDA => self.close_fd_array(*offset as usize),
+                _ => {}
             }
         }
     }
 
+    // Close the descriptors of the fd array object at `offset`.
+    fn close_fd_array(&self, offset: usize) {
+        let fda = unsafe {
+            std::ptr::read_unaligned(self.data.as_ptr().add(offset) as *const binder_fd_array_object)
+        };
+        match self.fd_array(&fda) {
+            Ok(fds) => fds.into_iter().for_each(|fd| {
+                drop(unsafe { OwnedFd::from_raw_fd(fd) });
+            }),
+            Err(e) => log::error!("Parcel: unable to close fd array: {e:?}"),
+        }
+    }
+
     pub fn set_data_position(&mut self, pos: usize) {
         self.pos = pos;
     }
@@ -328,6 +390,11 @@ impl Parcel {
     }
 
     pub(crate) fn read_object(&mut self, null_meta: bool) -> Result<&flat_binder_object> {
+        if self.is_for_rpc() {
+            log::error!("Parcel of an RPC session can't contain binder objects.");
+            return Err(StatusCode::FdsNotAllowed);
+        }
+
         let data_pos = self.pos as u64;
         let size = std::mem::size_of::<flat_binder_object>();
 
@@ -368,6 +435,24 @@ impl Parcel {
         Err(StatusCode::BadType)
     }
 
+    // Read a plain file descriptor object. The parcel owns the descriptor it
+    // received, so it is duplicated.
+    pub(crate) fn read_fd(&mut self) -> Result<OwnedFd> {
+        let obj = self.read_object(true)?;
+        if obj.header_type() != BINDER_TYPE_FD {
+            return Err(StatusCode::BadType);
+        }
+        Ok(rustix::io::fcntl_dupfd_cloexec(obj.borrowed_fd(), 0)?)
+    }
+
+    // Write a plain file descriptor object, which doesn't own `fd`.
+    pub(crate) fn write_fd(&mut self, fd: BorrowedFd) -> Result<()> {
+        self.write_object(
+            &flat_binder_object::new_with_fd(fd.as_raw_fd(), false),
+            true,
+        )
+    }
+
     /// Safely read a sized parcelable.
     ///
     /// Read the size of a parcelable, compute the end position
@@ -631,27 +716,341 @@ impl Parcel {
     }
 
     pub(crate) fn write_object(&mut self, obj: &flat_binder_object, null_meta: bool) -> Result<()> {
+        if self.is_for_rpc() {
+            log::error!("Parcel of an RPC session can't contain binder objects.");
+            return Err(StatusCode::FdsNotAllowed);
+        }
+
         let data_pos = self.pos;
         self.write_aligned(obj);
 
         if null_meta || obj.pointer() != 0 {
-            obj.acquire()?;
+            obj.acquire(self.process)?;
             self.objects.push(data_pos as _);
         }
 
         Ok(())
     }
 
+    /// Write a buffer object (`BINDER_TYPE_PTR`) and return its object index.
+    ///
+    /// The contents of `buffer` are not serialized into the parcel data. The
+    /// binder driver copies them straight into the transaction buffer of the
+    /// receiving process, which sends the parcel with `BC_TRANSACTION_SG`.
+    pub fn write_buffer(&mut self, buffer: Vec<u8>) -> Result<usize> {
+        self.write_buffer_object(buffer, None)
+    }
+
+    /// Write a buffer object embedded in the buffer object with index `parent`.
+    ///
+    /// `parent_offset` is the position of a pointer within the parent buffer.
+    /// The driver patches it to the address of `buffer` in the receiving process,
+    /// so nested structures arrive without further copies or fixups.
+    pub fn write_embedded_buffer(
+        &mut self,
+        buffer: Vec<u8>,
+        parent: usize,
+        parent_offset: usize,
+    ) -> Result<usize> {
+        self.write_buffer_object(buffer, Some((parent, parent_offset)))
+    }
+
+    fn write_buffer_object(
+        &mut self,
+        mut buffer: Vec<u8>,
+        parent: Option<(usize, usize)>,
+    ) -> Result<usize> {
+        if self.is_for_rpc() {
+            log::error!("Parcel of an RPC session can't contain binder objects.");
+            return Err(StatusCode::FdsNotAllowed);
+        }
+
+        let address = buffer.as_mut_ptr() as binder_uintptr_t;
+        let mut obj = binder_buffer_object {
+            hdr: binder_object_header {
+                type_: BINDER_TYPE_PTR,
+            },
+            flags: 0,
+            buffer: address,
+            length: buffer.len() as _,
+            parent: 0,
+            parent_offset: 0,
+        };
+
+        if let Some((parent, parent_offset)) = parent {
+            let parent_obj = self.buffer_object_at(parent)?;
+            if parent_offset + std::mem::size_of::<binder_uintptr_t>() > parent_obj.length as usize
+            {
+                log::error!("Parcel: embedded buffer offset {parent_offset} is out of its parent");
+                return Err(StatusCode::BadValue);
+            }
+            // Keep the pointer valid for readers of this parcel as well.
+            let parent_buffer = self
+                .buffers
+                .iter_mut()
+                .find(|b| b.as_ptr() as binder_uintptr_t == parent_obj.buffer)
+                .ok_or(StatusCode::BadValue)?;
+            parent_buffer[parent_offset..parent_offset + std::mem::size_of::<binder_uintptr_t>()]
+                .copy_from_slice(&address.to_ne_bytes());
+
+            obj.flags = BINDER_BUFFER_FLAG_HAS_PARENT;
+            obj.parent = parent as _;
+            obj.parent_offset = parent_offset as _;
+        }
+
+        let index = self.objects.len();
+        self.objects.push(self.pos as _);
+        self.write_aligned(&obj);
+        self.buffers.push(buffer);
+        Ok(index)
+    }
+
+    /// Read a buffer object written by [`Parcel::write_buffer`] and return its
+    /// object index and contents.
+    pub fn read_buffer(&mut self) -> Result<(usize, &[u8])> {
+        let (index, obj) = self.read_buffer_object()?;
+        if obj.flags & BINDER_BUFFER_FLAG_HAS_PARENT != 0 {
+            log::error!("Parcel: buffer object {index} is embedded in another buffer");
+            return Err(StatusCode::BadValue);
+        }
+        Ok((index, self.buffer_slice(&obj)))
+    }
+
+    /// Read a buffer object written by [`Parcel::write_embedded_buffer`] and
+    /// return its object index and contents. It must be embedded at
+    /// `parent_offset` in the buffer object with index `parent`.
+    pub fn read_embedded_buffer(
+        &mut self,
+        parent: usize,
+        parent_offset: usize,
+    ) -> Result<(usize, &[u8])> {
+        let (index, obj) = self.read_buffer_object()?;
+        if obj.flags & BINDER_BUFFER_FLAG_HAS_PARENT == 0
+            || obj.parent != parent as binder_size_t
+            || obj.parent_offset != parent_offset as binder_size_t
+        {
+            log::error!(
+                "Parcel: buffer object {index} is not embedded at {parent}:{parent_offset}"
+            );
+            return Err(StatusCode::BadValue);
+        }
+
+        let parent_obj = self.buffer_object_at(parent)?;
+        let pointer = self
+            .buffer_slice(&parent_obj)
+            .get(parent_offset..parent_offset + std::mem::size_of::<binder_uintptr_t>())
+            .ok_or(StatusCode::BadValue)?;
+        if binder_uintptr_t::from_ne_bytes(pointer.try_into()?) != obj.buffer {
+            log::error!("Parcel: parent of buffer object {index} doesn't point to it");
+            return Err(StatusCode::BadValue);
+        }
+        Ok((index, self.buffer_slice(&obj)))
+    }
+
+    /// The size of the buffers which have to be sent with `BC_TRANSACTION_SG`.
+    pub(crate) fn buffers_size(&self) -> usize {
+        self.buffers.iter().map(|b| (b.len() + 7) & !7).sum()
+    }
+
+    /// Write a file descriptor array object (`BINDER_TYPE_FDA`) holding duplicates of `fds`.
+    ///
+    /// The descriptors are stored in a buffer object of their own, which is
+    /// written right before the array. All of them are sent as one kernel object.
+    pub fn write_fd_array(&mut self, fds: &[ParcelFileDescriptor]) -> Result<()> {
+        let parent = self.write_buffer(vec![0u8; fds.len() * std::mem::size_of::<u32>()])?;
+        self.write_embedded_fd_array(fds, parent, 0)
+    }
+
+    /// Write a file descriptor array object whose descriptors are stored at
+    /// `parent_offset` in the buffer object with index `parent`, like the
+    /// file descriptors of a HIDL native handle.
+    pub fn write_embedded_fd_array(
+        &mut self,
+        fds: &[ParcelFileDescriptor],
+        parent: usize,
+        parent_offset: usize,
+    ) -> Result<()> {
+        if self.is_for_rpc() {
+            log::error!("Parcel of an RPC session can't contain binder objects.");
+            return Err(StatusCode::FdsNotAllowed);
+        }
+
+        let parent_obj = self.buffer_object_at(parent)?;
+        let size = fds.len() * std::mem::size_of::<u32>();
+        if parent_offset % std::mem::size_of::<u32>() != 0
+            || parent_offset + size > parent_obj.length as usize
+        {
+            log::error!("Parcel: fd array offset {parent_offset} is out of its parent");
+            return Err(StatusCode::BadValue);
+        }
+
+        let dups = fds
+            .iter()
+            .map(|fd| rustix::io::fcntl_dupfd_cloexec(fd.as_ref(), 0))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let parent_buffer = self
+            .buffers
+            .iter_mut()
+            .find(|b| b.as_ptr() as binder_uintptr_t == parent_obj.buffer)
+            .ok_or(StatusCode::BadValue)?;
+        // The duplicates are owned by the parcel from now on.
+        for (slot, fd) in parent_buffer[parent_offset..parent_offset + size]
+            .chunks_exact_mut(std::mem::size_of::<u32>())
+            .zip(dups)
+        {
+            slot.copy_from_slice(&fd.into_raw_fd().to_ne_bytes());
+        }
+
+        let obj = binder_fd_array_object {
+            hdr: binder_object_header {
+                type_: BINDER_TYPE_FDA,
+            },
+            pad: 0,
+            num_fds: fds.len() as _,
+            parent: parent as _,
+            parent_offset: parent_offset as _,
+        };
+        self.objects.push(self.pos as _);
+        self.write_aligned(&obj);
+        Ok(())
+    }
+
+    /// Read a file descriptor array written by [`Parcel::write_fd_array`].
+    /// The returned descriptors are duplicates owned by the caller.
+    pub fn read_fd_array(&mut self) -> Result<Vec<ParcelFileDescriptor>> {
+        let (parent, _) = self.read_buffer()?;
+        self.read_embedded_fd_array(parent, 0)
+    }
+
+    /// Read a file descriptor array written by [`Parcel::write_embedded_fd_array`].
+    /// Its descriptors must be stored at `parent_offset` in the buffer object
+    /// with index `parent`.
+    pub fn read_embedded_fd_array(
+        &mut self,
+        parent: usize,
+        parent_offset: usize,
+    ) -> Result<Vec<ParcelFileDescriptor>> {
+        let index = self.object_index_at(self.pos)?;
+        let size = std::mem::size_of::<binder_fd_array_object>();
+        let fda = unsafe {
+            std::ptr::read_unaligned(
+                self.read_aligned_data(size)?.as_ptr() as *const binder_fd_array_object
+            )
+        };
+        if fda.hdr.type_ != BINDER_TYPE_FDA {
+            log::error!("Parcel: object {index} is not a fd array");
+            return Err(StatusCode::BadType);
+        }
+        if fda.parent != parent as binder_size_t
+            || fda.parent_offset != parent_offset as binder_size_t
+        {
+            log::error!("Parcel: fd array {index} is not embedded at {parent}:{parent_offset}");
+            return Err(StatusCode::BadValue);
+        }
+
+        self.fd_array(&fda)?
+            .into_iter()
+            .map(|fd| {
+                let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+                Ok(ParcelFileDescriptor::new(rustix::io::fcntl_dupfd_cloexec(
+                    fd, 0,
+                )?))
+            })
+            .collect()
+    }
+
+    // The descriptors of a fd array, which live in its parent buffer.
+    fn fd_array(&self, fda: &binder_fd_array_object) -> Result<Vec<RawFd>> {
+        let parent = self.buffer_object_at(fda.parent as usize)?;
+        let offset = fda.parent_offset as usize;
+        let size = (fda.num_fds as usize)
+            .checked_mul(std::mem::size_of::<u32>())
+            .ok_or(StatusCode::BadValue)?;
+        let array = self
+            .buffer_slice(&parent)
+            .get(offset..offset.checked_add(size).ok_or(StatusCode::BadValue)?)
+            .ok_or(StatusCode::BadValue)?;
+        Ok(array
+            .chunks_exact(std::mem::size_of::<u32>())
+            .map(|fd| RawFd::from_ne_bytes(fd.try_into().unwrap()))
+            .collect())
+    }
+
+    fn object_index_at(&self, data_pos: usize) -> Result<usize> {
+        self.objects
+            .as_slice()
+            .iter()
+            .position(|&offset| offset as usize == data_pos)
+            .ok_or_else(|| {
+                log::error!("Parcel: unable to find object at index {data_pos}");
+                StatusCode::BadType
+            })
+    }
+
+    fn read_buffer_object(&mut self) -> Result<(usize, binder_buffer_object)> {
+        let index = self.object_index_at(self.pos)?;
+        let obj = self.buffer_object_at(index)?;
+        self.read_aligned_data(std::mem::size_of::<binder_buffer_object>())?;
+        Ok((index, obj))
+    }
+
+    fn buffer_object_at(&self, index: usize) -> Result<binder_buffer_object> {
+        let offset = *self
+            .objects
+            .as_slice()
+            .get(index)
+            .ok_or(StatusCode::BadIndex)? as usize;
+        let data = self.data.as_slice();
+        if offset + std::mem::size_of::<binder_buffer_object>() > data.len() {
+            return Err(StatusCode::NotEnoughData);
+        }
+        let obj = unsafe {
+            std::ptr::read_unaligned(data.as_ptr().add(offset) as *const binder_buffer_object)
+        };
+        if obj.hdr.type_ != BINDER_TYPE_PTR {
+            log::error!("Parcel: object {index} is not a buffer object");
+            return Err(StatusCode::BadType);
+        }
+
+        // The driver placed the buffers of a received parcel, but the buffers of
+        // a local parcel must be owned by it.
+        if self.free_buffer.is_none()
+            && !self.buffers.iter().any(|b| {
+                b.as_ptr() as binder_uintptr_t == obj.buffer
+                    && b.len() as binder_size_t == obj.length
+            })
+        {
+            log::error!("Parcel: buffer object {index} refers to unknown memory");
+            return Err(StatusCode::BadValue);
+        }
+        Ok(obj)
+    }
+
+    fn buffer_slice(&self, obj: &binder_buffer_object) -> &[u8] {
+        if obj.length == 0 {
+            return &[];
+        }
+        unsafe { std::slice::from_raw_parts(obj.buffer as *const u8, obj.length as usize) }
+    }
+
     pub(crate) fn write_interface_token(&mut self, interface: &str) -> Result<()> {
-        self.write(&(thread_state::strict_mode_policy() | STRICT_MODE_PENALTY_GATHER))?;
-        self.update_work_source_request_header_pos();
-        let work_source: i32 = if thread_state::should_propagate_work_source() {
-            thread_state::calling_work_source_uid() as _
+        // RPC binder has no strict mode policy and work source.
+        if !self.is_for_rpc() {
+            self.write(&(thread_state::strict_mode_policy() | STRICT_MODE_PENALTY_GATHER))?;
+            self.update_work_source_request_header_pos();
+            let work_source: i32 = if thread_state::should_propagate_work_source() {
+                thread_state::calling_work_source_uid() as _
+            } else {
+                thread_state::UNSET_WORK_SOURCE
+            };
+            self.write(&work_source)?;
+        }
+        let header = if self.is_for_rpc() {
+            binder::INTERFACE_HEADER
         } else {
-            thread_state::UNSET_WORK_SOURCE
+            self.process().interface_header()
         };
-        self.write(&work_source)?;
-        self.write(&binder::INTERFACE_HEADER)?;
+        self.write(&header)?;
         self.write(&interface)?;
 
         Ok(())
@@ -728,10 +1127,11 @@ impl Parcel {
         let mut first_idx: i32 = -1;
         let mut last_idx: i32 = -2;
         {
-            let object_size = std::mem::size_of::<flat_binder_object>() as u64;
-            let objects = self.objects.as_slice();
+            let objects = other.objects.as_slice();
 
             for (i, &off) in objects.iter().enumerate() {
+                let obj: &flat_binder_object = (other.data.as_ptr(), off as usize).into();
+                let object_size = object_size(obj.header_type()) as u64;
                 if off >= offset as _ && (off + object_size) <= (offset + size) as u64 {
                     if first_idx == -1 {
                         first_idx = i as i32;
@@ -757,29 +1157,166 @@ impl Parcel {
         self.set_data_position(self.pos + size);
 
         if num_objects > 0 {
-            let mut idx = self.objects.len();
-            self.objects.resize(idx + (num_objects as usize));
-
-            let objects = self.objects.as_mut_slice();
-            for i in first_idx..=last_idx {
-                let off = objects[i as usize] as usize - offset + start_pos;
-                objects[idx] = off as _;
-                idx += 1;
-                let flat: &mut flat_binder_object = (self.data.as_mut_ptr(), off).into();
-                flat.acquire()?;
-                if flat.header_type() == BINDER_TYPE_FD {
-                    //                    flat.set_handle(nix::fcntl::fcntl(flat.handle() as _, nix::fcntl::FcntlArg::F_DUPFD_CLOEXEC(0))? as _);
-                    flat.set_handle(
-                        rustix::io::fcntl_dupfd_cloexec(flat.borrowed_fd(), 0)?.into_raw_fd() as _,
-                    );
-                    flat.set_cookie(1);
+            let (first_idx, last_idx) = (first_idx as usize, last_idx as usize);
+            let base_idx = self.objects.len();
+            // The parent of an embedded object must be appended with it.
+            let rebase = |parent: binder_size_t, idx: usize| -> Result<binder_size_t> {
+                match (parent as usize).checked_sub(first_idx) {
+                    Some(parent) if parent < idx - base_idx => Ok((base_idx + parent) as _),
+                    _ => {
+                        log::error!(
+                            "Parcel::append_from: the parent of object {idx} isn't appended"
+                        );
+                        Err(StatusCode::BadValue)
+                    }
+                }
+            };
+            let mut addresses = Vec::new();
+
+            for (idx, i) in (base_idx..).zip(first_idx..=last_idx) {
+                let off = other.objects.as_slice()[i] as usize - offset + start_pos;
+                let obj: &flat_binder_object = (self.data.as_ptr(), off).into();
+                match obj.header_type() {
+                    BINDER_TYPE_PTR => {
+                        // The buffer may be owned by the other parcel or by the driver.
+                        let from = other.buffer_object_at(i)?;
+                        let buffer = other.buffer_slice(&from).to_vec();
+                        addresses.push((from.buffer, buffer.as_ptr() as binder_uintptr_t));
+                        self.buffers.push(buffer);
+
+                        if from.flags & BINDER_BUFFER_FLAG_HAS_PARENT != 0 {
+                            let ptr = unsafe { self.data.as_mut_ptr().add(off) }
+                                as *mut binder_buffer_object;
+                            let mut buffer_obj = unsafe { ptr.read_unaligned() };
+                            buffer_obj.parent = rebase(buffer_obj.parent, idx)?;
+                            unsafe { ptr.write_unaligned(buffer_obj) };
+                        }
+                    }
+                    BINDER_TYPE_FDA => {
+                        let ptr = unsafe { self.data.as_mut_ptr().add(off) }
+                            as *mut binder_fd_array_object;
+                        let mut fda = unsafe { ptr.read_unaligned() };
+                        fda.parent = rebase(fda.parent, idx)?;
+                        unsafe { ptr.write_unaligned(fda) };
+                    }
+                    _ => {}
                 }
+                self.clone_object(off, &addresses)?;
+                self.objects.push(off as _);
             }
         }
 
         Ok(())
     }
 
+    /// Duplicate a parcel written by this process, e.g. to send it from another
+    /// thread. The copy holds its own references to the binder objects and its
+    /// own duplicates of the file descriptors.
+    pub(crate) fn try_clone(&self) -> Result<Parcel> {
+        if self.free_buffer.is_some() {
+            log::error!("Parcel: a parcel received from the driver can't be cloned");
+            return Err(StatusCode::InvalidOperation);
+        }
+
+        let mut copy = Parcel::from_vec(self.data.as_slice().to_vec());
+        copy.pos = self.pos;
+        copy.request_header_present = self.request_header_present;
+        copy.work_source_request_header_pos = self.work_source_request_header_pos;
+        copy.rpc_session = self.rpc_session.clone();
+        copy.process = self.process;
+        copy.buffers = self.buffers.clone();
+        let addresses: Vec<(binder_uintptr_t, binder_uintptr_t)> = self
+            .buffers
+            .iter()
+            .zip(&copy.buffers)
+            .map(|(from, to)| (from.as_ptr() as _, to.as_ptr() as _))
+            .collect();
+
+        // The copy only owns the objects which were cloned, so an error leaves
+        // the rest of them alone.
+        for &offset in self.objects.as_slice() {
+            copy.clone_object(offset as usize, &addresses)?;
+            copy.objects.push(offset);
+        }
+
+        Ok(copy)
+    }
+
+    // Take ownership of the object at `offset`, which was copied from another parcel.
+    fn clone_object(
+        &mut self,
+        offset: usize,
+        addresses: &[(binder_uintptr_t, binder_uintptr_t)],
+    ) -> Result<()> {
+        let obj: &mut flat_binder_object = (self.data.as_mut_ptr(), offset).into();
+        match obj.header_type() {
+            BINDER_TYPE_PTR => {
+                let ptr =
+                    unsafe { self.data.as_mut_ptr().add(offset) } as *mut binder_buffer_object;
+                let mut buffer_obj = unsafe { ptr.read_unaligned() };
+                buffer_obj.buffer = addresses
+                    .iter()
+                    .find(|(from, _)| *from == buffer_obj.buffer)
+                    .ok_or(StatusCode::BadValue)?
+                    .1;
+                unsafe { ptr.write_unaligned(buffer_obj) };
+
+                if buffer_obj.flags & BINDER_BUFFER_FLAG_HAS_PARENT != 0 {
+                    let parent = self.buffer_object_at(buffer_obj.parent as usize)?;
+                    let parent_offset = buffer_obj.parent_offset as usize;
+                    self.buffers
+                        .iter_mut()
+                        .find(|b| b.as_ptr() as binder_uintptr_t == parent.buffer)
+                        .and_then(|b| {
+                            b.get_mut(
+                                parent_offset
+                                    ..parent_offset + std::mem::size_of::<binder_uintptr_t>(),
+                            )
+                        })
+                        .ok_or(StatusCode::BadValue)?
+                        .copy_from_slice(&buffer_obj.buffer.to_ne_bytes());
+                }
+            }
+            BINDER_TYPE_FDA => {
+                let fda = unsafe {
+                    std::ptr::read_unaligned(
+                        self.data.as_ptr().add(offset) as *const binder_fd_array_object
+                    )
+                };
+                let dups = self
+                    .fd_array(&fda)?
+                    .into_iter()
+                    .map(|fd| {
+                        let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+                        rustix::io::fcntl_dupfd_cloexec(fd, 0)
+                    })
+                    .collect::<std::result::Result<Vec<_>, _>>()?;
+                let parent = self.buffer_object_at(fda.parent as usize)?;
+                let parent_offset = fda.parent_offset as usize;
+                let array = self
+                    .buffers
+                    .iter_mut()
+                    .find(|b| b.as_ptr() as binder_uintptr_t == parent.buffer)
+                    .and_then(|b| {
+                        b.get_mut(
+                            parent_offset..parent_offset + dups.len() * std::mem::size_of::<u32>(),
+                        )
+                    })
+                    .ok_or(StatusCode::BadValue)?;
+                for (slot, fd) in array.chunks_exact_mut(std::mem::size_of::<u32>()).zip(dups) {
+                    slot.copy_from_slice(&fd.into_raw_fd().to_ne_bytes());
+                }
+            }
+            BINDER_TYPE_FD => {
+                let fd = rustix::io::fcntl_dupfd_cloexec(obj.borrowed_fd(), 0)?;
+                obj.set_handle(fd.into_raw_fd() as _);
+                obj.set_cookie(1);
+            }
+            _ => obj.acquire(self.process)?,
+        }
+        Ok(())
+    }
+
     fn release_objects(&self) {
         if self.objects.len() == 0 {
             return;
@@ -787,7 +1324,12 @@ impl Parcel {
 
         for pos in self.objects.as_slice() {
             let obj: &flat_binder_object = (self.data.as_ptr(), *pos as usize).into();
-            obj.release()
+            if obj.header_type() == BINDER_TYPE_FDA {
+                // The descriptors of a fd array are always owned by the parcel.
+                self.close_fd_array(*pos as usize);
+                continue;
+            }
+            obj.release(self.process)
                 .map_err(|e| log::error!("Parcel: unable to release object: {e:?}"))
                 .ok();
         }
@@ -798,13 +1340,16 @@ impl Drop for Parcel {
     fn drop(&mut self) {
         match self.free_buffer {
             Some(free_buffer) => {
-                free_buffer(
-                    Some(self),
-                    self.data.as_ptr() as _,
-                    self.data.len(),
-                    self.objects.as_ptr() as _,
-                    self.objects.len(),
-                )
+                // The buffer must be returned to the driver it came from.
+                thread_state::with_process(self.process(), || {
+                    free_buffer(
+                        Some(self),
+                        self.data.as_ptr() as _,
+                        self.data.len(),
+                        self.objects.as_ptr() as _,
+                        self.objects.len(),
+                    )
+                })
                 .unwrap();
             }
             None => {
@@ -846,6 +1391,9 @@ impl<const N: usize> TryFrom<&mut Parcel> for [u8; N] {
 
 #[cfg(test)]
 mod tests {
+    use std::os::fd::OwnedFd;
+
+    use crate::sys::binder::binder_buffer_object;
     use crate::*;
 
     #[test]
@@ -966,6 +1514,190 @@ mod tests {
     //     Ok(())
     // }
 
+    #[test]
+    fn test_buffer_objects() -> Result<()> {
+        let mut parcel = Parcel::new();
+        parcel.write(&7i32)?;
+        let parent = parcel.write_buffer(vec![0u8; 16])?;
+        let child = parcel.write_embedded_buffer(b"child".to_vec(), parent, 8)?;
+        assert_eq!((parent, child), (0, 1));
+        assert_eq!(parcel.buffers_size(), 24);
+        assert_eq!(
+            parcel.write_embedded_buffer(vec![0u8; 4], parent, 12).err(),
+            Some(StatusCode::BadValue)
+        );
+
+        parcel.set_data_position(0);
+        assert_eq!(parcel.read::<i32>()?, 7);
+        let (index, buffer) = parcel.read_buffer()?;
+        assert_eq!(index, parent);
+        let pointer = u64::from_ne_bytes(buffer[8..16].try_into().unwrap());
+        let (index, buffer) = parcel.read_embedded_buffer(parent, 8)?;
+        assert_eq!(index, child);
+        assert_eq!(buffer, b"child");
+        assert_eq!(pointer, buffer.as_ptr() as u64);
+
+        let position = parcel.data_position();
+        assert_eq!(parcel.read_buffer().err(), Some(StatusCode::BadType));
+        parcel.set_data_position(position - std::mem::size_of::<binder_buffer_object>());
+        assert_eq!(
+            parcel.read_embedded_buffer(parent, 0).err(),
+            Some(StatusCode::BadValue)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_fd_array() -> Result<()> {
+        use std::os::fd::AsRawFd;
+        use std::os::unix::fs::MetadataExt;
+
+        let file = std::fs::File::open(concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml")).unwrap();
+        let ino = file.metadata().unwrap().ino();
+        let fds = vec![
+            ParcelFileDescriptor::new(file.try_clone().unwrap()),
+            ParcelFileDescriptor::new(file),
+        ];
+
+        let mut parcel = Parcel::new();
+        parcel.write_fd_array(&fds)?;
+        // A native handle: numFds, numInts and the descriptors.
+        let handle = parcel.write_buffer(vec![0u8; 12])?;
+        parcel.write_embedded_fd_array(&fds[..1], handle, 8)?;
+        assert_eq!(
+            parcel.write_embedded_fd_array(&fds, handle, 8).err(),
+            Some(StatusCode::BadValue)
+        );
+
+        parcel.set_data_position(0);
+        let received = parcel.read_fd_array()?;
+        assert_eq!(received.len(), 2);
+        for fd in received {
+            assert!(fds.iter().all(|sent| sent.as_raw_fd() != fd.as_raw_fd()));
+            let file = std::fs::File::from(OwnedFd::from(fd));
+            assert_eq!(file.metadata().unwrap().ino(), ino);
+        }
+        let (handle, _) = parcel.read_buffer()?;
+        assert_eq!(
+            parcel.read_embedded_fd_array(handle, 4).err(),
+            Some(StatusCode::BadValue)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_try_clone() -> Result<()> {
+        use std::os::fd::AsRawFd;
+
+        let file = std::fs::File::open(concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml")).unwrap();
+        let fds = vec![ParcelFileDescriptor::new(file)];
+
+        let mut parcel = Parcel::new();
+        parcel.write(&7i32)?;
+        let parent = parcel.write_buffer(vec![0u8; 16])?;
+        parcel.write_embedded_buffer(b"child".to_vec(), parent, 8)?;
+        parcel.write_fd_array(&fds)?;
+
+        let mut copy = parcel.try_clone()?;
+        drop(parcel);
+        copy.set_data_position(0);
+        assert_eq!(copy.read::<i32>()?, 7);
+        let (parent, _) = copy.read_buffer()?;
+        let (_, child) = copy.read_embedded_buffer(parent, 8)?;
+        assert_eq!(child, b"child");
+        let received = copy.read_fd_array()?;
+        assert_eq!(received.len(), 1);
+        assert_ne!(received[0].as_raw_fd(), fds[0].as_raw_fd());
+        Ok(())
+    }
+
+    #[test]
+    fn test_append_from() -> Result<()> {
+        use std::os::fd::AsRawFd;
+        use std::os::unix::fs::MetadataExt;
+
+        let file = std::fs::File::open(concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml")).unwrap();
+        let ino = file.metadata().unwrap().ino();
+        let fd = ParcelFileDescriptor::new(file);
+
+        let mut other = Parcel::new();
+        other.write(&1i32)?;
+        other.write(&fd)?;
+
+        let mut parcel = Parcel::new();
+        parcel.write(&7i32)?;
+        parcel.append_all_from(&mut other)?;
+        drop(other);
+
+        parcel.set_data_position(0);
+        assert_eq!(parcel.read::<i32>()?, 7);
+        assert_eq!(parcel.read::<i32>()?, 1);
+        let received: ParcelFileDescriptor = parcel.read()?;
+        assert_ne!(received.as_raw_fd(), fd.as_raw_fd());
+        let file = std::fs::File::from(OwnedFd::from(received));
+        assert_eq!(file.metadata().unwrap().ino(), ino);
+        Ok(())
+    }
+
+    #[test]
+    fn test_append_buffer_objects() -> Result<()> {
+        let mut other = Parcel::new();
+        let parent = other.write_buffer(vec![0u8; 16])?;
+        let child_pos = other.data_position();
+        other.write_embedded_buffer(b"child".to_vec(), parent, 8)?;
+
+        let mut parcel = Parcel::new();
+        parcel.write_buffer(b"first".to_vec())?;
+        parcel.append_all_from(&mut other)?;
+        drop(other);
+        assert_eq!(parcel.buffers_size(), 32);
+
+        parcel.set_data_position(0);
+        let (_, first) = parcel.read_buffer()?;
+        assert_eq!(first, b"first");
+        let (parent, _) = parcel.read_buffer()?;
+        assert_eq!(parent, 1);
+        let (_, child) = parcel.read_embedded_buffer(parent, 8)?;
+        assert_eq!(child, b"child");
+
+        // An embedded buffer can't be appended without its parent.
+        let mut other = Parcel::new();
+        let parent = other.write_buffer(vec![0u8; 16])?;
+        other.write_embedded_buffer(b"child".to_vec(), parent, 8)?;
+        let size = other.data_size() - child_pos;
+        assert_eq!(
+            Parcel::new().append_from(&mut other, child_pos, size).err(),
+            Some(StatusCode::BadValue)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_append_fd_array() -> Result<()> {
+        use std::os::fd::AsRawFd;
+        use std::os::unix::fs::MetadataExt;
+
+        let file = std::fs::File::open(concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml")).unwrap();
+        let ino = file.metadata().unwrap().ino();
+        let fds = vec![ParcelFileDescriptor::new(file)];
+
+        let mut other = Parcel::new();
+        other.write_fd_array(&fds)?;
+        let mut parcel = Parcel::new();
+        parcel.write_buffer(b"first".to_vec())?;
+        parcel.append_all_from(&mut other)?;
+        drop(other);
+
+        parcel.set_data_position(0);
+        parcel.read_buffer()?;
+        let received = parcel.read_fd_array()?;
+        assert_eq!(received.len(), 1);
+        assert_ne!(received[0].as_raw_fd(), fds[0].as_raw_fd());
+        let file = std::fs::File::from(OwnedFd::from(received.into_iter().next().unwrap()));
+        assert_eq!(file.metadata().unwrap().ino(), ino);
+        Ok(())
+    }
+
     #[test]
     fn test_errors() -> Result<()> {
         Ok(())
@@ -23,7 +23,7 @@
 //! serialized and deserialized in binder parcels, providing the foundation
 //! for AIDL-generated types and custom parcelable implementations.
 
-use crate::{binder::*, binder_object::*, error::*, parcel::Parcel, process_state::*, sys::*};
+use crate::{binder::*, binder_object::*, error::*, parcel::Parcel, sys::*};
 
 /// Core trait for types that can be serialized to and from parcels.
 ///
@@ -377,6 +377,8 @@ parcelable_struct! {
 
     impl Serialize for binder_transaction_data;
     impl Deserialize for binder_transaction_data;
+
+    impl Deserialize for binder_frozen_state_info;
 }
 
 impl Serialize for String {
@@ -448,10 +450,32 @@ impl Serialize for SIBinder {
 
 impl SerializeOption for SIBinder {
     fn serialize_option(this: Option<&Self>, parcel: &mut Parcel) -> Result<()> {
+        if let Some(session) = parcel.rpc_session().cloned() {
+            return session.write_binder(parcel, this);
+        }
+
         match this {
             Some(binder) => {
-                parcel.write::<flat_binder_object>(&binder.into())?;
-                parcel.write::<i32>(&Stability::System.into())?;
+                let process = parcel.process();
+                if let Some(proxy) = binder.as_proxy() {
+                    // A handle is only meaningful to the driver which issued it.
+                    if !proxy.is_in_context(process) {
+                        log::error!(
+                            "Binder {} of another binder context can't be written to the parcel.",
+                            proxy.descriptor()
+                        );
+                        return Err(StatusCode::BadValue);
+                    }
+                }
+                parcel.write::<flat_binder_object>(&flat_binder_object::from_binder(
+                    binder, process,
+                ))?;
+                // A binder without a declared stability has the stability of its process.
+                let stability = match binder.stability() {
+                    Stability::Local => process.stability(),
+                    stability => stability,
+                };
+                parcel.write::<i32>(&stability.into())?;
                 Ok(())
             }
 
@@ -482,8 +506,14 @@ impl Deserialize for SIBinder {
 
 impl DeserializeOption for SIBinder {
     fn deserialize_option(parcel: &mut Parcel) -> Result<Option<Self>> {
+        if let Some(session) = parcel.rpc_session().cloned() {
+            return session.read_binder(parcel);
+        }
+
         let flat: flat_binder_object = parcel.read()?;
-        let stability: i32 = parcel.read()?;
+        // An unknown stability, e.g. of a newer Android version, is treated as
+        // the least stable one.
+        let stability = Stability::try_from(parcel.read::<i32>()?).unwrap_or(Stability::Local);
 
         match flat.header_type() {
             BINDER_TYPE_BINDER => {
@@ -497,8 +527,10 @@ impl DeserializeOption for SIBinder {
             }
 
             BINDER_TYPE_HANDLE => {
-                let res = ProcessState::as_self()
-                    .strong_proxy_for_handle_stability(flat.handle(), stability.try_into()?)?;
+                let res = parcel.process().strong_proxy_for_handle(flat.handle())?;
+                res.as_proxy()
+                    .ok_or(StatusCode::BadType)?
+                    .set_stability(stability)?;
                 Ok(Some(res))
             }
 
@@ -2,14 +2,14 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::collections::HashMap;
-use std::fs::File;
-use std::os::raw::c_void;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
-use std::sync::{Arc, OnceLock, RwLock};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
 use std::thread;
+use std::time::{Duration, Instant};
 
-use crate::{binder::*, error::*, proxy::*, sys::binder, thread_state};
+use crate::driver::{BinderDriver, ClosedDriver, KernelDriver};
+use crate::{binder::*, error::*, proxy::*, thread_state};
 
 #[derive(Debug, Clone, Copy)]
 pub enum CallRestriction {
@@ -21,21 +21,30 @@ pub enum CallRestriction {
     FatalIfNotOneway,
 }
 
-const DEFAULT_MAX_BINDER_THREADS: u32 = 15;
-const DEFAULT_ENABLE_ONEWAY_SPAM_DETECTION: u32 = 1;
-
-struct MemoryMap {
-    ptr: *mut c_void,
-    size: usize,
+/// What a frozen process received, as reported by [`ProcessState::frozen_info`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrozenInfo {
+    /// A synchronous transaction was received (and rejected) since the process was frozen.
+    pub sync_received: bool,
+    /// The process has not replied to a synchronous transaction yet.
+    pub sync_pending: bool,
+    /// A oneway transaction was received since the process was frozen.
+    pub async_received: bool,
 }
-unsafe impl Sync for MemoryMap {}
-unsafe impl Send for MemoryMap {}
+
+const DEFAULT_MAX_BINDER_THREADS: u32 = 15;
+const DEFAULT_ENABLE_ONEWAY_SPAM_DETECTION: bool = true;
 
 pub struct ProcessState {
     max_threads: u32,
     driver_name: PathBuf,
-    driver: Arc<File>,
-    mmap: RwLock<MemoryMap>,
+    driver: RwLock<Arc<dyn BinderDriver>>,
+    stability: Stability,
+    security_context: bool,
+    thread_name_prefix: Option<String>,
+    on_thread_start: Option<ThreadHook>,
+    on_thread_stop: Option<ThreadHook>,
+    service_manager: OnceLock<Arc<crate::hub::ServiceManager>>,
     context_manager: RwLock<Option<SIBinder>>,
     handle_to_proxy: RwLock<HashMap<u32, WIBinder>>,
     disable_background_scheduling: AtomicBool,
@@ -44,6 +53,8 @@ pub struct ProcessState {
     thread_pool_seq: AtomicUsize,
     kernel_started_threads: AtomicUsize,
     pub(crate) current_threads: AtomicUsize,
+    pool_threads: Mutex<Vec<thread::JoinHandle<Result<()>>>>,
+    shutting_down: AtomicBool,
 }
 
 impl ProcessState {
@@ -70,60 +81,24 @@ impl ProcessState {
         *self.call_restriction.read().unwrap()
     }
 
-    fn inner_init(
-        driver_name: &str,
-        max_threads: u32,
-    ) -> std::result::Result<ProcessState, Box<dyn std::error::Error>> {
-        let max_threads = if max_threads != 0 && max_threads < DEFAULT_MAX_BINDER_THREADS {
-            max_threads
-        } else {
-            DEFAULT_MAX_BINDER_THREADS
-        };
-
-        let driver_name = PathBuf::from(driver_name);
-
-        let driver = open_driver(&driver_name, max_threads)?;
-
-        let vm_size = (1024 * 1024) - rustix::param::page_size() * 2;
-        // let vm_size = std::num::NonZeroUsize::new(vm_size).ok_or("vm_size is zero!")?;
-
-        let mmap = unsafe {
-            // let vm_start = nix::sys::mman::mmap(None,
-            //     vm_size,
-            //     nix::sys::mman::ProtFlags::PROT_READ,
-            //     nix::sys::mman::MapFlags::MAP_PRIVATE | nix::sys::mman::MapFlags::MAP_NORESERVE,
-            //     &driver,
-            //     0)?;
-
-            let vm_start = rustix::mm::mmap(
-                std::ptr::null_mut(),
-                vm_size,
-                rustix::mm::ProtFlags::READ,
-                rustix::mm::MapFlags::PRIVATE | rustix::mm::MapFlags::NORESERVE,
-                &driver,
-                0,
-            )?;
+    /// The stability of the partition the process is on, see
+    /// [`ProcessStateBuilder::stability`].
+    pub fn stability(&self) -> Stability {
+        self.stability
+    }
 
-            (vm_start, vm_size)
-        };
+    // The interface header of the transactions the process sends and accepts.
+    pub(crate) fn interface_header(&self) -> u32 {
+        match self.stability {
+            Stability::Vendor => VENDOR_INTERFACE_HEADER,
+            _ => INTERFACE_HEADER,
+        }
+    }
 
-        Ok(ProcessState {
-            max_threads,
-            driver_name,
-            driver: driver.into(),
-            mmap: RwLock::new(MemoryMap {
-                ptr: mmap.0,
-                size: mmap.1,
-            }),
-            context_manager: RwLock::new(None),
-            handle_to_proxy: RwLock::new(HashMap::new()),
-            disable_background_scheduling: AtomicBool::new(false),
-            call_restriction: RwLock::new(CallRestriction::None),
-            thread_pool_started: AtomicBool::new(false),
-            thread_pool_seq: AtomicUsize::new(1),
-            kernel_started_threads: AtomicUsize::new(0),
-            current_threads: AtomicUsize::new(0),
-        })
+    /// Create a builder to initialize ProcessState with custom settings,
+    /// e.g. a larger receive buffer or hooks for the binder threads.
+    pub fn builder() -> ProcessStateBuilder {
+        ProcessStateBuilder::new()
     }
 
     /// Initialize ProcessState with binder path and max threads.
@@ -132,10 +107,16 @@ impl ProcessState {
     pub fn init(driver_name: &str, max_threads: u32) -> &'static ProcessState {
         // TODO: panic! is not good. It should return Result.
         // But, get_or_try_init is not stable yet.
-        Self::instance().get_or_init(|| match Self::inner_init(driver_name, max_threads) {
-            Ok(instance) => instance,
-            Err(e) => {
-                panic!("Error in init(): {e}");
+        Self::instance().get_or_init(|| {
+            match Self::builder()
+                .driver_name(driver_name)
+                .max_threads(max_threads)
+                .build()
+            {
+                Ok(instance) => instance,
+                Err(e) => {
+                    panic!("Error in init(): {e}");
+                }
             }
         })
     }
@@ -147,6 +128,27 @@ impl ProcessState {
         Self::init(crate::DEFAULT_BINDER_PATH, 0)
     }
 
+    /// Initialize ProcessState on top of an already opened binder driver.
+    /// It is used to run without the kernel driver, e.g. with [`crate::driver::MemoryDevice`].
+    /// The meaning of zero max threads is to use the default value.
+    pub fn init_with_driver(
+        driver: Arc<dyn BinderDriver>,
+        max_threads: u32,
+    ) -> &'static ProcessState {
+        Self::instance().get_or_init(|| {
+            match Self::builder()
+                .driver(driver)
+                .max_threads(max_threads)
+                .build()
+            {
+                Ok(instance) => instance,
+                Err(e) => {
+                    panic!("Error in init_with_driver(): {e}");
+                }
+            }
+        })
+    }
+
     /// Get binder service manager.
     pub fn become_context_manager(
         &self,
@@ -155,18 +157,8 @@ impl ProcessState {
         let mut context_manager = self.context_manager.write().unwrap();
 
         if context_manager.is_none() {
-            let obj = binder::flat_binder_object::new_binder_with_flags(
-                binder::FLAT_BINDER_FLAG_ACCEPTS_FDS,
-            );
-
-            if binder::set_context_mgr_ext(&self.driver, obj).is_err() {
-                //     android_errorWriteLog(0x534e4554, "121035042");
-                // let unused: i32 = 0;
-                if let Err(e) = binder::set_context_mgr(&self.driver, 0) {
-                    return Err(
-                        format!("Binder ioctl to become context manager failed: {e}").into(),
-                    );
-                }
+            if let Err(e) = self.driver().become_context_manager(self.security_context) {
+                return Err(format!("Binder ioctl to become context manager failed: {e}").into());
             }
             *context_manager = Some(binder);
         }
@@ -174,26 +166,25 @@ impl ProcessState {
         Ok(())
     }
 
+    pub(crate) fn service_manager(&self) -> &OnceLock<Arc<crate::hub::ServiceManager>> {
+        &self.service_manager
+    }
+
     pub(crate) fn context_manager(&self) -> Option<SIBinder> {
         self.context_manager.read().unwrap().clone()
     }
 
     /// Get binder service manager.
-    pub fn context_object(&self) -> Result<SIBinder> {
+    pub fn context_object(&'static self) -> Result<SIBinder> {
         self.strong_proxy_for_handle(0)
     }
 
     /// Get binder from handle.
     /// If the binder is not cached, it will create a new binder.
-    pub fn strong_proxy_for_handle(&self, handle: u32) -> Result<SIBinder> {
-        self.strong_proxy_for_handle_stability(handle, Default::default())
-    }
-
-    pub(crate) fn strong_proxy_for_handle_stability(
-        &self,
-        handle: u32,
-        stability: Stability,
-    ) -> Result<SIBinder> {
+    ///
+    /// A new binder has the stability of the process until a parcel declares
+    /// its stability.
+    pub fn strong_proxy_for_handle(&'static self, handle: u32) -> Result<SIBinder> {
         // Double-Checked Locking Pattern is used.
         if let Some(weak) = self.handle_to_proxy.read().unwrap().get(&handle) {
             return weak.upgrade();
@@ -204,18 +195,20 @@ impl ProcessState {
             return weak.upgrade();
         }
 
-        if handle == 0 {
-            let original_call_restriction = thread_state::call_restriction();
-            thread_state::set_call_restriction(CallRestriction::None);
+        let interface = thread_state::with_process(self, || -> Result<String> {
+            if handle == 0 {
+                let original_call_restriction = thread_state::call_restriction();
+                thread_state::set_call_restriction(CallRestriction::None);
 
-            thread_state::ping_binder(handle)?;
+                thread_state::ping_binder(handle)?;
 
-            thread_state::set_call_restriction(original_call_restriction);
-        }
+                thread_state::set_call_restriction(original_call_restriction);
+            }
 
-        let interface: String = thread_state::query_interface(handle)?;
+            thread_state::query_interface(handle)
+        })?;
 
-        let proxy: Arc<dyn IBinder> = ProxyHandle::new(handle, &interface, stability);
+        let proxy: Arc<dyn IBinder> = ProxyHandle::new_in_context(self, handle, &interface);
         let weak = WIBinder::new(proxy)?;
 
         handle_to_proxy.insert(handle, weak.clone());
@@ -232,6 +225,57 @@ impl ProcessState {
         Ok(())
     }
 
+    pub(crate) fn cached_proxy_for_handle(&self, handle: u32) -> Option<WIBinder> {
+        self.handle_to_proxy.read().unwrap().get(&handle).cloned()
+    }
+
+    pub(crate) fn send_frozen_state_for_handle(&self, handle: u32, frozen: bool) -> Result<()> {
+        if let Some(weak) = self.cached_proxy_for_handle(handle) {
+            if let Ok(strong) = weak.upgrade() {
+                let state = if frozen {
+                    FrozenState::Frozen
+                } else {
+                    FrozenState::Unfrozen
+                };
+                strong
+                    .as_proxy()
+                    .unwrap()
+                    .on_frozen_state_changed(&weak, state);
+            }
+        }
+        Ok(())
+    }
+
+    /// Freeze or unfreeze the binder state of process `pid`, like the cached apps
+    /// freezer of Android does before freezing the process with the cgroup freezer.
+    ///
+    /// While frozen, synchronous transactions to the process fail with
+    /// `FailedTransaction`, and oneway transactions are queued until it is
+    /// unfrozen. Freezing waits up to `timeout` for transactions already queued
+    /// to the process and fails with `WouldBlock` if the process is still
+    /// serving a synchronous transaction; the process is left unfrozen then.
+    pub fn freeze(&self, pid: i32, enable: bool, timeout: Duration) -> Result<()> {
+        let timeout_ms = timeout.as_millis().min(u32::MAX as _) as u32;
+        self.driver()
+            .freeze(pid as _, enable, timeout_ms)
+            .inspect_err(|&e| {
+                log::error!("Binder ioctl(BINDER_FREEZE) for pid {pid} failed: {e:?}");
+            })?;
+        Ok(())
+    }
+
+    /// What process `pid` received since it was frozen.
+    pub fn frozen_info(&self, pid: i32) -> Result<FrozenInfo> {
+        let info = self.driver().frozen_info(pid as _).inspect_err(|&e| {
+            log::error!("Binder ioctl(BINDER_GET_FROZEN_INFO) for pid {pid} failed: {e:?}");
+        })?;
+        Ok(FrozenInfo {
+            sync_received: info.sync_recv & 1 != 0,
+            sync_pending: info.sync_recv & 2 != 0,
+            async_received: info.async_recv != 0,
+        })
+    }
+
     pub fn disable_background_scheduling(&self, disable: bool) {
         self.disable_background_scheduling
             .store(disable, Ordering::Relaxed);
@@ -241,43 +285,63 @@ impl ProcessState {
         self.disable_background_scheduling.load(Ordering::Relaxed)
     }
 
-    pub fn driver(&self) -> Arc<File> {
-        self.driver.clone()
+    pub fn driver(&self) -> Arc<dyn BinderDriver> {
+        self.driver.read().unwrap().clone()
     }
 
+    /// Start the thread pool of the default context.
     pub fn start_thread_pool() {
-        let this = Self::as_self();
-        if this
+        Self::as_self().start_pool();
+    }
+
+    /// Start the thread pool of this context.
+    pub fn start_pool(&'static self) {
+        if self
             .thread_pool_started
             .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
             .is_ok()
         {
-            if this.max_threads == 0 {
+            if self.max_threads == 0 {
                 log::warn!("Extra binder thread started, but 0 threads requested.\nDo not use *start_thread_pool when zero threads are requested.");
             }
-            this.spawn_pooled_thread(true);
+            self.spawn_pooled_thread(true);
         }
     }
 
     fn make_binder_thread_name(&self) -> String {
         let seq = self.thread_pool_seq.fetch_add(1, Ordering::SeqCst);
         let pid = std::process::id();
-        let driver_name = self
-            .driver_name
-            .file_name()
-            .and_then(|name| name.to_str())
-            .map(|name| name.to_owned())
-            .unwrap_or("BINDER".to_owned());
-        format!("{driver_name}:{pid}_{seq:X}")
+        let prefix = self.thread_name_prefix.clone().unwrap_or_else(|| {
+            self.driver_name
+                .file_name()
+                .and_then(|name| name.to_str())
+                .map(|name| name.to_owned())
+                .unwrap_or("BINDER".to_owned())
+        });
+        format!("{prefix}:{pid}_{seq:X}")
     }
 
-    pub(crate) fn spawn_pooled_thread(&self, is_main: bool) {
-        if self.thread_pool_started.load(Ordering::Relaxed) {
+    pub(crate) fn spawn_pooled_thread(&'static self, is_main: bool) {
+        if self.thread_pool_started.load(Ordering::Relaxed) && !self.is_shutting_down() {
             let name = self.make_binder_thread_name();
             log::info!("Spawning new pooled thread, name={name}");
-            let _ = thread::Builder::new()
-                .name(name)
-                .spawn(move || thread_state::join_thread_pool(is_main));
+            let on_start = self.on_thread_start.clone();
+            let on_stop = self.on_thread_stop.clone();
+            let spawned = thread::Builder::new().name(name).spawn(move || {
+                if let Some(hook) = on_start {
+                    hook();
+                }
+                let result =
+                    thread_state::with_process(self, || thread_state::join_thread_pool(is_main));
+                if let Some(hook) = on_stop {
+                    hook();
+                }
+                result
+            });
+            match spawned {
+                Ok(handle) => self.pool_threads.lock().unwrap().push(handle),
+                Err(e) => log::error!("Failed to spawn a pooled thread: {e}"),
+            }
 
             self.kernel_started_threads.fetch_add(1, Ordering::SeqCst);
         }
@@ -289,71 +353,290 @@ impl ProcessState {
     }
 
     pub fn strong_ref_count_for_node(&self, node: &ProxyHandle) -> Result<usize> {
-        let mut info = binder::binder_node_info_for_ref {
-            handle: node.handle(),
-            strong_count: 0,
-            weak_count: 0,
-            reserved1: 0,
-            reserved2: 0,
-            reserved3: 0,
-        };
-
-        binder::get_node_info_for_ref(&self.driver, &mut info).inspect_err(|&e| {
-            log::error!("Binder ioctl(BINDER_GET_NODE_INFO_FOR_REF) failed: {e:?}");
-        })?;
-        Ok(info.strong_count as usize)
+        let count = self
+            .driver()
+            .strong_ref_count_for_handle(node.handle())
+            .inspect_err(|&e| {
+                log::error!("Binder ioctl(BINDER_GET_NODE_INFO_FOR_REF) failed: {e:?}");
+            })?;
+        Ok(count)
     }
 
+    /// Join the calling thread to the thread pool of the default context.
     pub fn join_thread_pool() -> Result<()> {
-        thread_state::join_thread_pool(true)
+        Self::as_self().join_pool()
     }
+
+    /// Join the calling thread to the thread pool of this context.
+    /// It returns when the context is shut down.
+    pub fn join_pool(&'static self) -> Result<()> {
+        thread_state::with_process(self, || thread_state::join_thread_pool(true))
+    }
+
+    /// Shut down the thread pool and close the binder driver of this context.
+    ///
+    /// The threads of the pool, including the ones which joined it with
+    /// [`ProcessState::join_pool`], finish the transactions they are serving,
+    /// leave the pool with `BC_EXIT_LOOPER` and exit. It fails with `TimedOut`
+    /// if they are still busy after `timeout`; it can be called again then.
+    ///
+    /// Afterwards binder calls of the context fail. The driver, including the
+    /// mapped receive buffer, is released when the last thread which made binder
+    /// calls exits, so parcels received from the driver must be dropped before.
+    /// It can't be called from a thread of the pool.
+    pub fn shutdown(&'static self, timeout: Duration) -> Result<()> {
+        if thread_state::is_looper(self) {
+            log::error!("ProcessState::shutdown() can't be called from the thread pool.");
+            return Err(StatusCode::InvalidOperation);
+        }
+
+        self.shutting_down.store(true, Ordering::SeqCst);
+        let deadline = Instant::now() + timeout;
+        loop {
+            let mut pool_threads = self.pool_threads.lock().unwrap();
+            let (finished, running) = std::mem::take(&mut *pool_threads)
+                .into_iter()
+                .partition::<Vec<_>, _>(|handle| handle.is_finished());
+            *pool_threads = running;
+            let idle = pool_threads.is_empty() && self.current_threads.load(Ordering::SeqCst) == 0;
+            drop(pool_threads);
+
+            for handle in finished {
+                if let Ok(Err(e)) = handle.join() {
+                    log::warn!("A pooled thread left with {e}");
+                }
+            }
+            if idle {
+                break;
+            }
+            if Instant::now() >= deadline {
+                log::error!("Binder threads are still busy, shutdown timed out.");
+                return Err(StatusCode::TimedOut);
+            }
+
+            // A thread may start waiting right after the wakeup, so repeat it.
+            if let Err(e) = self.driver().wake_threads() {
+                log::warn!("Failed to wake up binder threads: {e}");
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        self.handle_to_proxy.write().unwrap().clear();
+        self.context_manager.write().unwrap().take();
+        thread_state::release_thread_state(self);
+        let mut driver = self.driver.write().unwrap();
+        *driver = Arc::new(ClosedDriver::new(driver.name()));
+        log::info!("Binder context {} is shut down.", driver.name());
+
+        Ok(())
+    }
+
+    pub(crate) fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+}
+
+/// A hook run by a binder thread of the pool, see [`ProcessStateBuilder::on_thread_start`].
+pub type ThreadHook = Arc<dyn Fn() + Send + Sync>;
+
+enum DriverSource {
+    Path(String),
+    Opened(Arc<dyn BinderDriver>),
 }
 
-fn open_driver(
-    driver: &Path,
+/// Builder of the [`ProcessState`] of the process, created by [`ProcessState::builder`].
+///
+/// ```no_run
+/// # use rsbinder::ProcessState;
+/// ProcessState::builder()
+///     .vm_size(4 * 1024 * 1024)
+///     .thread_name_prefix("camera")
+///     .on_thread_start(|| log::info!("binder thread started"))
+///     .init()
+///     .expect("binder");
+/// ProcessState::start_thread_pool();
+/// ```
+pub struct ProcessStateBuilder {
+    driver: DriverSource,
     max_threads: u32,
-) -> std::result::Result<File, Box<dyn std::error::Error>> {
-    let fd = File::options()
-        .read(true)
-        .write(true)
-        .open(driver)
-        .map_err(|e| format!("Opening '{}' failed: {}\n", driver.to_string_lossy(), e))?;
+    vm_size: Option<usize>,
+    oneway_spam_detection: bool,
+    stability: Stability,
+    security_context: bool,
+    thread_name_prefix: Option<String>,
+    on_thread_start: Option<ThreadHook>,
+    on_thread_stop: Option<ThreadHook>,
+}
+
+impl ProcessStateBuilder {
+    fn new() -> Self {
+        ProcessStateBuilder {
+            driver: DriverSource::Path(crate::DEFAULT_BINDER_PATH.to_owned()),
+            max_threads: 0,
+            vm_size: None,
+            oneway_spam_detection: DEFAULT_ENABLE_ONEWAY_SPAM_DETECTION,
+            stability: Stability::System,
+            security_context: false,
+            thread_name_prefix: None,
+            on_thread_start: None,
+            on_thread_stop: None,
+        }
+    }
 
-    let mut vers = binder::binder_version {
-        protocol_version: 0,
-    };
+    /// Path of the binder device to open. The default is DEFAULT_BINDER_PATH.
+    pub fn driver_name(mut self, driver_name: &str) -> Self {
+        self.driver = DriverSource::Path(driver_name.to_owned());
+        self
+    }
 
-    binder::version(&fd, &mut vers)
-        .map_err(|e| format!("Binder ioctl to obtain version failed: {e}"))?;
-    log::info!("Binder driver protocol version: {}", vers.protocol_version);
+    /// Use an already opened binder driver, e.g. [`crate::driver::MemoryDevice`].
+    pub fn driver(mut self, driver: Arc<dyn BinderDriver>) -> Self {
+        self.driver = DriverSource::Opened(driver);
+        self
+    }
 
-    if vers.protocol_version != binder::BINDER_CURRENT_PROTOCOL_VERSION as i32 {
-        return Err(format!(
-            "Binder driver protocol({}) does not match user space protocol({})!",
-            vers.protocol_version,
-            binder::BINDER_CURRENT_PROTOCOL_VERSION
-        )
-        .into());
+    /// Maximum number of binder threads the driver may ask for.
+    /// The meaning of zero max threads is to use the default value.
+    pub fn max_threads(mut self, max_threads: u32) -> Self {
+        self.max_threads = max_threads;
+        self
     }
 
-    binder::set_max_threads(&fd, max_threads)
-        .map_err(|e| format!("Binder ioctl to set max threads failed: {e}"))?;
-    log::info!("Binder driver max threads set to {max_threads}");
+    /// Size of the receive buffer mapped from the binder device, see
+    /// [`KernelDriver::open_with_vm_size`]. It can't be set for an opened driver.
+    pub fn vm_size(mut self, vm_size: usize) -> Self {
+        self.vm_size = Some(vm_size);
+        self
+    }
 
-    let enable = DEFAULT_ENABLE_ONEWAY_SPAM_DETECTION;
-    if let Err(e) = binder::enable_oneway_spam_detection(&fd, enable) {
-        log::warn!("Binder ioctl to enable oneway spam detection failed: {e}")
+    /// Whether the driver reports oneway spam, which is enabled by default.
+    pub fn oneway_spam_detection(mut self, enable: bool) -> Self {
+        self.oneway_spam_detection = enable;
+        self
     }
 
-    Ok(fd)
-}
+    /// The partition the process is on, [`Stability::System`] by default or
+    /// [`Stability::Vendor`].
+    ///
+    /// The process only calls the remote objects which are stable on its
+    /// partition, and its local objects of [`Stability::Local`] are sent with
+    /// its stability. Its transactions carry the interface header of the
+    /// partition, so that the services of the other partition refuse them.
+    pub fn stability(mut self, stability: Stability) -> Self {
+        self.stability = stability;
+        self
+    }
+
+    /// Whether the transactions to the context manager of the process carry the
+    /// security context of the caller, see [`ProcessState::become_context_manager`].
+    /// It is disabled by default.
+    pub fn security_context(mut self, enable: bool) -> Self {
+        self.security_context = enable;
+        self
+    }
+
+    /// Prefix of the names of binder threads, which are named `"{prefix}:{pid}_{seq}"`.
+    /// The default is the file name of the driver.
+    pub fn thread_name_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.thread_name_prefix = Some(prefix.into());
+        self
+    }
+
+    /// Run `hook` on every thread spawned for the thread pool before it starts
+    /// handling transactions, e.g. to set the CPU affinity or the scheduler policy.
+    pub fn on_thread_start(mut self, hook: impl Fn() + Send + Sync + 'static) -> Self {
+        self.on_thread_start = Some(Arc::new(hook));
+        self
+    }
+
+    /// Run `hook` on a thread spawned for the thread pool when it leaves the pool.
+    pub fn on_thread_stop(mut self, hook: impl Fn() + Send + Sync + 'static) -> Self {
+        self.on_thread_stop = Some(Arc::new(hook));
+        self
+    }
 
-impl Drop for ProcessState {
-    fn drop(self: &mut ProcessState) {
-        let mmap = self.mmap.read().unwrap();
-        unsafe {
-            rustix::mm::munmap(mmap.ptr, mmap.size).expect("Failed to unmap memory");
+    /// Initialize the default ProcessState of the process, which is returned by
+    /// [`ProcessState::as_self`].
+    /// It fails if the driver can't be opened or ProcessState is already initialized.
+    pub fn init(self) -> std::result::Result<&'static ProcessState, Box<dyn std::error::Error>> {
+        let instance = ProcessState::instance();
+        if instance.get().is_some() {
+            return Err("ProcessState is already initialized".into());
         }
+        instance
+            .set(self.build()?)
+            .map_err(|_| "ProcessState is already initialized")?;
+        Ok(ProcessState::as_self())
+    }
+
+    /// Open an additional binder context, e.g. for `/dev/binderfs/vndbinder`.
+    ///
+    /// The context has its own handles, thread pool and [`crate::hub`] client,
+    /// and lives until the process exits. Binders received from it can only be
+    /// sent back over the same context.
+    pub fn init_context(
+        self,
+    ) -> std::result::Result<&'static ProcessState, Box<dyn std::error::Error>> {
+        Ok(Box::leak(Box::new(self.build()?)))
+    }
+
+    fn build(self) -> std::result::Result<ProcessState, Box<dyn std::error::Error>> {
+        if !matches!(self.stability, Stability::System | Stability::Vendor) {
+            return Err(format!(
+                "A process can't have the stability {:?}, only System or Vendor",
+                self.stability
+            )
+            .into());
+        }
+
+        let driver: Arc<dyn BinderDriver> = match (self.driver, self.vm_size) {
+            (DriverSource::Path(path), vm_size) => Arc::new(KernelDriver::open_with_vm_size(
+                &path,
+                vm_size.unwrap_or_else(crate::driver::default_vm_size),
+            )?),
+            (DriverSource::Opened(driver), None) => driver,
+            (DriverSource::Opened(_), Some(_)) => {
+                return Err("The vm size of an opened driver can't be changed".into())
+            }
+        };
+
+        let max_threads = if self.max_threads != 0 && self.max_threads < DEFAULT_MAX_BINDER_THREADS
+        {
+            self.max_threads
+        } else {
+            DEFAULT_MAX_BINDER_THREADS
+        };
+
+        driver
+            .set_max_threads(max_threads)
+            .map_err(|e| format!("Binder ioctl to set max threads failed: {e}"))?;
+        log::info!("Binder driver max threads set to {max_threads}");
+
+        if let Err(e) = driver.enable_oneway_spam_detection(self.oneway_spam_detection) {
+            log::warn!("Binder ioctl to enable oneway spam detection failed: {e}")
+        }
+
+        Ok(ProcessState {
+            max_threads,
+            driver_name: PathBuf::from(driver.name()),
+            driver: RwLock::new(driver),
+            stability: self.stability,
+            security_context: self.security_context,
+            thread_name_prefix: self.thread_name_prefix,
+            on_thread_start: self.on_thread_start,
+            on_thread_stop: self.on_thread_stop,
+            service_manager: OnceLock::new(),
+            context_manager: RwLock::new(None),
+            handle_to_proxy: RwLock::new(HashMap::new()),
+            disable_background_scheduling: AtomicBool::new(false),
+            call_restriction: RwLock::new(CallRestriction::None),
+            thread_pool_started: AtomicBool::new(false),
+            thread_pool_seq: AtomicUsize::new(1),
+            kernel_started_threads: AtomicUsize::new(0),
+            current_threads: AtomicUsize::new(0),
+            pool_threads: Mutex::new(Vec::new()),
+            shutting_down: AtomicBool::new(false),
+        })
     }
 }
 
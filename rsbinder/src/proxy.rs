@@ -10,14 +10,32 @@
 use std::any::Any;
 use std::fmt::{Debug, Formatter};
 use std::mem::ManuallyDrop;
-use std::os::fd::IntoRawFd;
+use std::os::fd::{AsFd, IntoRawFd};
 use std::sync::atomic::AtomicBool;
-use std::sync::{self, Arc, RwLock};
+use std::sync::{self, mpsc, Arc, RwLock};
+use std::time::{Duration, Instant};
 
 use crate::{
-    binder::*, binder_object::*, error::*, parcel::*, ref_counter::RefCounter, thread_state,
+    binder::*,
+    binder_object::*,
+    driver::DriverFeature,
+    error::*,
+    parcel::*,
+    process_state::ProcessState,
+    ref_counter::RefCounter,
+    rpc::{RpcSession, RpcTarget},
+    thread_state,
 };
 
+#[derive(Default)]
+struct FrozenStateWatch {
+    // The last state reported by the driver.
+    state: Option<FrozenState>,
+    // BC_REQUEST_FREEZE_NOTIFICATION was sent for the handle.
+    requested: bool,
+    callbacks: Vec<sync::Weak<dyn FrozenStateChangeCallback>>,
+}
+
 /// Handle for a proxy to a remote binder service.
 ///
 /// `ProxyHandle` represents the client-side handle to a remote service,
@@ -26,9 +44,17 @@ use crate::{
 pub struct ProxyHandle {
     handle: u32,
     descriptor: String,
-    stability: Stability,
+    stability: RwLock<Stability>,
+    // Whether a parcel declared the stability. Otherwise it is the stability
+    // of the process, which any declared stability replaces.
+    stability_declared: AtomicBool,
     obituary_sent: AtomicBool,
     recipients: RwLock<Vec<sync::Weak<dyn DeathRecipient>>>,
+    frozen: RwLock<FrozenStateWatch>,
+    rpc: Option<RpcTarget>,
+    // The binder context which issued the handle. None is the default context.
+    process: Option<&'static ProcessState>,
+    call_timeout: RwLock<Option<Duration>>,
 
     strong: RefCounter,
     weak: RefCounter,
@@ -40,14 +66,134 @@ impl ProxyHandle {
         Arc::new(Self {
             handle,
             descriptor: descriptor.to_owned(),
-            stability,
+            stability: RwLock::new(stability),
+            stability_declared: AtomicBool::new(true),
             obituary_sent: AtomicBool::new(false),
             recipients: RwLock::new(Vec::new()),
+            frozen: Default::default(),
+            rpc: None,
+            process: None,
+            call_timeout: RwLock::new(None),
             strong: Default::default(),
             weak: Default::default(),
         })
     }
 
+    /// Create a proxy for a binder owned by the peer of an RPC session.
+    pub(crate) fn new_rpc(target: RpcTarget, descriptor: &str, stability: Stability) -> Arc<Self> {
+        Arc::new(Self {
+            handle: 0,
+            descriptor: descriptor.to_owned(),
+            stability: RwLock::new(stability),
+            stability_declared: AtomicBool::new(true),
+            obituary_sent: AtomicBool::new(false),
+            recipients: RwLock::new(Vec::new()),
+            frozen: Default::default(),
+            rpc: Some(target),
+            process: None,
+            call_timeout: RwLock::new(None),
+            strong: Default::default(),
+            weak: Default::default(),
+        })
+    }
+
+    /// Create a proxy for a handle issued by the driver of `process`. It has
+    /// the stability of `process` until a parcel declares its stability.
+    pub(crate) fn new_in_context(
+        process: &'static ProcessState,
+        handle: u32,
+        descriptor: &str,
+    ) -> Arc<Self> {
+        let mut proxy = Self::new(handle, descriptor, process.stability());
+        let inner = Arc::get_mut(&mut proxy).expect("The proxy is not shared yet");
+        inner.process = Some(process);
+        inner.stability_declared = AtomicBool::new(false);
+        proxy
+    }
+
+    /// Give the proxy the stability its sender declared, when it is received again.
+    ///
+    /// A declared stability can only be lowered, e.g. from [`Stability::Vintf`]
+    /// to [`Stability::System`]. A sender which claims more is refused. The
+    /// stability of the process, which a proxy has until then, is replaced.
+    pub(crate) fn set_stability(&self, stability: Stability) -> Result<()> {
+        let mut current = self.stability.write().unwrap();
+        if self
+            .stability_declared
+            .load(std::sync::atomic::Ordering::Relaxed)
+            && *current != stability
+            && *current != Stability::Local
+            && !current.check(stability)
+        {
+            log::error!(
+                "Interface {} being set with {stability:?} but it is already marked as {:?}",
+                self.descriptor,
+                *current
+            );
+            return Err(StatusCode::BadType);
+        }
+        *current = stability;
+        self.stability_declared
+            .store(true, std::sync::atomic::Ordering::Relaxed);
+        Ok(())
+    }
+
+    // Whether the remote object can be called with `flags` by this process.
+    fn check_stability(&self, code: TransactionCode, flags: TransactionFlags) -> Result<()> {
+        if !(FIRST_CALL_TRANSACTION..=LAST_CALL_TRANSACTION).contains(&code) {
+            return Ok(());
+        }
+        let required = if flags & FLAG_PRIVATE_VENDOR != 0 {
+            Stability::Vendor
+        } else {
+            // RPC sessions aren't bound to a partition.
+            self.process()
+                .map_or(Stability::System, ProcessState::stability)
+        };
+        let stability = *self.stability.read().unwrap();
+        if !stability.check(required) {
+            log::error!(
+                "Cannot do a user transaction on a {stability:?} binder ({}) in a {required:?} context",
+                self.descriptor
+            );
+            return Err(StatusCode::BadType);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn rpc_target(&self) -> Option<&RpcTarget> {
+        self.rpc.as_ref()
+    }
+
+    /// The RPC session the binder belongs to, or `None` for a binder of the kernel driver.
+    pub fn rpc_session(&self) -> Option<&Arc<RpcSession>> {
+        self.rpc.as_ref().map(|target| &target.session)
+    }
+
+    /// The binder context the handle belongs to, or `None` for a binder of an RPC session.
+    pub fn process(&self) -> Option<&'static ProcessState> {
+        match self.rpc {
+            Some(_) => None,
+            None => Some(self.process.unwrap_or_else(ProcessState::as_self)),
+        }
+    }
+
+    pub(crate) fn is_in_context(&self, process: &ProcessState) -> bool {
+        self.rpc.is_none()
+            && match self.process {
+                Some(this) => std::ptr::eq(this, process),
+                None => std::ptr::eq(ProcessState::as_self(), process),
+            }
+    }
+
+    // Run `f` in the binder context of the handle.
+    fn in_context<R>(&self, f: impl FnOnce() -> R) -> R {
+        match self.process {
+            Some(process) => thread_state::with_process(process, f),
+            None => f(),
+        }
+    }
+
     /// Get the underlying binder handle number.
     pub fn handle(&self) -> u32 {
         self.handle
@@ -58,21 +204,130 @@ impl ProxyHandle {
         &self.descriptor
     }
 
+    /// Set how long synchronous transactions submitted with
+    /// [`ProxyHandle::submit_transact`] wait for a reply before they fail with
+    /// `TimedOut`. `None`, the default, waits forever.
+    ///
+    /// The proxy is shared by all the users of the remote binder in the process.
+    pub fn set_call_timeout(&self, timeout: Option<Duration>) {
+        *self.call_timeout.write().unwrap() = timeout;
+    }
+
+    /// The timeout set with [`ProxyHandle::set_call_timeout`].
+    pub fn call_timeout(&self) -> Option<Duration> {
+        *self.call_timeout.read().unwrap()
+    }
+
     /// Submit a transaction to the remote service.
+    ///
+    /// A synchronous transaction with a deadline, set with
+    /// [`ProxyHandle::set_call_timeout`] or [`with_call_timeout`], fails with
+    /// `TimedOut` if the reply doesn't arrive in time.
     pub fn submit_transact(
         &self,
         code: TransactionCode,
         data: &Parcel,
         flags: TransactionFlags,
     ) -> Result<Option<Parcel>> {
-        thread_state::transact(self.handle(), code, data, flags)
+        self.check_stability(code, flags)?;
+        let flags = flags & !FLAG_PRIVATE_VENDOR;
+
+        if flags & FLAG_ONEWAY == 0 {
+            let timeout = self.call_timeout().map(|timeout| Instant::now() + timeout);
+            let deadline = match (thread_state::call_deadline(), timeout) {
+                (Some(deadline), Some(timeout)) => Some(deadline.min(timeout)),
+                (deadline, timeout) => deadline.or(timeout),
+            };
+            if let Some(deadline) = deadline {
+                return self.submit_transact_until(code, data, flags, deadline);
+            }
+        }
+
+        match &self.rpc {
+            Some(target) => target.session.transact(target.address, code, data, flags),
+            None => self.in_context(|| thread_state::transact(self.handle(), code, data, flags)),
+        }
+    }
+
+    // The transaction is made by a thread of its own, so that the caller can
+    // stop waiting for it. A reply which arrives too late is freed by that thread.
+    // A thread handling a transaction makes the call itself, as nested calls and
+    // callbacks are routed to it, and waits for the reply without a deadline.
+    fn submit_transact_until(
+        &self,
+        code: TransactionCode,
+        data: &Parcel,
+        flags: TransactionFlags,
+        deadline: Instant,
+    ) -> Result<Option<Parcel>> {
+        if self.rpc.is_none() && thread_state::is_handling_transaction() {
+            return self.in_context(|| thread_state::transact(self.handle(), code, data, flags));
+        }
+
+        let data = data.try_clone()?;
+        let (sender, receiver) = mpsc::sync_channel(1);
+        let thread = std::thread::Builder::new().name("binder-timed".to_owned());
+        let call = match &self.rpc {
+            Some(target) => {
+                let (session, address) = (target.session.clone(), target.address);
+                thread.spawn(move || {
+                    let result = session.transact(address, code, &data, flags);
+                    let _ = sender.send((result, None));
+                })
+            }
+            None => {
+                let (handle, caller) = (self.handle, self.in_context(thread_state::caller_state));
+                thread.spawn(move || {
+                    let result = thread_state::as_caller(caller, || {
+                        thread_state::transact(handle, code, &data, flags)
+                    });
+                    // A reply the caller stopped waiting for is freed before
+                    // the commands of the thread are flushed.
+                    let _ = sender.send(result);
+                    thread_state::release_thread_state(caller.process());
+                })
+            }
+        }
+        .map_err(|e| {
+            log::error!("Failed to spawn the thread of a call with a deadline: {e}");
+            StatusCode::from(e)
+        })?;
+
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match receiver.recv_timeout(timeout) {
+            Ok((result, extended_error)) => {
+                thread_state::set_last_extended_error(extended_error);
+                result
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                log::warn!(
+                    "Transaction {code} of {} timed out waiting for a reply",
+                    self.descriptor
+                );
+                thread_state::set_last_extended_error(None);
+                Err(StatusCode::TimedOut)
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => match call.join() {
+                Err(panic) => std::panic::resume_unwind(panic),
+                Ok(()) => unreachable!("The thread of a call exited without a result"),
+            },
+        }
     }
 
     pub fn prepare_transact(&self, write_header: bool) -> Result<Parcel> {
-        let mut data = Parcel::new();
+        let mut data = match &self.rpc {
+            Some(target) => target.session.new_parcel(),
+            None => {
+                let mut data = Parcel::new();
+                if let Some(process) = self.process {
+                    data.set_process(process);
+                }
+                data
+            }
+        };
 
         if write_header {
-            data.write_interface_token(self.descriptor())?;
+            self.in_context(|| data.write_interface_token(self.descriptor()))?;
         }
 
         Ok(data)
@@ -83,9 +338,11 @@ impl ProxyHandle {
             .store(true, std::sync::atomic::Ordering::Relaxed);
 
         let recipients = self.recipients.read().unwrap();
-        if !recipients.is_empty() {
-            thread_state::clear_death_notification(self.handle())?;
-            thread_state::flush_commands()?;
+        if !recipients.is_empty() && self.rpc.is_none() {
+            self.in_context(|| {
+                thread_state::clear_death_notification(self.handle())?;
+                thread_state::flush_commands()
+            })?;
         }
 
         // To remember the recipients to remove
@@ -111,6 +368,108 @@ impl ProxyHandle {
         Ok(())
     }
 
+    /// Register a callback for the frozen state of the process hosting this object.
+    ///
+    /// The callback is called with the current state soon after it is registered
+    /// and then on every change. It needs a driver which supports
+    /// [`DriverFeature::FreezeNotification`], and is not supported over RPC sessions.
+    pub fn add_frozen_state_change_callback(
+        &self,
+        callback: sync::Weak<dyn FrozenStateChangeCallback>,
+    ) -> Result<()> {
+        if self.rpc.is_some() {
+            log::error!("Frozen state notifications are not supported over RPC sessions.");
+            return Err(StatusCode::InvalidOperation);
+        }
+        if self
+            .obituary_sent
+            .load(std::sync::atomic::Ordering::Relaxed)
+        {
+            return Err(StatusCode::DeadObject);
+        }
+
+        let mut frozen = self.frozen.write().unwrap();
+        if !frozen.requested {
+            let process = self.process.unwrap_or_else(ProcessState::as_self);
+            if !process
+                .driver()
+                .is_feature_enabled(DriverFeature::FreezeNotification)
+            {
+                log::error!("The binder driver does not support frozen state notifications.");
+                return Err(StatusCode::InvalidOperation);
+            }
+            self.in_context(|| {
+                thread_state::request_freeze_notification(self.handle())?;
+                thread_state::flush_commands()
+            })?;
+            frozen.requested = true;
+        }
+        frozen.callbacks.push(callback.clone());
+        let state = frozen.state;
+        drop(frozen);
+
+        // The driver reported the state before, so the new callback won't hear it from the driver.
+        if let (Some(state), Some(callback)) = (state, callback.upgrade()) {
+            let process = self.process.unwrap_or_else(ProcessState::as_self);
+            if let Some(who) = process.cached_proxy_for_handle(self.handle()) {
+                callback.on_state_changed(&who, state);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Remove a callback registered by [`ProxyHandle::add_frozen_state_change_callback`].
+    pub fn remove_frozen_state_change_callback(
+        &self,
+        callback: sync::Weak<dyn FrozenStateChangeCallback>,
+    ) -> Result<()> {
+        let mut frozen = self.frozen.write().unwrap();
+        frozen
+            .callbacks
+            .retain(|c| !sync::Weak::ptr_eq(c, &callback));
+        if frozen.callbacks.is_empty() && frozen.requested {
+            frozen.state = None;
+            frozen.requested = false;
+            self.in_context(|| {
+                thread_state::clear_freeze_notification(self.handle())?;
+                thread_state::flush_commands()
+            })?;
+        }
+        Ok(())
+    }
+
+    /// The last frozen state reported for the process hosting this object,
+    /// or `None` if no callback is registered or no state was reported yet.
+    pub fn frozen_state(&self) -> Option<FrozenState> {
+        self.frozen.read().unwrap().state
+    }
+
+    pub(crate) fn on_frozen_state_changed(&self, who: &WIBinder, state: FrozenState) {
+        let callbacks = {
+            let mut frozen = self.frozen.write().unwrap();
+            if !frozen.requested {
+                // A notification which was sent before the callbacks were removed.
+                return;
+            }
+            frozen.state = Some(state);
+            frozen.callbacks.clone()
+        };
+
+        let mut released = false;
+        for callback in callbacks {
+            match callback.upgrade() {
+                Some(callback) => callback.on_state_changed(who, state),
+                None => released = true,
+            }
+        }
+
+        if released {
+            let mut frozen = self.frozen.write().unwrap();
+            frozen.callbacks.retain(|c| c.strong_count() > 0);
+        }
+    }
+
     pub fn dump<F: IntoRawFd>(&self, fd: F, args: &[String]) -> Result<()> {
         let mut send = Parcel::new();
         let obj = flat_binder_object::new_with_fd(fd.into_raw_fd(), true);
@@ -123,6 +482,67 @@ impl ProxyHandle {
         self.submit_transact(DUMP_TRANSACTION, &send, FLAG_CLEAR_BUF)?;
         Ok(())
     }
+
+    /// Ask the remote object to record the transactions it receives to `fd`.
+    ///
+    /// See [`recording`](crate::recording) for the format of the recording.
+    pub fn start_recording(&self, fd: impl AsFd) -> Result<()> {
+        let mut send = self.prepare_transact(false)?;
+        send.write_fd(fd.as_fd())?;
+        self.submit_transact(START_RECORDING_TRANSACTION, &send, 0)?;
+        Ok(())
+    }
+
+    /// Ask the remote object to stop recording its transactions.
+    pub fn stop_recording(&self) -> Result<()> {
+        let send = self.prepare_transact(false)?;
+        self.submit_transact(STOP_RECORDING_TRANSACTION, &send, 0)?;
+        Ok(())
+    }
+
+    /// Run a shell command of the remote object with the given standard
+    /// input, output and error.
+    ///
+    /// The command runs in the service while the transaction lasts, so it has
+    /// finished when this returns. The service sends its exit code to
+    /// `result_receiver`, typically a [`ResultReceiver`](crate::shell::ResultReceiver),
+    /// with a oneway transaction which may arrive a little later.
+    pub fn shell_command(
+        &self,
+        input: impl AsFd,
+        output: impl AsFd,
+        error: impl AsFd,
+        args: &[String],
+        result_receiver: Option<&SIBinder>,
+    ) -> Result<()> {
+        let mut send = self.prepare_transact(false)?;
+        for fd in [input.as_fd(), output.as_fd(), error.as_fd()] {
+            send.write_fd(fd)?;
+        }
+
+        send.write::<i32>(&(args.len() as i32))?;
+        for arg in args {
+            send.write(arg)?;
+        }
+        // No shell callback.
+        send.write(&Option::<SIBinder>::None)?;
+        send.write(&result_receiver.cloned())?;
+        self.submit_transact(SHELL_COMMAND_TRANSACTION, &send, FLAG_CLEAR_BUF)?;
+        Ok(())
+    }
+}
+
+/// Run `f` with a deadline of `timeout` for the synchronous transactions it
+/// submits with [`ProxyHandle::submit_transact`], e.g. the methods of generated
+/// proxies. A transaction which doesn't get a reply in time fails with
+/// `TimedOut`. Async proxies which are called in `f` keep the deadline.
+///
+/// A call with a deadline is made by a thread of its own, so a callback from
+/// the remote service isn't served by the calling thread. A binder thread which
+/// is handling a transaction makes the call itself, so that nested calls and
+/// callbacks reach it, and waits for the reply without the deadline.
+pub fn with_call_timeout<R>(timeout: Duration, f: impl FnOnce() -> R) -> R {
+    thread_state::with_call_deadline(Some(Instant::now() + timeout), f)
 }
 
 impl Debug for ProxyHandle {
@@ -130,7 +550,7 @@ impl Debug for ProxyHandle {
         f.debug_struct("Inner")
             .field("handle", &self.handle)
             .field("descriptor", &self.descriptor)
-            .field("stability", &self.stability)
+            .field("stability", &*self.stability.read().unwrap())
             .field("obituary_sent", &self.obituary_sent)
             .finish()
     }
@@ -138,7 +558,17 @@ impl Debug for ProxyHandle {
 
 impl PartialEq for ProxyHandle {
     fn eq(&self, other: &Self) -> bool {
-        self.handle() == other.handle()
+        match (&self.rpc, &other.rpc) {
+            (None, None) => {
+                self.handle() == other.handle()
+                    && self.process.map(|p| p as *const ProcessState)
+                        == other.process.map(|p| p as *const ProcessState)
+            }
+            (Some(this), Some(other)) => {
+                this.address == other.address && Arc::ptr_eq(&this.session, &other.session)
+            }
+            _ => false,
+        }
     }
 }
 
@@ -152,9 +582,21 @@ impl IBinder for ProxyHandle {
             return Err(StatusCode::DeadObject);
         } else {
             let mut recipients = self.recipients.write().unwrap();
-            if recipients.is_empty() {
-                thread_state::request_death_notification(self.handle())?;
-                thread_state::flush_commands()?;
+            match &self.rpc {
+                // The session notifies its proxies when it is shut down.
+                Some(target) => {
+                    if target.session.is_shutdown() {
+                        return Err(StatusCode::DeadObject);
+                    }
+                }
+                None => {
+                    if recipients.is_empty() {
+                        self.in_context(|| {
+                            thread_state::request_death_notification(self.handle())?;
+                            thread_state::flush_commands()
+                        })?;
+                    }
+                }
             }
 
             recipients.push(recipient);
@@ -175,9 +617,11 @@ impl IBinder for ProxyHandle {
             let mut recipients = self.recipients.write().unwrap();
 
             recipients.retain(|r| !sync::Weak::ptr_eq(r, &recipient));
-            if recipients.is_empty() {
-                thread_state::clear_death_notification(self.handle())?;
-                thread_state::flush_commands()?;
+            if recipients.is_empty() && self.rpc.is_none() {
+                self.in_context(|| {
+                    thread_state::clear_death_notification(self.handle())?;
+                    thread_state::flush_commands()
+                })?;
             }
         }
         Ok(())
@@ -185,12 +629,19 @@ impl IBinder for ProxyHandle {
 
     /// Send a ping transaction to this object
     fn ping_binder(&self) -> Result<()> {
-        thread_state::ping_binder(self.handle())
+        match &self.rpc {
+            Some(_) => {
+                let data = self.prepare_transact(false)?;
+                self.submit_transact(PING_TRANSACTION, &data, 0)?;
+                Ok(())
+            }
+            None => self.in_context(|| thread_state::ping_binder(self.handle())),
+        }
     }
 
-    // fn stability(&self) -> Stability {
-    //     self.stability
-    // }
+    fn stability(&self) -> Stability {
+        *self.stability.read().unwrap()
+    }
 
     fn as_any(&self) -> &dyn Any {
         self
@@ -208,18 +659,43 @@ impl IBinder for ProxyHandle {
         true
     }
 
+    fn get_extension(&self) -> Result<Option<SIBinder>> {
+        let data = self.prepare_transact(false)?;
+        match self.submit_transact(EXTENSION_TRANSACTION, &data, 0)? {
+            Some(mut reply) => reply.read(),
+            None => Err(StatusCode::UnexpectedNull),
+        }
+    }
+
     fn inc_strong(&self, strong: &SIBinder) -> Result<()> {
         // In the Android implementation, it simultaneously increases the weak reference,
         // but until the necessity is confirmed, we will not support the related functionality here.
-        self.strong
-            .inc(|| thread_state::inc_strong_handle(self.handle(), strong.clone()))
+        match &self.rpc {
+            // The reference to the peer's binder was taken when the binder was received.
+            Some(target) => self
+                .strong
+                .inc(|| target.session.check_proxy(target.address)),
+            None => self.strong.inc(|| {
+                self.in_context(|| thread_state::inc_strong_handle(self.handle(), strong.clone()))
+            }),
+        }
     }
 
     fn attempt_inc_strong(&self) -> bool {
+        if let Some(target) = &self.rpc {
+            return self.strong.attempt_inc(
+                false,
+                || target.session.check_proxy(target.address).is_ok(),
+                || {},
+            );
+        }
+
         self.strong.attempt_inc(
             false,
             || {
-                if let Err(err) = thread_state::attempt_inc_strong_handle(self.handle()) {
+                if let Err(err) =
+                    self.in_context(|| thread_state::attempt_inc_strong_handle(self.handle()))
+                {
                     log::error!("Error in attempt_inc_strong_handle() is {err:?}");
                     false
                 } else {
@@ -227,25 +703,40 @@ impl IBinder for ProxyHandle {
                 }
             },
             || {
-                thread_state::dec_strong_handle(self.handle())
+                self.in_context(|| thread_state::dec_strong_handle(self.handle()))
                     .expect("Failed to decrease the binder strong reference count.");
             },
         )
     }
 
     fn dec_strong(&self, _strong: Option<ManuallyDrop<SIBinder>>) -> Result<()> {
-        self.strong
-            .dec(|| thread_state::dec_strong_handle(self.handle()))
+        match &self.rpc {
+            Some(target) => self
+                .strong
+                .dec(|| target.session.send_dec_strong_to_target(target.address, 0)),
+            None => self
+                .strong
+                .dec(|| self.in_context(|| thread_state::dec_strong_handle(self.handle()))),
+        }
     }
 
     fn inc_weak(&self, weak: &WIBinder) -> Result<()> {
-        self.weak
-            .inc(|| thread_state::inc_weak_handle(self.handle(), weak))
+        match &self.rpc {
+            // RPC binder has no weak references on the wire.
+            Some(_) => self.weak.inc(|| Ok(())),
+            None => self
+                .weak
+                .inc(|| self.in_context(|| thread_state::inc_weak_handle(self.handle(), weak))),
+        }
     }
 
     fn dec_weak(&self) -> Result<()> {
-        self.weak
-            .dec(|| thread_state::dec_weak_handle(self.handle()))
+        match &self.rpc {
+            Some(_) => self.weak.dec(|| Ok(())),
+            None => self
+                .weak
+                .dec(|| self.in_context(|| thread_state::dec_weak_handle(self.handle()))),
+        }
     }
 }
 
@@ -285,4 +776,37 @@ mod tests {
         let handle2 = ProxyHandle::new(1, "test", Stability::Local);
         assert_eq!(handle, handle2);
     }
+
+    #[test]
+    fn test_proxy_handle_stability() {
+        let handle = ProxyHandle::new(1, "test", Stability::Local);
+        assert!(handle.set_stability(Stability::Vintf).is_ok());
+        assert_eq!(IBinder::stability(&*handle), Stability::Vintf);
+
+        // A declared stability can be lowered, but not raised again.
+        assert!(handle.set_stability(Stability::Vendor).is_ok());
+        assert_eq!(
+            handle.set_stability(Stability::System),
+            Err(StatusCode::BadType)
+        );
+        assert_eq!(
+            handle.set_stability(Stability::Vintf),
+            Err(StatusCode::BadType)
+        );
+        assert_eq!(IBinder::stability(&*handle), Stability::Vendor);
+
+        // The stability of the process is replaced by a declared one, which
+        // may be higher.
+        let handle = ProxyHandle::new(2, "test", Stability::System);
+        handle
+            .stability_declared
+            .store(false, std::sync::atomic::Ordering::Relaxed);
+        assert!(handle.set_stability(Stability::Vintf).is_ok());
+        assert_eq!(IBinder::stability(&*handle), Stability::Vintf);
+        assert!(handle.set_stability(Stability::System).is_ok());
+        assert_eq!(
+            handle.set_stability(Stability::Vintf),
+            Err(StatusCode::BadType)
+        );
+    }
 }
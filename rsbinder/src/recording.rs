@@ -0,0 +1,264 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+//! Recording and replay of binder transactions.
+//!
+//! A client starts the recording of a service with
+//! [`ProxyHandle::start_recording`](crate::proxy::ProxyHandle::start_recording),
+//! which sends `START_RECORDING_TRANSACTION` with a file. From then on, the
+//! service appends every transaction it receives to the file, until
+//! `STOP_RECORDING_TRANSACTION`. The recorded transactions can be read back with
+//! [`RecordedTransaction::read_from`] and sent to a service again with
+//! [`RecordedTransaction::replay`], to reproduce a bug or to check that the
+//! service still answers the same way.
+//!
+//! A recording is a sequence of records in little-endian byte order:
+//!
+//! | Field          | Type              |
+//! |----------------|-------------------|
+//! | magic          | `b"RSBT"`         |
+//! | version        | `u32`             |
+//! | code           | `u32`             |
+//! | flags          | `u32`             |
+//! | timestamp      | `u64`, nanoseconds since the Unix epoch |
+//! | status         | `i32`, the result of the transaction |
+//! | data length    | `u32`             |
+//! | object count   | `u32`             |
+//! | reply length   | `u32`             |
+//! | data           | bytes of the data parcel |
+//! | objects        | `u64` offsets of the objects in the data |
+//! | reply          | bytes of the reply parcel |
+//!
+//! Binder objects and file descriptors can't be recorded. Their offsets are
+//! kept, and replay puts placeholders in their place: null binders, and file
+//! descriptors of `/dev/null`.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::IntoRawFd;
+use std::time::{Duration, SystemTime};
+
+use crate::{
+    binder::*,
+    binder_object::flat_binder_object,
+    error::*,
+    parcel::Parcel,
+    sys::{
+        BINDER_TYPE_BINDER, BINDER_TYPE_FD, BINDER_TYPE_HANDLE, BINDER_TYPE_WEAK_BINDER,
+        BINDER_TYPE_WEAK_HANDLE,
+    },
+};
+
+const MAGIC: &[u8; 4] = b"RSBT";
+const VERSION: u32 = 1;
+// The largest data or reply of a recorded transaction, like the largest body
+// of an RPC transaction.
+const MAX_PARCEL_SIZE: usize = 64 * 1024 * 1024;
+
+/// A transaction received by a service while it was recording.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedTransaction {
+    /// The transaction code.
+    pub code: TransactionCode,
+    /// The transaction flags, e.g. [`FLAG_ONEWAY`].
+    pub flags: TransactionFlags,
+    /// When the transaction was received, since the Unix epoch.
+    pub timestamp: Duration,
+    /// The result of the transaction, as a raw status code.
+    pub status: i32,
+    /// The bytes of the data parcel.
+    pub data: Vec<u8>,
+    /// The offsets of the binder objects and file descriptors in `data`.
+    pub objects: Vec<u64>,
+    /// The bytes of the reply parcel.
+    pub reply: Vec<u8>,
+}
+
+impl RecordedTransaction {
+    pub(crate) fn new(
+        code: TransactionCode,
+        flags: TransactionFlags,
+        timestamp: SystemTime,
+        data: &Parcel,
+        reply: &Parcel,
+        status: &Result<()>,
+    ) -> Self {
+        Self {
+            code,
+            flags,
+            timestamp: timestamp
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default(),
+            status: match status {
+                Ok(()) => StatusCode::Ok.into(),
+                Err(err) => (*err).into(),
+            },
+            data: data.as_slice().to_vec(),
+            objects: data.objects.as_slice().to_vec(),
+            reply: reply.as_slice().to_vec(),
+        }
+    }
+
+    /// Write the transaction to a recording.
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        let mut record =
+            Vec::with_capacity(36 + self.data.len() + self.objects.len() * 8 + self.reply.len());
+        record.extend_from_slice(MAGIC);
+        record.extend_from_slice(&VERSION.to_le_bytes());
+        record.extend_from_slice(&self.code.to_le_bytes());
+        record.extend_from_slice(&self.flags.to_le_bytes());
+        record.extend_from_slice(&(self.timestamp.as_nanos() as u64).to_le_bytes());
+        record.extend_from_slice(&self.status.to_le_bytes());
+        record.extend_from_slice(&(self.data.len() as u32).to_le_bytes());
+        record.extend_from_slice(&(self.objects.len() as u32).to_le_bytes());
+        record.extend_from_slice(&(self.reply.len() as u32).to_le_bytes());
+        record.extend_from_slice(&self.data);
+        for offset in &self.objects {
+            record.extend_from_slice(&offset.to_le_bytes());
+        }
+        record.extend_from_slice(&self.reply);
+        // A record is written at once, so that records of transactions
+        // running in parallel don't interleave.
+        writer.write_all(&record)
+    }
+
+    /// Read the next transaction of a recording, or `None` at its end.
+    pub fn read_from(reader: &mut impl Read) -> io::Result<Option<Self>> {
+        let mut magic = [0u8; 4];
+        match reader.read_exact(&mut magic) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err),
+        }
+        if &magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a binder transaction recording",
+            ));
+        }
+        let version = read_u32(reader)?;
+        if version != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported recording version {version}"),
+            ));
+        }
+
+        let code = read_u32(reader)?;
+        let flags = read_u32(reader)?;
+        let timestamp = Duration::from_nanos(read_u64(reader)?);
+        let status = read_u32(reader)? as i32;
+        let data_len = read_u32(reader)? as usize;
+        let object_count = read_u32(reader)? as usize;
+        let reply_len = read_u32(reader)? as usize;
+        // Each object takes at least a flat_binder_object of the data.
+        if data_len > MAX_PARCEL_SIZE
+            || reply_len > MAX_PARCEL_SIZE
+            || object_count > data_len / std::mem::size_of::<flat_binder_object>()
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "recorded transaction too large: {data_len} bytes of data, \
+                     {object_count} objects, {reply_len} bytes of reply"
+                ),
+            ));
+        }
+        let data = read_bytes(reader, data_len)?;
+        let objects = (0..object_count)
+            .map(|_| read_u64(reader))
+            .collect::<io::Result<Vec<_>>>()?;
+        let reply = read_bytes(reader, reply_len)?;
+
+        Ok(Some(Self {
+            code,
+            flags,
+            timestamp,
+            status,
+            data,
+            objects,
+            reply,
+        }))
+    }
+
+    /// Send the transaction again to the remote object `binder`.
+    ///
+    /// Returns the reply, or `None` for a oneway transaction. A transaction
+    /// with objects other than binders and file descriptors, like buffers,
+    /// can't be replayed and fails with `StatusCode::BadType`.
+    pub fn replay(&self, binder: &SIBinder) -> Result<Option<Parcel>> {
+        let proxy = binder.as_proxy().ok_or(StatusCode::InvalidOperation)?;
+        let mut data = proxy.prepare_transact(false)?;
+
+        let mut pos = 0;
+        for &offset in &self.objects {
+            let offset = usize::try_from(offset).map_err(|_| StatusCode::BadValue)?;
+            let end = offset
+                .checked_add(std::mem::size_of::<flat_binder_object>())
+                .ok_or(StatusCode::BadValue)?;
+            if offset < pos || end > self.data.len() {
+                return Err(StatusCode::BadValue);
+            }
+            data.write_aligned_data(&self.data[pos..offset]);
+            let header = u32::from_ne_bytes(self.data[offset..offset + 4].try_into().unwrap());
+            match header {
+                BINDER_TYPE_BINDER
+                | BINDER_TYPE_WEAK_BINDER
+                | BINDER_TYPE_HANDLE
+                | BINDER_TYPE_WEAK_HANDLE => data.write_aligned(&flat_binder_object::default()),
+                BINDER_TYPE_FD => {
+                    let placeholder = File::open("/dev/null")?;
+                    let obj = flat_binder_object::new_with_fd(placeholder.into_raw_fd(), true);
+                    data.write_object(&obj, true)?;
+                }
+                _ => {
+                    log::error!("Can't replay a binder object of type {header:#x}");
+                    return Err(StatusCode::BadType);
+                }
+            }
+            pos = end;
+        }
+        data.write_aligned_data(&self.data[pos..]);
+
+        proxy.submit_transact(
+            self.code,
+            &data,
+            self.flags & (FLAG_ONEWAY | FLAG_CLEAR_BUF),
+        )
+    }
+
+    /// Whether `reply`, the reply of a replay, has the bytes of the recorded
+    /// reply. Replies which have binder objects or file descriptors rarely do.
+    pub fn reply_matches(&self, reply: Option<&Parcel>) -> bool {
+        reply.map_or(&[][..], |reply| reply.as_slice()) == self.reply.as_slice()
+    }
+}
+
+// Read `len` bytes, which are allocated as they are read, so that a length
+// beyond the end of the recording fails before it is allocated.
+fn read_bytes(reader: &mut impl Read, len: usize) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    reader.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "recorded transaction truncated: {} of {len} bytes",
+                bytes.len()
+            ),
+        ));
+    }
+    Ok(bytes)
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
@@ -0,0 +1,64 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+//! # RPC Binder
+//!
+//! Binder transactions over sockets instead of the kernel driver, wire compatible
+//! with `RpcSession`/`RpcServer` of Android's libbinder (protocol version 1).
+//!
+//! An [`RpcServer`] serves a root object; an [`RpcSession`] connects to it and
+//! returns the root object as an `SIBinder`. From there on, generated `Bp*`/`Bn*`
+//! types are used as usual: binder objects passed as arguments or return values
+//! become proxies on the other side, oneway transactions keep their order per
+//! object, and a thread serving a transaction can call back into the peer
+//! (nested transactions).
+//!
+//! Sessions run over Unix domain sockets or TCP. Other byte streams can be used
+//! by implementing [`RpcTransport`] and [`RpcListener`] and passing them to
+//! [`RpcSession::setup_client`] and [`RpcServer::setup_server`].
+//!
+//! The RPC binder does not need [`ProcessState`](crate::ProcessState). File
+//! descriptors can't be transferred, and binders of the kernel driver can't be
+//! sent over a session.
+//!
+//! ```rust,no_run
+//! use rsbinder::{rpc::*, *};
+//!
+//! # fn main() -> Result<()> {
+//! # let service: SIBinder = unimplemented!();
+//! let server = RpcServer::new();
+//! server.set_root_object(service);
+//! server.setup_unix_domain_server("/tmp/hello.sock")?;
+//! std::thread::spawn(move || server.join());
+//!
+//! let session = RpcSession::new();
+//! session.setup_unix_domain_client("/tmp/hello.sock")?;
+//! let root = session.root_object()?;
+//! root.ping_binder()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Over TCP, a client gets the same typed interface as with the kernel driver:
+//!
+//! ```rust,no_run
+//! # use rsbinder::{rpc::*, *};
+//! # fn main() -> Result<()> {
+//! let session = RpcSession::new();
+//! session.setup_inet_client("192.168.0.10:5000")?;
+//! let root = session.root_object()?;
+//! // let foo: Strong<dyn IFoo> = root.into_interface()?;
+//! # Ok(())
+//! # }
+//! ```
+
+mod server;
+mod session;
+mod transport;
+mod wire;
+
+pub use server::RpcServer;
+pub use session::RpcSession;
+pub use transport::{RpcListener, RpcTransport};
+
+pub(crate) use session::RpcTarget;
@@ -0,0 +1,230 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashMap;
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+
+use super::session::{io_error_status, spawn_session_thread, RpcConnection, RpcSession};
+use super::transport::{RpcListener, RpcTransport, UnixDomainListener};
+use super::wire::*;
+use crate::{binder::SIBinder, error::*};
+
+const SESSION_ID_SIZE: usize = 32;
+const DEFAULT_MAX_THREADS: usize = 1;
+
+/// Accepts RPC binder sessions and serves a root object to them.
+///
+/// Each client session is served by [`RpcServer::max_threads`] threads, one
+/// per connection the client opens.
+pub struct RpcServer {
+    this: Weak<RpcServer>,
+    listener: Mutex<Option<Arc<dyn RpcListener>>>,
+    root: RwLock<Option<SIBinder>>,
+    max_threads: AtomicUsize,
+    sessions: Mutex<HashMap<Vec<u8>, Arc<RpcSession>>>,
+    shutdown: AtomicBool,
+}
+
+impl RpcServer {
+    pub fn new() -> Arc<Self> {
+        Arc::new_cyclic(|this| Self {
+            this: this.clone(),
+            listener: Mutex::new(None),
+            root: RwLock::new(None),
+            max_threads: AtomicUsize::new(DEFAULT_MAX_THREADS),
+            sessions: Mutex::new(HashMap::new()),
+            shutdown: AtomicBool::new(false),
+        })
+    }
+
+    /// Set the object returned to clients by [`RpcSession::root_object`].
+    pub fn set_root_object(&self, binder: SIBinder) {
+        *self.root.write().unwrap() = Some(binder);
+    }
+
+    pub fn root_object(&self) -> Option<SIBinder> {
+        self.root.read().unwrap().clone()
+    }
+
+    /// Set the number of threads serving each session. It must be at least 1.
+    pub fn set_max_threads(&self, threads: usize) {
+        assert!(threads > 0, "RpcServer must have at least one thread.");
+        self.max_threads.store(threads, Ordering::Relaxed);
+    }
+
+    pub fn max_threads(&self) -> usize {
+        self.max_threads.load(Ordering::Relaxed)
+    }
+
+    /// Listen on a Unix domain socket at `path`. The path must not exist yet.
+    pub fn setup_unix_domain_server<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let listener = UnixDomainListener::bind(path.to_path_buf()).map_err(|e| {
+            log::error!("Failed to bind {}: {e}", path.display());
+            io_error_status(e)
+        })?;
+        self.setup_server(listener)
+    }
+
+    /// Listen on a TCP socket at `addr` and return the bound address, which
+    /// tells the assigned port when `addr` has port 0.
+    ///
+    /// The RPC binder has no authentication; bind to an address that only
+    /// trusted peers can reach.
+    pub fn setup_inet_server<A: ToSocketAddrs>(&self, addr: A) -> Result<SocketAddr> {
+        let listener = TcpListener::bind(addr).map_err(|e| {
+            log::error!("Failed to bind a TCP socket: {e}");
+            io_error_status(e)
+        })?;
+        let local_addr = listener.local_addr().map_err(io_error_status)?;
+        self.setup_server(listener)?;
+        Ok(local_addr)
+    }
+
+    /// Listen on a custom transport.
+    pub fn setup_server<L: RpcListener + 'static>(&self, listener: L) -> Result<()> {
+        let mut current = self.listener.lock().unwrap();
+        if current.is_some() {
+            log::error!("RpcServer is already set up.");
+            return Err(StatusCode::InvalidOperation);
+        }
+        *current = Some(Arc::new(listener));
+        Ok(())
+    }
+
+    /// Accept connections on the current thread until [`RpcServer::shutdown`] is called.
+    pub fn join(&self) -> Result<()> {
+        let listener = self.listener.lock().unwrap().clone().ok_or_else(|| {
+            log::error!("RpcServer::join() is called before setting up a listener.");
+            StatusCode::NoInit
+        })?;
+
+        loop {
+            let stream = listener.accept();
+            if self.shutdown.load(Ordering::Acquire) {
+                break;
+            }
+
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    log::warn!("Failed to accept an RPC connection: {e}");
+                    continue;
+                }
+            };
+
+            let this = self.this.upgrade().ok_or(StatusCode::DeadObject)?;
+            let result = spawn_session_thread(move || {
+                if let Err(err) = this.establish_connection(stream) {
+                    log::warn!("Failed to establish an RPC connection: {err:?}");
+                }
+            });
+            if let Err(err) = result {
+                log::error!("Failed to serve an RPC connection: {err:?}");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Stop accepting connections and shut down all sessions.
+    pub fn shutdown(&self) {
+        if self.shutdown.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        // Wake up join() that is blocked in accept().
+        if let Some(listener) = self.listener.lock().unwrap().as_ref() {
+            listener.wake();
+        }
+
+        let sessions: Vec<_> = self.sessions.lock().unwrap().drain().collect();
+        for (_, session) in sessions {
+            session.shutdown();
+        }
+    }
+
+    /// The sessions currently connected to the server.
+    pub fn sessions(&self) -> Vec<Arc<RpcSession>> {
+        self.sessions.lock().unwrap().values().cloned().collect()
+    }
+
+    pub(crate) fn remove_session(&self, id: &[u8]) {
+        self.sessions.lock().unwrap().remove(id);
+    }
+
+    fn establish_connection(&self, stream: Box<dyn RpcTransport>) -> Result<()> {
+        let connection = Arc::new(RpcConnection::new(stream));
+
+        let mut header = [0u8; RpcConnectionHeader::SIZE];
+        connection.read_exact(&mut header)?;
+        let header = RpcConnectionHeader::decode(&header)?;
+
+        let session_id_size = header.session_id_size as usize;
+        if session_id_size > MAX_SESSION_ID_SIZE {
+            log::error!("RPC session id is too large: {session_id_size}");
+            return Err(StatusCode::BadValue);
+        }
+        let mut session_id = vec![0u8; session_id_size];
+        connection.read_exact(&mut session_id)?;
+
+        if header.file_descriptor_transport_mode != FILE_DESCRIPTOR_TRANSPORT_MODE_NONE {
+            log::error!(
+                "File descriptor transport mode {} is not supported.",
+                header.file_descriptor_transport_mode
+            );
+            return Err(StatusCode::BadValue);
+        }
+
+        let incoming = (header.options & RPC_CONNECTION_OPTION_INCOMING) != 0;
+
+        if session_id.is_empty() {
+            if incoming {
+                log::error!("An incoming connection must belong to an existing session.");
+                return Err(StatusCode::BadValue);
+            }
+            if header.version < RPC_WIRE_PROTOCOL_VERSION {
+                log::error!("RPC protocol version {} is not supported.", header.version);
+                return Err(StatusCode::BadValue);
+            }
+            let version = header.version.min(RPC_WIRE_PROTOCOL_VERSION);
+
+            let session = {
+                let mut sessions = self.sessions.lock().unwrap();
+                if self.shutdown.load(Ordering::Acquire) {
+                    return Err(StatusCode::DeadObject);
+                }
+                let id = loop {
+                    let mut id = vec![0u8; SESSION_ID_SIZE];
+                    rustix::rand::getrandom(&mut id, rustix::rand::GetRandomFlags::empty())?;
+                    if !sessions.contains_key(&id) {
+                        break id;
+                    }
+                };
+                let session = RpcSession::new_for_server(self.this.clone(), id.clone(), version);
+                sessions.insert(id, Arc::clone(&session));
+                session
+            };
+
+            connection.write_all(&RpcNewSessionResponse { version }.encode())?;
+            session.join(connection);
+        } else {
+            let session = self.sessions.lock().unwrap().get(&session_id).cloned();
+            let Some(session) = session else {
+                log::error!("Unknown RPC session id {session_id:02x?}");
+                return Err(StatusCode::NameNotFound);
+            };
+
+            if incoming {
+                session.add_outgoing_connection(connection, true)?;
+            } else {
+                session.join(connection);
+            }
+        }
+
+        Ok(())
+    }
+}
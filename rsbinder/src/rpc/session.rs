@@ -0,0 +1,1149 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashMap;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock, Weak};
+use std::thread::{self, ThreadId};
+
+use super::server::RpcServer;
+use super::transport::RpcTransport;
+use super::wire::*;
+use crate::{binder::*, error::*, parcel::*, proxy::ProxyHandle};
+
+/// A single socket of a session.
+///
+/// A connection is used by one thread at a time: either the thread serving it,
+/// or the client thread that holds it exclusively for a whole transaction.
+pub(crate) struct RpcConnection {
+    transport: Box<dyn RpcTransport>,
+}
+
+impl RpcConnection {
+    pub(crate) fn new(transport: Box<dyn RpcTransport>) -> Self {
+        Self { transport }
+    }
+
+    pub(crate) fn read_exact(&self, buf: &mut [u8]) -> Result<()> {
+        self.transport.read_exact(buf).map_err(|e| {
+            log::debug!("RPC connection read failed: {e}");
+            StatusCode::DeadObject
+        })
+    }
+
+    pub(crate) fn write_all(&self, buf: &[u8]) -> Result<()> {
+        self.transport.write_all(buf).map_err(|e| {
+            log::debug!("RPC connection write failed: {e}");
+            StatusCode::DeadObject
+        })
+    }
+
+    fn shutdown(&self) {
+        self.transport.shutdown();
+    }
+}
+
+struct ConnectionSlot {
+    connection: Arc<RpcConnection>,
+    owner: Option<ThreadId>,
+    // Set while the serving thread processes a synchronous transaction, i.e.
+    // while the peer is waiting for a reply and will handle nested commands.
+    allow_nested: bool,
+}
+
+impl ConnectionSlot {
+    fn new(connection: Arc<RpcConnection>, owner: Option<ThreadId>) -> Self {
+        Self {
+            connection,
+            owner,
+            allow_nested: false,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Connections {
+    outgoing: Vec<ConnectionSlot>,
+    incoming: Vec<ConnectionSlot>,
+    outgoing_offset: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConnectionUse {
+    Client,
+    ClientAsync,
+    ClientRefcount,
+}
+
+/// A connection reserved for the current thread. Releasing it makes the
+/// connection available to other threads again, unless it was already owned
+/// by this thread before (nested transactions).
+struct ExclusiveConnection<'a> {
+    session: &'a RpcSession,
+    connection: Arc<RpcConnection>,
+    reentrant: bool,
+}
+
+impl Drop for ExclusiveConnection<'_> {
+    fn drop(&mut self) {
+        if self.reentrant {
+            return;
+        }
+        let mut connections = self.session.connections.lock().unwrap();
+        if let Some(slot) = connections
+            .outgoing
+            .iter_mut()
+            .find(|slot| Arc::ptr_eq(&slot.connection, &self.connection))
+        {
+            slot.owner = None;
+        }
+        self.session.available.notify_all();
+    }
+}
+
+struct AsyncTodo {
+    async_number: u64,
+    code: TransactionCode,
+    flags: TransactionFlags,
+    data: Vec<u8>,
+}
+
+struct BinderNode {
+    binder: WIBinder,
+    // Only set for binders owned by the peer.
+    proxy: Option<Arc<ProxyHandle>>,
+    // Keeps the binder alive while the peer holds references to it.
+    sent_ref: Option<SIBinder>,
+    times_sent: usize,
+    times_recd: usize,
+    // For local binders, the number of the next oneway transaction to process.
+    // For proxies, the number of the next oneway transaction to send.
+    async_number: u64,
+    async_todo: Vec<AsyncTodo>,
+}
+
+impl BinderNode {
+    fn new(binder: WIBinder, proxy: Option<Arc<ProxyHandle>>) -> Self {
+        Self {
+            binder,
+            proxy,
+            sent_ref: None,
+            times_sent: 0,
+            times_recd: 0,
+            async_number: 0,
+            async_todo: Vec::new(),
+        }
+    }
+}
+
+struct RpcState {
+    nodes: HashMap<u64, BinderNode>,
+    next_id: u32,
+    terminated: bool,
+}
+
+/// The session-level address of a binder owned by the peer.
+pub(crate) struct RpcTarget {
+    pub(crate) session: Arc<RpcSession>,
+    pub(crate) address: u64,
+}
+
+/// Map a socket error to the closest [`StatusCode`].
+pub(crate) fn io_error_status(e: std::io::Error) -> StatusCode {
+    rustix::io::Errno::from_io_error(&e)
+        .map(StatusCode::from)
+        .unwrap_or(StatusCode::DeadObject)
+}
+
+pub(crate) fn spawn_session_thread(f: impl FnOnce() + Send + 'static) -> Result<()> {
+    static SEQ: AtomicUsize = AtomicUsize::new(1);
+    let name = format!(
+        "rpc:{}_{:X}",
+        std::process::id(),
+        SEQ.fetch_add(1, Ordering::SeqCst)
+    );
+    thread::Builder::new().name(name).spawn(f).map_err(|e| {
+        log::error!("Failed to spawn RPC session thread: {e}");
+        StatusCode::NoMemory
+    })?;
+    Ok(())
+}
+
+/// One RPC binder session between a client and an [`RpcServer`].
+///
+/// A session owns a set of socket connections. Outgoing connections carry
+/// transactions started by this side; every incoming connection is served by a
+/// dedicated thread of the session. Binder objects written into parcels of the
+/// session are exchanged by session-level addresses, and received remote
+/// binders are represented by [`ProxyHandle`]s, so generated `Bp*` types work
+/// on top of a session exactly as they do on top of the kernel driver.
+///
+/// The session stays alive while its threads or proxies use it. Call
+/// [`RpcSession::shutdown`] to close it.
+pub struct RpcSession {
+    server: Option<Weak<RpcServer>>,
+    id: RwLock<Vec<u8>>,
+    protocol_version: AtomicU32,
+    max_incoming_threads: AtomicUsize,
+    state: Mutex<RpcState>,
+    connections: Mutex<Connections>,
+    available: Condvar,
+    shutdown: AtomicBool,
+}
+
+impl RpcSession {
+    fn new_inner(server: Option<Weak<RpcServer>>, id: Vec<u8>, protocol_version: u32) -> Arc<Self> {
+        Arc::new(Self {
+            server,
+            id: RwLock::new(id),
+            protocol_version: AtomicU32::new(protocol_version),
+            max_incoming_threads: AtomicUsize::new(0),
+            state: Mutex::new(RpcState {
+                nodes: HashMap::new(),
+                next_id: 1,
+                terminated: false,
+            }),
+            connections: Mutex::new(Connections::default()),
+            available: Condvar::new(),
+            shutdown: AtomicBool::new(false),
+        })
+    }
+
+    /// Create a client session. It is connected with [`RpcSession::setup_unix_domain_client`].
+    pub fn new() -> Arc<Self> {
+        Self::new_inner(None, Vec::new(), RPC_WIRE_PROTOCOL_VERSION)
+    }
+
+    pub(crate) fn new_for_server(
+        server: Weak<RpcServer>,
+        id: Vec<u8>,
+        protocol_version: u32,
+    ) -> Arc<Self> {
+        Self::new_inner(Some(server), id, protocol_version)
+    }
+
+    /// Set the number of connections on which the server may start transactions
+    /// towards this client, e.g. to call back into binders sent by the client
+    /// outside of a nested transaction. Each of them is served by a thread of
+    /// the session. It must be called before connecting. The default is 0.
+    pub fn set_max_incoming_threads(&self, threads: usize) {
+        self.max_incoming_threads.store(threads, Ordering::Relaxed);
+    }
+
+    pub fn max_incoming_threads(&self) -> usize {
+        self.max_incoming_threads.load(Ordering::Relaxed)
+    }
+
+    /// The session id assigned by the server.
+    pub fn session_id(&self) -> Vec<u8> {
+        self.id.read().unwrap().clone()
+    }
+
+    /// The negotiated RPC wire protocol version.
+    pub fn protocol_version(&self) -> u32 {
+        self.protocol_version.load(Ordering::Relaxed)
+    }
+
+    fn is_server(&self) -> bool {
+        self.server.is_some()
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.shutdown.load(Ordering::Acquire)
+    }
+
+    /// Connect to an [`RpcServer`] listening on the Unix domain socket at `path`.
+    pub fn setup_unix_domain_client<P: AsRef<Path>>(self: &Arc<Self>, path: P) -> Result<()> {
+        let path = path.as_ref().to_path_buf();
+        self.setup_client(move || {
+            UnixStream::connect(&path).inspect_err(|e| {
+                log::error!("Failed to connect to {}: {e}", path.display());
+            })
+        })
+    }
+
+    /// Connect to an [`RpcServer`] listening on a TCP socket at `addr`.
+    pub fn setup_inet_client<A: ToSocketAddrs>(self: &Arc<Self>, addr: A) -> Result<()> {
+        // Resolve once, so that all connections of the session go to the same server.
+        let addrs: Vec<_> = addr.to_socket_addrs().map_err(io_error_status)?.collect();
+        self.setup_client(move || {
+            let stream = TcpStream::connect(&addrs[..]).inspect_err(|e| {
+                log::error!("Failed to connect to {addrs:?}: {e}");
+            })?;
+            stream.set_nodelay(true)?;
+            Ok(stream)
+        })
+    }
+
+    /// Set up the session over a custom transport. `connect` is called once per
+    /// connection of the session and must return a new stream to the same server.
+    pub fn setup_client<T, F>(self: &Arc<Self>, connect: F) -> Result<()>
+    where
+        T: RpcTransport + 'static,
+        F: Fn() -> std::io::Result<T>,
+    {
+        let connect = || -> Result<Box<dyn RpcTransport>> {
+            Ok(Box::new(connect().map_err(io_error_status)?))
+        };
+
+        if self.is_server() || !self.connections.lock().unwrap().outgoing.is_empty() {
+            log::error!("RpcSession is already set up.");
+            return Err(StatusCode::InvalidOperation);
+        }
+
+        let result = (|| -> Result<()> {
+            let connection = self.connect_and_init(&connect, &[], false)?;
+
+            let mut response = [0u8; RpcNewSessionResponse::SIZE];
+            connection.read_exact(&mut response)?;
+            let version = RpcNewSessionResponse::decode(&response)?.version;
+            if version != RPC_WIRE_PROTOCOL_VERSION {
+                log::error!(
+                    "Server replied with RPC protocol version {version}, but {RPC_WIRE_PROTOCOL_VERSION} is supported."
+                );
+                return Err(StatusCode::BadValue);
+            }
+            self.protocol_version.store(version, Ordering::Relaxed);
+
+            let max_threads = self.remote_max_threads()?;
+            let mut reply = self.special_transact(RPC_SPECIAL_TRANSACT_GET_SESSION_ID)?;
+            let id: Vec<u8> = reply.read()?;
+            *self.id.write().unwrap() = id.clone();
+
+            // The first connection is already set up.
+            for _ in 1..max_threads {
+                self.connect_and_init(&connect, &id, false)?;
+            }
+            for _ in 0..self.max_incoming_threads() {
+                self.connect_and_init(&connect, &id, true)?;
+            }
+            Ok(())
+        })();
+
+        if result.is_err() {
+            self.shutdown();
+        }
+        result
+    }
+
+    fn connect_and_init(
+        self: &Arc<Self>,
+        connect: &impl Fn() -> Result<Box<dyn RpcTransport>>,
+        session_id: &[u8],
+        incoming: bool,
+    ) -> Result<Arc<RpcConnection>> {
+        let connection = Arc::new(RpcConnection::new(connect()?));
+
+        let header = RpcConnectionHeader {
+            version: self.protocol_version(),
+            options: if incoming {
+                RPC_CONNECTION_OPTION_INCOMING
+            } else {
+                0
+            },
+            file_descriptor_transport_mode: FILE_DESCRIPTOR_TRANSPORT_MODE_NONE,
+            session_id_size: session_id.len() as u16,
+        };
+        connection.write_all(&[&header.encode()[..], session_id].concat())?;
+
+        if incoming {
+            let session = Arc::clone(self);
+            let serving = Arc::clone(&connection);
+            spawn_session_thread(move || session.join(serving))?;
+        } else {
+            self.add_outgoing_connection(Arc::clone(&connection), true)?;
+        }
+
+        Ok(connection)
+    }
+
+    pub(crate) fn add_outgoing_connection(
+        &self,
+        connection: Arc<RpcConnection>,
+        init: bool,
+    ) -> Result<()> {
+        if init {
+            connection.write_all(&RpcOutgoingConnectionInit::encode())?;
+        }
+
+        let mut connections = self.connections.lock().unwrap();
+        if self.is_shutdown() {
+            connection.shutdown();
+            return Err(StatusCode::DeadObject);
+        }
+        connections
+            .outgoing
+            .push(ConnectionSlot::new(connection, None));
+        self.available.notify_all();
+        Ok(())
+    }
+
+    /// Serve an incoming connection on the current thread until the session ends.
+    pub(crate) fn join(self: Arc<Self>, connection: Arc<RpcConnection>) {
+        {
+            let mut connections = self.connections.lock().unwrap();
+            if self.is_shutdown() {
+                connection.shutdown();
+                return;
+            }
+            connections.incoming.push(ConnectionSlot::new(
+                Arc::clone(&connection),
+                Some(thread::current().id()),
+            ));
+        }
+
+        let result = (|| -> Result<()> {
+            let mut init = [0u8; RpcOutgoingConnectionInit::SIZE];
+            connection.read_exact(&mut init)?;
+            RpcOutgoingConnectionInit::check(&init)?;
+
+            loop {
+                let (command, body) = self.read_command(&connection)?;
+                self.process_command(&connection, command, &body)?;
+            }
+        })();
+
+        if let Err(err) = result {
+            log::debug!("RPC session thread finished: {err:?}");
+        }
+        self.shutdown();
+    }
+
+    /// Close all connections of the session.
+    ///
+    /// Pending and future transactions fail with [`StatusCode::DeadObject`], and
+    /// death recipients linked to binders of the peer are notified.
+    pub fn shutdown(&self) {
+        if self.shutdown.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        {
+            let mut guard = self.connections.lock().unwrap();
+            let connections = &mut *guard;
+            for slot in connections
+                .outgoing
+                .drain(..)
+                .chain(connections.incoming.drain(..))
+            {
+                slot.connection.shutdown();
+            }
+            self.available.notify_all();
+        }
+
+        let nodes = {
+            let mut state = self.state.lock().unwrap();
+            state.terminated = true;
+            std::mem::take(&mut state.nodes)
+        };
+
+        for node in nodes.values() {
+            if let Some(proxy) = &node.proxy {
+                if let Err(err) = proxy.send_obituary(&node.binder) {
+                    log::warn!("Failed to send obituary: {err:?}");
+                }
+            }
+        }
+        drop(nodes);
+
+        if let Some(server) = self.server.as_ref().and_then(Weak::upgrade) {
+            server.remove_session(&self.session_id());
+        }
+    }
+
+    /// The root object of the server.
+    pub fn root_object(self: &Arc<Self>) -> Result<SIBinder> {
+        let mut reply = self.special_transact(RPC_SPECIAL_TRANSACT_GET_ROOT)?;
+        reply.read()
+    }
+
+    /// The number of threads the server serves this session with. The client opens
+    /// one outgoing connection per server thread.
+    pub fn remote_max_threads(self: &Arc<Self>) -> Result<usize> {
+        let mut reply = self.special_transact(RPC_SPECIAL_TRANSACT_GET_MAX_THREADS)?;
+        let max_threads: i32 = reply.read()?;
+        if max_threads <= 0 {
+            log::error!("Server reported invalid max threads: {max_threads}");
+            return Err(StatusCode::BadValue);
+        }
+        Ok(max_threads as usize)
+    }
+
+    fn special_transact(self: &Arc<Self>, code: u32) -> Result<Parcel> {
+        let data = Parcel::new();
+        self.transact(0, code, &data, 0)?
+            .ok_or(StatusCode::UnexpectedNull)
+    }
+
+    /// Create an empty parcel for transactions of this session.
+    pub fn new_parcel(self: &Arc<Self>) -> Parcel {
+        let mut parcel = Parcel::new();
+        parcel.set_rpc_session(Arc::clone(self));
+        parcel
+    }
+
+    fn exclusive_connection(&self, usage: ConnectionUse) -> Result<ExclusiveConnection<'_>> {
+        let tid = thread::current().id();
+        let mut connections = self.connections.lock().unwrap();
+
+        loop {
+            if self.is_shutdown() {
+                return Err(StatusCode::DeadObject);
+            }
+
+            let count = connections.outgoing.len();
+            let mut exclusive = None;
+            let mut available = None;
+            for i in 0..count {
+                let index = (connections.outgoing_offset + i) % count;
+                match connections.outgoing[index].owner {
+                    Some(owner) if owner == tid => {
+                        exclusive = Some(index);
+                        break;
+                    }
+                    None if available.is_none() => available = Some(index),
+                    _ => {}
+                }
+            }
+
+            // Spread oneway transactions over the connections, so that a synchronous
+            // call doesn't queue up behind a oneway call being processed by the peer.
+            if usage == ConnectionUse::ClientAsync && (exclusive.is_some() || available.is_some()) {
+                connections.outgoing_offset = (connections.outgoing_offset + 1) % count;
+            }
+
+            // Nested transactions go back over the connection this thread is serving.
+            if usage != ConnectionUse::ClientAsync {
+                if let Some(slot) = connections
+                    .incoming
+                    .iter()
+                    .find(|slot| slot.owner == Some(tid))
+                {
+                    if slot.allow_nested
+                        || (usage == ConnectionUse::ClientRefcount && available.is_none())
+                    {
+                        return Ok(ExclusiveConnection {
+                            session: self,
+                            connection: Arc::clone(&slot.connection),
+                            reentrant: true,
+                        });
+                    }
+                }
+            }
+
+            if let Some(index) = exclusive {
+                return Ok(ExclusiveConnection {
+                    session: self,
+                    connection: Arc::clone(&connections.outgoing[index].connection),
+                    reentrant: true,
+                });
+            }
+
+            if let Some(index) = available {
+                let slot = &mut connections.outgoing[index];
+                slot.owner = Some(tid);
+                return Ok(ExclusiveConnection {
+                    session: self,
+                    connection: Arc::clone(&slot.connection),
+                    reentrant: false,
+                });
+            }
+
+            if count == 0 {
+                log::error!(
+                    "RPC session has no outgoing connections ({usage:?}). Call set_max_incoming_threads() on the client to let the server start transactions."
+                );
+                return Err(StatusCode::WouldBlock);
+            }
+
+            connections = self.available.wait(connections).unwrap();
+        }
+    }
+
+    fn set_allow_nested(&self, connection: &Arc<RpcConnection>, allow: bool) -> bool {
+        let mut connections = self.connections.lock().unwrap();
+        match connections
+            .incoming
+            .iter_mut()
+            .find(|slot| Arc::ptr_eq(&slot.connection, connection))
+        {
+            Some(slot) => std::mem::replace(&mut slot.allow_nested, allow),
+            None => false,
+        }
+    }
+
+    fn send(&self, connection: &RpcConnection, command: u32, parts: &[&[u8]]) -> Result<()> {
+        let body_size: usize = parts.iter().map(|part| part.len()).sum();
+        let header = RpcWireHeader {
+            command,
+            body_size: body_size as u32,
+        };
+
+        let mut buffer = Vec::with_capacity(RpcWireHeader::SIZE + body_size);
+        buffer.extend_from_slice(&header.encode());
+        for part in parts {
+            buffer.extend_from_slice(part);
+        }
+
+        connection
+            .write_all(&buffer)
+            .inspect_err(|_| self.shutdown())
+    }
+
+    fn read_command(&self, connection: &RpcConnection) -> Result<(u32, Vec<u8>)> {
+        let mut header = [0u8; RpcWireHeader::SIZE];
+        connection
+            .read_exact(&mut header)
+            .inspect_err(|_| self.shutdown())?;
+        let header = RpcWireHeader::decode(&header)?;
+
+        let body_size = header.body_size as usize;
+        if body_size > MAX_BODY_SIZE {
+            log::error!("RPC command body is too large: {body_size}");
+            return Err(StatusCode::BadValue);
+        }
+
+        let mut body = vec![0u8; body_size];
+        connection
+            .read_exact(&mut body)
+            .inspect_err(|_| self.shutdown())?;
+        Ok((header.command, body))
+    }
+
+    pub(crate) fn transact(
+        self: &Arc<Self>,
+        address: u64,
+        code: TransactionCode,
+        data: &Parcel,
+        flags: TransactionFlags,
+    ) -> Result<Option<Parcel>> {
+        match data.rpc_session() {
+            Some(session) if Arc::ptr_eq(session, self) => {}
+            None if data.data_size() == 0 => {}
+            _ => {
+                log::error!("Parcel must be created for the RPC session of the binder.");
+                return Err(StatusCode::BadType);
+            }
+        }
+
+        let oneway = (flags & FLAG_ONEWAY) != 0;
+        let async_number = if oneway {
+            let mut state = self.state.lock().unwrap();
+            let node = state.nodes.get_mut(&address).ok_or_else(|| {
+                log::error!("Sending oneway transaction to unknown address {address:#x}");
+                StatusCode::DeadObject
+            })?;
+            let async_number = node.async_number;
+            node.async_number += 1;
+            async_number
+        } else {
+            0
+        };
+
+        let connection = self.exclusive_connection(if oneway {
+            ConnectionUse::ClientAsync
+        } else {
+            ConnectionUse::Client
+        })?;
+
+        let payload = data.as_slice();
+        let transaction = RpcWireTransaction {
+            address,
+            code,
+            flags: flags & (FLAG_ONEWAY | FLAG_CLEAR_BUF),
+            async_number,
+            parcel_data_size: payload.len() as u32,
+        };
+        self.send(
+            &connection.connection,
+            RPC_COMMAND_TRANSACT,
+            &[&transaction.encode(), payload],
+        )?;
+
+        if oneway {
+            return Ok(None);
+        }
+
+        self.wait_for_reply(&connection.connection).map(Some)
+    }
+
+    fn wait_for_reply(self: &Arc<Self>, connection: &Arc<RpcConnection>) -> Result<Parcel> {
+        loop {
+            let (command, body) = self.read_command(connection)?;
+            if command != RPC_COMMAND_REPLY {
+                self.process_command(connection, command, &body)?;
+                continue;
+            }
+
+            let reply = RpcWireReply::decode(&body)?;
+            if reply.status != 0 {
+                return Err(reply.status.into());
+            }
+            let data = parcel_data(&body, RpcWireReply::SIZE, reply.parcel_data_size)?;
+
+            let mut parcel = Parcel::from_vec(data.to_vec());
+            parcel.set_rpc_session(Arc::clone(self));
+            return Ok(parcel);
+        }
+    }
+
+    fn process_command(
+        self: &Arc<Self>,
+        connection: &Arc<RpcConnection>,
+        command: u32,
+        body: &[u8],
+    ) -> Result<()> {
+        match command {
+            RPC_COMMAND_TRANSACT => self.process_transact(connection, body),
+            RPC_COMMAND_DEC_STRONG => self.process_dec_strong(body),
+            _ => {
+                log::error!("Unknown RPC command: {command}");
+                self.shutdown();
+                Err(StatusCode::BadValue)
+            }
+        }
+    }
+
+    fn send_reply(&self, connection: &RpcConnection, result: Result<Parcel>) -> Result<()> {
+        let (status, reply) = match result {
+            Ok(reply) => (0, Some(reply)),
+            Err(err) => (err.into(), None),
+        };
+        let payload = reply.as_ref().map_or(&[][..], |reply| reply.as_slice());
+        let header = RpcWireReply {
+            status,
+            parcel_data_size: payload.len() as u32,
+        };
+        self.send(connection, RPC_COMMAND_REPLY, &[&header.encode(), payload])
+    }
+
+    fn process_transact(
+        self: &Arc<Self>,
+        connection: &Arc<RpcConnection>,
+        body: &[u8],
+    ) -> Result<()> {
+        let transaction = RpcWireTransaction::decode(body)?;
+        let data = parcel_data(body, RpcWireTransaction::SIZE, transaction.parcel_data_size)?;
+        let oneway = (transaction.flags & FLAG_ONEWAY) != 0;
+
+        if transaction.address == 0 {
+            if oneway {
+                log::error!("Special RPC transactions can't be oneway.");
+                return Ok(());
+            }
+            let reply = self.process_special_transact(transaction.code);
+            return self.send_reply(connection, reply);
+        }
+
+        let target = {
+            let mut state = self.state.lock().unwrap();
+            let Some(node) = state.nodes.get_mut(&transaction.address) else {
+                log::error!("Unknown binder address {:#x}", transaction.address);
+                drop(state);
+                return if oneway {
+                    Ok(())
+                } else {
+                    self.send_reply(connection, Err(StatusCode::BadValue))
+                };
+            };
+
+            if oneway && transaction.async_number != node.async_number {
+                node.async_todo.push(AsyncTodo {
+                    async_number: transaction.async_number,
+                    code: transaction.code,
+                    flags: transaction.flags,
+                    data: data.to_vec(),
+                });
+                return Ok(());
+            }
+
+            node.binder.clone()
+        };
+
+        let target = match target.upgrade() {
+            Ok(target) if !target.is_remote() => target,
+            _ => {
+                log::error!("Not a local binder: {:#x}", transaction.address);
+                return if oneway {
+                    Ok(())
+                } else {
+                    self.send_reply(connection, Err(StatusCode::BadValue))
+                };
+            }
+        };
+
+        self.execute(
+            connection,
+            &target,
+            transaction.code,
+            transaction.flags,
+            data,
+        )?;
+
+        if oneway {
+            loop {
+                let todo = {
+                    let mut state = self.state.lock().unwrap();
+                    let Some(node) = state.nodes.get_mut(&transaction.address) else {
+                        break;
+                    };
+                    node.async_number += 1;
+                    let next = node.async_number;
+                    node.async_todo
+                        .iter()
+                        .position(|todo| todo.async_number == next)
+                        .map(|index| node.async_todo.swap_remove(index))
+                };
+
+                match todo {
+                    Some(todo) => {
+                        self.execute(connection, &target, todo.code, todo.flags, &todo.data)?
+                    }
+                    None => break,
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn execute(
+        self: &Arc<Self>,
+        connection: &Arc<RpcConnection>,
+        target: &SIBinder,
+        code: TransactionCode,
+        flags: TransactionFlags,
+        data: &[u8],
+    ) -> Result<()> {
+        let oneway = (flags & FLAG_ONEWAY) != 0;
+
+        let mut reader = Parcel::from_vec(data.to_vec());
+        reader.set_rpc_session(Arc::clone(self));
+        let mut reply = self.new_parcel();
+
+        let result = match target.as_transactable() {
+            Some(transactable) => {
+                let allow_nested = self.set_allow_nested(connection, !oneway);
+                let result = transactable.transact(code, &mut reader, &mut reply);
+                self.set_allow_nested(connection, allow_nested);
+                result
+            }
+            None => Err(StatusCode::UnknownTransaction),
+        };
+        // Release the binders of the request before the peer gets the reply.
+        drop(reader);
+
+        if oneway {
+            if let Err(err) = result {
+                log::warn!("Oneway RPC transaction {code} failed: {err:?}");
+            }
+            return Ok(());
+        }
+
+        self.send_reply(connection, result.map(|_| reply))
+    }
+
+    fn process_special_transact(self: &Arc<Self>, code: u32) -> Result<Parcel> {
+        let server = self.server.as_ref().and_then(Weak::upgrade);
+        let mut reply = self.new_parcel();
+
+        match code {
+            RPC_SPECIAL_TRANSACT_GET_ROOT => {
+                let root = server.and_then(|server| server.root_object());
+                reply.write(&root)?;
+            }
+            RPC_SPECIAL_TRANSACT_GET_MAX_THREADS => {
+                let max_threads = server.map_or(0, |server| server.max_threads());
+                reply.write(&(max_threads as i32))?;
+            }
+            RPC_SPECIAL_TRANSACT_GET_SESSION_ID => {
+                reply.write(&self.session_id())?;
+            }
+            _ => {
+                log::error!("Unknown special RPC transaction: {code}");
+                return Err(StatusCode::UnknownTransaction);
+            }
+        }
+
+        Ok(reply)
+    }
+
+    fn process_dec_strong(&self, body: &[u8]) -> Result<()> {
+        let dec = RpcDecStrong::decode(body)?;
+
+        let released = {
+            let mut state = self.state.lock().unwrap();
+            let Some(node) = state.nodes.get_mut(&dec.address) else {
+                log::error!("Unknown binder address {:#x}, not dec strong.", dec.address);
+                return Ok(());
+            };
+
+            if dec.amount as usize > node.times_sent {
+                log::error!(
+                    "Tried to dec strong {:#x} {} times, but only {} were sent.",
+                    dec.address,
+                    dec.amount,
+                    node.times_sent
+                );
+                return Err(StatusCode::BadValue);
+            }
+
+            node.times_sent -= dec.amount as usize;
+            if node.times_sent == 0 {
+                let sent_ref = node.sent_ref.take();
+                let removed = if node.times_recd == 0 {
+                    state.nodes.remove(&dec.address)
+                } else {
+                    None
+                };
+                (sent_ref, removed)
+            } else {
+                (None, None)
+            }
+        };
+        // The binders may only be dropped without the state lock held.
+        drop(released);
+
+        Ok(())
+    }
+
+    /// Write `binder` into `parcel` in the RPC format:
+    /// `i32 present, [u64 address,] i32 stability`.
+    pub(crate) fn write_binder(
+        self: &Arc<Self>,
+        parcel: &mut Parcel,
+        binder: Option<&SIBinder>,
+    ) -> Result<()> {
+        match binder {
+            Some(binder) => {
+                let address = self.on_binder_leaving(binder)?;
+                parcel.write::<i32>(&1)?;
+                parcel.write::<u64>(&address)?;
+                // RPC sessions aren't bound to a partition.
+                let stability = match binder.stability() {
+                    Stability::Local => Stability::System,
+                    stability => stability,
+                };
+                parcel.write::<i32>(&stability.into())
+            }
+            None => {
+                parcel.write::<i32>(&0)?;
+                parcel.write::<i32>(&Stability::Local.into())
+            }
+        }
+    }
+
+    pub(crate) fn read_binder(self: &Arc<Self>, parcel: &mut Parcel) -> Result<Option<SIBinder>> {
+        let present: i32 = parcel.read()?;
+        if present == 0 {
+            let _stability: i32 = parcel.read()?;
+            return Ok(None);
+        }
+
+        let address: u64 = parcel.read()?;
+        let stability: i32 = parcel.read()?;
+
+        let binder = self.on_binder_entering(address, stability.try_into()?)?;
+        // Keep one reference for a proxy. A local binder needs none.
+        self.send_dec_strong_to_target(address, binder.is_remote() as usize)?;
+        Ok(Some(binder))
+    }
+
+    fn on_binder_leaving(self: &Arc<Self>, binder: &SIBinder) -> Result<u64> {
+        if let Some(proxy) = binder.as_proxy() {
+            match proxy.rpc_target() {
+                Some(target) if Arc::ptr_eq(&target.session, self) => {}
+                Some(_) => {
+                    log::error!("Cannot send binder from unrelated binder RPC session.");
+                    return Err(StatusCode::InvalidOperation);
+                }
+                None => {
+                    log::error!("Cannot send kernel binder over RPC.");
+                    return Err(StatusCode::InvalidOperation);
+                }
+            }
+        }
+
+        let weak = SIBinder::downgrade(binder);
+        let sent_ref = binder.clone();
+
+        let mut state = self.state.lock().unwrap();
+        if state.terminated {
+            return Err(StatusCode::DeadObject);
+        }
+
+        if let Some((address, node)) = state.nodes.iter_mut().find(|(_, node)| node.binder == weak)
+        {
+            node.times_sent += 1;
+            if node.sent_ref.is_none() {
+                node.sent_ref = Some(sent_ref);
+            }
+            return Ok(*address);
+        }
+
+        if binder.is_remote() {
+            log::error!("RPC binder must have a known address.");
+            return Err(StatusCode::DeadObject);
+        }
+
+        let mut options = RPC_WIRE_ADDRESS_OPTION_CREATED;
+        if self.is_server() {
+            options |= RPC_WIRE_ADDRESS_OPTION_FOR_SERVER;
+        }
+        let address = loop {
+            let address = RpcWireAddress {
+                options,
+                address: state.next_id,
+            }
+            .to_raw();
+            state.next_id = state.next_id.wrapping_add(1);
+            if !state.nodes.contains_key(&address) {
+                break address;
+            }
+        };
+
+        let mut node = BinderNode::new(weak, None);
+        node.times_sent = 1;
+        node.sent_ref = Some(sent_ref);
+        state.nodes.insert(address, node);
+
+        Ok(address)
+    }
+
+    fn on_binder_entering(
+        self: &Arc<Self>,
+        address: u64,
+        stability: Stability,
+    ) -> Result<SIBinder> {
+        if let Some(weak) = self.acquire_known_node(address)? {
+            let binder = weak.upgrade()?;
+            if let Some(proxy) = binder.as_proxy() {
+                proxy.set_stability(stability)?;
+            }
+            return Ok(binder);
+        }
+
+        let wire_address = RpcWireAddress::from_raw(address);
+        let for_server = (wire_address.options & RPC_WIRE_ADDRESS_OPTION_FOR_SERVER) != 0;
+        if (wire_address.options & RPC_WIRE_ADDRESS_OPTION_CREATED) == 0
+            || self.is_server() == for_server
+        {
+            log::error!("Invalid binder address {address:#x} was received.");
+            return Err(StatusCode::BadValue);
+        }
+
+        let descriptor = self.query_interface(address)?;
+
+        let weak = {
+            let mut state = self.state.lock().unwrap();
+            if state.terminated {
+                return Err(StatusCode::DeadObject);
+            }
+            match state.nodes.get_mut(&address) {
+                Some(node) => {
+                    node.times_recd += 1;
+                    node.binder.clone()
+                }
+                None => {
+                    let proxy = ProxyHandle::new_rpc(
+                        RpcTarget {
+                            session: Arc::clone(self),
+                            address,
+                        },
+                        &descriptor,
+                        stability,
+                    );
+                    let weak = WIBinder::new(proxy.clone())?;
+                    let mut node = BinderNode::new(weak.clone(), Some(proxy));
+                    node.times_recd = 1;
+                    state.nodes.insert(address, node);
+                    weak
+                }
+            }
+        };
+
+        weak.upgrade()
+    }
+
+    fn acquire_known_node(&self, address: u64) -> Result<Option<WIBinder>> {
+        let mut state = self.state.lock().unwrap();
+        if state.terminated {
+            return Err(StatusCode::DeadObject);
+        }
+        Ok(state.nodes.get_mut(&address).map(|node| {
+            // A received binder implicitly carries a strong reference.
+            node.times_recd += 1;
+            node.binder.clone()
+        }))
+    }
+
+    fn query_interface(self: &Arc<Self>, address: u64) -> Result<String> {
+        let data = self.new_parcel();
+        let mut reply = self
+            .transact(address, INTERFACE_TRANSACTION, &data, 0)?
+            .ok_or(StatusCode::UnexpectedNull)?;
+        reply.read()
+    }
+
+    /// Tell the peer to drop the references received for `address` above `target`.
+    pub(crate) fn send_dec_strong_to_target(&self, address: u64, target: usize) -> Result<()> {
+        let (amount, removed) = {
+            let mut state = self.state.lock().unwrap();
+            if state.terminated {
+                return Ok(());
+            }
+            let Some(node) = state.nodes.get_mut(&address) else {
+                return Ok(());
+            };
+            if node.times_recd <= target {
+                return Ok(());
+            }
+
+            let amount = node.times_recd - target;
+            node.times_recd = target;
+            let removed = if node.times_recd == 0 && node.times_sent == 0 {
+                state.nodes.remove(&address)
+            } else {
+                None
+            };
+            (amount, removed)
+        };
+        drop(removed);
+
+        let connection = self.exclusive_connection(ConnectionUse::ClientRefcount)?;
+        let dec = RpcDecStrong {
+            address,
+            amount: amount as u32,
+        };
+        self.send(
+            &connection.connection,
+            RPC_COMMAND_DEC_STRONG,
+            &[&dec.encode()],
+        )
+    }
+
+    /// Check that the proxy for `address` is still known to the session.
+    pub(crate) fn check_proxy(&self, address: u64) -> Result<()> {
+        let state = self.state.lock().unwrap();
+        match state.nodes.get(&address) {
+            Some(node) if node.proxy.is_some() => Ok(()),
+            _ => Err(StatusCode::DeadObject),
+        }
+    }
+}
+
+fn parcel_data(body: &[u8], offset: usize, size: u32) -> Result<&[u8]> {
+    let end = offset + size as usize;
+    let data = body.get(offset..end).ok_or_else(|| {
+        log::error!("RPC parcel data is truncated: {} < {end}", body.len());
+        StatusCode::BadValue
+    })?;
+    if body.len() != end {
+        log::error!("File descriptors are not supported by this RPC session.");
+        return Err(StatusCode::FdsNotAllowed);
+    }
+    Ok(data)
+}
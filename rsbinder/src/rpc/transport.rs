@@ -0,0 +1,108 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+//! Socket layer of the RPC binder.
+//!
+//! [`RpcSession`](super::RpcSession) and [`RpcServer`](super::RpcServer) only
+//! need a reliable, ordered byte stream. Unix domain sockets and TCP are
+//! supported out of the box; other streams can be plugged in by implementing
+//! [`RpcTransport`] and [`RpcListener`].
+
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+
+/// A connected stream carrying one connection of a session.
+///
+/// A transport is used by one thread at a time for reading, and by one thread
+/// at a time for writing, but the reading and the writing thread may differ.
+pub trait RpcTransport: Send + Sync {
+    fn read_exact(&self, buf: &mut [u8]) -> io::Result<()>;
+    fn write_all(&self, buf: &[u8]) -> io::Result<()>;
+    /// Shut down both directions, waking up a thread blocked in `read_exact()`.
+    fn shutdown(&self);
+}
+
+/// A listening socket of an [`RpcServer`](super::RpcServer).
+pub trait RpcListener: Send + Sync {
+    fn accept(&self) -> io::Result<Box<dyn RpcTransport>>;
+    /// Wake up a thread blocked in `accept()`, e.g. by connecting to itself.
+    fn wake(&self);
+}
+
+impl RpcTransport for UnixStream {
+    fn read_exact(&self, buf: &mut [u8]) -> io::Result<()> {
+        Read::read_exact(&mut &*self, buf)
+    }
+
+    fn write_all(&self, buf: &[u8]) -> io::Result<()> {
+        Write::write_all(&mut &*self, buf)
+    }
+
+    fn shutdown(&self) {
+        UnixStream::shutdown(self, Shutdown::Both).ok();
+    }
+}
+
+impl RpcTransport for TcpStream {
+    fn read_exact(&self, buf: &mut [u8]) -> io::Result<()> {
+        Read::read_exact(&mut &*self, buf)
+    }
+
+    fn write_all(&self, buf: &[u8]) -> io::Result<()> {
+        Write::write_all(&mut &*self, buf)
+    }
+
+    fn shutdown(&self) {
+        TcpStream::shutdown(self, Shutdown::Both).ok();
+    }
+}
+
+/// [`UnixListener`] together with its path, which is needed to wake it up.
+pub(crate) struct UnixDomainListener {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl UnixDomainListener {
+    pub(crate) fn bind(path: PathBuf) -> io::Result<Self> {
+        Ok(Self {
+            listener: UnixListener::bind(&path)?,
+            path,
+        })
+    }
+}
+
+impl RpcListener for UnixDomainListener {
+    fn accept(&self) -> io::Result<Box<dyn RpcTransport>> {
+        let (stream, _) = self.listener.accept()?;
+        Ok(Box::new(stream))
+    }
+
+    fn wake(&self) {
+        UnixStream::connect(&self.path).ok();
+    }
+}
+
+impl RpcListener for TcpListener {
+    fn accept(&self) -> io::Result<Box<dyn RpcTransport>> {
+        let (stream, _) = TcpListener::accept(self)?;
+        // Transactions are small request/reply exchanges.
+        stream.set_nodelay(true)?;
+        Ok(Box::new(stream))
+    }
+
+    fn wake(&self) {
+        let Ok(mut addr) = self.local_addr() else {
+            return;
+        };
+        if addr.ip().is_unspecified() {
+            addr.set_ip(match addr {
+                SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+            });
+        }
+        TcpStream::connect(addr).ok();
+    }
+}
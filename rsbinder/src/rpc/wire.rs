@@ -0,0 +1,355 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+//! On-the-wire structures of the RPC binder protocol.
+//!
+//! The layouts follow `RpcWireFormat.h` of Android's libbinder. Every structure is
+//! encoded in little-endian byte order with the exact size of its C counterpart.
+
+use crate::error::{Result, StatusCode};
+
+pub(crate) const RPC_WIRE_PROTOCOL_VERSION: u32 = 1;
+
+pub(crate) const RPC_CONNECTION_OPTION_INCOMING: u8 = 0x1;
+
+pub(crate) const RPC_WIRE_ADDRESS_OPTION_CREATED: u32 = 1 << 0;
+pub(crate) const RPC_WIRE_ADDRESS_OPTION_FOR_SERVER: u32 = 1 << 1;
+
+pub(crate) const RPC_COMMAND_TRANSACT: u32 = 0;
+pub(crate) const RPC_COMMAND_REPLY: u32 = 1;
+pub(crate) const RPC_COMMAND_DEC_STRONG: u32 = 2;
+
+pub(crate) const RPC_SPECIAL_TRANSACT_GET_ROOT: u32 = 0;
+pub(crate) const RPC_SPECIAL_TRANSACT_GET_MAX_THREADS: u32 = 1;
+pub(crate) const RPC_SPECIAL_TRANSACT_GET_SESSION_ID: u32 = 2;
+
+/// File descriptors can't be transferred over the session.
+pub(crate) const FILE_DESCRIPTOR_TRANSPORT_MODE_NONE: u8 = 0;
+
+pub(crate) const RPC_CONNECTION_INIT_OKAY: [u8; 4] = *b"cci\0";
+
+/// Upper bound of a session id. It protects the server from a bogus header.
+pub(crate) const MAX_SESSION_ID_SIZE: usize = 1024;
+
+/// Upper bound of a single command body.
+pub(crate) const MAX_BODY_SIZE: usize = 64 * 1024 * 1024;
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn bytes<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let end = self.pos + N;
+        if end > self.data.len() {
+            log::error!("RPC wire data is too short: {} < {end}", self.data.len());
+            return Err(StatusCode::BadValue);
+        }
+        let bytes = self.data[self.pos..end].try_into()?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.bytes()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes()?))
+    }
+
+    fn i32(&mut self) -> Result<i32> {
+        Ok(i32::from_le_bytes(self.bytes()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes()?))
+    }
+}
+
+/// `RpcWireAddress`: identifies a binder object within a session.
+///
+/// The raw `u64` form is what is written into parcels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RpcWireAddress {
+    pub(crate) options: u32,
+    pub(crate) address: u32,
+}
+
+impl RpcWireAddress {
+    pub(crate) fn from_raw(raw: u64) -> Self {
+        Self {
+            options: raw as u32,
+            address: (raw >> 32) as u32,
+        }
+    }
+
+    pub(crate) fn to_raw(self) -> u64 {
+        (self.address as u64) << 32 | self.options as u64
+    }
+}
+
+/// `RpcConnectionHeader`: sent by the client on every new connection,
+/// followed by `session_id_size` bytes of the session id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RpcConnectionHeader {
+    pub(crate) version: u32,
+    pub(crate) options: u8,
+    pub(crate) file_descriptor_transport_mode: u8,
+    pub(crate) session_id_size: u16,
+}
+
+impl RpcConnectionHeader {
+    pub(crate) const SIZE: usize = 16;
+
+    pub(crate) fn encode(&self) -> [u8; Self::SIZE] {
+        let mut out = [0u8; Self::SIZE];
+        out[0..4].copy_from_slice(&self.version.to_le_bytes());
+        out[4] = self.options;
+        out[5] = self.file_descriptor_transport_mode;
+        // out[6..14] is reserved.
+        out[14..16].copy_from_slice(&self.session_id_size.to_le_bytes());
+        out
+    }
+
+    pub(crate) fn decode(data: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(data);
+        let version = reader.u32()?;
+        let options = reader.u8()?;
+        let file_descriptor_transport_mode = reader.u8()?;
+        reader.bytes::<8>()?;
+        let session_id_size = reader.u16()?;
+        Ok(Self {
+            version,
+            options,
+            file_descriptor_transport_mode,
+            session_id_size,
+        })
+    }
+}
+
+/// `RpcNewSessionResponse`: the server's answer to a connection without a session id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RpcNewSessionResponse {
+    pub(crate) version: u32,
+}
+
+impl RpcNewSessionResponse {
+    pub(crate) const SIZE: usize = 8;
+
+    pub(crate) fn encode(&self) -> [u8; Self::SIZE] {
+        let mut out = [0u8; Self::SIZE];
+        out[0..4].copy_from_slice(&self.version.to_le_bytes());
+        out
+    }
+
+    pub(crate) fn decode(data: &[u8]) -> Result<Self> {
+        Ok(Self {
+            version: Reader::new(data).u32()?,
+        })
+    }
+}
+
+/// `RpcOutgoingConnectionInit`: the first message on a connection, written by
+/// the side that is going to send transactions over it.
+pub(crate) struct RpcOutgoingConnectionInit;
+
+impl RpcOutgoingConnectionInit {
+    pub(crate) const SIZE: usize = 8;
+
+    pub(crate) fn encode() -> [u8; Self::SIZE] {
+        let mut out = [0u8; Self::SIZE];
+        out[0..4].copy_from_slice(&RPC_CONNECTION_INIT_OKAY);
+        out
+    }
+
+    pub(crate) fn check(data: &[u8]) -> Result<()> {
+        if data.len() < Self::SIZE || data[0..4] != RPC_CONNECTION_INIT_OKAY {
+            log::error!("Unexpected RPC connection init message: {data:?}");
+            return Err(StatusCode::BadValue);
+        }
+        Ok(())
+    }
+}
+
+/// `RpcWireHeader`: precedes the body of every command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RpcWireHeader {
+    pub(crate) command: u32,
+    pub(crate) body_size: u32,
+}
+
+impl RpcWireHeader {
+    pub(crate) const SIZE: usize = 16;
+
+    pub(crate) fn encode(&self) -> [u8; Self::SIZE] {
+        let mut out = [0u8; Self::SIZE];
+        out[0..4].copy_from_slice(&self.command.to_le_bytes());
+        out[4..8].copy_from_slice(&self.body_size.to_le_bytes());
+        out
+    }
+
+    pub(crate) fn decode(data: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(data);
+        Ok(Self {
+            command: reader.u32()?,
+            body_size: reader.u32()?,
+        })
+    }
+}
+
+/// `RpcWireTransaction`: followed by the parcel data and the object table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RpcWireTransaction {
+    pub(crate) address: u64,
+    pub(crate) code: u32,
+    pub(crate) flags: u32,
+    pub(crate) async_number: u64,
+    pub(crate) parcel_data_size: u32,
+}
+
+impl RpcWireTransaction {
+    pub(crate) const SIZE: usize = 40;
+
+    pub(crate) fn encode(&self) -> [u8; Self::SIZE] {
+        let mut out = [0u8; Self::SIZE];
+        out[0..8].copy_from_slice(&self.address.to_le_bytes());
+        out[8..12].copy_from_slice(&self.code.to_le_bytes());
+        out[12..16].copy_from_slice(&self.flags.to_le_bytes());
+        out[16..24].copy_from_slice(&self.async_number.to_le_bytes());
+        out[24..28].copy_from_slice(&self.parcel_data_size.to_le_bytes());
+        out
+    }
+
+    pub(crate) fn decode(data: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(data);
+        let this = Self {
+            address: reader.u64()?,
+            code: reader.u32()?,
+            flags: reader.u32()?,
+            async_number: reader.u64()?,
+            parcel_data_size: reader.u32()?,
+        };
+        reader.bytes::<12>()?;
+        Ok(this)
+    }
+}
+
+/// `RpcWireReply`: followed by the parcel data and the object table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RpcWireReply {
+    pub(crate) status: i32,
+    pub(crate) parcel_data_size: u32,
+}
+
+impl RpcWireReply {
+    pub(crate) const SIZE: usize = 20;
+
+    pub(crate) fn encode(&self) -> [u8; Self::SIZE] {
+        let mut out = [0u8; Self::SIZE];
+        out[0..4].copy_from_slice(&self.status.to_le_bytes());
+        out[4..8].copy_from_slice(&self.parcel_data_size.to_le_bytes());
+        out
+    }
+
+    pub(crate) fn decode(data: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(data);
+        let this = Self {
+            status: reader.i32()?,
+            parcel_data_size: reader.u32()?,
+        };
+        reader.bytes::<12>()?;
+        Ok(this)
+    }
+}
+
+/// `RpcDecStrong`: releases `amount` strong references previously sent for `address`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RpcDecStrong {
+    pub(crate) address: u64,
+    pub(crate) amount: u32,
+}
+
+impl RpcDecStrong {
+    pub(crate) const SIZE: usize = 16;
+
+    pub(crate) fn encode(&self) -> [u8; Self::SIZE] {
+        let mut out = [0u8; Self::SIZE];
+        out[0..8].copy_from_slice(&self.address.to_le_bytes());
+        out[8..12].copy_from_slice(&self.amount.to_le_bytes());
+        out
+    }
+
+    pub(crate) fn decode(data: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(data);
+        let this = Self {
+            address: reader.u64()?,
+            amount: reader.u32()?,
+        };
+        reader.u32()?;
+        Ok(this)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wire_address() {
+        let address = RpcWireAddress {
+            options: RPC_WIRE_ADDRESS_OPTION_CREATED | RPC_WIRE_ADDRESS_OPTION_FOR_SERVER,
+            address: 7,
+        };
+        let raw = address.to_raw();
+        assert_eq!(raw, 0x0000_0007_0000_0003);
+        assert_eq!(RpcWireAddress::from_raw(raw), address);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let header = RpcConnectionHeader {
+            version: RPC_WIRE_PROTOCOL_VERSION,
+            options: RPC_CONNECTION_OPTION_INCOMING,
+            file_descriptor_transport_mode: FILE_DESCRIPTOR_TRANSPORT_MODE_NONE,
+            session_id_size: 32,
+        };
+        assert_eq!(
+            RpcConnectionHeader::decode(&header.encode()).unwrap(),
+            header
+        );
+
+        let txn = RpcWireTransaction {
+            address: 0x1_0000_0001,
+            code: 3,
+            flags: 1,
+            async_number: 9,
+            parcel_data_size: 128,
+        };
+        assert_eq!(RpcWireTransaction::decode(&txn.encode()).unwrap(), txn);
+
+        let reply = RpcWireReply {
+            status: -22,
+            parcel_data_size: 0,
+        };
+        assert_eq!(RpcWireReply::decode(&reply.encode()).unwrap(), reply);
+
+        let dec = RpcDecStrong {
+            address: 0x2_0000_0001,
+            amount: 2,
+        };
+        assert_eq!(RpcDecStrong::decode(&dec.encode()).unwrap(), dec);
+
+        assert!(RpcOutgoingConnectionInit::check(&RpcOutgoingConnectionInit::encode()).is_ok());
+        assert!(RpcWireTransaction::decode(&[0u8; 8]).is_err());
+    }
+}
@@ -0,0 +1,425 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+//! A dedicated pool of binder client threads for async proxies.
+//!
+//! A blocking binder call submitted to [`ClientPool`] runs on one of a bounded
+//! number of long-lived threads, which keep their binder thread state between
+//! calls. When all threads are busy the call waits in a bounded queue, and
+//! submitting more calls waits until the queue has room again.
+//!
+//! Dropping the future of a call stops waiting for it. A call that has not
+//! started yet is skipped, and the reply of a call that is already running is
+//! dropped on the pool thread when it arrives.
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+use crate::StatusCode;
+
+const DEFAULT_MAX_THREADS: usize = 8;
+const DEFAULT_MAX_QUEUED: usize = 64;
+const DEFAULT_THREAD_NAME: &str = "binder-client";
+
+static GLOBAL_POOL: OnceLock<ClientPool> = OnceLock::new();
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Counters of a [`ClientPool`], as reported by [`ClientPool::stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClientPoolStats {
+    /// Threads started by the pool.
+    pub threads: usize,
+    /// Threads running a call.
+    pub busy: usize,
+    /// Calls waiting for a free thread.
+    pub queued: usize,
+    /// Calls which ran to completion.
+    pub completed: u64,
+    /// Calls whose future was dropped before they completed.
+    pub cancelled: u64,
+}
+
+/// A bounded pool of threads making blocking binder calls for async code.
+///
+/// The pool used by the async runtimes of rsbinder is [`ClientPool::global`].
+#[derive(Clone)]
+pub struct ClientPool {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    max_threads: usize,
+    max_queued: usize,
+    thread_name: String,
+    state: Mutex<PoolState>,
+    job_ready: Condvar,
+    busy: AtomicUsize,
+    completed: AtomicU64,
+    cancelled: AtomicU64,
+}
+
+#[derive(Default)]
+struct PoolState {
+    jobs: VecDeque<Job>,
+    threads: usize,
+    idle: usize,
+    // Submitters waiting for room in the queue.
+    submitters: Vec<Waker>,
+}
+
+impl ClientPool {
+    /// Create a builder of a client pool.
+    pub fn builder() -> ClientPoolBuilder {
+        ClientPoolBuilder::new()
+    }
+
+    /// The pool shared by the async runtimes of rsbinder.
+    ///
+    /// It is created with the default configuration unless
+    /// [`ClientPoolBuilder::init_global`] was called before.
+    pub fn global() -> &'static ClientPool {
+        GLOBAL_POOL.get_or_init(|| ClientPool::builder().build())
+    }
+
+    /// Run `call` on a thread of the pool.
+    ///
+    /// The returned future resolves to the value returned by `call`; if `call`
+    /// panics, the panic is resumed by the future. Dropping the future before
+    /// `call` started means it never runs.
+    pub fn spawn<F, A>(&self, call: F) -> PoolCall<A>
+    where
+        F: FnOnce() -> A + Send + 'static,
+        A: Send + 'static,
+    {
+        let slot = Arc::new(Mutex::new(Slot::default()));
+        let job: Job = {
+            let shared = self.shared.clone();
+            let slot = slot.clone();
+            Box::new(move || {
+                if slot.lock().unwrap().cancelled {
+                    shared.cancelled.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+                let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(call));
+
+                let mut slot = slot.lock().unwrap();
+                if slot.cancelled {
+                    shared.cancelled.fetch_add(1, Ordering::Relaxed);
+                    // Free the result, e.g. the reply parcel, on this thread.
+                    drop(slot);
+                    drop(result);
+                    return;
+                }
+                shared.completed.fetch_add(1, Ordering::Relaxed);
+                slot.result = Some(result);
+                if let Some(waker) = slot.waker.take() {
+                    waker.wake();
+                }
+            })
+        };
+
+        let mut pool_call = PoolCall {
+            shared: self.shared.clone(),
+            job: Some(job),
+            slot,
+        };
+        pool_call.submit(None);
+        pool_call
+    }
+
+    /// The counters of the pool.
+    pub fn stats(&self) -> ClientPoolStats {
+        let state = self.shared.state.lock().unwrap();
+        ClientPoolStats {
+            threads: state.threads,
+            busy: self.shared.busy.load(Ordering::Relaxed),
+            queued: state.jobs.len(),
+            completed: self.shared.completed.load(Ordering::Relaxed),
+            cancelled: self.shared.cancelled.load(Ordering::Relaxed),
+        }
+    }
+
+    /// The maximum number of threads of the pool.
+    pub fn max_threads(&self) -> usize {
+        self.shared.max_threads
+    }
+}
+
+impl Shared {
+    fn spawn_thread(self: &Arc<Self>, state: &mut PoolState) {
+        let name = format!("{}:{}", self.thread_name, state.threads + 1);
+        let shared = self.clone();
+        match thread::Builder::new()
+            .name(name)
+            .spawn(move || shared.run())
+        {
+            Ok(_) => state.threads += 1,
+            Err(e) => log::error!("Failed to spawn a binder client thread: {e}"),
+        }
+    }
+
+    fn run(&self) {
+        loop {
+            let job = {
+                let mut state = self.state.lock().unwrap();
+                while state.jobs.is_empty() {
+                    state.idle += 1;
+                    state = self.job_ready.wait(state).unwrap();
+                    state.idle -= 1;
+                }
+                let job = state.jobs.pop_front();
+                for waker in state.submitters.drain(..) {
+                    waker.wake();
+                }
+                job
+            };
+
+            if let Some(job) = job {
+                self.busy.fetch_add(1, Ordering::Relaxed);
+                job();
+                self.busy.fetch_sub(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+struct Slot<A> {
+    result: Option<thread::Result<A>>,
+    waker: Option<Waker>,
+    cancelled: bool,
+}
+
+impl<A> Default for Slot<A> {
+    fn default() -> Self {
+        Slot {
+            result: None,
+            waker: None,
+            cancelled: false,
+        }
+    }
+}
+
+/// The future of a call submitted with [`ClientPool::spawn`].
+#[must_use = "a call is cancelled when its future is dropped"]
+pub struct PoolCall<A> {
+    shared: Arc<Shared>,
+    // The call until it is accepted by the queue.
+    job: Option<Job>,
+    slot: Arc<Mutex<Slot<A>>>,
+}
+
+impl<A> PoolCall<A> {
+    // Queue the call if there is room, or register `waker` to try again.
+    fn submit(&mut self, waker: Option<&Waker>) {
+        let shared = &self.shared;
+        let mut state = shared.state.lock().unwrap();
+        if state.jobs.len() >= shared.max_queued {
+            if let Some(waker) = waker {
+                state.submitters.push(waker.clone());
+            }
+            return;
+        }
+
+        if let Some(job) = self.job.take() {
+            state.jobs.push_back(job);
+            if state.idle < state.jobs.len() && state.threads < shared.max_threads {
+                shared.spawn_thread(&mut state);
+            }
+            shared.job_ready.notify_one();
+        }
+    }
+}
+
+impl<A> Future for PoolCall<A> {
+    type Output = Result<A, StatusCode>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.job.is_some() {
+            self.submit(Some(cx.waker()));
+            if self.job.is_some() {
+                return Poll::Pending;
+            }
+        }
+
+        let mut slot = self.slot.lock().unwrap();
+        match slot.result.take() {
+            Some(Ok(value)) => Poll::Ready(Ok(value)),
+            Some(Err(panic)) => std::panic::resume_unwind(panic),
+            None if slot.cancelled => Poll::Ready(Err(StatusCode::FailedTransaction)),
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<A> Drop for PoolCall<A> {
+    fn drop(&mut self) {
+        self.slot.lock().unwrap().cancelled = true;
+    }
+}
+
+/// Builder of a [`ClientPool`].
+pub struct ClientPoolBuilder {
+    max_threads: usize,
+    max_queued: usize,
+    thread_name: String,
+}
+
+impl ClientPoolBuilder {
+    fn new() -> Self {
+        ClientPoolBuilder {
+            max_threads: DEFAULT_MAX_THREADS,
+            max_queued: DEFAULT_MAX_QUEUED,
+            thread_name: DEFAULT_THREAD_NAME.to_owned(),
+        }
+    }
+
+    /// Set the maximum number of threads making binder calls. Threads are
+    /// started on demand and live as long as the process.
+    pub fn max_threads(mut self, max_threads: usize) -> Self {
+        self.max_threads = max_threads.max(1);
+        self
+    }
+
+    /// Set how many calls may wait for a free thread before submitting more
+    /// calls waits.
+    pub fn max_queued(mut self, max_queued: usize) -> Self {
+        self.max_queued = max_queued.max(1);
+        self
+    }
+
+    /// Set the name of the threads; a sequence number is appended to it.
+    pub fn thread_name(mut self, name: &str) -> Self {
+        self.thread_name = name.to_owned();
+        self
+    }
+
+    /// Create the pool.
+    pub fn build(self) -> ClientPool {
+        ClientPool {
+            shared: Arc::new(Shared {
+                max_threads: self.max_threads,
+                max_queued: self.max_queued,
+                thread_name: self.thread_name,
+                state: Mutex::new(PoolState::default()),
+                job_ready: Condvar::new(),
+                busy: AtomicUsize::new(0),
+                completed: AtomicU64::new(0),
+                cancelled: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Create the pool and make it [`ClientPool::global`].
+    ///
+    /// It fails if the global pool was already created.
+    pub fn init_global(self) -> Result<&'static ClientPool, Box<dyn std::error::Error>> {
+        let mut pool = Some(self.build());
+        let global = GLOBAL_POOL.get_or_init(|| pool.take().unwrap());
+        if pool.is_some() {
+            return Err("The global binder client pool is already initialized.".into());
+        }
+        Ok(global)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::task::Wake;
+    use std::time::Duration;
+
+    struct ThreadWaker(thread::Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = std::pin::pin!(future);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            thread::park();
+        }
+    }
+
+    #[test]
+    fn test_spawn() {
+        let pool = ClientPool::builder().max_threads(2).build();
+        let calls: Vec<_> = (0..8).map(|i| pool.spawn(move || i * 2)).collect();
+        let results: Vec<_> = calls.into_iter().map(|c| block_on(c).unwrap()).collect();
+        assert_eq!(results, (0..8).map(|i| i * 2).collect::<Vec<_>>());
+
+        let stats = pool.stats();
+        assert!(stats.threads <= 2);
+        assert_eq!(stats.completed, 8);
+        assert_eq!(stats.cancelled, 0);
+    }
+
+    #[test]
+    fn test_backpressure() {
+        let pool = ClientPool::builder().max_threads(1).max_queued(1).build();
+        let (release, released) = mpsc::channel::<()>();
+        let running = pool.spawn(move || released.recv().unwrap());
+        while pool.stats().busy == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let queued = pool.spawn(|| 1);
+        let mut waiting = pool.spawn(|| 2);
+        assert_eq!(pool.stats().queued, 1);
+        assert!(waiting.job.is_some());
+
+        release.send(()).unwrap();
+        block_on(running).unwrap();
+        assert_eq!(block_on(queued), Ok(1));
+        assert_eq!(block_on(&mut waiting), Ok(2));
+    }
+
+    #[test]
+    fn test_cancel() {
+        let pool = ClientPool::builder().max_threads(1).build();
+        let (release, released) = mpsc::channel::<()>();
+        let (dropped, is_dropped) = mpsc::channel::<()>();
+
+        struct Reply(mpsc::Sender<()>);
+        impl Drop for Reply {
+            fn drop(&mut self) {
+                self.0.send(()).unwrap();
+            }
+        }
+
+        let running = pool.spawn(move || {
+            released.recv().unwrap();
+            Reply(dropped)
+        });
+        let queued = pool.spawn(|| -> i32 { unreachable!() });
+        while pool.stats().busy == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        drop(running);
+        drop(queued);
+
+        release.send(()).unwrap();
+        is_dropped.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(block_on(pool.spawn(|| 3)), Ok(3));
+        let stats = pool.stats();
+        assert_eq!(stats.cancelled, 2);
+        assert_eq!(stats.completed, 1);
+    }
+}
@@ -0,0 +1,120 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+//! Executor independent async support.
+//!
+//! [`Generic`] runs binder calls on [`ClientPool::global`] and works with any
+//! executor, e.g. smol or async-std:
+//! ```text
+//! use rsbinder::Generic;
+//!
+//! let service = rsbinder::get_interface::<dyn SomeAsyncInterface<Generic>>("...").await?;
+//! ```
+//!
+//! With the `futures` feature, [`FuturesRuntime`] runs an async binder server
+//! with `futures::executor::block_on`.
+
+use crate::{hub, BinderAsyncPool, BoxFuture, ClientPool, FromIBinder, StatusCode, Strong};
+use std::future::Future;
+
+/// Retrieve an existing service for a particular interface, sleeping for a few
+/// seconds if it doesn't yet exist.
+pub async fn get_interface<T: FromIBinder + ?Sized + 'static>(
+    name: &str,
+) -> Result<Strong<T>, StatusCode> {
+    if crate::is_handling_transaction() {
+        // See comment in the BinderAsyncPool impl of Generic.
+        return hub::get_interface::<T>(name);
+    }
+
+    let name = name.to_string();
+    ClientPool::global()
+        .spawn(move || hub::get_interface::<T>(&name))
+        .await?
+}
+
+pub(crate) fn spawn_on_client_pool<'a, F1, F2, Fut, A, B, E>(
+    spawn_me: F1,
+    after_spawn: F2,
+) -> BoxFuture<'a, Result<B, E>>
+where
+    F1: FnOnce() -> A,
+    F2: FnOnce(A) -> Fut,
+    Fut: Future<Output = Result<B, E>>,
+    F1: Send + 'static,
+    F2: Send + 'a,
+    Fut: Send + 'a,
+    A: Send + 'static,
+    B: Send + 'a,
+    E: From<crate::StatusCode>,
+{
+    if crate::is_handling_transaction() {
+        // We are currently on the thread pool for a binder server, so we should execute the
+        // transaction on the current thread so that the binder kernel driver is able to apply
+        // its deadlock prevention strategy to the sub-call.
+        //
+        // This shouldn't cause issues with blocking the thread as only one task will run in a
+        // call to `block_on`, so there aren't other tasks to block.
+        let result = spawn_me();
+        Box::pin(after_spawn(result))
+    } else {
+        // The deadline of the calling thread applies to the call as well.
+        let deadline = crate::thread_state::call_deadline();
+        let call = ClientPool::global()
+            .spawn(move || crate::thread_state::with_call_deadline(deadline, spawn_me));
+        Box::pin(async move {
+            match call.await {
+                Ok(res) => after_spawn(res).await,
+                Err(e) => Err(e.into()),
+            }
+        })
+    }
+}
+
+/// Use any executor with AIDL; binder calls run on [`ClientPool::global`].
+pub enum Generic {}
+
+impl BinderAsyncPool for Generic {
+    fn spawn<'a, F1, F2, Fut, A, B, E>(spawn_me: F1, after_spawn: F2) -> BoxFuture<'a, Result<B, E>>
+    where
+        F1: FnOnce() -> A,
+        F2: FnOnce(A) -> Fut,
+        Fut: Future<Output = Result<B, E>>,
+        F1: Send + 'static,
+        F2: Send + 'a,
+        Fut: Send + 'a,
+        A: Send + 'static,
+        B: Send + 'a,
+        E: From<crate::StatusCode>,
+    {
+        spawn_on_client_pool(spawn_me, after_spawn)
+    }
+}
+
+/// Runtime for an async binder server using `futures::executor::block_on`.
+#[cfg(feature = "futures")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FuturesRuntime;
+
+#[cfg(feature = "futures")]
+impl crate::BinderAsyncRuntime for FuturesRuntime {
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        futures_executor::block_on(future)
+    }
+}
+
+#[cfg(all(test, feature = "futures"))]
+mod tests {
+    use super::*;
+    use crate::BinderAsyncRuntime;
+
+    #[test]
+    fn test_generic_spawn() {
+        let future = Generic::spawn(
+            || std::thread::current().name().map(str::to_owned),
+            |name| async move { Ok::<_, StatusCode>(name) },
+        );
+        let name = FuturesRuntime.block_on(future).unwrap().unwrap();
+        assert!(name.starts_with("binder-client:"));
+    }
+}
@@ -1,3 +1,11 @@
+mod client_pool;
+pub use client_pool::*;
+
+mod generic_rt;
+#[cfg(feature = "tokio")]
+pub(crate) use generic_rt::spawn_on_client_pool;
+pub use generic_rt::*;
+
 #[cfg(feature = "tokio")]
 mod tokio_rt;
 #[cfg(feature = "tokio")]
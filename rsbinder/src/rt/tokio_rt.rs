@@ -17,8 +17,8 @@
  * limitations under the License.
  */
 
-//! This crate lets you use the Tokio `spawn_blocking` pool with AIDL in async
-//! Rust code.
+//! This crate lets you use AIDL in async Rust code running on Tokio. Binder
+//! calls are made on the threads of [`ClientPool::global`].
 //!
 //! This crate works by defining a type [`Tokio`], which you can use as the
 //! generic parameter in the async version of the trait generated by the AIDL
@@ -31,58 +31,10 @@
 //!
 //! [`Tokio`]: crate::Tokio
 
-use crate::{hub, BinderAsyncPool, BinderAsyncRuntime, BoxFuture, FromIBinder, StatusCode, Strong};
+use crate::{BinderAsyncPool, BinderAsyncRuntime, BoxFuture};
 use std::future::Future;
 
-/// Retrieve an existing service for a particular interface, sleeping for a few
-/// seconds if it doesn't yet exist.
-pub async fn get_interface<T: FromIBinder + ?Sized + 'static>(
-    name: &str,
-) -> Result<Strong<T>, StatusCode> {
-    if crate::is_handling_transaction() {
-        // See comment in the BinderAsyncPool impl.
-        return hub::get_interface::<T>(name);
-    }
-
-    let name = name.to_string();
-    let res = tokio::task::spawn_blocking(move || hub::get_interface::<T>(&name)).await;
-
-    // The `is_panic` branch is not actually reachable in Android as we compile
-    // with `panic = abort`.
-    match res {
-        Ok(Ok(service)) => Ok(service),
-        Ok(Err(err)) => Err(err),
-        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
-        Err(e) if e.is_cancelled() => Err(StatusCode::FailedTransaction),
-        Err(_) => Err(StatusCode::Unknown),
-    }
-}
-
-// /// Retrieve an existing service for a particular interface, or start it if it
-// /// is configured as a dynamic service and isn't yet started.
-// pub async fn wait_for_interface<T: FromIBinder + ?Sized + 'static>(
-//     name: &str,
-// ) -> Result<Strong<T>, StatusCode> {
-//     if rsbinder::is_handling_transaction() {
-//         // See comment in the BinderAsyncPool impl.
-//         return rsbinder::wait_for_interface::<T>(name);
-//     }
-
-//     let name = name.to_string();
-//     let res = tokio::task::spawn_blocking(move || rsbinder::wait_for_interface::<T>(&name)).await;
-
-//     // The `is_panic` branch is not actually reachable in Android as we compile
-//     // with `panic = abort`.
-//     match res {
-//         Ok(Ok(service)) => Ok(service),
-//         Ok(Err(err)) => Err(err),
-//         Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
-//         Err(e) if e.is_cancelled() => Err(StatusCode::FailedTransaction),
-//         Err(_) => Err(StatusCode::Unknown),
-//     }
-// }
-
-/// Use the Tokio `spawn_blocking` pool with AIDL.
+/// Use Tokio with AIDL; binder calls run on [`ClientPool::global`].
 pub enum Tokio {}
 
 impl BinderAsyncPool for Tokio {
@@ -98,28 +50,7 @@ impl BinderAsyncPool for Tokio {
         B: Send + 'a,
         E: From<crate::StatusCode>,
     {
-        if crate::is_handling_transaction() {
-            // We are currently on the thread pool for a binder server, so we should execute the
-            // transaction on the current thread so that the binder kernel driver is able to apply
-            // its deadlock prevention strategy to the sub-call.
-            //
-            // This shouldn't cause issues with blocking the thread as only one task will run in a
-            // call to `block_on`, so there aren't other tasks to block.
-            let result = spawn_me();
-            Box::pin(after_spawn(result))
-        } else {
-            let handle = tokio::task::spawn_blocking(spawn_me);
-            Box::pin(async move {
-                // The `is_panic` branch is not actually reachable in Android as we compile
-                // with `panic = abort`.
-                match handle.await {
-                    Ok(res) => after_spawn(res).await,
-                    Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
-                    Err(e) if e.is_cancelled() => Err(StatusCode::FailedTransaction.into()),
-                    Err(_) => Err(StatusCode::Unknown.into()),
-                }
-            })
-        }
+        super::spawn_on_client_pool(spawn_me, after_spawn)
     }
 }
 
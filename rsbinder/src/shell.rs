@@ -0,0 +1,131 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+//! Shell commands of binder objects.
+//!
+//! `SHELL_COMMAND_TRANSACTION` runs a command inside a service, much like a
+//! program: the caller passes its standard input, output and error together
+//! with the arguments, and the service reports an exit code to a result
+//! receiver when the command is done. This is what Android's `cmd` tool uses,
+//! and the wire format is compatible with it.
+//!
+//! Services handle the commands with [`Interface::shell_command`], and clients
+//! run them with [`ProxyHandle::shell_command`](crate::proxy::ProxyHandle::shell_command).
+
+use std::fs::File;
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+use crate::{binder::*, error::*, native::Binder, parcel::Parcel};
+
+/// Interface descriptor of the result receiver of shell commands.
+pub const RESULT_RECEIVER_DESCRIPTOR: &str = "com.android.internal.os.IResultReceiver";
+
+const RESULT_RECEIVER_SEND: TransactionCode = FIRST_CALL_TRANSACTION;
+
+/// A local result receiver, which waits for the exit code of a shell command.
+///
+/// It is a binder object, so the process must run a thread pool to receive
+/// the result.
+#[derive(Default)]
+pub struct ResultReceiver {
+    result: Mutex<Option<i32>>,
+    condvar: Condvar,
+}
+
+impl ResultReceiver {
+    /// Create a new result receiver binder.
+    pub fn new() -> Binder<Self> {
+        Binder::new(Self::default())
+    }
+
+    /// Wait for the result.
+    pub fn wait(&self) -> i32 {
+        let result = self
+            .condvar
+            .wait_while(self.result.lock().unwrap(), |result| result.is_none())
+            .unwrap();
+        result.unwrap()
+    }
+
+    /// Wait for the result for at most `timeout`.
+    pub fn wait_timeout(&self, timeout: Duration) -> Option<i32> {
+        let (result, _) = self
+            .condvar
+            .wait_timeout_while(self.result.lock().unwrap(), timeout, |result| {
+                result.is_none()
+            })
+            .unwrap();
+        *result
+    }
+}
+
+impl Remotable for ResultReceiver {
+    fn descriptor() -> &'static str {
+        RESULT_RECEIVER_DESCRIPTOR
+    }
+
+    fn on_transact(
+        &self,
+        code: TransactionCode,
+        reader: &mut Parcel,
+        _reply: &mut Parcel,
+    ) -> Result<()> {
+        match code {
+            RESULT_RECEIVER_SEND => {
+                *self.result.lock().unwrap() = Some(reader.read::<i32>()?);
+                self.condvar.notify_all();
+                Ok(())
+            }
+            _ => Err(StatusCode::UnknownTransaction),
+        }
+    }
+
+    fn on_dump(&self, _writer: &mut dyn std::io::Write, _args: &[String]) -> Result<()> {
+        Ok(())
+    }
+}
+
+// Send the exit code of a command to its result receiver.
+fn send_result(receiver: &SIBinder, result: i32) -> Result<()> {
+    match receiver.as_proxy() {
+        Some(proxy) => {
+            let mut data = proxy.prepare_transact(true)?;
+            data.write(&result)?;
+            proxy.submit_transact(RESULT_RECEIVER_SEND, &data, FLAG_ONEWAY)?;
+            Ok(())
+        }
+        None => {
+            let transactable = receiver.as_transactable().ok_or(StatusCode::BadType)?;
+            let mut data = Parcel::new();
+            data.write_interface_token(RESULT_RECEIVER_DESCRIPTOR)?;
+            data.write(&result)?;
+            transactable.transact(RESULT_RECEIVER_SEND, &mut data, &mut Parcel::new())
+        }
+    }
+}
+
+// Run SHELL_COMMAND_TRANSACTION with the shell command handler of a service.
+pub(crate) fn on_shell_command_transaction<F>(reader: &mut Parcel, handler: F) -> Result<()>
+where
+    F: FnOnce(&mut File, &mut File, &mut File, &[String]) -> Result<i32>,
+{
+    let mut input = File::from(reader.read_fd()?);
+    let mut output = File::from(reader.read_fd()?);
+    let mut error = File::from(reader.read_fd()?);
+    let argc = reader.read::<i32>()?;
+    let mut args = Vec::new();
+    for _ in 0..argc {
+        args.push(reader.read::<String>()?);
+    }
+    // Android's IShellCallback, which opens files on behalf of the service,
+    // isn't supported.
+    let _shell_callback: Option<SIBinder> = reader.read()?;
+    let result_receiver: Option<SIBinder> = reader.read()?;
+
+    let result = handler(&mut input, &mut output, &mut error, &args).unwrap_or_else(i32::from);
+    match result_receiver {
+        Some(receiver) => send_result(&receiver, result),
+        None => Ok(()),
+    }
+}
@@ -8,7 +8,7 @@
 //! errors and application-specific exceptions.
 
 use crate::error;
-use crate::error::StatusCode;
+use crate::error::{ExtendedError, StatusCode};
 use crate::parcel::*;
 use crate::parcelable::*;
 use std::fmt::{Debug, Display, Formatter};
@@ -131,6 +131,7 @@ pub struct Status {
     code: StatusCode,
     exception: ExceptionCode,
     message: Option<String>,
+    extended_error: Option<ExtendedError>,
 }
 
 impl PartialEq for Status {
@@ -145,6 +146,7 @@ impl Status {
             code: status,
             exception,
             message,
+            extended_error: None,
         }
     }
 
@@ -172,6 +174,12 @@ impl Status {
         }
     }
 
+    /// Why the binder driver failed the transaction, captured when this status
+    /// was created from a [`StatusCode`]. See [`StatusCode::extended_error`].
+    pub fn extended_error(&self) -> Option<ExtendedError> {
+        self.extended_error
+    }
+
     pub fn service_specific_error(&self) -> i32 {
         if let StatusCode::ServiceSpecific(err) = self.code {
             err
@@ -194,7 +202,11 @@ impl Display for Status {
                 self.exception,
                 self.code,
                 self.message.as_ref().unwrap_or(&"".to_owned())
-            )
+            )?;
+            match &self.extended_error {
+                Some(error) => write!(f, " ({error})"),
+                None => Ok(()),
+            }
         }
     }
 }
@@ -245,7 +257,10 @@ impl From<(ExceptionCode, &str)> for Status {
 
 impl From<StatusCode> for Status {
     fn from(status: StatusCode) -> Self {
-        Status::new(status.into(), status, None)
+        Status {
+            extended_error: status.extended_error(),
+            ..Status::new(status.into(), status, None)
+        }
     }
 }
 
@@ -49,6 +49,12 @@ pub mod binder {
         binder_driver_return_protocol_BR_FROZEN_REPLY;
     pub const BR_ONEWAY_SPAM_SUSPECT: binder_driver_return_protocol =
         binder_driver_return_protocol_BR_ONEWAY_SPAM_SUSPECT;
+    pub const BR_TRANSACTION_PENDING_FROZEN: binder_driver_return_protocol =
+        binder_driver_return_protocol_BR_TRANSACTION_PENDING_FROZEN;
+    pub const BR_FROZEN_BINDER: binder_driver_return_protocol =
+        binder_driver_return_protocol_BR_FROZEN_BINDER;
+    pub const BR_CLEAR_FREEZE_NOTIFICATION_DONE: binder_driver_return_protocol =
+        binder_driver_return_protocol_BR_CLEAR_FREEZE_NOTIFICATION_DONE;
 
     pub const BC_TRANSACTION: binder_driver_command_protocol =
         binder_driver_command_protocol_BC_TRANSACTION;
@@ -87,6 +93,12 @@ pub mod binder {
         binder_driver_command_protocol_BC_TRANSACTION_SG;
     pub const BC_REPLY_SG: binder_driver_command_protocol =
         binder_driver_command_protocol_BC_REPLY_SG;
+    pub const BC_REQUEST_FREEZE_NOTIFICATION: binder_driver_command_protocol =
+        binder_driver_command_protocol_BC_REQUEST_FREEZE_NOTIFICATION;
+    pub const BC_CLEAR_FREEZE_NOTIFICATION: binder_driver_command_protocol =
+        binder_driver_command_protocol_BC_CLEAR_FREEZE_NOTIFICATION;
+    pub const BC_FREEZE_NOTIFICATION_DONE: binder_driver_command_protocol =
+        binder_driver_command_protocol_BC_FREEZE_NOTIFICATION_DONE;
 
     use rustix::{io, ioctl};
     use std::os::fd::AsFd;
@@ -280,4 +292,19 @@ pub mod binder {
             ioctl::ioctl(fd, ctl)
         }
     }
+
+    // nix::ioctl_readwrite!(get_extended_error, b'b', 17, binder_extended_error);
+    pub(crate) fn get_extended_error<Fd: AsFd>(
+        fd: Fd,
+        extended_error: &mut binder_extended_error,
+    ) -> std::result::Result<(), io::Errno> {
+        unsafe {
+            // BINDER_GET_EXTENDED_ERROR
+            let ctl = ioctl::Updater::<
+                { ioctl::opcode::read_write::<binder_extended_error>(b'b', 17) },
+                _,
+            >::new(extended_error);
+            ioctl::ioctl(fd, ctl)
+        }
+    }
 }
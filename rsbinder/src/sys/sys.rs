@@ -3023,6 +3023,46 @@ pub const transaction_flags_TF_ACCEPT_FDS: transaction_flags = 16;
 pub const transaction_flags_TF_CLEAR_BUF: transaction_flags = 32;
 pub type transaction_flags = ::std::os::raw::c_uint;
 #[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct binder_frozen_state_info {
+    pub cookie: binder_uintptr_t,
+    pub is_frozen: __u32,
+    pub reserved: __u32,
+}
+#[test]
+fn bindgen_test_layout_binder_frozen_state_info() {
+    assert_eq!(
+        ::std::mem::size_of::<binder_frozen_state_info>(),
+        16usize,
+        concat!("Size of: ", stringify!(binder_frozen_state_info))
+    );
+    assert_eq!(
+        ::std::mem::align_of::<binder_frozen_state_info>(),
+        8usize,
+        concat!("Alignment of ", stringify!(binder_frozen_state_info))
+    );
+}
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct binder_extended_error {
+    pub id: __u32,
+    pub command: __u32,
+    pub param: __s32,
+}
+#[test]
+fn bindgen_test_layout_binder_extended_error() {
+    assert_eq!(
+        ::std::mem::size_of::<binder_extended_error>(),
+        12usize,
+        concat!("Size of: ", stringify!(binder_extended_error))
+    );
+    assert_eq!(
+        ::std::mem::align_of::<binder_extended_error>(),
+        4usize,
+        concat!("Alignment of ", stringify!(binder_extended_error))
+    );
+}
+#[repr(C)]
 #[derive(Copy, Clone)]
 pub struct binder_transaction_data {
     pub target: binder_transaction_data__bindgen_ty_1,
@@ -3749,6 +3789,12 @@ pub const binder_driver_return_protocol_BR_FAILED_REPLY: binder_driver_return_pr
 pub const binder_driver_return_protocol_BR_FROZEN_REPLY: binder_driver_return_protocol = 29202;
 pub const binder_driver_return_protocol_BR_ONEWAY_SPAM_SUSPECT: binder_driver_return_protocol =
     29203;
+pub const binder_driver_return_protocol_BR_TRANSACTION_PENDING_FROZEN:
+    binder_driver_return_protocol = 29204;
+pub const binder_driver_return_protocol_BR_FROZEN_BINDER: binder_driver_return_protocol =
+    2148561429;
+pub const binder_driver_return_protocol_BR_CLEAR_FREEZE_NOTIFICATION_DONE:
+    binder_driver_return_protocol = 2148037142;
 pub type binder_driver_return_protocol = ::std::os::raw::c_uint;
 pub const binder_driver_command_protocol_BC_TRANSACTION: binder_driver_command_protocol =
     1077961472;
@@ -3779,4 +3825,10 @@ pub const binder_driver_command_protocol_BC_DEAD_BINDER_DONE: binder_driver_comm
 pub const binder_driver_command_protocol_BC_TRANSACTION_SG: binder_driver_command_protocol =
     1078485777;
 pub const binder_driver_command_protocol_BC_REPLY_SG: binder_driver_command_protocol = 1078485778;
+pub const binder_driver_command_protocol_BC_REQUEST_FREEZE_NOTIFICATION:
+    binder_driver_command_protocol = 1074553619;
+pub const binder_driver_command_protocol_BC_CLEAR_FREEZE_NOTIFICATION:
+    binder_driver_command_protocol = 1074553620;
+pub const binder_driver_command_protocol_BC_FREEZE_NOTIFICATION_DONE:
+    binder_driver_command_protocol = 1074291477;
 pub type binder_driver_command_protocol = ::std::os::raw::c_uint;
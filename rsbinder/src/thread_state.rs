@@ -25,20 +25,33 @@
 
 use log::error;
 use std::backtrace::Backtrace;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::ffi::{CStr, CString};
 use std::fmt::Debug;
-use std::fs::File;
+use std::rc::Rc;
 use std::sync::{atomic::Ordering, Arc};
+use std::time::Instant;
 
-use crate::{binder::*, binder_object::*, error::*, parcel::*, process_state::*, sys::*};
+use crate::{
+    binder::*, binder_object::*, driver::BinderDriver, error::*, parcel::*, process_state::*,
+    sys::*,
+};
 
 thread_local! {
-    static THREAD_STATE: RefCell<ThreadState> = RefCell::new(ThreadState::new());
+    // A thread talks to every binder context through its own ThreadState.
+    static THREAD_STATES: RefCell<HashMap<usize, Rc<RefCell<ThreadState>>>> =
+        RefCell::new(HashMap::new());
+    // The context of the binder calls made by the thread. None is the default context.
+    static CURRENT_PROCESS: Cell<Option<&'static ProcessState>> = const { Cell::new(None) };
     static BINDER_DEREFS: RefCell<BinderDerefs> = RefCell::new(BinderDerefs::new());
+    // Kept apart from THREAD_STATES, which need an initialized ProcessState.
+    static EXTENDED_ERROR: Cell<Option<ExtendedError>> = const { Cell::new(None) };
+    // The deadline of the binder calls made by the thread, see with_call_deadline().
+    static CALL_DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
 }
 
-const RETURN_STRINGS: [&str; 21] = [
+const RETURN_STRINGS: [&str; 23] = [
     "BR_ERROR",
     "BR_OK",
     "BR_TRANSACTION",
@@ -59,10 +72,12 @@ const RETURN_STRINGS: [&str; 21] = [
     "BR_FAILED_REPLY",
     "BR_FROZEN_REPLY",
     "BR_ONEWAY_SPAM_SUSPECT",
-    "BR_TRANSACTION_SEC_CTX",
+    "BR_TRANSACTION_PENDING_FROZEN",
+    "BR_FROZEN_BINDER",
+    "BR_CLEAR_FREEZE_NOTIFICATION_DONE",
 ];
 
-fn return_to_str(cmd: std::os::raw::c_uint) -> &'static str {
+pub(crate) fn return_to_str(cmd: std::os::raw::c_uint) -> &'static str {
     if cmd == binder::BR_TRANSACTION_SEC_CTX {
         "BR_TRANSACTION_SEC_CTX"
     } else {
@@ -76,7 +91,7 @@ fn return_to_str(cmd: std::os::raw::c_uint) -> &'static str {
     }
 }
 
-const COMMAND_STRINGS: [&str; 19] = [
+const COMMAND_STRINGS: [&str; 22] = [
     "BC_TRANSACTION",
     "BC_REPLY",
     "BC_ACQUIRE_RESULT",
@@ -96,6 +111,9 @@ const COMMAND_STRINGS: [&str; 19] = [
     "BC_DEAD_BINDER_DONE",
     "BC_TRANSACTION_SG",
     "BC_REPLY_SG",
+    "BC_REQUEST_FREEZE_NOTIFICATION",
+    "BC_CLEAR_FREEZE_NOTIFICATION",
+    "BC_FREEZE_NOTIFICATION_DONE",
 ];
 
 fn command_to_str(cmd: std::os::raw::c_uint) -> &'static str {
@@ -193,11 +211,11 @@ pub(crate) struct ThreadState {
     is_looper: bool,
     is_flushing: bool,
     call_restriction: CallRestriction,
-    driver: Arc<File>,
+    driver: Arc<dyn BinderDriver>,
 }
 
 impl ThreadState {
-    fn new() -> Self {
+    fn new(process: &ProcessState) -> Self {
         ThreadState {
             in_parcel: Parcel::new(),
             out_parcel: Parcel::new(),
@@ -205,8 +223,8 @@ impl ThreadState {
             strict_mode_policy: 0,
             is_looper: false,
             is_flushing: false,
-            call_restriction: ProcessState::as_self().call_restriction(),
-            driver: ProcessState::as_self().driver(),
+            call_restriction: process.call_restriction(),
+            driver: process.driver(),
         }
     }
 
@@ -225,12 +243,29 @@ impl ThreadState {
         }
     }
 
+    // Ask the driver why the last transaction of this thread failed.
+    // Drivers without BINDER_GET_EXTENDED_ERROR leave it unknown.
+    fn fetch_extended_error(&self) -> Option<ExtendedError> {
+        let error = self
+            .driver
+            .extended_error()
+            .ok()
+            .filter(|error| error.command != binder::BR_OK)
+            .map(|error| ExtendedError {
+                id: error.id,
+                command: error.command,
+                param: error.param,
+            });
+        EXTENDED_ERROR.with(|cell| cell.set(error));
+        error
+    }
+
     fn is_process_pending_derefs(&mut self) -> bool {
         self.in_parcel.data_position() >= self.in_parcel.data_size()
     }
 
     fn clear_propagate_work_source(&mut self) {
-        if let Some(mut state) = self.transaction {
+        if let Some(state) = self.transaction.as_mut() {
             state.propagate_work_source = false;
         }
     }
@@ -241,7 +276,7 @@ impl ThreadState {
 
     fn set_calling_work_source_uid(&mut self, uid: binder::uid_t) -> i64 {
         let token = self.set_calling_work_source_uid_without_propagation(uid);
-        if let Some(mut state) = self.transaction {
+        if let Some(state) = self.transaction.as_mut() {
             state.propagate_work_source = true;
         }
         token
@@ -251,8 +286,8 @@ impl ThreadState {
         &mut self,
         uid: binder::uid_t,
     ) -> i64 {
-        match self.transaction {
-            Some(mut state) => {
+        match self.transaction.as_mut() {
+            Some(state) => {
                 let propagated_bit =
                     (state.propagate_work_source as i64) << WORK_SOURCE_PROPAGATED_BIT_INDEX;
                 let token = propagated_bit | (state.work_source as i64);
@@ -323,29 +358,179 @@ impl ThreadState {
             }
         };
 
-        self.out_parcel.write::<u32>(&cmd)?;
-        self.out_parcel.write_aligned(&tr);
+        // Buffer objects are copied by the driver only for the scatter-gather commands.
+        let buffers_size = data.buffers_size();
+        if buffers_size > 0 && *status == StatusCode::Ok.into() {
+            let cmd = if cmd == binder::BC_REPLY {
+                binder::BC_REPLY_SG
+            } else {
+                binder::BC_TRANSACTION_SG
+            };
+            self.out_parcel.write::<u32>(&cmd)?;
+            self.out_parcel.write_aligned(&binder_transaction_data_sg {
+                transaction_data: tr,
+                buffers_size: buffers_size as _,
+            });
+        } else {
+            self.out_parcel.write::<u32>(&cmd)?;
+            self.out_parcel.write_aligned(&tr);
+        }
 
         Ok(())
     }
 }
 
+/// The binder context of the calling thread: the context whose thread pool the
+/// thread belongs to, or whose binder it is calling. Otherwise the default one.
+pub(crate) fn current_process() -> &'static ProcessState {
+    CURRENT_PROCESS
+        .with(|current| current.get())
+        .unwrap_or_else(ProcessState::as_self)
+}
+
+/// Run `f` with `process` as the binder context of the calling thread.
+pub(crate) fn with_process<R>(process: &'static ProcessState, f: impl FnOnce() -> R) -> R {
+    struct Restore(Option<&'static ProcessState>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            CURRENT_PROCESS.with(|current| current.set(self.0));
+        }
+    }
+
+    let _restore = Restore(CURRENT_PROCESS.with(|current| current.replace(Some(process))));
+    f()
+}
+
+/// Run `f` with `deadline` for the binder calls of the calling thread. An
+/// earlier deadline which is already set is kept.
+pub(crate) fn with_call_deadline<R>(deadline: Option<Instant>, f: impl FnOnce() -> R) -> R {
+    struct Restore(Option<Instant>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            CALL_DEADLINE.with(|current| current.set(self.0));
+        }
+    }
+
+    let previous = CALL_DEADLINE.with(|current| current.get());
+    let deadline = match (previous, deadline) {
+        (Some(previous), Some(deadline)) => Some(previous.min(deadline)),
+        (previous, deadline) => previous.or(deadline),
+    };
+    let _restore = Restore(CALL_DEADLINE.with(|current| current.replace(deadline)));
+    f()
+}
+
+/// The deadline of the binder calls of the calling thread.
+pub(crate) fn call_deadline() -> Option<Instant> {
+    CALL_DEADLINE.with(|current| current.get())
+}
+
+fn thread_state_key(process: &ProcessState) -> usize {
+    process as *const ProcessState as usize
+}
+
+// Whether the calling thread belongs to the thread pool of `process`.
+pub(crate) fn is_looper(process: &ProcessState) -> bool {
+    THREAD_STATES.with(|states| {
+        states
+            .borrow()
+            .get(&thread_state_key(process))
+            .is_some_and(|state| state.borrow().is_looper)
+    })
+}
+
+// Flush the pending commands of the calling thread to `process` and drop its
+// ThreadState, which holds a reference to the driver.
+pub(crate) fn release_thread_state(process: &'static ProcessState) {
+    let key = thread_state_key(process);
+    if THREAD_STATES.with(|states| states.borrow().contains_key(&key)) {
+        if let Err(e) = with_process(process, flush_commands) {
+            log::warn!("Failed to flush binder commands: {e}");
+        }
+        THREAD_STATES.with(|states| states.borrow_mut().remove(&key));
+    }
+}
+
+fn with_thread_state<R>(f: impl FnOnce(&RefCell<ThreadState>) -> R) -> R {
+    let process = current_process();
+    let thread_state = THREAD_STATES.with(|states| {
+        states
+            .borrow_mut()
+            .entry(thread_state_key(process))
+            .or_insert_with(|| Rc::new(RefCell::new(ThreadState::new(process))))
+            .clone()
+    });
+    f(&thread_state)
+}
+
+/// The state of a thread which hands a binder call over to another thread.
+#[derive(Clone, Copy)]
+pub(crate) struct CallerState {
+    process: &'static ProcessState,
+    strict_mode_policy: i32,
+    call_restriction: CallRestriction,
+}
+
+/// The state of the calling thread which applies to its binder calls.
+pub(crate) fn caller_state() -> CallerState {
+    with_thread_state(|thread_state| {
+        let thread_state = thread_state.borrow();
+        CallerState {
+            process: current_process(),
+            strict_mode_policy: thread_state.strict_mode_policy,
+            call_restriction: thread_state.call_restriction,
+        }
+    })
+}
+
+impl CallerState {
+    /// The binder context of the calls.
+    pub(crate) fn process(&self) -> &'static ProcessState {
+        self.process
+    }
+}
+
+/// Make the binder calls of `f` for the thread whose state is `caller`.
+/// Returns the result of `f` and the extended error of its last failed call,
+/// for that thread.
+pub(crate) fn as_caller<R>(
+    caller: CallerState,
+    f: impl FnOnce() -> R,
+) -> (R, Option<ExtendedError>) {
+    with_process(caller.process, || {
+        with_thread_state(|thread_state| {
+            let mut thread_state = thread_state.borrow_mut();
+            thread_state.strict_mode_policy = caller.strict_mode_policy;
+            thread_state.call_restriction = caller.call_restriction;
+        });
+        let result = f();
+        (result, EXTENDED_ERROR.with(|cell| cell.get()))
+    })
+}
+
+/// Report `error` as the extended error of the last failed call of the calling thread.
+pub(crate) fn set_last_extended_error(error: Option<ExtendedError>) {
+    EXTENDED_ERROR.with(|cell| cell.set(error));
+}
+
 pub(crate) fn set_call_restriction(call_restriction: CallRestriction) {
-    THREAD_STATE.with(|thread_state| {
+    with_thread_state(|thread_state| {
         thread_state.borrow_mut().call_restriction = call_restriction;
     })
 }
 
 pub(crate) fn call_restriction() -> CallRestriction {
-    THREAD_STATE.with(|thread_state| thread_state.borrow().call_restriction)
+    with_thread_state(|thread_state| thread_state.borrow().call_restriction)
 }
 
 pub(crate) fn strict_mode_policy() -> i32 {
-    THREAD_STATE.with(|thread_state| thread_state.borrow().strict_mode_policy)
+    with_thread_state(|thread_state| thread_state.borrow().strict_mode_policy)
 }
 
 pub(crate) fn should_propagate_work_source() -> bool {
-    THREAD_STATE.with(|thread_state| {
+    with_thread_state(|thread_state| {
         thread_state
             .borrow()
             .transaction
@@ -353,8 +538,12 @@ pub(crate) fn should_propagate_work_source() -> bool {
     })
 }
 
+pub(crate) fn transaction_flags() -> u32 {
+    with_thread_state(|thread_state| thread_state.borrow().last_transaction_binder_flags())
+}
+
 pub(crate) fn calling_work_source_uid() -> binder::uid_t {
-    THREAD_STATE.with(|thread_state| {
+    with_thread_state(|thread_state| {
         thread_state
             .borrow()
             .transaction
@@ -363,7 +552,7 @@ pub(crate) fn calling_work_source_uid() -> binder::uid_t {
 }
 
 pub(crate) fn _setup_polling() -> Result<()> {
-    THREAD_STATE.with(|thread_state| -> Result<()> {
+    with_thread_state(|thread_state| -> Result<()> {
         thread_state
             .borrow_mut()
             .out_parcel
@@ -380,7 +569,7 @@ enum UntilResponse {
 }
 
 fn wait_for_response(until: UntilResponse) -> Result<Option<Parcel>> {
-    THREAD_STATE.with(|thread_state| -> Result<Option<Parcel>> {
+    with_thread_state(|thread_state| -> Result<Option<Parcel>> {
         loop {
             talk_with_driver(true)?;
 
@@ -405,24 +594,35 @@ fn wait_for_response(until: UntilResponse) -> Result<Option<Parcel>> {
                         break;
                     }
                 }
+                binder::BR_TRANSACTION_PENDING_FROZEN => {
+                    // The oneway transaction is queued until the target is unfrozen.
+                    log::warn!("Sending oneway calls to frozen process.");
+                    if let UntilResponse::TransactionComplete = until {
+                        break;
+                    }
+                }
                 binder::BR_DEAD_REPLY => {
+                    thread_state.borrow().fetch_extended_error();
                     return Err(StatusCode::DeadObject);
                 }
                 binder::BR_FAILED_REPLY => {
+                    let thread_state = thread_state.borrow();
+                    let error = thread_state.fetch_extended_error();
                     log::error!(
-                        "Received FAILED_REPLY transaction reply for pid {}",
+                        "Received FAILED_REPLY transaction reply for pid {}{}",
                         thread_state
-                            .borrow()
                             .transaction
-                            .map_or(0, |state| state.calling_pid)
+                            .map_or(0, |state| state.calling_pid),
+                        error.map_or(String::new(), |error| format!(": {error}"))
                     );
                     return Err(StatusCode::FailedTransaction);
                 }
                 binder::BR_FROZEN_REPLY => {
+                    let thread_state = thread_state.borrow();
+                    thread_state.fetch_extended_error();
                     log::error!(
                         "Received FROZEN_REPLY transaction reply for pid {}",
                         thread_state
-                            .borrow()
                             .transaction
                             .map_or(0, |state| state.calling_pid)
                     );
@@ -449,7 +649,7 @@ fn wait_for_response(until: UntilResponse) -> Result<Option<Parcel>> {
                     let (buffer, offsets) = unsafe { (tr.data.ptr.buffer, tr.data.ptr.offsets) };
                     if let UntilResponse::Reply = until {
                         if (tr.flags & transaction_flags_TF_STATUS_CODE) == 0 {
-                            let reply = Parcel::from_ipc_parts(
+                            let mut reply = Parcel::from_ipc_parts(
                                 buffer as _,
                                 tr.data_size as _,
                                 offsets as _,
@@ -457,6 +657,7 @@ fn wait_for_response(until: UntilResponse) -> Result<Option<Parcel>> {
                                     / std::mem::size_of::<binder::binder_size_t>(),
                                 free_buffer,
                             );
+                            reply.set_process(current_process());
                             return Ok(Some(reply));
                         } else {
                             // Safe approach: verify buffer size before reading
@@ -507,7 +708,7 @@ fn wait_for_response(until: UntilResponse) -> Result<Option<Parcel>> {
 fn execute_command(cmd: i32) -> Result<()> {
     let cmd: std::os::raw::c_uint = cmd as _;
 
-    THREAD_STATE.with(|thread_state| -> Result<()> {
+    with_thread_state(|thread_state| -> Result<()> {
         match cmd {
             binder::BR_ERROR => {
                 let other: StatusCode = thread_state.borrow_mut().in_parcel.read::<i32>()?.into();
@@ -544,6 +745,7 @@ fn execute_command(cmd: i32) -> Result<()> {
                         free_buffer,
                     )
                 };
+                reader.set_process(current_process());
 
                 // TODO: Skip now, because if below implmentation is mandatory.
                 // const void* origServingStackPointer = mServingStackPointer;
@@ -563,6 +765,7 @@ fn execute_command(cmd: i32) -> Result<()> {
                 };
 
                 let mut reply = Parcel::new();
+                reply.set_process(current_process());
 
                 let result = {
                     let target_ptr = unsafe { tr_secctx.transaction_data.target.ptr };
@@ -584,7 +787,7 @@ fn execute_command(cmd: i32) -> Result<()> {
                             Err(StatusCode::UnknownTransaction)
                         }
                     } else {
-                        let context = ProcessState::as_self()
+                        let context = current_process()
                             .context_manager()
                             .expect("Transactable is None.");
                         context
@@ -685,7 +888,7 @@ fn execute_command(cmd: i32) -> Result<()> {
             }
             binder::BR_NOOP => {}
             binder::BR_SPAWN_LOOPER => {
-                ProcessState::as_self().spawn_pooled_thread(false);
+                current_process().spawn_pooled_thread(false);
             }
             binder::BR_FINISHED => {
                 return Err(StatusCode::TimedOut);
@@ -697,7 +900,7 @@ fn execute_command(cmd: i32) -> Result<()> {
                 };
 
                 log::trace!("BR_DEAD_BINDER: handle {handle:X}");
-                ProcessState::as_self().send_obituary_for_handle(handle as _)?;
+                current_process().send_obituary_for_handle(handle as _)?;
 
                 {
                     let mut state = thread_state.borrow_mut();
@@ -713,6 +916,34 @@ fn execute_command(cmd: i32) -> Result<()> {
                 let mut state = thread_state.borrow_mut();
                 state.in_parcel.read::<binder::binder_uintptr_t>()?;
             }
+            binder::BR_FROZEN_BINDER => {
+                let info = {
+                    let mut state = thread_state.borrow_mut();
+                    state.in_parcel.read::<binder::binder_frozen_state_info>()?
+                };
+
+                log::trace!(
+                    "BR_FROZEN_BINDER: handle {:X}, frozen {}",
+                    info.cookie,
+                    info.is_frozen
+                );
+                current_process()
+                    .send_frozen_state_for_handle(info.cookie as _, info.is_frozen != 0)?;
+
+                {
+                    let mut state = thread_state.borrow_mut();
+                    state
+                        .out_parcel
+                        .write::<u32>(&(binder::BC_FREEZE_NOTIFICATION_DONE))?;
+                    state
+                        .out_parcel
+                        .write::<binder::binder_uintptr_t>(&info.cookie)?;
+                }
+            }
+            binder::BR_CLEAR_FREEZE_NOTIFICATION_DONE => {
+                let mut state = thread_state.borrow_mut();
+                state.in_parcel.read::<binder::binder_uintptr_t>()?;
+            }
             _ => {
                 log::error!("*** BAD COMMAND {cmd} received from Binder driver\n");
                 return Err(StatusCode::Unknown);
@@ -724,7 +955,7 @@ fn execute_command(cmd: i32) -> Result<()> {
 }
 
 fn talk_with_driver(do_receive: bool) -> Result<()> {
-    THREAD_STATE.with(|thread_state| -> Result<()> {
+    with_thread_state(|thread_state| -> Result<()> {
         let mut bwr = {
             let mut thread_state = thread_state.borrow_mut();
             let need_read = thread_state.in_parcel.is_empty();
@@ -781,7 +1012,7 @@ fn talk_with_driver(do_receive: bool) -> Result<()> {
         // }
 
         loop {
-            let res = binder::write_read(&thread_state.borrow().driver, &mut bwr);
+            let res = thread_state.borrow().driver.write_read(&mut bwr);
             match res {
                 Ok(_) => break,
                 Err(errno) if errno != rustix::io::Errno::INTR => {
@@ -840,7 +1071,7 @@ fn talk_with_driver(do_receive: bool) -> Result<()> {
 fn get_and_execute_command() -> Result<()> {
     talk_with_driver(true)?;
 
-    let cmd = THREAD_STATE.with(|thread_state| -> Result<i32> {
+    let cmd = with_thread_state(|thread_state| -> Result<i32> {
         thread_state.borrow_mut().in_parcel.read::<i32>()
     })?;
     execute_command(cmd)?;
@@ -851,7 +1082,7 @@ fn get_and_execute_command() -> Result<()> {
 pub(crate) fn flush_commands() -> Result<()> {
     talk_with_driver(false)?;
 
-    THREAD_STATE.with(|thread_state| -> Result<()> {
+    with_thread_state(|thread_state| -> Result<()> {
         if thread_state.borrow().out_parcel.data_size() > 0 {
             talk_with_driver(false)?;
         }
@@ -866,7 +1097,7 @@ pub(crate) fn flush_commands() -> Result<()> {
 
 pub(crate) fn attempt_inc_strong_handle(handle: u32) -> Result<()> {
     log::trace!("attempt_inc_strong_handle: {handle}");
-    THREAD_STATE.with(|thread_state| -> Result<()> {
+    with_thread_state(|thread_state| -> Result<()> {
         let mut state = thread_state.borrow_mut();
 
         state
@@ -880,7 +1111,7 @@ pub(crate) fn attempt_inc_strong_handle(handle: u32) -> Result<()> {
 
 pub(crate) fn inc_strong_handle(handle: u32, proxy: SIBinder) -> Result<()> {
     log::trace!("inc_strong_handle: {handle}");
-    THREAD_STATE.with(|thread_state| -> Result<()> {
+    with_thread_state(|thread_state| -> Result<()> {
         {
             let mut state = thread_state.borrow_mut();
 
@@ -900,7 +1131,7 @@ pub(crate) fn inc_strong_handle(handle: u32, proxy: SIBinder) -> Result<()> {
 
 pub(crate) fn dec_strong_handle(handle: u32) -> Result<()> {
     log::trace!("dec_strong_handle: {handle}");
-    THREAD_STATE.with(|thread_state| -> Result<()> {
+    with_thread_state(|thread_state| -> Result<()> {
         {
             let mut state = thread_state.borrow_mut();
 
@@ -916,7 +1147,7 @@ pub(crate) fn dec_strong_handle(handle: u32) -> Result<()> {
 
 pub(crate) fn inc_weak_handle(handle: u32, weak: &WIBinder) -> Result<()> {
     log::trace!("inc_weak_handle: {handle}");
-    THREAD_STATE.with(|thread_state| -> Result<()> {
+    with_thread_state(|thread_state| -> Result<()> {
         {
             let mut state = thread_state.borrow_mut();
 
@@ -940,7 +1171,7 @@ pub(crate) fn inc_weak_handle(handle: u32, weak: &WIBinder) -> Result<()> {
 
 pub(crate) fn dec_weak_handle(handle: u32) -> Result<()> {
     log::trace!("dec_weak_handle: {handle}");
-    THREAD_STATE.with(|thread_state| -> Result<()> {
+    with_thread_state(|thread_state| -> Result<()> {
         {
             let mut state = thread_state.borrow_mut();
 
@@ -955,7 +1186,7 @@ pub(crate) fn dec_weak_handle(handle: u32) -> Result<()> {
 }
 
 pub(crate) fn flash_if_needed() -> Result<bool> {
-    THREAD_STATE.with(|thread_state| -> Result<bool> {
+    with_thread_state(|thread_state| -> Result<bool> {
         {
             let thread_state = thread_state.borrow();
             if thread_state.is_looper || thread_state.is_flushing {
@@ -975,7 +1206,7 @@ pub(crate) fn _handle_commands() -> Result<()> {
     while {
         get_and_execute_command()?;
 
-        THREAD_STATE.with(|thread_state| -> bool { !thread_state.borrow().in_parcel.is_empty() })
+        with_thread_state(|thread_state| -> bool { !thread_state.borrow().in_parcel.is_empty() })
     } {
         flush_commands()?;
     }
@@ -983,9 +1214,34 @@ pub(crate) fn _handle_commands() -> Result<()> {
 }
 
 pub fn check_interface(reader: &mut Parcel, descriptor: &str) -> Result<bool> {
+    let (header, expected) = if reader.is_for_rpc() {
+        (reader.read()?, INTERFACE_HEADER)
+    } else {
+        // Only the transactions of the same partition are accepted.
+        (
+            check_interface_policy(reader)?,
+            reader.process().interface_header(),
+        )
+    };
+
+    if header != expected {
+        log::error!("Expecting header {expected:#x} but found {header:#x}.");
+        return Ok(false);
+    }
+
+    let parcel_interface: String = reader.read()?;
+    if parcel_interface.eq(descriptor) {
+        Ok(true)
+    } else {
+        log::error!("check_interface() expected '{descriptor}' but read '{parcel_interface}'");
+        Ok(false)
+    }
+}
+
+fn check_interface_policy(reader: &mut Parcel) -> Result<u32> {
     let mut strict_policy: i32 = reader.read()?;
 
-    let header = THREAD_STATE.with(|thread_state| -> Result<u32> {
+    with_thread_state(|thread_state| -> Result<u32> {
         let mut thread_state = thread_state.borrow_mut();
 
         if (thread_state.last_transaction_binder_flags() & FLAG_ONEWAY) != 0 {
@@ -998,20 +1254,7 @@ pub fn check_interface(reader: &mut Parcel, descriptor: &str) -> Result<bool> {
         thread_state.set_calling_work_source_uid_without_propagation(work_source as _);
 
         reader.read()
-    })?;
-
-    if header != INTERFACE_HEADER {
-        log::error!("Expecting header {INTERFACE_HEADER:#x} but found {header:#x}.");
-        return Ok(false);
-    }
-
-    let parcel_interface: String = reader.read()?;
-    if parcel_interface.eq(descriptor) {
-        Ok(true)
-    } else {
-        log::error!("check_interface() expected '{descriptor}' but read '{parcel_interface}'");
-        Ok(false)
-    }
+    })
 }
 
 pub(crate) fn transact(
@@ -1023,8 +1266,9 @@ pub(crate) fn transact(
     let mut reply: Option<Parcel> = None;
 
     flags |= transaction_flags_TF_ACCEPT_FDS;
+    EXTENDED_ERROR.with(|cell| cell.set(None));
 
-    let call_restriction = THREAD_STATE.with(|thread_state| -> Result<CallRestriction> {
+    let call_restriction = with_thread_state(|thread_state| -> Result<CallRestriction> {
         let mut thread_state = thread_state.borrow_mut();
         thread_state.write_transaction_data(
             binder::BC_TRANSACTION,
@@ -1067,7 +1311,7 @@ fn free_buffer(
         parcel.close_file_descriptors()
     }
 
-    THREAD_STATE.with(|thread_state| -> Result<()> {
+    with_thread_state(|thread_state| -> Result<()> {
         let mut thread_state = thread_state.borrow_mut();
         thread_state
             .out_parcel
@@ -1098,14 +1342,14 @@ pub(crate) fn ping_binder(handle: u32) -> Result<()> {
 }
 
 pub(crate) fn join_thread_pool(is_main: bool) -> Result<()> {
-    THREAD_STATE.with(|thread_state| -> Result<()> {
+    with_thread_state(|thread_state| -> Result<()> {
         log::debug!(
             "**** THREAD {:?} (PID {}) IS JOINING THE THREAD POOL",
             std::thread::current().id(),
             std::process::id()
         );
 
-        ProcessState::as_self()
+        current_process()
             .current_threads
             .fetch_add(1, Ordering::SeqCst);
 
@@ -1124,6 +1368,10 @@ pub(crate) fn join_thread_pool(is_main: bool) -> Result<()> {
         let result;
 
         loop {
+            if current_process().is_shutting_down() {
+                result = StatusCode::Ok;
+                break;
+            }
             if thread_state.borrow_mut().is_process_pending_derefs() {
                 BINDER_DEREFS.with(|binder_derefs| -> Result<()> {
                     binder_derefs.borrow_mut().process_pending_derefs()
@@ -1164,16 +1412,23 @@ pub(crate) fn join_thread_pool(is_main: bool) -> Result<()> {
         }
 
         talk_with_driver(false)?;
-        ProcessState::as_self()
+        current_process()
             .current_threads
             .fetch_sub(1, Ordering::SeqCst);
         Ok(())
-    })
+    })?;
+
+    // A thread leaving a context that is shutting down doesn't use it again.
+    let process = current_process();
+    if process.is_shutting_down() {
+        release_thread_state(process);
+    }
+    Ok(())
 }
 
 pub(crate) fn request_death_notification(handle: u32) -> Result<()> {
     log::trace!("request_death_notification: {handle}");
-    THREAD_STATE.with(|thread_state| -> Result<()> {
+    with_thread_state(|thread_state| -> Result<()> {
         {
             let mut state = thread_state.borrow_mut();
 
@@ -1193,7 +1448,7 @@ pub(crate) fn request_death_notification(handle: u32) -> Result<()> {
 
 pub(crate) fn clear_death_notification(handle: u32) -> Result<()> {
     log::trace!("clear_death_notification: {handle}");
-    THREAD_STATE.with(|thread_state| -> Result<()> {
+    with_thread_state(|thread_state| -> Result<()> {
         {
             let mut state = thread_state.borrow_mut();
 
@@ -1211,6 +1466,41 @@ pub(crate) fn clear_death_notification(handle: u32) -> Result<()> {
     })
 }
 
+pub(crate) fn request_freeze_notification(handle: u32) -> Result<()> {
+    log::trace!("request_freeze_notification: {handle}");
+    with_thread_state(|thread_state| -> Result<()> {
+        let mut state = thread_state.borrow_mut();
+
+        state
+            .out_parcel
+            .write::<u32>(&(binder::BC_REQUEST_FREEZE_NOTIFICATION))?;
+        state.out_parcel.write::<u32>(&(handle))?;
+        // The handle is used as the cookie, as for death notifications.
+        state
+            .out_parcel
+            .write::<binder::binder_uintptr_t>(&(handle as _))?;
+
+        Ok(())
+    })
+}
+
+pub(crate) fn clear_freeze_notification(handle: u32) -> Result<()> {
+    log::trace!("clear_freeze_notification: {handle}");
+    with_thread_state(|thread_state| -> Result<()> {
+        let mut state = thread_state.borrow_mut();
+
+        state
+            .out_parcel
+            .write::<u32>(&(binder::BC_CLEAR_FREEZE_NOTIFICATION))?;
+        state.out_parcel.write::<u32>(&(handle))?;
+        state
+            .out_parcel
+            .write::<binder::binder_uintptr_t>(&(handle as _))?;
+
+        Ok(())
+    })
+}
+
 #[derive(Debug)]
 pub struct CallingContext {
     pub pid: binder::pid_t,
@@ -1220,7 +1510,7 @@ pub struct CallingContext {
 
 impl std::default::Default for CallingContext {
     fn default() -> CallingContext {
-        THREAD_STATE.with(|thread_state| -> CallingContext {
+        with_thread_state(|thread_state| -> CallingContext {
             let thread_state = thread_state.borrow();
             match thread_state.transaction.as_ref() {
                 Some(transaction) => {
@@ -1252,8 +1542,21 @@ impl std::default::Default for CallingContext {
     }
 }
 
+/// Why the last failed transaction of the calling thread failed, if the
+/// binder driver reported it. See [`StatusCode::extended_error`].
+pub fn last_extended_error() -> Option<ExtendedError> {
+    EXTENDED_ERROR.with(|cell| cell.get())
+}
+
 pub fn is_handling_transaction() -> bool {
-    THREAD_STATE.with(|thread_state| thread_state.borrow().transaction.is_some())
+    // A thread which never used binder isn't handling a transaction, and
+    // there might be no ProcessState yet.
+    THREAD_STATES.with(|states| {
+        states
+            .borrow()
+            .values()
+            .any(|state| state.borrow().transaction.is_some())
+    })
 }
 
 #[cfg(test)]
@@ -1303,6 +1606,15 @@ mod tests {
             return_to_str(binder::BR_ONEWAY_SPAM_SUSPECT),
             "BR_ONEWAY_SPAM_SUSPECT"
         );
+        assert_eq!(
+            return_to_str(binder::BR_TRANSACTION_PENDING_FROZEN),
+            "BR_TRANSACTION_PENDING_FROZEN"
+        );
+        assert_eq!(return_to_str(binder::BR_FROZEN_BINDER), "BR_FROZEN_BINDER");
+        assert_eq!(
+            return_to_str(binder::BR_CLEAR_FREEZE_NOTIFICATION_DONE),
+            "BR_CLEAR_FREEZE_NOTIFICATION_DONE"
+        );
     }
 
     #[test]
@@ -1347,5 +1659,17 @@ mod tests {
             "BC_TRANSACTION_SG"
         );
         assert_eq!(command_to_str(binder::BC_REPLY_SG), "BC_REPLY_SG");
+        assert_eq!(
+            command_to_str(binder::BC_REQUEST_FREEZE_NOTIFICATION),
+            "BC_REQUEST_FREEZE_NOTIFICATION"
+        );
+        assert_eq!(
+            command_to_str(binder::BC_CLEAR_FREEZE_NOTIFICATION),
+            "BC_CLEAR_FREEZE_NOTIFICATION"
+        );
+        assert_eq!(
+            command_to_str(binder::BC_FREEZE_NOTIFICATION_DONE),
+            "BC_FREEZE_NOTIFICATION_DONE"
+        );
     }
 }
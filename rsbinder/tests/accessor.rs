@@ -0,0 +1,205 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+#![allow(non_snake_case)]
+
+use std::collections::HashMap;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use hub::android_16::android::os::{
+    ConnectionInfo::ConnectionInfo, IClientCallback::IClientCallback, Service::Service,
+    ServiceWithMetadata::ServiceWithMetadata,
+};
+use hub::android_16::{BnAccessor, BnServiceManager, IAccessor, IServiceManager};
+use hub::{IServiceCallback, ServiceDebugInfo};
+use rsbinder::driver::MemoryDevice;
+use rsbinder::rpc::RpcServer;
+use rsbinder::*;
+
+const NAME: &str = "rsbinder.test.IAccessed/default";
+const WRONG_NAME: &str = "rsbinder.test.IAccessed/wrong";
+const ACCESSOR: &str = "rsbinder.test.accessor";
+
+// A service manager which provides NAME and WRONG_NAME through the accessor
+// registered as ACCESSOR, like rsb_hub with a manifest which declares it.
+#[derive(Default)]
+struct Manager {
+    services: Mutex<HashMap<String, SIBinder>>,
+}
+
+impl Interface for Manager {}
+
+fn unsupported<T>() -> status::Result<T> {
+    Err(StatusCode::UnknownTransaction.into())
+}
+
+impl IServiceManager for Manager {
+    fn getService(&self, name: &str) -> status::Result<Option<SIBinder>> {
+        self.checkService(name)
+    }
+    fn getService2(&self, name: &str) -> status::Result<Service> {
+        self.checkService2(name)
+    }
+    fn checkService(&self, name: &str) -> status::Result<Option<SIBinder>> {
+        Ok(self.services.lock().unwrap().get(name).cloned())
+    }
+    fn checkService2(&self, name: &str) -> status::Result<Service> {
+        if name == NAME || name == WRONG_NAME {
+            return Ok(Service::Accessor(self.checkService(ACCESSOR)?));
+        }
+        Ok(match self.checkService(name)? {
+            Some(binder) => Service::ServiceWithMetadata(ServiceWithMetadata {
+                service: Some(binder),
+                isLazyService: false,
+            }),
+            None => Service::Accessor(None),
+        })
+    }
+    fn addService(
+        &self,
+        name: &str,
+        service: &SIBinder,
+        _allowIsolated: bool,
+        _dumpPriority: i32,
+    ) -> status::Result<()> {
+        self.services
+            .lock()
+            .unwrap()
+            .insert(name.to_owned(), service.clone());
+        Ok(())
+    }
+    fn listServices(&self, _dumpPriority: i32) -> status::Result<Vec<String>> {
+        unsupported()
+    }
+    fn registerForNotifications(
+        &self,
+        _name: &str,
+        _callback: &Strong<dyn IServiceCallback>,
+    ) -> status::Result<()> {
+        unsupported()
+    }
+    fn unregisterForNotifications(
+        &self,
+        _name: &str,
+        _callback: &Strong<dyn IServiceCallback>,
+    ) -> status::Result<()> {
+        unsupported()
+    }
+    fn isDeclared(&self, _name: &str) -> status::Result<bool> {
+        unsupported()
+    }
+    fn getDeclaredInstances(&self, _iface: &str) -> status::Result<Vec<String>> {
+        unsupported()
+    }
+    fn updatableViaApex(&self, _name: &str) -> status::Result<Option<String>> {
+        unsupported()
+    }
+    fn getUpdatableNames(&self, _apexName: &str) -> status::Result<Vec<String>> {
+        unsupported()
+    }
+    fn getConnectionInfo(&self, name: &str) -> status::Result<Option<ConnectionInfo>> {
+        Ok((name == NAME).then(|| ConnectionInfo {
+            ipAddress: "127.0.0.1".to_owned(),
+            port: 5000,
+        }))
+    }
+    fn registerClientCallback(
+        &self,
+        _name: &str,
+        _service: &SIBinder,
+        _callback: &Strong<dyn IClientCallback>,
+    ) -> status::Result<()> {
+        unsupported()
+    }
+    fn tryUnregisterService(&self, _name: &str, _service: &SIBinder) -> status::Result<()> {
+        unsupported()
+    }
+    fn getServiceDebugInfo(&self) -> status::Result<Vec<ServiceDebugInfo>> {
+        unsupported()
+    }
+}
+
+// The accessor connects to the RPC server at `path`. The root object of the
+// server is an accessor too, which only tells its name.
+struct Accessor {
+    instance: String,
+    path: Option<PathBuf>,
+}
+
+impl Interface for Accessor {}
+
+impl IAccessor for Accessor {
+    fn addConnection(&self) -> status::Result<ParcelFileDescriptor> {
+        let path = self.path.as_ref().ok_or(StatusCode::InvalidOperation)?;
+        let stream = UnixStream::connect(path).map_err(|err| {
+            Status::new_service_specific_error(
+                hub::android_16::android::os::IAccessor::ERROR_FAILED_TO_CONNECT_TO_SOCKET,
+                Some(err.to_string()),
+            )
+        })?;
+        Ok(ParcelFileDescriptor::new(stream))
+    }
+    fn getInstanceName(&self) -> status::Result<String> {
+        Ok(self.instance.clone())
+    }
+}
+
+#[test]
+fn accessor_provides_service() -> Result<()> {
+    let path = std::env::temp_dir().join(format!("rsbinder-accessor-{}.sock", std::process::id()));
+    let server = RpcServer::new();
+    server.set_root_object(
+        BnAccessor::new_binder(Accessor {
+            instance: "served over rpc".to_owned(),
+            path: None,
+        })
+        .as_binder(),
+    );
+    server.setup_unix_domain_server(&path)?;
+    let serving = Arc::clone(&server);
+    std::thread::spawn(move || serving.join());
+
+    let device = MemoryDevice::new();
+    let process = ProcessState::builder()
+        .driver(Arc::new(device.open()))
+        .init()
+        .expect("init");
+    ProcessState::start_thread_pool();
+    let manager = BnServiceManager::new_binder(Manager::default());
+    process
+        .become_context_manager(manager.as_binder())
+        .expect("context manager");
+
+    // The process of the accessor.
+    let service_process = ProcessState::builder()
+        .driver(Arc::new(device.open()))
+        .init_context()
+        .expect("service context");
+    service_process.start_pool();
+    let accessor = BnAccessor::new_binder(Accessor {
+        instance: NAME.to_owned(),
+        path: Some(path.clone()),
+    });
+    hub::for_context(service_process).add_service(ACCESSOR, accessor.as_binder())?;
+
+    let sm = hub::android_16::BpServiceManager::from_binder(process.context_object()?)
+        .expect("service manager");
+    let service = hub::android_16::get_service(&sm, NAME)
+        .and_then(|service| service.service)
+        .expect("service through the accessor");
+    let service: Strong<dyn IAccessor> = FromIBinder::try_from(service)?;
+    assert_eq!(service.getInstanceName()?, "served over rpc");
+    assert!(hub::android_16::check_service(&sm, NAME).is_some());
+
+    // The accessor must be responsible for the instance.
+    assert!(hub::android_16::get_service(&sm, WRONG_NAME).is_none());
+
+    let info = hub::android_16::get_connection_info(&sm, NAME).expect("connection info");
+    assert_eq!((info.ipAddress.as_str(), info.port), ("127.0.0.1", 5000));
+    assert!(hub::android_16::get_connection_info(&sm, WRONG_NAME).is_none());
+
+    server.shutdown();
+    std::fs::remove_file(&path).ok();
+    Ok(())
+}
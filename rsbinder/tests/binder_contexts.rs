@@ -0,0 +1,68 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+use rsbinder::*;
+
+mod common;
+use common::{serve_context, serve_with, Service};
+
+const THREAD_NAME: TransactionCode = FIRST_CALL_TRANSACTION;
+const PING: TransactionCode = FIRST_CALL_TRANSACTION + 1;
+
+fn on_transact(code: TransactionCode, reader: &mut Parcel, reply: &mut Parcel) -> Result<()> {
+    match code {
+        THREAD_NAME => reply.write(std::thread::current().name().unwrap_or_default()),
+        PING => {
+            let binder: SIBinder = reader.read()?;
+            binder.ping_binder()
+        }
+        _ => Err(StatusCode::UnknownTransaction),
+    }
+}
+
+fn thread_name(binder: &SIBinder) -> Result<String> {
+    let proxy = binder.as_proxy().unwrap();
+    let data = proxy.prepare_transact(true)?;
+    proxy
+        .submit_transact(THREAD_NAME, &data, 0)?
+        .expect("reply")
+        .read()
+}
+
+#[test]
+fn contexts_on_memory_devices() -> Result<()> {
+    let service = Binder::new(Service::new(on_transact));
+    let (_binder, default) = serve_with(
+        ProcessState::builder().thread_name_prefix("binder"),
+        &service.as_binder(),
+    );
+    let vendor_service = Binder::new(Service::new(on_transact));
+    let (_vndbinder, vendor) = serve_context(
+        ProcessState::builder().thread_name_prefix("vndbinder"),
+        &vendor_service.as_binder(),
+    );
+    assert!(!std::ptr::eq(default, vendor));
+
+    // Every context has its own handles and thread pool.
+    let context = default.context_object()?;
+    let vendor_context = vendor.context_object()?;
+    let process = context.as_proxy().unwrap().process().unwrap();
+    assert!(std::ptr::eq(process, default));
+    let process = vendor_context.as_proxy().unwrap().process().unwrap();
+    assert!(std::ptr::eq(process, vendor));
+    assert!(thread_name(&context)?.starts_with("binder:"));
+    assert!(thread_name(&vendor_context)?.starts_with("vndbinder:"));
+
+    // A handle can't be sent over the driver of another context.
+    let proxy = context.as_proxy().unwrap();
+    let mut data = proxy.prepare_transact(true)?;
+    assert_eq!(data.write(&vendor_context), Err(StatusCode::BadValue));
+
+    // Local binders can be sent over any context.
+    let vendor_proxy = vendor_context.as_proxy().unwrap();
+    let mut data = vendor_proxy.prepare_transact(true)?;
+    data.write(&service.as_binder())?;
+    vendor_proxy.submit_transact(PING, &data, 0)?;
+
+    Ok(())
+}
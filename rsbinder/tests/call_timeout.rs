@@ -0,0 +1,149 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+use std::future::Future;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::time::{Duration, Instant};
+
+use rsbinder::*;
+
+mod common;
+use common::{serve, Service};
+
+const SLEEP: TransactionCode = FIRST_CALL_TRANSACTION;
+const PING: TransactionCode = FIRST_CALL_TRANSACTION + 1;
+const THREAD_NAME: TransactionCode = FIRST_CALL_TRANSACTION + 2;
+const CALL_BACK: TransactionCode = FIRST_CALL_TRANSACTION + 3;
+
+fn on_transact(code: TransactionCode, reader: &mut Parcel, reply: &mut Parcel) -> Result<()> {
+    match code {
+        SLEEP => {
+            let millis: i32 = reader.read()?;
+            std::thread::sleep(Duration::from_millis(millis as _));
+            reply.write(&millis)
+        }
+        PING => {
+            let binder: SIBinder = reader.read()?;
+            binder.ping_binder()?;
+            reply.write(&true)
+        }
+        THREAD_NAME => reply.write(std::thread::current().name().unwrap_or_default()),
+        // Call this service again through the driver.
+        CALL_BACK => {
+            let context = ProcessState::as_self().context_object()?;
+            let name = with_call_timeout(Duration::from_secs(10), || thread_name(&context))?;
+            reply.write(&name)
+        }
+        _ => Err(StatusCode::UnknownTransaction),
+    }
+}
+
+fn sleep(binder: &SIBinder, millis: i32) -> Result<i32> {
+    let proxy = binder.as_proxy().unwrap();
+    let mut data = proxy.prepare_transact(true)?;
+    data.write(&millis)?;
+    proxy
+        .submit_transact(SLEEP, &data, 0)?
+        .expect("reply")
+        .read()
+}
+
+fn thread_name(binder: &SIBinder) -> Result<String> {
+    let proxy = binder.as_proxy().unwrap();
+    let data = proxy.prepare_transact(true)?;
+    proxy
+        .submit_transact(THREAD_NAME, &data, 0)?
+        .expect("reply")
+        .read()
+}
+
+struct ThreadWaker(std::thread::Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+fn block_on<F: Future>(future: F) -> F::Output {
+    let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = std::pin::pin!(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        std::thread::park();
+    }
+}
+
+#[test]
+fn call_timeouts() -> Result<()> {
+    let service = Binder::new(Service::new(on_transact));
+    let (_device, process) = serve(&service.as_binder());
+    let context = process.context_object()?;
+
+    // A deadline of the calling thread.
+    let started = Instant::now();
+    let result = with_call_timeout(Duration::from_millis(100), || sleep(&context, 1000));
+    assert_eq!(result, Err(StatusCode::TimedOut));
+    assert!(started.elapsed() < Duration::from_millis(900));
+    assert_eq!(
+        with_call_timeout(Duration::from_secs(10), || sleep(&context, 10)),
+        Ok(10)
+    );
+
+    // A timeout of the proxy, shortened by the deadline of the thread.
+    let proxy = context.as_proxy().unwrap();
+    proxy.set_call_timeout(Some(Duration::from_millis(100)));
+    assert_eq!(sleep(&context, 1000), Err(StatusCode::TimedOut));
+    proxy.set_call_timeout(Some(Duration::from_secs(10)));
+    assert_eq!(
+        with_call_timeout(Duration::from_millis(100), || sleep(&context, 1000)),
+        Err(StatusCode::TimedOut)
+    );
+    assert_eq!(sleep(&context, 10), Ok(10));
+
+    // Objects are sent by the thread making the call.
+    let mut data = proxy.prepare_transact(true)?;
+    data.write(&service.as_binder())?;
+    let reply = proxy.submit_transact(PING, &data, 0)?;
+    assert!(reply.expect("reply").read::<bool>()?);
+    proxy.set_call_timeout(None);
+
+    // A binder thread makes a timed call itself, so the callback of its call
+    // goes back to the thread which waits for it.
+    let data = proxy.prepare_transact(true)?;
+    let mut reply = proxy.submit_transact(CALL_BACK, &data, 0)?.expect("reply");
+    assert_eq!(
+        reply.read::<String>()?,
+        std::thread::current().name().unwrap()
+    );
+
+    // More calls time out than a shared pool of calling threads would have,
+    // and the timed calls made after them still go through.
+    for _ in 0..10 {
+        assert_eq!(
+            with_call_timeout(Duration::from_millis(20), || sleep(&context, 200)),
+            Err(StatusCode::TimedOut)
+        );
+    }
+    assert_eq!(
+        with_call_timeout(Duration::from_secs(5), || sleep(&context, 10)),
+        Ok(10)
+    );
+
+    // Async calls keep the deadline of the thread which made them.
+    let call = with_call_timeout(Duration::from_millis(100), || {
+        let binder = context.clone();
+        Generic::spawn(move || sleep(&binder, 1000), |result| async move { result })
+    });
+    assert_eq!(block_on(call), Err(StatusCode::TimedOut));
+
+    // The late replies don't disturb later calls.
+    std::thread::sleep(Duration::from_millis(1000));
+    assert_eq!(sleep(&context, 10), Ok(10));
+
+    Ok(())
+}
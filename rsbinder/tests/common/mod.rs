@@ -0,0 +1,93 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+//! The fixtures of the tests which serve a binder service on a `MemoryDevice`.
+
+// Each test uses a part of the fixtures.
+#![allow(dead_code)]
+
+use std::sync::Arc;
+
+use rsbinder::driver::MemoryDevice;
+use rsbinder::*;
+
+/// A service which handles its transactions with a closure.
+pub struct Service<F>(F);
+
+impl<F> Service<F>
+where
+    F: Fn(TransactionCode, &mut Parcel, &mut Parcel) -> Result<()> + Send + Sync + 'static,
+{
+    pub fn new(on_transact: F) -> Self {
+        Service(on_transact)
+    }
+}
+
+impl<F> Remotable for Service<F>
+where
+    F: Fn(TransactionCode, &mut Parcel, &mut Parcel) -> Result<()> + Send + Sync + 'static,
+{
+    fn descriptor() -> &'static str {
+        "rsbinder.test.IService"
+    }
+
+    fn on_transact(
+        &self,
+        code: TransactionCode,
+        reader: &mut Parcel,
+        reply: &mut Parcel,
+    ) -> Result<()> {
+        (self.0)(code, reader, reply)
+    }
+
+    fn on_dump(&self, _writer: &mut dyn std::io::Write, _args: &[String]) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Initialize the process on a new device, start its thread pool and make
+/// `context_manager` the context manager of the device.
+pub fn serve(context_manager: &SIBinder) -> (MemoryDevice, &'static ProcessState) {
+    serve_with(ProcessState::builder(), context_manager)
+}
+
+/// [`serve`] with the options of `builder`.
+pub fn serve_with(
+    builder: ProcessStateBuilder,
+    context_manager: &SIBinder,
+) -> (MemoryDevice, &'static ProcessState) {
+    let device = MemoryDevice::new();
+    let process = builder
+        .driver(Arc::new(device.open()))
+        .init()
+        .expect("init");
+    ProcessState::start_thread_pool();
+    process
+        .become_context_manager(context_manager.clone())
+        .expect("context manager");
+    (device, process)
+}
+
+/// [`serve_with`] from an additional binder context of the process.
+pub fn serve_context(
+    builder: ProcessStateBuilder,
+    context_manager: &SIBinder,
+) -> (MemoryDevice, &'static ProcessState) {
+    let device = MemoryDevice::new();
+    let process = context(&device, builder);
+    process
+        .become_context_manager(context_manager.clone())
+        .expect("context manager");
+    (device, process)
+}
+
+/// Open an additional binder context on `device` with the options of
+/// `builder`, and start its thread pool.
+pub fn context(device: &MemoryDevice, builder: ProcessStateBuilder) -> &'static ProcessState {
+    let process = builder
+        .driver(Arc::new(device.open()))
+        .init_context()
+        .expect("context");
+    process.start_pool();
+    process
+}
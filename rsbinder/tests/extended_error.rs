@@ -0,0 +1,44 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+use rsbinder::*;
+
+mod common;
+use common::{serve, Service};
+
+const SINK: TransactionCode = FIRST_CALL_TRANSACTION;
+
+#[test]
+fn extended_error_on_memory_driver() -> Result<()> {
+    let service = Binder::new(Service::new(|code, _reader, _reply| match code {
+        SINK => Ok(()),
+        _ => Err(StatusCode::UnknownTransaction),
+    }));
+    let (_device, process) = serve(&service.as_binder());
+    let context = process.context_object()?;
+    let proxy = context.as_proxy().unwrap();
+
+    context.ping_binder()?;
+    assert_eq!(thread_state::last_extended_error(), None);
+
+    // The transaction doesn't fit into the buffer of the target.
+    let mut data = proxy.prepare_transact(true)?;
+    data.write(&vec![0u8; 2 * 1024 * 1024])?;
+    let code = proxy.submit_transact(SINK, &data, 0).unwrap_err();
+    assert_eq!(code, StatusCode::FailedTransaction);
+
+    let error = code.extended_error().expect("extended error");
+    assert_eq!(error.errno(), Some(rustix::io::Errno::NOSPC));
+    assert!(error.to_string().starts_with("BR_FAILED_REPLY"));
+
+    let status = Status::from(code);
+    assert_eq!(status.extended_error(), Some(error));
+    assert!(status.to_string().contains("BR_FAILED_REPLY"));
+
+    // Other status codes don't carry the error, and the next transaction clears it.
+    assert_eq!(StatusCode::BadValue.extended_error(), None);
+    context.ping_binder()?;
+    assert_eq!(code.extended_error(), None);
+
+    Ok(())
+}
@@ -0,0 +1,71 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+#![allow(non_snake_case)]
+
+use std::sync::{mpsc, Mutex};
+use std::time::Duration;
+
+use hub::{BnClientCallback, BnServiceCallback, IClientCallback, IServiceCallback};
+use rsbinder::*;
+
+mod common;
+use common::{context, serve};
+
+// The base interface, which knows nothing about its extension.
+struct Base;
+
+impl Interface for Base {}
+
+impl IClientCallback for Base {
+    fn onClients(&self, _registered: &SIBinder, _has_clients: bool) -> status::Result<()> {
+        Ok(())
+    }
+}
+
+// The extension, which passes on what it is told.
+struct Extension(Mutex<mpsc::Sender<String>>);
+
+impl Interface for Extension {}
+
+impl IServiceCallback for Extension {
+    fn onRegistration(&self, name: &str, _binder: &SIBinder) -> status::Result<()> {
+        self.0.lock().unwrap().send(name.to_owned()).unwrap();
+        Ok(())
+    }
+}
+
+#[test]
+fn extension_of_remote_binder() -> std::result::Result<(), Status> {
+    let base = BnClientCallback::new_binder(Base);
+    assert!(base.as_binder().get_extension()?.is_none());
+    let (sender, receiver) = mpsc::channel();
+    let extension_binder = BnServiceCallback::new_binder(Extension(Mutex::new(sender)));
+    BnClientCallback::set_extension(&base, &extension_binder.as_binder())?;
+    assert_eq!(
+        base.as_binder().get_extension()?,
+        Some(extension_binder.as_binder())
+    );
+    let (device, _process) = serve(&base.as_binder());
+
+    // The client lives in a binder context of its own.
+    let client = context(&device, ProcessState::builder());
+    let remote: Strong<dyn IClientCallback> = client.context_object()?.into_interface()?;
+    assert!(remote.as_binder().is_remote());
+    let remote_extension = remote
+        .get_extension::<dyn IServiceCallback>()?
+        .expect("extension");
+    assert!(remote_extension.as_binder().is_remote());
+    remote_extension.onRegistration("extended", &remote_extension.as_binder())?;
+    assert_eq!(
+        receiver.recv_timeout(Duration::from_secs(5)).as_deref(),
+        Ok("extended")
+    );
+
+    // The extension must implement the requested interface.
+    assert_eq!(
+        remote.get_extension::<dyn IClientCallback>().err(),
+        Some(StatusCode::BadType)
+    );
+
+    Ok(())
+}
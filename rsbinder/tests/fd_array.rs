@@ -0,0 +1,105 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+use std::io::{Read, Seek, Write};
+use std::os::fd::OwnedFd;
+
+use rsbinder::*;
+
+mod common;
+use common::{serve, Service};
+
+const WRITE_ALL: TransactionCode = FIRST_CALL_TRANSACTION;
+const NATIVE_HANDLE: TransactionCode = FIRST_CALL_TRANSACTION + 1;
+
+fn on_transact(code: TransactionCode, reader: &mut Parcel, reply: &mut Parcel) -> Result<()> {
+    match code {
+        WRITE_ALL => {
+            let fds = reader.read_fd_array()?;
+            for (i, fd) in fds.into_iter().enumerate() {
+                let mut file = std::fs::File::from(OwnedFd::from(fd));
+                write!(file, "file {i}").unwrap();
+            }
+            Ok(())
+        }
+        NATIVE_HANDLE => {
+            // numFds, numInts, the descriptors and the ints.
+            let (handle, header) = reader.read_buffer()?;
+            let num_fds = i32::from_ne_bytes(header[0..4].try_into().unwrap()) as usize;
+            let fds = reader.read_embedded_fd_array(handle, 8)?;
+            assert_eq!(fds.len(), num_fds);
+            reply.write_fd_array(&fds)
+        }
+        _ => Err(StatusCode::UnknownTransaction),
+    }
+}
+
+fn temp_file(name: &str) -> std::fs::File {
+    let path =
+        std::env::temp_dir().join(format!("rsbinder-fd-array-{}-{name}", std::process::id()));
+    let file = std::fs::File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    file
+}
+
+fn open_fds() -> usize {
+    std::fs::read_dir("/proc/self/fd").unwrap().count()
+}
+
+fn contents(file: &mut std::fs::File) -> String {
+    let mut contents = String::new();
+    file.rewind().unwrap();
+    file.read_to_string(&mut contents).unwrap();
+    contents
+}
+
+#[test]
+fn fd_array_on_memory_driver() -> Result<()> {
+    let service = Binder::new(Service::new(on_transact));
+    let (_device, process) = serve(&service.as_binder());
+    let context = process.context_object()?;
+    let proxy = context.as_proxy().unwrap();
+    let before = open_fds();
+
+    let mut files = vec![temp_file("0"), temp_file("1"), temp_file("2")];
+    let fds = files
+        .iter()
+        .map(|file| ParcelFileDescriptor::new(file.try_clone().unwrap()))
+        .collect::<Vec<_>>();
+
+    let mut data = proxy.prepare_transact(true)?;
+    data.write_fd_array(&fds)?;
+    proxy.submit_transact(WRITE_ALL, &data, 0)?;
+    drop(data);
+    for (i, file) in files.iter_mut().enumerate() {
+        assert_eq!(contents(file), format!("file {i}"));
+    }
+
+    let mut header = vec![0u8; 8 + fds.len() * 4 + 4];
+    header[0..4].copy_from_slice(&(fds.len() as i32).to_ne_bytes());
+    header[4..8].copy_from_slice(&1i32.to_ne_bytes());
+    let mut data = proxy.prepare_transact(true)?;
+    let handle = data.write_buffer(header)?;
+    data.write_embedded_fd_array(&fds, handle, 8)?;
+    let mut reply = proxy
+        .submit_transact(NATIVE_HANDLE, &data, 0)?
+        .expect("reply");
+    let echoed = reply.read_fd_array()?;
+    assert_eq!(echoed.len(), fds.len());
+    for (i, fd) in echoed.into_iter().enumerate() {
+        let mut file = std::fs::File::from(OwnedFd::from(fd));
+        assert_eq!(contents(&mut file), format!("file {i}"));
+    }
+
+    // Parcels close the descriptors of their fd arrays.
+    drop((data, reply, fds, files));
+    assert_eq!(open_fds(), before);
+
+    Ok(())
+}
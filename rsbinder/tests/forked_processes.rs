@@ -0,0 +1,177 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+use std::io::{Read, Seek, Write};
+use std::os::fd::{FromRawFd, OwnedFd};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+
+use rsbinder::driver::MemoryDevice;
+use rsbinder::thread_state::CallingContext;
+use rsbinder::*;
+
+const ECHO: TransactionCode = FIRST_CALL_TRANSACTION;
+const SWAP_FILES: TransactionCode = FIRST_CALL_TRANSACTION + 1;
+const CALL_BACK: TransactionCode = FIRST_CALL_TRANSACTION + 2;
+const EXIT: TransactionCode = FIRST_CALL_TRANSACTION + 3;
+
+struct Service;
+
+impl Remotable for Service {
+    fn descriptor() -> &'static str {
+        "rsbinder.test.IForked"
+    }
+
+    fn on_transact(
+        &self,
+        code: TransactionCode,
+        reader: &mut Parcel,
+        reply: &mut Parcel,
+    ) -> Result<()> {
+        match code {
+            // The message and the pid of the caller.
+            ECHO => {
+                let message: String = reader.read()?;
+                reply.write(&message)?;
+                reply.write(&CallingContext::default().pid)
+            }
+            // Write into the received file, and send back a file of this process.
+            SWAP_FILES => {
+                let fd: ParcelFileDescriptor = reader.read()?;
+                let mut received = std::fs::File::from(OwnedFd::from(fd));
+                write!(received, "written by {}", std::process::id()).unwrap();
+
+                let mut file = temp_file();
+                write!(file, "file of {}", std::process::id()).unwrap();
+                reply.write(&ParcelFileDescriptor::new(file))
+            }
+            CALL_BACK => {
+                let callback: SIBinder = reader.read()?;
+                let (message, _) = echo(&callback, "callback")?;
+                reply.write(&message)
+            }
+            EXIT => unsafe { libc::_exit(0) },
+            _ => Err(StatusCode::UnknownTransaction),
+        }
+    }
+
+    fn on_dump(&self, _writer: &mut dyn std::io::Write, _args: &[String]) -> Result<()> {
+        Ok(())
+    }
+}
+
+struct Recipient(Mutex<mpsc::Sender<()>>);
+
+impl DeathRecipient for Recipient {
+    fn binder_died(&self, _who: &WIBinder) {
+        self.0.lock().unwrap().send(()).unwrap();
+    }
+}
+
+fn temp_file() -> std::fs::File {
+    let path = std::env::temp_dir().join(format!("rsbinder-forked-{}", std::process::id()));
+    let file = std::fs::File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    file
+}
+
+fn contents(file: &mut std::fs::File) -> String {
+    let mut contents = String::new();
+    file.rewind().unwrap();
+    file.read_to_string(&mut contents).unwrap();
+    contents
+}
+
+fn echo(binder: &SIBinder, message: &str) -> Result<(String, i32)> {
+    let proxy = binder.as_proxy().unwrap();
+    let mut data = proxy.prepare_transact(true)?;
+    data.write(message)?;
+    let mut reply = proxy.submit_transact(ECHO, &data, 0)?.expect("reply");
+    Ok((reply.read()?, reply.read()?))
+}
+
+// Serve the device as the context manager of a forked process, and tell the
+// parent through `ready` once the context object is registered.
+fn run_child(device: &MemoryDevice, ready: OwnedFd) -> Result<()> {
+    let process = ProcessState::init_with_driver(Arc::new(device.open()), 0);
+    ProcessState::start_thread_pool();
+    process
+        .become_context_manager(Binder::new(Service).as_binder())
+        .expect("context manager");
+
+    std::fs::File::from(ready).write_all(b"x").unwrap();
+    ProcessState::join_thread_pool()
+}
+
+#[test]
+fn memory_device_across_fork() -> Result<()> {
+    let device = MemoryDevice::new();
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) }, 0);
+    let (read_end, write_end) =
+        unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+
+    let child = unsafe { libc::fork() };
+    assert!(child >= 0);
+    if child == 0 {
+        drop(read_end);
+        let res = std::panic::catch_unwind(|| run_child(&device, write_end));
+        unsafe { libc::_exit(if matches!(res, Ok(Ok(()))) { 0 } else { 1 }) };
+    }
+    drop(write_end);
+    let mut ready = [0u8; 1];
+    std::fs::File::from(read_end)
+        .read_exact(&mut ready)
+        .unwrap();
+
+    let process = ProcessState::init_with_driver(Arc::new(device.open()), 0);
+    ProcessState::start_thread_pool();
+    let context = process.context_object()?;
+    assert_eq!(context.descriptor(), Service::descriptor());
+
+    let (message, pid) = echo(&context, "hello")?;
+    assert_eq!(message, "hello");
+    assert_eq!(pid, std::process::id() as i32);
+
+    // Descriptors go both ways.
+    let proxy = context.as_proxy().unwrap();
+    let mut file = temp_file();
+    let mut data = proxy.prepare_transact(true)?;
+    data.write(&ParcelFileDescriptor::new(file.try_clone().unwrap()))?;
+    let mut reply = proxy.submit_transact(SWAP_FILES, &data, 0)?.expect("reply");
+    let fd: ParcelFileDescriptor = reply.read()?;
+    assert_eq!(contents(&mut file), format!("written by {child}"));
+    let mut received = std::fs::File::from(OwnedFd::from(fd));
+    assert_eq!(contents(&mut received), format!("file of {child}"));
+
+    // The child calls back into this process while it serves a call from it.
+    let callback = Binder::new(Service);
+    let mut data = proxy.prepare_transact(true)?;
+    data.write(&callback.as_binder())?;
+    let mut reply = proxy.submit_transact(CALL_BACK, &data, 0)?.expect("reply");
+    assert_eq!(reply.read::<String>()?, "callback");
+
+    // The exit of the child kills its binders.
+    let (sender, receiver) = mpsc::channel();
+    let recipient = Arc::new(Recipient(Mutex::new(sender)));
+    let weak = Arc::downgrade(&recipient) as std::sync::Weak<dyn DeathRecipient>;
+    context.link_to_death(weak)?;
+    let data = proxy.prepare_transact(true)?;
+    assert!(proxy.submit_transact(EXIT, &data, FLAG_ONEWAY)?.is_none());
+    receiver
+        .recv_timeout(Duration::from_secs(10))
+        .expect("death notification");
+    assert_eq!(context.ping_binder(), Err(StatusCode::DeadObject));
+
+    let mut status = 0;
+    assert_eq!(unsafe { libc::waitpid(child, &mut status, 0) }, child);
+    assert!(libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0);
+
+    Ok(())
+}
@@ -0,0 +1,101 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rsbinder::*;
+
+mod common;
+use common::{serve, Service};
+
+const PUSH: TransactionCode = FIRST_CALL_TRANSACTION;
+
+#[derive(Default)]
+struct Watcher(Mutex<Vec<FrozenState>>);
+
+impl FrozenStateChangeCallback for Watcher {
+    fn on_state_changed(&self, _who: &WIBinder, state: FrozenState) {
+        self.0.lock().unwrap().push(state);
+    }
+}
+
+fn wait_until(mut f: impl FnMut() -> bool) -> bool {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !f() {
+        if Instant::now() > deadline {
+            return false;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    true
+}
+
+// The memory device lets the process transact with its own nodes, so the
+// process freezes itself here.
+#[test]
+fn freeze_on_memory_driver() -> Result<()> {
+    let values = Arc::new(Mutex::new(Vec::<i32>::new()));
+    let pushed = values.clone();
+    let service = Binder::new(Service::new(move |code, reader, _reply| match code {
+        PUSH => {
+            pushed.lock().unwrap().push(reader.read()?);
+            Ok(())
+        }
+        _ => Err(StatusCode::UnknownTransaction),
+    }));
+    let (_device, process) = serve(&service.as_binder());
+
+    let context = process.context_object()?;
+    let proxy = context.as_proxy().unwrap();
+    let pid = std::process::id() as i32;
+
+    let watcher = Arc::new(Watcher::default());
+    let callback = Arc::downgrade(&watcher) as std::sync::Weak<dyn FrozenStateChangeCallback>;
+    proxy.add_frozen_state_change_callback(callback.clone())?;
+    assert!(wait_until(|| watcher.0.lock().unwrap().len() == 1));
+    assert_eq!(proxy.frozen_state(), Some(FrozenState::Unfrozen));
+
+    process.freeze(pid, true, Duration::from_millis(100))?;
+    assert!(wait_until(|| watcher.0.lock().unwrap().len() == 2));
+    assert_eq!(proxy.frozen_state(), Some(FrozenState::Frozen));
+
+    assert_eq!(
+        context.ping_binder().err(),
+        Some(StatusCode::FailedTransaction)
+    );
+    let mut data = proxy.prepare_transact(true)?;
+    data.write(&7i32)?;
+    assert!(proxy.submit_transact(PUSH, &data, FLAG_ONEWAY)?.is_none());
+
+    let info = process.frozen_info(pid)?;
+    assert!(info.sync_received);
+    assert!(info.async_received);
+    std::thread::sleep(Duration::from_millis(50));
+    assert!(values.lock().unwrap().is_empty());
+
+    process.freeze(pid, false, Duration::ZERO)?;
+    assert!(wait_until(|| watcher.0.lock().unwrap().len() == 3));
+    assert_eq!(
+        *watcher.0.lock().unwrap(),
+        vec![
+            FrozenState::Unfrozen,
+            FrozenState::Frozen,
+            FrozenState::Unfrozen
+        ]
+    );
+    assert!(wait_until(|| *values.lock().unwrap() == vec![7]));
+    context.ping_binder()?;
+    assert_eq!(process.frozen_info(pid)?, FrozenInfo::default());
+
+    proxy.remove_frozen_state_change_callback(callback)?;
+    assert_eq!(proxy.frozen_state(), None);
+
+    // No binder process has this pid.
+    assert_eq!(
+        process.freeze(-1, true, Duration::ZERO).err(),
+        Some(StatusCode::BadValue)
+    );
+
+    Ok(())
+}
@@ -0,0 +1,230 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+#![allow(non_snake_case)]
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use hub::android_16::android::os::{
+    ConnectionInfo::ConnectionInfo, Service::Service, ServiceWithMetadata::ServiceWithMetadata,
+};
+use hub::android_16::{BnServiceManager, IServiceManager};
+use hub::{IClientCallback, IServiceCallback, ServiceDebugInfo};
+use rsbinder::driver::MemoryDevice;
+use rsbinder::*;
+
+// A service manager whose clients are made up by the test.
+#[derive(Default, Clone)]
+struct Manager {
+    services: Arc<Mutex<HashMap<String, SIBinder>>>,
+    registrations: Arc<Mutex<HashMap<String, usize>>>,
+    callbacks: Arc<Mutex<HashMap<String, Strong<dyn IClientCallback>>>>,
+    // Services which refuse to be unregistered, as if a client just got them.
+    busy: Arc<Mutex<HashSet<String>>>,
+}
+
+impl Manager {
+    fn set_clients(&self, name: &str, has_clients: bool) {
+        let service = self.services.lock().unwrap()[name].clone();
+        let callback = self.callbacks.lock().unwrap()[name].clone();
+        callback.onClients(&service, has_clients).unwrap();
+    }
+
+    fn registrations(&self, name: &str) -> usize {
+        self.registrations
+            .lock()
+            .unwrap()
+            .get(name)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    fn is_registered(&self, name: &str) -> bool {
+        self.services.lock().unwrap().contains_key(name)
+    }
+}
+
+impl Interface for Manager {}
+
+fn unsupported<T>() -> status::Result<T> {
+    Err(StatusCode::UnknownTransaction.into())
+}
+
+impl IServiceManager for Manager {
+    fn getService(&self, name: &str) -> status::Result<Option<SIBinder>> {
+        self.checkService(name)
+    }
+    fn getService2(&self, name: &str) -> status::Result<Service> {
+        self.checkService2(name)
+    }
+    fn checkService(&self, name: &str) -> status::Result<Option<SIBinder>> {
+        Ok(self.services.lock().unwrap().get(name).cloned())
+    }
+    fn checkService2(&self, name: &str) -> status::Result<Service> {
+        Ok(match self.checkService(name)? {
+            Some(binder) => Service::ServiceWithMetadata(ServiceWithMetadata {
+                service: Some(binder),
+                isLazyService: false,
+            }),
+            None => Service::Accessor(None),
+        })
+    }
+    fn addService(
+        &self,
+        name: &str,
+        service: &SIBinder,
+        _allowIsolated: bool,
+        _dumpPriority: i32,
+    ) -> status::Result<()> {
+        self.services
+            .lock()
+            .unwrap()
+            .insert(name.to_owned(), service.clone());
+        *self
+            .registrations
+            .lock()
+            .unwrap()
+            .entry(name.to_owned())
+            .or_default() += 1;
+        Ok(())
+    }
+    fn listServices(&self, _dumpPriority: i32) -> status::Result<Vec<String>> {
+        Ok(self.services.lock().unwrap().keys().cloned().collect())
+    }
+    fn registerForNotifications(
+        &self,
+        _name: &str,
+        _callback: &Strong<dyn IServiceCallback>,
+    ) -> status::Result<()> {
+        unsupported()
+    }
+    fn unregisterForNotifications(
+        &self,
+        _name: &str,
+        _callback: &Strong<dyn IServiceCallback>,
+    ) -> status::Result<()> {
+        unsupported()
+    }
+    fn isDeclared(&self, _name: &str) -> status::Result<bool> {
+        Ok(false)
+    }
+    fn getDeclaredInstances(&self, _iface: &str) -> status::Result<Vec<String>> {
+        unsupported()
+    }
+    fn updatableViaApex(&self, _name: &str) -> status::Result<Option<String>> {
+        unsupported()
+    }
+    fn getUpdatableNames(&self, _apexName: &str) -> status::Result<Vec<String>> {
+        unsupported()
+    }
+    fn getConnectionInfo(&self, _name: &str) -> status::Result<Option<ConnectionInfo>> {
+        unsupported()
+    }
+    fn registerClientCallback(
+        &self,
+        name: &str,
+        service: &SIBinder,
+        callback: &Strong<dyn IClientCallback>,
+    ) -> status::Result<()> {
+        if self.services.lock().unwrap().get(name) != Some(service) {
+            return Err((ExceptionCode::IllegalArgument, "wrong service").into());
+        }
+        self.callbacks
+            .lock()
+            .unwrap()
+            .insert(name.to_owned(), callback.clone());
+        Ok(())
+    }
+    fn tryUnregisterService(&self, name: &str, _service: &SIBinder) -> status::Result<()> {
+        if self.busy.lock().unwrap().contains(name) {
+            return Err((ExceptionCode::IllegalState, "there are clients").into());
+        }
+        self.services.lock().unwrap().remove(name);
+        Ok(())
+    }
+    fn getServiceDebugInfo(&self) -> status::Result<Vec<ServiceDebugInfo>> {
+        unsupported()
+    }
+}
+
+fn wait_for(what: &str, condition: impl Fn() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !condition() {
+        assert!(Instant::now() < deadline, "timed out waiting for {what}");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn lazy_services() -> std::result::Result<(), Status> {
+    let device = MemoryDevice::new();
+    let process = ProcessState::builder()
+        .driver(Arc::new(device.open()))
+        .init()
+        .expect("init");
+    ProcessState::start_thread_pool();
+    let manager = Manager::default();
+    let manager_binder = BnServiceManager::new_binder(manager.clone());
+    process
+        .become_context_manager(manager_binder.as_binder())
+        .expect("context manager");
+
+    // The lazy services live in a binder context of their own.
+    let services = ProcessState::builder()
+        .driver(Arc::new(device.open()))
+        .init_context()
+        .expect("service context");
+    services.start_pool();
+    let registrar = hub::LazyServiceRegistrar::with_service_manager(hub::for_context(services));
+    let (sender, receiver) = mpsc::channel();
+    let handled = Arc::new(Mutex::new(true));
+    {
+        let handled = handled.clone();
+        registrar.set_active_services_callback(move |has_clients| {
+            sender.send(has_clients).unwrap();
+            *handled.lock().unwrap()
+        });
+    }
+    for name in ["lazy.a", "lazy.b"] {
+        let service = BnServiceManager::new_binder(Manager::default());
+        registrar.register_service(name, service.as_binder())?;
+    }
+    assert!(manager.is_registered("lazy.a") && manager.is_registered("lazy.b"));
+
+    // The hook hears only about the services of the process as a whole.
+    let timeout = Duration::from_secs(5);
+    manager.set_clients("lazy.a", true);
+    assert_eq!(receiver.recv_timeout(timeout), Ok(true));
+    manager.set_clients("lazy.b", true);
+    manager.set_clients("lazy.a", false);
+    manager.set_clients("lazy.b", false);
+    assert_eq!(receiver.recv_timeout(timeout), Ok(false));
+    assert!(manager.is_registered("lazy.a") && manager.is_registered("lazy.b"));
+
+    // A hook which handles the shutdown itself.
+    assert!(registrar.try_unregister());
+    assert!(!manager.is_registered("lazy.a") && !manager.is_registered("lazy.b"));
+    registrar.re_register();
+    assert_eq!(manager.registrations("lazy.a"), 2);
+    assert_eq!(manager.registrations("lazy.b"), 2);
+
+    // A shutdown which races a new client registers the services again.
+    *handled.lock().unwrap() = false;
+    manager.busy.lock().unwrap().insert("lazy.b".to_owned());
+    manager.set_clients("lazy.a", true);
+    assert_eq!(receiver.recv_timeout(timeout), Ok(true));
+    manager.set_clients("lazy.a", false);
+    assert_eq!(receiver.recv_timeout(timeout), Ok(false));
+    wait_for("re-registration", || manager.registrations("lazy.a") == 3);
+    assert!(manager.is_registered("lazy.a") && manager.is_registered("lazy.b"));
+    assert_eq!(manager.registrations("lazy.b"), 2);
+
+    // Nothing happens while the process is forced to persist.
+    registrar.force_persist(true);
+    manager.set_clients("lazy.a", true);
+    manager.set_clients("lazy.a", false);
+    assert!(receiver.recv_timeout(Duration::from_millis(200)).is_err());
+
+    Ok(())
+}
@@ -0,0 +1,61 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+use std::sync::Arc;
+
+use rsbinder::driver::MemoryDevice;
+use rsbinder::*;
+
+struct Echo;
+
+impl Remotable for Echo {
+    fn descriptor() -> &'static str {
+        "rsbinder.test.IEcho"
+    }
+
+    fn on_transact(
+        &self,
+        code: TransactionCode,
+        reader: &mut Parcel,
+        reply: &mut Parcel,
+    ) -> Result<()> {
+        match code {
+            FIRST_CALL_TRANSACTION => {
+                let message: String = reader.read()?;
+                reply.write(&message)
+            }
+            _ => Err(StatusCode::UnknownTransaction),
+        }
+    }
+
+    fn on_dump(&self, _writer: &mut dyn std::io::Write, _args: &[String]) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn process_state_on_memory_driver() -> Result<()> {
+    let device = MemoryDevice::new();
+    let process = ProcessState::init_with_driver(Arc::new(device.open()), 0);
+    ProcessState::start_thread_pool();
+
+    let service = Binder::new(Echo);
+    process
+        .become_context_manager(service.as_binder())
+        .expect("context manager");
+
+    let context = process.context_object()?;
+    assert_eq!(context.descriptor(), Echo::descriptor());
+    context.ping_binder()?;
+
+    let proxy = context.as_proxy().unwrap();
+    let mut data = proxy.prepare_transact(true)?;
+    data.write("hello")?;
+    let mut reply = proxy
+        .submit_transact(FIRST_CALL_TRANSACTION, &data, 0)?
+        .expect("reply");
+    let message: String = reply.read()?;
+    assert_eq!(message, "hello");
+
+    Ok(())
+}
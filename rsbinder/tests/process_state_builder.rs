@@ -0,0 +1,56 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rsbinder::*;
+
+mod common;
+use common::{serve_with, Service};
+
+#[test]
+fn builder_on_memory_driver() -> Result<()> {
+    let started = Arc::new(Mutex::new(Vec::new()));
+    let builder = ProcessState::builder()
+        .max_threads(2)
+        .oneway_spam_detection(false)
+        .thread_name_prefix("camera")
+        .on_thread_start({
+            let started = started.clone();
+            move || {
+                let name = std::thread::current().name().map(str::to_owned);
+                started.lock().unwrap().push(name.unwrap_or_default());
+            }
+        });
+    let service = Binder::new(Service::new(|_code, _reader, _reply| {
+        Err(StatusCode::UnknownTransaction)
+    }));
+    let (device, process) = serve_with(builder, &service.as_binder());
+
+    // The receive buffer of an opened driver is already mapped.
+    assert!(ProcessState::builder()
+        .driver(Arc::new(device.open()))
+        .vm_size(4 * 1024 * 1024)
+        .init_context()
+        .is_err());
+
+    // Only one ProcessState exists per process.
+    assert!(ProcessState::builder()
+        .driver(Arc::new(device.open()))
+        .init()
+        .is_err());
+
+    process.context_object()?.ping_binder()?;
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while started.lock().unwrap().is_empty() && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let started = started.lock().unwrap();
+    assert!(!started.is_empty());
+    let prefix = format!("camera:{}_", std::process::id());
+    assert!(started.iter().all(|name| name.starts_with(&prefix)));
+
+    Ok(())
+}
@@ -0,0 +1,185 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fs::File;
+use std::io::Read;
+use std::os::fd::AsFd;
+use std::sync::Arc;
+
+use rsbinder::driver::MemoryDevice;
+use rsbinder::recording::RecordedTransaction;
+use rsbinder::*;
+
+const ECHO: TransactionCode = FIRST_CALL_TRANSACTION;
+const READ_FILE: TransactionCode = FIRST_CALL_TRANSACTION + 1;
+const NOTIFY: TransactionCode = FIRST_CALL_TRANSACTION + 2;
+
+struct Service;
+
+impl Remotable for Service {
+    fn descriptor() -> &'static str {
+        "rsbinder.test.IRecording"
+    }
+
+    fn on_transact(
+        &self,
+        code: TransactionCode,
+        reader: &mut Parcel,
+        reply: &mut Parcel,
+    ) -> Result<()> {
+        match code {
+            ECHO => reply.write(&reader.read::<String>()?.to_uppercase()),
+            READ_FILE => {
+                let fd: ParcelFileDescriptor = reader.read()?;
+                let mut text = String::new();
+                File::from(std::os::fd::OwnedFd::from(fd)).read_to_string(&mut text)?;
+                reply.write(&text)
+            }
+            NOTIFY => Ok(()),
+            _ => Err(StatusCode::UnknownTransaction),
+        }
+    }
+
+    fn on_dump(&self, _writer: &mut dyn std::io::Write, _args: &[String]) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn call(binder: &SIBinder, code: TransactionCode, flags: TransactionFlags) -> Result<Parcel> {
+    let proxy = binder.as_proxy().unwrap();
+    let mut data = proxy.prepare_transact(true)?;
+    match code {
+        ECHO => data.write("recorded")?,
+        READ_FILE => {
+            let file = File::open("/proc/self/cmdline")?;
+            data.write(&ParcelFileDescriptor::new(file))?
+        }
+        _ => {}
+    }
+    Ok(proxy
+        .submit_transact(code, &data, flags)?
+        .unwrap_or_default())
+}
+
+#[test]
+fn record_and_replay() -> Result<()> {
+    let device = MemoryDevice::new();
+    let process = ProcessState::builder()
+        .driver(Arc::new(device.open()))
+        .init()
+        .expect("init");
+    ProcessState::start_thread_pool();
+    let service = Binder::new(Service);
+    process
+        .become_context_manager(service.as_binder())
+        .expect("context manager");
+
+    // The client lives in a binder context of its own.
+    let client = ProcessState::builder()
+        .driver(Arc::new(device.open()))
+        .init_context()
+        .expect("client context");
+    client.start_pool();
+    let remote = client.context_object()?;
+    let proxy = remote.as_proxy().unwrap();
+
+    // A client of another user.
+    let uid = rustix::process::getuid().as_raw();
+    let stranger = ProcessState::builder()
+        .driver(Arc::new(device.open_with_credentials(
+            rustix::process::getpid().as_raw_nonzero().get(),
+            if uid == 1000 { 1001 } else { 1000 },
+        )))
+        .init_context()
+        .expect("stranger context");
+    let stranger_remote = stranger.context_object()?;
+
+    let path = std::env::temp_dir().join(format!("rsbinder-recording-{}.rec", std::process::id()));
+    let recording = File::create(&path)?;
+    proxy.start_recording(recording.as_fd())?;
+    drop(recording);
+    assert_eq!(
+        proxy.start_recording(File::open(&path)?.as_fd()).err(),
+        Some(StatusCode::InvalidOperation)
+    );
+
+    // Another user can neither start nor stop the recording.
+    let stranger_proxy = stranger_remote.as_proxy().unwrap();
+    assert_eq!(
+        stranger_proxy.stop_recording().err(),
+        Some(StatusCode::PermissionDenied)
+    );
+    assert_eq!(
+        stranger_proxy
+            .start_recording(File::open(&path)?.as_fd())
+            .err(),
+        Some(StatusCode::PermissionDenied)
+    );
+
+    call(&remote, ECHO, 0)?;
+    call(&remote, READ_FILE, 0)?;
+    call(&remote, NOTIFY, FLAG_ONEWAY)?;
+    // A oneway transaction is handled by the time a later call returns.
+    remote.ping_binder()?;
+    proxy.stop_recording()?;
+    call(&remote, ECHO, 0)?;
+
+    let mut reader = File::open(&path)?;
+    let mut transactions = Vec::new();
+    while let Some(transaction) = RecordedTransaction::read_from(&mut reader)? {
+        transactions.push(transaction);
+    }
+    std::fs::remove_file(&path)?;
+
+    let codes: Vec<_> = transactions.iter().map(|t| t.code).collect();
+    assert_eq!(codes, vec![ECHO, READ_FILE, NOTIFY, PING_TRANSACTION]);
+    assert!(transactions.iter().all(|t| t.status == 0));
+    assert_eq!(transactions[0].flags & FLAG_ONEWAY, 0);
+    assert_ne!(transactions[2].flags & FLAG_ONEWAY, 0);
+    let mut recorded_reply = Parcel::from_vec(transactions[0].reply.clone());
+    assert_eq!(recorded_reply.read::<String>()?, "RECORDED");
+    assert_eq!(transactions[1].objects.len(), 1);
+    assert!(transactions
+        .windows(2)
+        .all(|t| t[0].timestamp <= t[1].timestamp));
+
+    // Replay gets the same reply, and /dev/null in place of the recorded file.
+    let reply = transactions[0].replay(&remote)?;
+    assert!(transactions[0].reply_matches(reply.as_ref()));
+    let mut reply = transactions[1].replay(&remote)?.expect("reply");
+    assert_eq!(reply.read::<String>()?, "");
+    assert!(!transactions[1].reply_matches(Some(&reply)));
+    assert!(transactions[2].replay(&remote)?.is_none());
+
+    // An object offset which overflows is rejected.
+    let mut bad_offset = transactions[0].clone();
+    bad_offset.objects = vec![u64::MAX];
+    assert_eq!(bad_offset.replay(&remote).err(), Some(StatusCode::BadValue));
+
+    Ok(())
+}
+
+#[test]
+fn corrupt_recordings() {
+    let transaction = RecordedTransaction {
+        code: ECHO,
+        flags: 0,
+        timestamp: Default::default(),
+        status: 0,
+        data: vec![0; 32],
+        objects: vec![8],
+        reply: vec![0; 8],
+    };
+    let mut record = Vec::new();
+    transaction.write_to(&mut record).unwrap();
+    let read = |record: &[u8]| RecordedTransaction::read_from(&mut &record[..]);
+    assert_eq!(read(&record).unwrap(), Some(transaction));
+
+    // The data length, object count and reply length follow the header.
+    for (offset, len) in [(28, u32::MAX), (32, 2), (36, u32::MAX), (36, 9)] {
+        let mut corrupt = record.clone();
+        corrupt[offset..offset + 4].copy_from_slice(&len.to_le_bytes());
+        let err = read(&corrupt).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData, "{err}");
+    }
+}
@@ -0,0 +1,109 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+
+use rsbinder::rpc::{RpcServer, RpcSession};
+use rsbinder::*;
+
+const GET_NAME: TransactionCode = FIRST_CALL_TRANSACTION;
+
+struct NamedService(String);
+
+impl Remotable for NamedService {
+    fn descriptor() -> &'static str {
+        "rsbinder.test.INamedService"
+    }
+
+    fn on_transact(
+        &self,
+        code: TransactionCode,
+        _reader: &mut Parcel,
+        reply: &mut Parcel,
+    ) -> Result<()> {
+        match code {
+            GET_NAME => reply.write(&self.0),
+            _ => Err(StatusCode::UnknownTransaction),
+        }
+    }
+
+    fn on_dump(&self, _writer: &mut dyn std::io::Write, _args: &[String]) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn get_name(binder: &SIBinder) -> Result<String> {
+    let proxy = binder.as_proxy().ok_or(StatusCode::BadType)?;
+    let data = proxy.prepare_transact(true)?;
+    proxy
+        .submit_transact(GET_NAME, &data, 0)?
+        .ok_or(StatusCode::UnexpectedNull)?
+        .read()
+}
+
+fn start_server(name: &str) -> (Arc<RpcServer>, SocketAddr) {
+    let server = RpcServer::new();
+    server.set_root_object(Binder::new(NamedService(name.to_owned())).as_binder());
+    server.set_max_threads(2);
+    let addr = server.setup_inet_server("127.0.0.1:0").unwrap();
+
+    let joined = Arc::clone(&server);
+    std::thread::spawn(move || joined.join());
+
+    (server, addr)
+}
+
+#[test]
+fn rpc_inet_transaction() -> Result<()> {
+    let (server, addr) = start_server("inet");
+    assert_ne!(addr.port(), 0);
+
+    let session = RpcSession::new();
+    session.setup_inet_client(addr)?;
+    assert_eq!(session.remote_max_threads()?, 2);
+
+    let root = session.root_object()?;
+    assert_eq!(root.descriptor(), NamedService::descriptor());
+    assert_eq!(get_name(&root)?, "inet");
+
+    // Calls from several threads are spread over the connections of the session.
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let root = root.clone();
+            std::thread::spawn(move || get_name(&root).unwrap())
+        })
+        .collect();
+    for handle in handles {
+        assert_eq!(handle.join().unwrap(), "inet");
+    }
+
+    session.shutdown();
+    server.shutdown();
+    Ok(())
+}
+
+#[test]
+fn rpc_custom_transport() -> Result<()> {
+    let (server, addr) = start_server("custom");
+
+    let session = RpcSession::new();
+    session.setup_client(|| TcpStream::connect(addr))?;
+    assert_eq!(get_name(&session.root_object()?)?, "custom");
+
+    session.shutdown();
+    server.shutdown();
+    Ok(())
+}
+
+#[test]
+fn rpc_inet_connection_refused() {
+    // Nothing listens on the port of a dropped listener.
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let session = RpcSession::new();
+    assert!(session.setup_inet_client(addr).is_err());
+    assert!(session.is_shutdown());
+}
@@ -0,0 +1,280 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rsbinder::rpc::{RpcServer, RpcSession};
+use rsbinder::*;
+
+const ECHO: TransactionCode = FIRST_CALL_TRANSACTION;
+const NEW_CHILD: TransactionCode = FIRST_CALL_TRANSACTION + 1;
+const CALL_BACK: TransactionCode = FIRST_CALL_TRANSACTION + 2;
+const PUSH: TransactionCode = FIRST_CALL_TRANSACTION + 3;
+const VALUES: TransactionCode = FIRST_CALL_TRANSACTION + 4;
+const CALL_BACK_LATER: TransactionCode = FIRST_CALL_TRANSACTION + 5;
+
+#[derive(Default)]
+struct Service {
+    values: Mutex<Vec<i32>>,
+}
+
+impl Remotable for Service {
+    fn descriptor() -> &'static str {
+        "rsbinder.test.IRpcService"
+    }
+
+    fn on_transact(
+        &self,
+        code: TransactionCode,
+        reader: &mut Parcel,
+        reply: &mut Parcel,
+    ) -> Result<()> {
+        match code {
+            ECHO => {
+                let message: String = reader.read()?;
+                reply.write(&message)
+            }
+            NEW_CHILD => reply.write(&Binder::new(Service::default()).as_binder()),
+            CALL_BACK => {
+                let callback: SIBinder = reader.read()?;
+                let message: String = reader.read()?;
+                let answer = call_echo(&callback, &message)?;
+                reply.write(&answer)
+            }
+            PUSH => {
+                let value: i32 = reader.read()?;
+                self.values.lock().unwrap().push(value);
+                Ok(())
+            }
+            VALUES => reply.write(&*self.values.lock().unwrap()),
+            CALL_BACK_LATER => {
+                // Call back from a thread that isn't serving a transaction of the session.
+                let callback: SIBinder = reader.read()?;
+                std::thread::spawn(move || call_echo(&callback, "later"));
+                Ok(())
+            }
+            _ => Err(StatusCode::UnknownTransaction),
+        }
+    }
+
+    fn on_dump(&self, _writer: &mut dyn std::io::Write, _args: &[String]) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Callback {
+    messages: Mutex<Vec<String>>,
+}
+
+impl Remotable for Callback {
+    fn descriptor() -> &'static str {
+        "rsbinder.test.IRpcCallback"
+    }
+
+    fn on_transact(
+        &self,
+        code: TransactionCode,
+        reader: &mut Parcel,
+        reply: &mut Parcel,
+    ) -> Result<()> {
+        match code {
+            ECHO => {
+                let message: String = reader.read()?;
+                self.messages.lock().unwrap().push(message.clone());
+                reply.write(&format!("callback: {message}"))
+            }
+            _ => Err(StatusCode::UnknownTransaction),
+        }
+    }
+
+    fn on_dump(&self, _writer: &mut dyn std::io::Write, _args: &[String]) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn call_echo(binder: &SIBinder, message: &str) -> Result<String> {
+    let proxy = binder.as_proxy().ok_or(StatusCode::BadType)?;
+    let mut data = proxy.prepare_transact(true)?;
+    data.write(message)?;
+    proxy
+        .submit_transact(ECHO, &data, 0)?
+        .ok_or(StatusCode::UnexpectedNull)?
+        .read()
+}
+
+fn socket_path(name: &str) -> PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("rsbinder-rpc-{}-{name}.sock", std::process::id()));
+    std::fs::remove_file(&path).ok();
+    path
+}
+
+fn start_server(name: &str, max_threads: usize) -> (Arc<RpcServer>, PathBuf) {
+    let path = socket_path(name);
+    let server = RpcServer::new();
+    server.set_root_object(Binder::new(Service::default()).as_binder());
+    server.set_max_threads(max_threads);
+    server.setup_unix_domain_server(&path).unwrap();
+
+    let joined = Arc::clone(&server);
+    std::thread::spawn(move || joined.join());
+
+    (server, path)
+}
+
+#[test]
+fn rpc_transaction() -> Result<()> {
+    let (server, path) = start_server("transaction", 1);
+
+    let session = RpcSession::new();
+    session.setup_unix_domain_client(&path)?;
+    assert_eq!(session.remote_max_threads()?, 1);
+    assert_eq!(session.session_id().len(), 32);
+
+    let root = session.root_object()?;
+    assert_eq!(root.descriptor(), Service::descriptor());
+    root.ping_binder()?;
+    assert_eq!(call_echo(&root, "hello")?, "hello");
+
+    // A binder returned by the server becomes another proxy of the session.
+    let mut reply = {
+        let proxy = root.as_proxy().unwrap();
+        let data = proxy.prepare_transact(true)?;
+        proxy.submit_transact(NEW_CHILD, &data, 0)?.unwrap()
+    };
+    let child: SIBinder = reply.read()?;
+    assert_ne!(child, root);
+    assert_eq!(call_echo(&child, "child")?, "child");
+    drop(child);
+
+    let proxy = root.as_proxy().unwrap();
+    let data = proxy.prepare_transact(true)?;
+    assert_eq!(
+        proxy.submit_transact(LAST_CALL_TRANSACTION, &data, 0).err(),
+        Some(StatusCode::UnknownTransaction)
+    );
+
+    session.shutdown();
+    server.shutdown();
+    Ok(())
+}
+
+#[test]
+fn rpc_nested_callback() -> Result<()> {
+    let (server, path) = start_server("nested", 2);
+
+    let session = RpcSession::new();
+    session.setup_unix_domain_client(&path)?;
+    let root = session.root_object()?;
+
+    let callback = Binder::new(Callback::default());
+    for _ in 0..3 {
+        let proxy = root.as_proxy().unwrap();
+        let mut data = proxy.prepare_transact(true)?;
+        data.write(&callback.as_binder())?;
+        data.write("ping")?;
+        let answer: String = proxy
+            .submit_transact(CALL_BACK, &data, 0)?
+            .unwrap()
+            .read()?;
+        assert_eq!(answer, "callback: ping");
+    }
+
+    session.shutdown();
+    server.shutdown();
+    Ok(())
+}
+
+#[test]
+fn rpc_oneway_order() -> Result<()> {
+    let (server, path) = start_server("oneway", 4);
+
+    let session = RpcSession::new();
+    session.setup_unix_domain_client(&path)?;
+    let root = session.root_object()?;
+    let proxy = root.as_proxy().unwrap();
+
+    const COUNT: i32 = 200;
+    for value in 0..COUNT {
+        let mut data = proxy.prepare_transact(true)?;
+        data.write(&value)?;
+        assert!(proxy.submit_transact(PUSH, &data, FLAG_ONEWAY)?.is_none());
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let values = loop {
+        let data = proxy.prepare_transact(true)?;
+        let values: Vec<i32> = proxy.submit_transact(VALUES, &data, 0)?.unwrap().read()?;
+        if values.len() == COUNT as usize || Instant::now() > deadline {
+            break values;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    assert_eq!(values, (0..COUNT).collect::<Vec<_>>());
+
+    session.shutdown();
+    server.shutdown();
+    Ok(())
+}
+
+#[test]
+fn rpc_incoming_threads() -> Result<()> {
+    let (server, path) = start_server("incoming", 1);
+
+    let session = RpcSession::new();
+    session.set_max_incoming_threads(2);
+    session.setup_unix_domain_client(&path)?;
+    let root = session.root_object()?;
+
+    let callback = Binder::new(Callback::default());
+    let proxy = root.as_proxy().unwrap();
+    let mut data = proxy.prepare_transact(true)?;
+    data.write(&callback.as_binder())?;
+    proxy.submit_transact(CALL_BACK_LATER, &data, FLAG_ONEWAY)?;
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while callback.messages.lock().unwrap().is_empty() && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(*callback.messages.lock().unwrap(), vec!["later".to_owned()]);
+
+    session.shutdown();
+    server.shutdown();
+    Ok(())
+}
+
+struct Recipient(Mutex<bool>);
+
+impl DeathRecipient for Recipient {
+    fn binder_died(&self, _who: &WIBinder) {
+        *self.0.lock().unwrap() = true;
+    }
+}
+
+#[test]
+fn rpc_death_notification() -> Result<()> {
+    let (server, path) = start_server("death", 1);
+
+    let session = RpcSession::new();
+    session.set_max_incoming_threads(1);
+    session.setup_unix_domain_client(&path)?;
+    let root = session.root_object()?;
+
+    let recipient = Arc::new(Recipient(Mutex::new(false)));
+    let weak = Arc::downgrade(&recipient) as std::sync::Weak<dyn DeathRecipient>;
+    root.link_to_death(weak)?;
+
+    server.shutdown();
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !*recipient.0.lock().unwrap() && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert!(*recipient.0.lock().unwrap());
+    assert!(session.is_shutdown());
+    assert_eq!(root.ping_binder().err(), Some(StatusCode::DeadObject));
+    Ok(())
+}
@@ -0,0 +1,61 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+use rsbinder::*;
+
+mod common;
+use common::{serve, Service};
+
+const ECHO_BUFFERS: TransactionCode = FIRST_CALL_TRANSACTION;
+
+// The parent buffer holds a pointer to the name and its length.
+const NAME_OFFSET: usize = 0;
+
+fn on_transact(code: TransactionCode, reader: &mut Parcel, reply: &mut Parcel) -> Result<()> {
+    match code {
+        ECHO_BUFFERS => {
+            let (parent, header) = reader.read_buffer()?;
+            let header = header.to_vec();
+            let (_, name) = reader.read_embedded_buffer(parent, NAME_OFFSET)?;
+            let name = name.to_vec();
+
+            // The name is reachable through the pointer patched by the driver.
+            let pointer = u64::from_ne_bytes(header[0..8].try_into().unwrap());
+            let length = u64::from_ne_bytes(header[8..16].try_into().unwrap());
+            let embedded =
+                unsafe { std::slice::from_raw_parts(pointer as *const u8, length as usize) };
+            assert_eq!(embedded, name);
+
+            let parent = reply.write_buffer(header)?;
+            reply.write_embedded_buffer(name, parent, NAME_OFFSET)?;
+            Ok(())
+        }
+        _ => Err(StatusCode::UnknownTransaction),
+    }
+}
+
+#[test]
+fn scatter_gather_on_memory_driver() -> Result<()> {
+    let service = Binder::new(Service::new(on_transact));
+    let (_device, process) = serve(&service.as_binder());
+    let context = process.context_object()?;
+    let proxy = context.as_proxy().unwrap();
+
+    let name = b"scatter-gather".to_vec();
+    let mut header = vec![0u8; 16];
+    header[8..16].copy_from_slice(&(name.len() as u64).to_ne_bytes());
+
+    let mut data = proxy.prepare_transact(true)?;
+    let parent = data.write_buffer(header)?;
+    data.write_embedded_buffer(name.clone(), parent, NAME_OFFSET)?;
+    let mut reply = proxy
+        .submit_transact(ECHO_BUFFERS, &data, 0)?
+        .expect("reply");
+
+    let (parent, header) = reply.read_buffer()?;
+    assert_eq!(&header[8..16], &(name.len() as u64).to_ne_bytes());
+    let (_, echoed) = reply.read_embedded_buffer(parent, NAME_OFFSET)?;
+    assert_eq!(echoed, name);
+
+    Ok(())
+}
@@ -0,0 +1,231 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+#![allow(non_snake_case)]
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use hub::android_16::android::os::{
+    ConnectionInfo::ConnectionInfo, IClientCallback::IClientCallback, Service::Service,
+    ServiceWithMetadata::ServiceWithMetadata,
+};
+use hub::android_16::{BnServiceManager, IServiceManager};
+use hub::{IServiceCallback, ServiceDebugInfo};
+use rsbinder::driver::MemoryDevice;
+use rsbinder::*;
+
+const NAME: &str = "rsbinder.test.restartable";
+const LATE_NAME: &str = "rsbinder.test.late";
+
+// A service manager which doesn't notice dead services, and a service which
+// tells which instance serves it with listServices().
+#[derive(Default)]
+struct Manager {
+    instance: String,
+    services: Mutex<HashMap<String, SIBinder>>,
+    callbacks: Mutex<HashMap<String, Vec<Strong<dyn IServiceCallback>>>>,
+}
+
+impl Interface for Manager {}
+
+fn unsupported<T>() -> status::Result<T> {
+    Err(StatusCode::UnknownTransaction.into())
+}
+
+impl IServiceManager for Manager {
+    fn getService(&self, name: &str) -> status::Result<Option<SIBinder>> {
+        self.checkService(name)
+    }
+    fn getService2(&self, name: &str) -> status::Result<Service> {
+        self.checkService2(name)
+    }
+    fn checkService(&self, name: &str) -> status::Result<Option<SIBinder>> {
+        Ok(self.services.lock().unwrap().get(name).cloned())
+    }
+    fn checkService2(&self, name: &str) -> status::Result<Service> {
+        Ok(match self.checkService(name)? {
+            Some(binder) => Service::ServiceWithMetadata(ServiceWithMetadata {
+                service: Some(binder),
+                isLazyService: false,
+            }),
+            None => Service::Accessor(None),
+        })
+    }
+    fn addService(
+        &self,
+        name: &str,
+        service: &SIBinder,
+        _allowIsolated: bool,
+        _dumpPriority: i32,
+    ) -> status::Result<()> {
+        self.services
+            .lock()
+            .unwrap()
+            .insert(name.to_owned(), service.clone());
+        for callback in self
+            .callbacks
+            .lock()
+            .unwrap()
+            .get(name)
+            .into_iter()
+            .flatten()
+        {
+            callback.onRegistration(name, service)?;
+        }
+        Ok(())
+    }
+    fn listServices(&self, _dumpPriority: i32) -> status::Result<Vec<String>> {
+        Ok(vec![self.instance.clone()])
+    }
+    fn registerForNotifications(
+        &self,
+        name: &str,
+        callback: &Strong<dyn IServiceCallback>,
+    ) -> status::Result<()> {
+        if let Some(service) = self.checkService(name)? {
+            callback.onRegistration(name, &service)?;
+        }
+        self.callbacks
+            .lock()
+            .unwrap()
+            .entry(name.to_owned())
+            .or_default()
+            .push(callback.clone());
+        Ok(())
+    }
+    fn unregisterForNotifications(
+        &self,
+        name: &str,
+        callback: &Strong<dyn IServiceCallback>,
+    ) -> status::Result<()> {
+        if let Some(callbacks) = self.callbacks.lock().unwrap().get_mut(name) {
+            callbacks.retain(|c| c.as_binder() != callback.as_binder());
+        }
+        Ok(())
+    }
+    fn isDeclared(&self, _name: &str) -> status::Result<bool> {
+        Ok(false)
+    }
+    fn getDeclaredInstances(&self, _iface: &str) -> status::Result<Vec<String>> {
+        unsupported()
+    }
+    fn updatableViaApex(&self, _name: &str) -> status::Result<Option<String>> {
+        unsupported()
+    }
+    fn getUpdatableNames(&self, _apexName: &str) -> status::Result<Vec<String>> {
+        unsupported()
+    }
+    fn getConnectionInfo(&self, _name: &str) -> status::Result<Option<ConnectionInfo>> {
+        unsupported()
+    }
+    fn registerClientCallback(
+        &self,
+        _name: &str,
+        _service: &SIBinder,
+        _callback: &Strong<dyn IClientCallback>,
+    ) -> status::Result<()> {
+        unsupported()
+    }
+    fn tryUnregisterService(&self, _name: &str, _service: &SIBinder) -> status::Result<()> {
+        unsupported()
+    }
+    fn getServiceDebugInfo(&self) -> status::Result<Vec<ServiceDebugInfo>> {
+        unsupported()
+    }
+}
+
+// The device of the test binary, whose context manager is a Manager.
+fn device() -> &'static MemoryDevice {
+    static DEVICE: OnceLock<MemoryDevice> = OnceLock::new();
+    DEVICE.get_or_init(|| {
+        let device = MemoryDevice::new();
+        let process = ProcessState::builder()
+            .driver(Arc::new(device.open()))
+            .init()
+            .expect("init");
+        ProcessState::start_thread_pool();
+        let manager = BnServiceManager::new_binder(Manager::default());
+        process
+            .become_context_manager(manager.as_binder())
+            .expect("context manager");
+        device
+    })
+}
+
+// Start an instance of the service `name` in a binder context of its own,
+// which plays the role of the service process.
+fn start_service(device: &MemoryDevice, name: &str, instance: &str) -> &'static ProcessState {
+    let process = ProcessState::builder()
+        .driver(Arc::new(device.open()))
+        .init_context()
+        .expect("service context");
+    process.start_pool();
+    let service = BnServiceManager::new_binder(Manager {
+        instance: instance.to_owned(),
+        ..Default::default()
+    });
+    hub::for_context(process)
+        .add_service(name, service.as_binder())
+        .expect("add service");
+    process
+}
+
+#[test]
+fn service_handle_follows_restarts() -> Result<()> {
+    let device = device();
+
+    let mut handle = hub::ServiceHandle::<dyn IServiceManager>::new(NAME)?;
+    handle.set_retry_policy(hub::RetryPolicy {
+        wait_timeout: Duration::from_millis(100),
+        ..Default::default()
+    });
+    assert_eq!(handle.get().err(), Some(StatusCode::DeadObject));
+    handle.set_retry_policy(hub::RetryPolicy {
+        poll_interval: Duration::from_millis(50),
+        ..Default::default()
+    });
+
+    let service = start_service(device, NAME, "first");
+    assert_eq!(
+        handle.call(|service| service.listServices(0))?,
+        vec!["first".to_owned()]
+    );
+
+    // The service dies and comes back while calls are made.
+    service.shutdown(Duration::from_secs(10))?;
+    let restart = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(200));
+        start_service(device, NAME, "second");
+    });
+    assert_eq!(
+        handle.call(|service| service.listServices(0))?,
+        vec!["second".to_owned()]
+    );
+    restart.join().unwrap();
+
+    // The handle keeps the proxy of the restarted service.
+    let service = handle.get()?;
+    assert_eq!(service.listServices(0)?, vec!["second".to_owned()]);
+
+    Ok(())
+}
+
+#[test]
+fn get_service_waits_for_registration() -> Result<()> {
+    let device = device();
+
+    // The service is registered after it is looked up, as a service started
+    // on demand by the service manager is.
+    let start = Instant::now();
+    let late = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(200));
+        start_service(device, LATE_NAME, "late");
+    });
+    let service: Strong<dyn IServiceManager> = hub::get_interface(LATE_NAME)?;
+    assert_eq!(service.listServices(0)?, vec!["late".to_owned()]);
+    assert!(start.elapsed() < Duration::from_secs(5));
+    late.join().unwrap();
+
+    Ok(())
+}
@@ -0,0 +1,149 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+#![allow(non_snake_case)]
+
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use hub::{BnClientCallback, BnServiceCallback, IClientCallback, IServiceCallback};
+use rsbinder::driver::MemoryDevice;
+use rsbinder::*;
+
+// The service keeps the binder it is told about with onClients().
+#[derive(Default, Clone)]
+struct Service(Arc<Mutex<Option<SIBinder>>>);
+
+impl Interface for Service {
+    fn shell_command(
+        &self,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+        error: &mut dyn Write,
+        args: &[String],
+    ) -> Result<i32> {
+        match args.first().map(String::as_str) {
+            Some("echo") => {
+                let mut text = String::new();
+                input.read_to_string(&mut text)?;
+                write!(output, "{} {}", args[1..].join(" "), text.to_uppercase())?;
+                Ok(0)
+            }
+            Some("fail") => {
+                writeln!(error, "failed")?;
+                Ok(3)
+            }
+            _ => Err(StatusCode::BadValue),
+        }
+    }
+}
+
+impl IClientCallback for Service {
+    fn onClients(&self, registered: &SIBinder, _has_clients: bool) -> status::Result<()> {
+        *self.0.lock().unwrap() = Some(registered.clone());
+        Ok(())
+    }
+}
+
+// A service without shell commands.
+struct Plain;
+
+impl Interface for Plain {}
+
+impl IServiceCallback for Plain {
+    fn onRegistration(&self, _name: &str, _binder: &SIBinder) -> status::Result<()> {
+        Ok(())
+    }
+}
+
+struct Command {
+    result: i32,
+    output: String,
+    error: String,
+}
+
+fn run(binder: &SIBinder, input: &str, args: &[&str]) -> Result<Command> {
+    let (mut input_writer, input_reader) = UnixStream::pair()?;
+    let (output_writer, mut output_reader) = UnixStream::pair()?;
+    let (error_writer, mut error_reader) = UnixStream::pair()?;
+    input_writer.write_all(input.as_bytes())?;
+    drop(input_writer);
+
+    let receiver = ResultReceiver::new();
+    let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+    binder.as_proxy().unwrap().shell_command(
+        &input_reader,
+        &output_writer,
+        &error_writer,
+        &args,
+        Some(&receiver.as_binder()),
+    )?;
+    let result = receiver
+        .wait_timeout(Duration::from_secs(5))
+        .expect("result");
+    drop((output_writer, error_writer));
+
+    let mut command = Command {
+        result,
+        output: String::new(),
+        error: String::new(),
+    };
+    output_reader.read_to_string(&mut command.output)?;
+    error_reader.read_to_string(&mut command.error)?;
+    Ok(command)
+}
+
+#[test]
+fn shell_commands() -> Result<()> {
+    let device = MemoryDevice::new();
+    let process = ProcessState::builder()
+        .driver(Arc::new(device.open()))
+        .init()
+        .expect("init");
+    ProcessState::start_thread_pool();
+    let service = Service::default();
+    let service_binder = BnClientCallback::new_binder(service.clone());
+    process
+        .become_context_manager(service_binder.as_binder())
+        .expect("context manager");
+
+    // The client lives in a binder context of its own.
+    let client = ProcessState::builder()
+        .driver(Arc::new(device.open()))
+        .init_context()
+        .expect("client context");
+    client.start_pool();
+    let remote = client.context_object()?;
+
+    let command = run(&remote, "world", &["echo", "hello"])?;
+    assert_eq!(command.result, 0);
+    assert_eq!(command.output, "hello WORLD");
+    assert!(command.error.is_empty());
+
+    let command = run(&remote, "", &["fail"])?;
+    assert_eq!(command.result, 3);
+    assert_eq!(command.error, "failed\n");
+
+    let command = run(&remote, "", &["unknown"])?;
+    assert_eq!(StatusCode::from(command.result), StatusCode::BadValue);
+
+    // Services fail the commands they don't implement.
+    let plain = BnServiceCallback::new_binder(Plain);
+    let remote_service: Strong<dyn IClientCallback> = remote.into_interface()?;
+    remote_service.onClients(&plain.as_binder(), true)?;
+    // onClients() is oneway.
+    let remote_plain = loop {
+        if let Some(binder) = service.0.lock().unwrap().clone() {
+            break binder;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    let command = run(&remote_plain, "", &["echo"])?;
+    assert_eq!(
+        StatusCode::from(command.result),
+        StatusCode::InvalidOperation
+    );
+
+    Ok(())
+}
@@ -0,0 +1,72 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rsbinder::*;
+
+mod common;
+use common::{serve_with, Service};
+
+const SLOW: TransactionCode = FIRST_CALL_TRANSACTION;
+
+fn on_transact(code: TransactionCode, _reader: &mut Parcel, reply: &mut Parcel) -> Result<()> {
+    match code {
+        SLOW => {
+            std::thread::sleep(Duration::from_millis(200));
+            reply.write(&true)
+        }
+        _ => Err(StatusCode::UnknownTransaction),
+    }
+}
+
+#[test]
+fn shutdown_drains_thread_pool() -> Result<()> {
+    let started = Arc::new(AtomicUsize::new(0));
+    let stopped = Arc::new(AtomicUsize::new(0));
+    let builder = ProcessState::builder()
+        .on_thread_start({
+            let started = started.clone();
+            move || {
+                started.fetch_add(1, Ordering::SeqCst);
+            }
+        })
+        .on_thread_stop({
+            let stopped = stopped.clone();
+            move || {
+                stopped.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+    let service = Binder::new(Service::new(on_transact));
+    let (_device, process) = serve_with(builder, &service.as_binder());
+    let weak_driver = Arc::downgrade(&process.driver());
+    drop(service);
+    let joined = std::thread::spawn(move || process.join_pool());
+
+    // A transaction in flight is served before the pool goes away.
+    let client = std::thread::spawn(move || -> Result<bool> {
+        let context = process.context_object()?;
+        let proxy = context.as_proxy().unwrap();
+        let data = proxy.prepare_transact(true)?;
+        let reply = proxy.submit_transact(SLOW, &data, 0)?;
+        reply.expect("reply").read()
+    });
+    std::thread::sleep(Duration::from_millis(50));
+    process.shutdown(Duration::from_secs(10))?;
+
+    assert!(client.join().unwrap()?);
+    joined.join().unwrap()?;
+    assert!(started.load(Ordering::SeqCst) > 0);
+    assert_eq!(
+        started.load(Ordering::SeqCst),
+        stopped.load(Ordering::SeqCst)
+    );
+
+    // The context is closed.
+    assert!(process.context_object().is_err());
+    assert!(weak_driver.upgrade().is_none());
+
+    Ok(())
+}
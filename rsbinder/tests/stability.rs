@@ -0,0 +1,94 @@
+// Copyright 2022 Jeff Kim <hiking90@gmail.com>
+// SPDX-License-Identifier: Apache-2.0
+
+use std::sync::Arc;
+
+use rsbinder::*;
+
+mod common;
+use common::{context, serve, Service};
+
+const ECHO: TransactionCode = FIRST_CALL_TRANSACTION;
+const GET_BINDER: TransactionCode = FIRST_CALL_TRANSACTION + 1;
+
+fn on_transact(code: TransactionCode, reader: &mut Parcel, reply: &mut Parcel) -> Result<()> {
+    match code {
+        ECHO => reply.write(&reader.read::<i32>()?),
+        GET_BINDER => {
+            let binder = match Stability::try_from(reader.read::<i32>()?)? {
+                Stability::Local => Binder::new(Service::new(on_transact)),
+                stability => Binder::new_with_stability(Service::new(on_transact), stability),
+            };
+            reply.write(&binder.as_binder())
+        }
+        _ => Err(StatusCode::UnknownTransaction),
+    }
+}
+
+// Returns the status written by the service.
+fn echo(binder: &SIBinder, flags: TransactionFlags) -> Result<StatusCode> {
+    let proxy = binder.as_proxy().unwrap();
+    let mut data = proxy.prepare_transact(true)?;
+    data.write(&7i32)?;
+    let mut reply = proxy.submit_transact(ECHO, &data, flags)?.unwrap();
+    Ok(match reply.read::<i32>()? {
+        7 => StatusCode::Ok,
+        status => status.into(),
+    })
+}
+
+fn get_binder(binder: &SIBinder, stability: Stability) -> Result<SIBinder> {
+    let proxy = binder.as_proxy().unwrap();
+    let mut data = proxy.prepare_transact(true)?;
+    data.write(&i32::from(stability))?;
+    proxy.submit_transact(GET_BINDER, &data, 0)?.unwrap().read()
+}
+
+#[test]
+fn stability() -> Result<()> {
+    let service = Binder::new(Service::new(on_transact));
+    let (device, process) = serve(&service.as_binder());
+    assert_eq!(process.stability(), Stability::System);
+
+    // A system client, in a binder context of its own.
+    let client = context(&device, ProcessState::builder());
+    let remote = client.context_object()?;
+    assert_eq!(remote.stability(), Stability::System);
+    assert_eq!(echo(&remote, 0)?, StatusCode::Ok);
+
+    // A binder without a declared stability has the stability of its process.
+    let local = get_binder(&remote, Stability::Local)?;
+    assert_eq!(local.stability(), Stability::System);
+    assert_eq!(echo(&local, 0)?, StatusCode::Ok);
+    assert_eq!(echo(&local, FLAG_PRIVATE_VENDOR), Err(StatusCode::BadType));
+
+    // A vendor binder can't be called by a system process, but it can be pinged.
+    let vendor = get_binder(&remote, Stability::Vendor)?;
+    assert_eq!(vendor.stability(), Stability::Vendor);
+    assert_eq!(echo(&vendor, 0), Err(StatusCode::BadType));
+    assert_eq!(echo(&vendor, FLAG_PRIVATE_VENDOR)?, StatusCode::Ok);
+    vendor.ping_binder()?;
+
+    let vintf = get_binder(&remote, Stability::Vintf)?;
+    assert_eq!(vintf.stability(), Stability::Vintf);
+    assert_eq!(echo(&vintf, 0)?, StatusCode::Ok);
+    assert_eq!(echo(&vintf, FLAG_PRIVATE_VENDOR)?, StatusCode::Ok);
+
+    // A system service refuses the transactions of a vendor process.
+    let vendor_client = context(
+        &device,
+        ProcessState::builder().stability(Stability::Vendor),
+    );
+    let remote = vendor_client.context_object()?;
+    assert_eq!(remote.stability(), Stability::Vendor);
+    assert_eq!(echo(&remote, 0)?, StatusCode::BadType);
+
+    // A process is on the system or on the vendor partition.
+    assert!(ProcessState::builder()
+        .driver(Arc::new(device.open()))
+        .stability(Stability::Vintf)
+        .init_context()
+        .is_err());
+
+    Ok(())
+}